@@ -0,0 +1,55 @@
+//! A minimal `no_std` user of a `LockedHeap`, whose code size is checked in CI without
+//! any of the optional features.
+//!
+//! The measurement builds it as a shared library, so that the linker removes all code that
+//! the exported functions don't use. It only leaves out the standard library when it is
+//! built with `panic = "abort"`, as the measurement does:
+//!
+//! ```text
+//! CARGO_PROFILE_RELEASE_OPT_LEVEL=z CARGO_PROFILE_RELEASE_LTO=true \
+//! CARGO_PROFILE_RELEASE_CODEGEN_UNITS=1 RUSTFLAGS="-C panic=abort" \
+//! cargo rustc --release --example tiny --crate-type cdylib
+//! size -A target/release/examples/libtiny.so
+//! ```
+#![cfg_attr(panic = "abort", no_std)]
+
+// only `no_std` crates get `core` by default in edition 2015
+#[cfg(not(panic = "abort"))]
+extern crate core;
+extern crate linked_list_allocator;
+
+use core::alloc::{GlobalAlloc, Layout};
+use linked_list_allocator::LockedHeap;
+
+static HEAP: LockedHeap = LockedHeap::empty();
+
+/// # Safety
+///
+/// See [`LockedHeap::init`].
+#[no_mangle]
+pub unsafe extern "C" fn heap_init(bottom: *mut u8, size: usize) {
+    HEAP.init(bottom, size);
+}
+
+/// # Safety
+///
+/// See [`GlobalAlloc::alloc`].
+#[no_mangle]
+pub unsafe extern "C" fn heap_alloc(size: usize, align: usize) -> *mut u8 {
+    HEAP.alloc(Layout::from_size_align_unchecked(size, align))
+}
+
+/// # Safety
+///
+/// See [`GlobalAlloc::dealloc`].
+#[no_mangle]
+pub unsafe extern "C" fn heap_free(ptr: *mut u8, size: usize, align: usize) {
+    HEAP.dealloc(ptr, Layout::from_size_align_unchecked(size, align))
+}
+
+// the standard library brings its own panic handler
+#[cfg(all(panic = "abort", not(feature = "std")))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
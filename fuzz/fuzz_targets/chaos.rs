@@ -1,7 +1,7 @@
 #![no_main]
 use arbitrary::Arbitrary;
 use libfuzzer_sys::fuzz_target;
-use linked_list_allocator::Heap;
+use linked_list_allocator::{Heap, ScanState};
 use std::alloc::Layout;
 use std::ptr::{addr_of, NonNull};
 
@@ -13,6 +13,8 @@ enum Action {
     Free { index: u8 },
     // extend the heap by amount specified
     Extend { additional: u16 },
+    // check the number of free blocks specified
+    Scan { budget: u8 },
 }
 use Action::*;
 
@@ -96,6 +98,10 @@ fn fuzz(size: u16, actions: Vec<Action>) {
                     println!("new heap size: {}, top: {:?}", heap.size(), heap.top());
                 }
             },
+            Scan { budget } => {
+                let state = heap.scan_step(budget as usize);
+                assert!(!matches!(state, ScanState::Corrupted { .. }), "{:?}", state);
+            }
         }
         if DEBUG {
             println!("after action:");
@@ -0,0 +1,144 @@
+#![no_main]
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use linked_list_allocator::Heap;
+use std::alloc::Layout;
+use std::collections::BTreeMap;
+use std::ptr::{addr_of, addr_of_mut, NonNull};
+
+#[derive(Debug, Arbitrary)]
+enum Action {
+    // allocate a chunk with the size specified
+    Alloc { size: u16, align_bit: u8 },
+    // free the pointer at the index specified
+    Free { index: u8 },
+    // extend the heap by amount specified
+    Extend { additional: u16 },
+    // free all live allocations
+    Drain,
+}
+use Action::*;
+
+const MAX_HEAP_SIZE: usize = 5000;
+static mut HEAP_MEM: [u8; MAX_HEAP_SIZE] = [0; MAX_HEAP_SIZE];
+
+/// A simple model of the heap that tracks the live allocations by address.
+struct Oracle {
+    // start address -> (end address, layout)
+    live: BTreeMap<usize, (usize, Layout)>,
+}
+
+impl Oracle {
+    fn allocated(&mut self, heap: &Heap, ptr: NonNull<u8>, layout: Layout) {
+        let start = ptr.as_ptr() as usize;
+        let end = start + layout.size();
+
+        assert_eq!(start % layout.align(), 0, "allocation is not aligned");
+        assert!(start >= heap.bottom() as usize, "allocation below the heap");
+        assert!(end <= heap.top() as usize, "allocation above the heap");
+
+        // the allocation must not overlap its neighbors
+        if let Some((_, (prev_end, _))) = self.live.range(..=start).next_back() {
+            assert!(*prev_end <= start, "allocation overlaps previous allocation");
+        }
+        if let Some((next_start, _)) = self.live.range(start..).next() {
+            assert!(end <= *next_start, "allocation overlaps next allocation");
+        }
+        self.live.insert(start, (end, layout));
+    }
+
+    fn freed(&mut self, ptr: NonNull<u8>) -> Layout {
+        let (_, layout) = self.live.remove(&(ptr.as_ptr() as usize)).unwrap();
+        layout
+    }
+}
+
+fuzz_target!(|data: (u16, Vec<Action>)| {
+    let (size, actions) = data;
+    let _ = fuzz(size, actions);
+});
+
+fn fuzz(size: u16, actions: Vec<Action>) {
+    // init heap
+    let mut heap = unsafe {
+        let size = size as usize;
+        if size > MAX_HEAP_SIZE || size < 3 * core::mem::size_of::<usize>() {
+            return;
+        }
+
+        Heap::new(addr_of_mut!(HEAP_MEM).cast(), size)
+    };
+    let mut oracle = Oracle {
+        live: BTreeMap::new(),
+    };
+    let mut ptrs: Vec<NonNull<u8>> = Vec::new();
+
+    // process operations
+    for action in actions {
+        match action {
+            Alloc { size, align_bit } => {
+                let layout = {
+                    let align = 1_usize.rotate_left(align_bit as u32);
+                    if align == 1 << 63 {
+                        return;
+                    }
+                    Layout::from_size_align(size as usize, align).unwrap()
+                };
+
+                if let Ok(ptr) = heap.allocate_first_fit(layout) {
+                    oracle.allocated(&heap, ptr, layout);
+                    ptrs.push(ptr);
+                }
+            }
+            Free { index } => {
+                if index as usize >= ptrs.len() {
+                    continue;
+                }
+
+                let ptr = ptrs.swap_remove(index as usize);
+                let layout = oracle.freed(ptr);
+                unsafe {
+                    heap.deallocate(ptr, layout);
+                }
+            }
+            Extend { additional } =>
+            // safety: new heap size never exceeds MAX_HEAP_SIZE
+            unsafe {
+                let remaining_space = addr_of!(HEAP_MEM)
+                    .cast::<u8>()
+                    .add(MAX_HEAP_SIZE)
+                    .offset_from(heap.top());
+                assert!(remaining_space >= 0);
+
+                if additional as isize > remaining_space {
+                    continue;
+                }
+
+                heap.extend(additional as usize);
+            },
+            Drain => drain(&mut heap, &mut oracle, &mut ptrs),
+        }
+    }
+
+    drain(&mut heap, &mut oracle, &mut ptrs);
+}
+
+/// Frees all live allocations and checks that no memory was lost.
+fn drain(heap: &mut Heap, oracle: &mut Oracle, ptrs: &mut Vec<NonNull<u8>>) {
+    for ptr in ptrs.drain(..) {
+        let layout = oracle.freed(ptr);
+        unsafe {
+            heap.deallocate(ptr, layout);
+        }
+    }
+    assert!(oracle.live.is_empty());
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.free(), heap.size());
+
+    // the whole heap must be reusable as a single block
+    let full = Layout::from_size_align(heap.size(), 1).unwrap();
+    let ptr = heap
+        .allocate_first_fit(full)
+        .expect("free memory is fragmented after a full drain");
+    unsafe { heap.deallocate(ptr, full) };
+}
@@ -0,0 +1,99 @@
+#![no_main]
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use linked_list_allocator::LockedHeap;
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr::addr_of_mut;
+use std::thread;
+
+#[derive(Debug, Arbitrary)]
+enum Action {
+    // allocate a chunk with the size specified
+    Alloc { size: u16, align_bit: u8 },
+    // free the pointer at the index specified
+    Free { index: u8 },
+    // resize the pointer at the index specified
+    Realloc { index: u8, new_size: u16 },
+}
+use Action::*;
+
+const MAX_THREADS: usize = 4;
+const HEAP_SIZE: usize = 1 << 16;
+static mut HEAP_MEM: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+
+fuzz_target!(|threads: Vec<Vec<Action>>| {
+    fuzz(threads);
+});
+
+fn fuzz(threads: Vec<Vec<Action>>) {
+    let heap = unsafe { LockedHeap::new(addr_of_mut!(HEAP_MEM).cast(), HEAP_SIZE) };
+
+    thread::scope(|scope| {
+        for (id, actions) in threads.into_iter().take(MAX_THREADS).enumerate() {
+            let heap = &heap;
+            scope.spawn(move || run_thread(heap, id as u8 + 1, actions));
+        }
+    });
+
+    // all threads freed their allocations, so the heap must be empty again
+    let stats = heap.stats();
+    assert_eq!(stats.used, 0);
+    assert_eq!(stats.holes, 1);
+}
+
+/// Runs the actions of a single thread. Every allocation is filled with the thread `tag`,
+/// which is checked before it is freed to detect allocations that were handed out twice.
+fn run_thread(heap: &LockedHeap, tag: u8, actions: Vec<Action>) {
+    let mut ptrs: Vec<(*mut u8, Layout)> = Vec::new();
+
+    for action in actions {
+        match action {
+            Alloc { size, align_bit } => {
+                let align = 1_usize << (align_bit % 12);
+                let layout = Layout::from_size_align(size as usize, align).unwrap();
+                let ptr = unsafe { heap.alloc(layout) };
+                if !ptr.is_null() {
+                    assert_eq!(ptr as usize % align, 0);
+                    unsafe { ptr.write_bytes(tag, layout.size()) };
+                    ptrs.push((ptr, layout));
+                }
+            }
+            Free { index } => {
+                if index as usize >= ptrs.len() {
+                    continue;
+                }
+                let (ptr, layout) = ptrs.swap_remove(index as usize);
+                check(ptr, layout.size(), tag);
+                unsafe { heap.dealloc(ptr, layout) };
+            }
+            Realloc { index, new_size } => {
+                if index as usize >= ptrs.len() {
+                    continue;
+                }
+                let (ptr, layout) = ptrs[index as usize];
+                check(ptr, layout.size(), tag);
+                let new_ptr = unsafe { heap.realloc(ptr, layout, new_size as usize) };
+                if !new_ptr.is_null() {
+                    let new_layout = Layout::from_size_align(new_size as usize, layout.align())
+                        .unwrap();
+                    check(new_ptr, layout.size().min(new_layout.size()), tag);
+                    unsafe { new_ptr.write_bytes(tag, new_layout.size()) };
+                    ptrs[index as usize] = (new_ptr, new_layout);
+                }
+            }
+        }
+    }
+
+    for (ptr, layout) in ptrs {
+        check(ptr, layout.size(), tag);
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+}
+
+fn check(ptr: *mut u8, len: usize, tag: u8) {
+    let contents = unsafe { std::slice::from_raw_parts(ptr, len) };
+    assert!(
+        contents.iter().all(|&byte| byte == tag),
+        "allocation was modified by another thread"
+    );
+}
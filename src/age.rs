@@ -0,0 +1,240 @@
+//! Creation times of allocations, recorded with the `timestamps` feature.
+
+use core::alloc::Layout;
+use core::cmp::Reverse;
+use core::fmt;
+#[cfg(feature = "call_sites")]
+use core::panic::Location;
+use core::ptr::NonNull;
+
+use crate::header::{self, Header};
+use crate::{Allocations, Heap};
+
+/// A source of timestamps for the allocations of a heap, see [`Heap::set_clock`].
+///
+/// The unit of the timestamps is up to the clock, e.g. milliseconds since boot or the ticks
+/// of a hardware timer. They only have to increase monotonically, wrapping around is fine.
+///
+/// This trait is implemented for all functions and closures that return the current time.
+pub trait Clock: Sync {
+    /// Returns the current time.
+    fn now(&self) -> u64;
+}
+
+impl<F: Fn() -> u64 + Sync> Clock for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+impl Heap {
+    /// Installs a [`Clock`] whose time is recorded in the header of every allocation.
+    /// Passing `None` removes the installed clock.
+    ///
+    /// Allocations that are made while no clock is installed have the time 0.
+    ///
+    /// ```ignore
+    /// fn millis() -> u64 { … }
+    ///
+    /// heap.set_clock(Some(&(millis as fn() -> u64)));
+    /// ```
+    pub fn set_clock(&mut self, clock: Option<&'static dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Returns the time at which the allocation at `ptr` was made, see
+    /// [`set_clock`][Self::set_clock].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation of this heap. Zero-sized allocations have no header,
+    /// so their pointers must not be passed.
+    pub unsafe fn allocation_time(&self, ptr: NonNull<u8>) -> u64 {
+        Header::of_block(Header::block_of(self.strip_tag(ptr))).created
+    }
+
+    /// Groups the live allocations that are at least `min_age` old by their
+    /// [tag][Self::allocate_tagged] and, with the `call_sites` feature, by their call site.
+    ///
+    /// This is meant for finding slow leaks on systems that run for a long time: memory that
+    /// is still allocated after hours was most likely leaked. The groups are written to the
+    /// start of `groups` in the order of their oldest allocation, and their number is
+    /// returned. Once `groups` is full, the allocations of further groups are left out.
+    /// Without a [clock][Self::set_clock], no allocation is old.
+    ///
+    /// The heap is walked once for every group, so the runtime is in `O(n * m)` where n is
+    /// the number of live allocations and free blocks and m is the length of `groups`.
+    pub fn old_allocations(&self, min_age: u64, groups: &mut [AgeGroup]) -> usize {
+        let now = match self.clock {
+            Some(clock) => clock.now(),
+            None => return 0,
+        };
+        let mut count = 0;
+        for header in self.headers() {
+            if header.is_pinned() || now.wrapping_sub(header.created) < min_age {
+                continue;
+            }
+            let group = groups[..count]
+                .iter_mut()
+                .position(|group| group.contains(header));
+            let group = match group {
+                Some(index) => &mut groups[index],
+                None if count < groups.len() => {
+                    groups[count] = AgeGroup::new(header);
+                    count += 1;
+                    &mut groups[count - 1]
+                }
+                None => continue,
+            };
+            group.count += 1;
+            group.bytes += header.layout.size();
+        }
+        // the blocks are visited in address order, not in the order of their age
+        groups[..count].sort_unstable_by_key(|group| Reverse(now.wrapping_sub(group.oldest)));
+        count
+    }
+
+    /// Returns the headers of the live blocks.
+    fn headers(&self) -> impl Iterator<Item = &Header> + '_ {
+        let mut blocks = Allocations::new(&self.holes, self.bottom());
+        core::iter::from_fn(move || blocks.next_block())
+            // SAFETY: The iterator only yields live blocks.
+            .map(|block| unsafe { Header::of_block(block) })
+    }
+
+    /// Records the current time in the header of the allocation at `ptr` with the given
+    /// layout.
+    pub(crate) fn record_time(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if let Some(clock) = self.clock {
+            if self.has_own_header(ptr, layout) {
+                // SAFETY: The allocation was just made and its header belongs to the heap.
+                unsafe { header::set_created(self.strip_tag(ptr), clock.now()) };
+            }
+        }
+    }
+}
+
+/// Live allocations with the same tag and call site that are older than a threshold,
+/// returned by [`Heap::old_allocations`].
+///
+/// The `Display` implementation prints the group on one line, e.g. for a report of
+/// suspected leaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AgeGroup {
+    /// The tag of the allocations, see [`Heap::allocate_tagged`].
+    pub tag: usize,
+    /// The call site of the allocations.
+    #[cfg(feature = "call_sites")]
+    pub site: Option<&'static Location<'static>>,
+    /// The number of allocations.
+    pub count: usize,
+    /// The number of requested bytes of the allocations.
+    pub bytes: usize,
+    /// The time of the oldest allocation.
+    pub oldest: u64,
+}
+
+impl AgeGroup {
+    fn new(header: &Header) -> Self {
+        AgeGroup {
+            tag: header.tag,
+            #[cfg(feature = "call_sites")]
+            site: header.site,
+            count: 0,
+            bytes: 0,
+            oldest: header.created,
+        }
+    }
+
+    /// Returns whether the allocation with the given header belongs to this group, and
+    /// updates the time of the oldest allocation if it does.
+    fn contains(&mut self, header: &Header) -> bool {
+        #[cfg(feature = "call_sites")]
+        if self.site != header.site {
+            return false;
+        }
+        if self.tag != header.tag {
+            return false;
+        }
+        if (header.created.wrapping_sub(self.oldest) as i64) < 0 {
+            self.oldest = header.created;
+        }
+        true
+    }
+}
+
+impl fmt::Display for AgeGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations with {} bytes, tag {}, the oldest from {}",
+            self.count, self.bytes, self.tag, self.oldest
+        )?;
+        #[cfg(feature = "call_sites")]
+        write!(f, ", allocated at {}", crate::site::Site(self.site))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AgeGroup;
+    use crate::test::new_heap;
+    use core::alloc::Layout;
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use std::string::ToString;
+
+    static NOW: AtomicU64 = AtomicU64::new(0);
+
+    fn now() -> u64 {
+        NOW.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn reports_old_allocations() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        let untimed = heap.allocate_first_fit(layout).unwrap();
+        assert_eq!(heap.old_allocations(0, &mut [AgeGroup::default(); 4]), 0);
+
+        heap.set_clock(Some(&(now as fn() -> u64)));
+        NOW.store(100, Ordering::Relaxed);
+        let mut old = [core::ptr::NonNull::dangling(); 2];
+        for (i, ptr) in old.iter_mut().enumerate() {
+            NOW.store(100 + i as u64, Ordering::Relaxed);
+            *ptr = heap.allocate_tagged(layout, 7).unwrap();
+        }
+        NOW.store(200, Ordering::Relaxed);
+        let young = heap.allocate_tagged(layout, 8).unwrap();
+        assert_eq!(unsafe { heap.allocation_time(untimed) }, 0);
+        assert_eq!(unsafe { heap.allocation_time(old[1]) }, 101);
+        assert_eq!(unsafe { heap.allocation_time(young) }, 200);
+
+        NOW.store(250, Ordering::Relaxed);
+        let mut groups = [AgeGroup::default(); 4];
+        assert_eq!(heap.old_allocations(100, &mut groups), 2);
+        // the allocation without a clock is the oldest
+        assert_eq!(
+            (groups[0].tag, groups[0].count, groups[0].oldest),
+            (0, 1, 0)
+        );
+        assert_eq!((groups[1].tag, groups[1].count), (7, 2));
+        assert_eq!((groups[1].bytes, groups[1].oldest), (32, 100));
+        assert!(groups[1]
+            .to_string()
+            .starts_with("2 allocations with 32 bytes, tag 7, the oldest from 100"));
+
+        // groups that don't fit are left out
+        assert_eq!(heap.old_allocations(0, &mut groups[..1]), 1);
+        assert_eq!(groups[0].tag, 0);
+
+        unsafe {
+            heap.deallocate(untimed, layout);
+            for ptr in old {
+                heap.deallocate(ptr, layout);
+            }
+            heap.deallocate(young, layout);
+        }
+        assert_eq!(heap.old_allocations(0, &mut groups), 0);
+    }
+}
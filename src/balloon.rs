@@ -0,0 +1,191 @@
+//! Support for balloon drivers, which temporarily give free memory to a hypervisor.
+//!
+//! A balloon driver looks for free pages with [`Heap::free_pages`], removes them from the
+//! heap with [`Heap::inflate`] and donates them to the hypervisor. When the hypervisor
+//! returns them, [`Heap::deflate`] makes them available for allocations again.
+
+use core::alloc::Layout;
+use core::mem::align_of;
+use core::ptr::NonNull;
+
+use crate::header::{self, FIXED_OFFSET};
+use crate::hole::{Hole, HoleList, Holes};
+use crate::{align_up, Heap};
+
+/// An iterator over the free pages of a [`Heap`], in address order.
+///
+/// Created by [`Heap::free_pages`]. Yields the start and the length of every range of
+/// whole pages that can be removed with [`Heap::inflate`].
+pub struct FreePages<'a> {
+    holes: Holes<'a>,
+    page_size: usize,
+}
+
+impl<'a> Iterator for FreePages<'a> {
+    type Item = (NonNull<u8>, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let page = self.page_size;
+        for (addr, size) in &mut self.holes {
+            let hole_end = addr.wrapping_add(size);
+            // the rest of the hole in front of and behind the pages must either be empty or
+            // large enough for a hole, and the block of the pages needs room for its header
+            let mut start = align_up(addr.wrapping_add(FIXED_OFFSET), page);
+            let front = start as usize - FIXED_OFFSET - addr as usize;
+            if front != 0 && front < HoleList::min_size() {
+                start = start.wrapping_add(page);
+            }
+            let mut end = hole_end.wrapping_sub(hole_end as usize % page);
+            let back = hole_end as usize - end as usize;
+            if back != 0 && back < HoleList::min_size() {
+                end = end.wrapping_sub(page);
+            }
+            if end > start {
+                // SAFETY: The range lies within the hole, which is not at address zero.
+                let start = unsafe { NonNull::new_unchecked(start) };
+                return Some((start, end as usize - start.as_ptr() as usize));
+            }
+        }
+        None
+    }
+}
+
+impl Heap {
+    /// Returns an iterator over the ranges of free memory that consist of whole pages of
+    /// `page_size` bytes and can be removed from the heap with [`inflate`][Heap::inflate].
+    ///
+    /// The `page_size` must be a power of two.
+    pub fn free_pages(&self, page_size: usize) -> FreePages<'_> {
+        assert!(
+            page_size.is_power_of_two(),
+            "page size must be a power of two"
+        );
+        FreePages {
+            holes: self.holes.holes(),
+            page_size: page_size.max(align_of::<Hole>()),
+        }
+    }
+
+    /// Removes the `len` bytes of free memory at `ptr` from the heap, e.g. to donate them
+    /// to a hypervisor. Any range or subrange of whole pages returned by
+    /// [`free_pages`][Heap::free_pages] can be removed.
+    ///
+    /// The range counts as used until it is returned with [`deflate`][Heap::deflate]. The
+    /// heap doesn't access it in the meantime. With the `headers` feature, the range is
+    /// preceded by a header and shows up in [`allocations`][Heap::allocations], but it is
+    /// never moved or freed by the heap.
+    ///
+    /// Returns `false` and leaves the heap untouched if the range is not free or if the
+    /// free memory around it would be too small to be used.
+    pub fn inflate(&mut self, ptr: NonNull<u8>, len: usize) -> bool {
+        let word = align_of::<Hole>();
+        let block = ptr.as_ptr().wrapping_sub(FIXED_OFFSET);
+        if ptr.as_ptr() as usize % word != 0 || len % word != 0 || len == 0 {
+            return false;
+        }
+        if block < self.bottom() || ptr.as_ptr().wrapping_add(len) > self.top() {
+            return false;
+        }
+        let block_size = FIXED_OFFSET + len;
+        if let Some(pages) = self.holes.pages {
+            // the header might lie in a decommitted page
+            unsafe { pages.commit(block, FIXED_OFFSET) };
+        }
+        if !self.holes.allocate_at(block, block_size) {
+            return false;
+        }
+        self.used += block_size;
+        self.update_pressure();
+        // SAFETY: The block was just allocated for this size.
+        unsafe {
+            let block = NonNull::new_unchecked(block);
+            let layout = Layout::from_size_align_unchecked(len, word);
+            let payload = header::write(block, block_size, layout, FIXED_OFFSET);
+            #[cfg(feature = "headers")]
+            header::set_flags(payload, header::PINNED);
+            debug_assert_eq!(payload, ptr);
+        }
+        true
+    }
+
+    /// Returns a range that was removed with [`inflate`][Heap::inflate] to the heap.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `len` must be the arguments of a successful call to `inflate`, and the
+    /// memory must be accessible again.
+    pub unsafe fn deflate(&mut self, ptr: NonNull<u8>, len: usize) {
+        let layout = Layout::from_size_align_unchecked(len, align_of::<Hole>());
+        let (block, block_layout) = header::block(ptr, layout);
+        let size = self.free_block(block, block_layout);
+        self.used = self.used.saturating_sub(size);
+        self.update_pressure();
+    }
+}
+
+// the heap of the tests is too small for the pages and the large blocks of this combination
+#[cfg(all(
+    test,
+    not(all(feature = "headers", feature = "redundant")),
+    not(all(feature = "call_sites", feature = "timestamps", feature = "checksum"))
+))]
+mod test {
+    use crate::header::FIXED_OFFSET;
+    use crate::test::new_heap;
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use std::vec::Vec;
+
+    #[test]
+    fn inflate_and_deflate() {
+        let mut heap = new_heap();
+        let bottom = heap.bottom();
+        let pages: Vec<_> = heap.free_pages(128).collect();
+        assert_eq!(pages.len(), 1);
+        let (start, len) = pages[0];
+        assert_eq!(start.as_ptr() as usize % 128, 0);
+        assert!(start.as_ptr() >= bottom.wrapping_add(FIXED_OFFSET) && len >= 768);
+
+        let ptr = NonNull::new(start.as_ptr().wrapping_add(128)).unwrap();
+        assert!(!heap.inflate(NonNull::new(bottom.wrapping_add(4)).unwrap(), 128));
+        assert!(heap.inflate(ptr, 256));
+        assert!(!heap.inflate(ptr, 128));
+        assert_eq!(heap.used(), FIXED_OFFSET + 256);
+        for (free, len) in heap.free_pages(128) {
+            let free = free.as_ptr();
+            assert!(
+                free.wrapping_add(len) <= ptr.as_ptr() || free >= ptr.as_ptr().wrapping_add(256)
+            );
+        }
+
+        let layout = Layout::from_size_align(128, 8).unwrap();
+        let mut allocations = Vec::new();
+        while let Ok(alloc) = heap.allocate_first_fit(layout) {
+            let addr = alloc.as_ptr();
+            assert!(
+                addr.wrapping_add(128) <= ptr.as_ptr() || addr >= ptr.as_ptr().wrapping_add(256)
+            );
+            allocations.push(alloc);
+        }
+        assert!(allocations.len() >= 3);
+        #[cfg(feature = "headers")]
+        {
+            assert!(heap.allocations().any(|(alloc, _)| alloc == ptr));
+            let mut relocator = |_: NonNull<u8>, _: NonNull<u8>, _: Layout| {};
+            for alloc in allocations.drain(..1) {
+                unsafe { heap.deallocate(alloc, layout) };
+            }
+            unsafe { heap.defragment(&mut relocator) };
+            assert!(heap.allocations().any(|(alloc, _)| alloc == ptr));
+            assert_eq!(unsafe { heap.free_all_tagged(0) }, allocations.len());
+            allocations.clear();
+        }
+
+        for alloc in allocations {
+            unsafe { heap.deallocate(alloc, layout) };
+        }
+        unsafe { heap.deflate(ptr, 256) };
+        assert_eq!(heap.used(), 0);
+        assert!(heap.free_pages(128).eq(pages));
+    }
+}
@@ -0,0 +1,228 @@
+//! Two-phase initialization with a bump allocator for the boot phase, see
+//! [`Heap::init_boot`].
+
+use core::alloc::Layout;
+use core::mem::align_of;
+use core::ptr::NonNull;
+
+#[cfg(feature = "headers")]
+use crate::header;
+use crate::header::FIXED_OFFSET;
+use crate::hole::{Hole, HoleList};
+use crate::{align_up_size, checked_align_up_size, AllocError, Heap};
+
+/// The region at the bottom of a heap that allocations are bumped into during the boot
+/// phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BootRegion {
+    /// The size of the region in bytes. It is kept as an offset from the bottom of the
+    /// heap, so that it moves along when the heap is relocated.
+    len: usize,
+    /// Whether allocations are still taken from the end of the region.
+    active: bool,
+}
+
+impl BootRegion {
+    pub const fn new() -> Self {
+        BootRegion {
+            len: 0,
+            active: false,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns whether `ptr` lies within the region of a heap starting at `bottom`.
+    pub fn contains(&self, bottom: *mut u8, ptr: NonNull<u8>) -> bool {
+        (ptr.as_ptr() as usize).wrapping_sub(bottom as usize) < self.len
+    }
+}
+
+impl Heap {
+    /// Like [`init`][Heap::init], but starts the heap in a boot phase in which allocations
+    /// are bump allocated.
+    ///
+    /// Until [`finalize`][Heap::finalize] is called, every allocation is placed directly
+    /// behind the previous one, without a header and without searching the list of free
+    /// blocks, which stays a single block behind the allocations. This is meant for early
+    /// boot code that makes many allocations that are never freed. Freeing them is allowed
+    /// but has no effect, both during and after the boot phase, so the memory of the boot
+    /// allocations is never reused. They must not be passed to
+    /// [`deallocate_unsized`][Heap::deallocate_unsized].
+    ///
+    /// With the `headers` feature, the boot allocations show up as a single pinned block in
+    /// [`allocations`][Heap::allocations].
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`init`][Heap::init] apply.
+    pub unsafe fn init_boot(&mut self, heap_bottom: *mut u8, heap_size: usize) {
+        self.init(heap_bottom, heap_size);
+        // room for the header of the region
+        let len = align_up_size(FIXED_OFFSET, align_of::<Hole>());
+        if len != 0 {
+            let reserved = self.holes.allocate_at(self.bottom(), len);
+            assert!(reserved, "heap is too small for a boot region");
+            self.used += len;
+        }
+        self.boot = BootRegion { len, active: true };
+        self.write_boot_header();
+    }
+
+    /// Ends the boot phase that was started by [`init_boot`][Heap::init_boot].
+    ///
+    /// Further allocations are taken from the list of free blocks as usual. The free memory
+    /// behind the boot allocations already forms a free block, so this takes constant time.
+    /// Calling it on a heap that is not in the boot phase has no effect.
+    pub fn finalize(&mut self) {
+        self.boot.active = false;
+    }
+
+    /// Returns whether the heap is in the boot phase, see [`init_boot`][Heap::init_boot].
+    pub fn is_booting(&self) -> bool {
+        self.boot.is_active()
+    }
+
+    /// Allocates a block for `layout` at the end of the boot region, so that the address
+    /// `offset` bytes into the payload is aligned.
+    pub(crate) fn allocate_boot(
+        &mut self,
+        layout: Layout,
+        offset: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let requested = layout;
+        let layout = self.padded_layout(layout)?;
+        let start = self.bottom().wrapping_add(self.boot.len);
+        let hole_size = match self.holes.first() {
+            // SAFETY: The holes of the list are valid.
+            Some(hole) if hole.as_ptr().cast::<u8>() == start => unsafe { hole.as_ref().size },
+            _ => return Err(AllocError::OutOfMemory),
+        };
+
+        let align = layout.align();
+        let padding = (align - (start as usize).wrapping_add(offset) % align) % align;
+        let size = padding
+            .checked_add(layout.size())
+            .and_then(|size| checked_align_up_size(size, align_of::<Hole>()))
+            .ok_or(AllocError::InvalidLayout)?;
+        let size = match hole_size.checked_sub(size) {
+            Some(rest) if rest == 0 || rest >= HoleList::min_size() => size,
+            // the rest of the free block is too small to stay one
+            Some(_) => hole_size,
+            None => return Err(AllocError::OutOfMemory),
+        };
+
+        if let Some(pages) = self.holes.pages {
+            // SAFETY: The block lies within the free block.
+            unsafe { pages.commit(start, size) };
+        }
+        let allocated = self.holes.allocate_at(start, size);
+        debug_assert!(allocated);
+        self.boot.len += size;
+        self.charge(requested, size);
+        self.write_boot_header();
+
+        // SAFETY: The payload lies within the heap, which is not at address zero.
+        let payload = unsafe { NonNull::new_unchecked(start.add(padding)) };
+        #[cfg(feature = "tagging")]
+        if let Some(tagger) = self.tagger {
+            // SAFETY: The payload is aligned to and padded to whole granules.
+            return Ok(unsafe { tagger.tag(payload, layout.size()) });
+        }
+        Ok(payload)
+    }
+
+    /// Writes the header that covers the whole boot region and marks it as pinned.
+    #[cfg(feature = "headers")]
+    fn write_boot_header(&mut self) {
+        let len = self.boot.len;
+        // SAFETY: The region is allocated and starts with room for the header.
+        unsafe {
+            let block = NonNull::new_unchecked(self.bottom());
+            let layout = Layout::from_size_align_unchecked(len - FIXED_OFFSET, align_of::<Hole>());
+            let payload = header::write(block, len, layout, FIXED_OFFSET);
+            header::set_flags(payload, header::PINNED);
+        }
+    }
+
+    #[cfg(not(feature = "headers"))]
+    fn write_boot_header(&mut self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::Chonk;
+    use crate::{AllocError, Heap};
+    use core::alloc::Layout;
+    use core::mem::align_of;
+
+    #[test]
+    fn bump_then_finalize() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let mut heap = Heap::empty();
+        unsafe { heap.init_boot(data, 1024) };
+        assert!(heap.is_booting());
+        let reserved = heap.used();
+
+        let word = Layout::from_size_align(8, 8).unwrap();
+        let a = heap.allocate_first_fit(word).unwrap();
+        let b = heap.allocate_first_fit(word).unwrap();
+        // boot allocations are placed back to back, without a header
+        assert_eq!(b.as_ptr() as usize - a.as_ptr() as usize, 8);
+        let aligned = Layout::from_size_align(24, 64).unwrap();
+        let c = heap.allocate_first_fit(aligned).unwrap();
+        assert_eq!(c.as_ptr() as usize % 64, 0);
+        let boot_used = heap.used();
+        assert_eq!(
+            boot_used - reserved,
+            c.as_ptr() as usize + 24 - a.as_ptr() as usize
+        );
+        assert_eq!(heap.stats().holes, 1);
+        #[cfg(feature = "headers")]
+        assert_eq!(heap.allocations().count(), 1);
+
+        // freeing a boot allocation has no effect
+        unsafe { heap.deallocate(b, word) };
+        assert_eq!(heap.used(), boot_used);
+        assert!(heap.allocate_first_fit(word).unwrap() > c);
+        let boot_used = heap.used();
+
+        heap.finalize();
+        assert!(!heap.is_booting());
+        let d = heap.allocate_first_fit(word).unwrap();
+        assert!(d > c);
+        unsafe {
+            heap.deallocate(d, word);
+            heap.deallocate(a, word);
+        }
+        assert_eq!(heap.used(), boot_used);
+
+        // the boot region only grows while there is free memory behind it
+        let mut heap = Heap::empty();
+        unsafe { heap.init_boot(data, 1024) };
+        let large = Layout::from_size_align(200, align_of::<usize>()).unwrap();
+        while heap.allocate_first_fit(large).is_ok() {}
+        assert_eq!(heap.allocate_first_fit(large), Err(AllocError::OutOfMemory));
+        assert!(heap.free() < 200);
+
+        // initializing the heap again drops the boot region, which would keep it from
+        // being merged on top of another heap
+        let mut lower = unsafe { Heap::new(data, 512) };
+        let mut upper = Heap::empty();
+        unsafe {
+            upper.init_boot(data.add(512), 512);
+            upper.init_persistent(data.add(512), 512);
+        }
+        assert!(!upper.is_booting());
+        lower = lower.merge(upper);
+        assert_eq!(lower.used(), 0);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+}
@@ -0,0 +1,255 @@
+//! An owning pointer to a value on a specific heap, see [`HeapBox`].
+
+use core::alloc::Layout;
+use core::cell::RefCell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+
+use crate::{AllocError, Heap};
+#[cfg(feature = "use_spin")]
+use crate::{LockedHeap, RawMutex};
+
+/// A heap that can allocate and free through a shared reference, so that a [`HeapBox`] can
+/// free its value on drop while other allocations are made from the same heap.
+///
+/// It is implemented for [`LockedHeap`] and for a [`Heap`] in a [`RefCell`].
+///
+/// # Safety
+///
+/// A successful `allocate` must return a block that is valid for `layout` and not used by
+/// anything else until it is passed to `deallocate`.
+pub unsafe trait SharedHeap {
+    /// Allocates a block for `layout`.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError>;
+
+    /// Frees a block that was returned by [`allocate`][SharedHeap::allocate].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by `self` for `layout` and not freed yet.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+#[cfg(feature = "use_spin")]
+unsafe impl<R: RawMutex> SharedHeap for LockedHeap<R> {
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.allocate_first_fit(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        LockedHeap::deallocate(self, ptr, layout)
+    }
+}
+
+/// Panics if the heap is already borrowed, e.g. when a `HeapBox` is dropped while the heap
+/// is borrowed mutably.
+unsafe impl SharedHeap for RefCell<Heap> {
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.borrow_mut().allocate_first_fit(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.borrow_mut().deallocate(ptr, layout)
+    }
+}
+
+/// A pointer to a value of type `T` that was moved into a block of the heap `H`.
+///
+/// Like a `Box`, it owns the value: when it is dropped, the destructor of the value runs and
+/// the block is freed to the heap that it was allocated from. This keeps the pointer and its
+/// layout together, so users of the heap API don't have to free blocks by hand.
+///
+/// ```ignore
+/// use core::cell::RefCell;
+/// use linked_list_allocator::{Heap, HeapBox};
+///
+/// let heap = RefCell::new(unsafe { Heap::new(heap_bottom, heap_size) });
+/// let mut packet = HeapBox::new_in([0u8; 1500], &heap)?;
+/// packet[0] = 0x45;
+/// // the block is freed here
+/// ```
+pub struct HeapBox<'a, T, H: SharedHeap + ?Sized> {
+    ptr: NonNull<T>,
+    heap: &'a H,
+    _owned: PhantomData<T>,
+}
+
+unsafe impl<'a, T: Send, H: SharedHeap + Sync + ?Sized> Send for HeapBox<'a, T, H> {}
+unsafe impl<'a, T: Sync, H: SharedHeap + Sync + ?Sized> Sync for HeapBox<'a, T, H> {}
+
+impl<'a, T, H: SharedHeap + ?Sized> HeapBox<'a, T, H> {
+    /// Allocates a block for a `T` from `heap` and moves `value` into it.
+    ///
+    /// Zero-sized types don't take any memory. If the allocation fails, `value` is dropped.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn new_in(value: T, heap: &'a H) -> Result<Self, AllocError> {
+        let ptr = heap.allocate(Layout::new::<T>())?.cast::<T>();
+        // SAFETY: The block was just allocated for a `T`.
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(HeapBox {
+            ptr,
+            heap,
+            _owned: PhantomData,
+        })
+    }
+
+    /// Takes ownership of a value that was moved into a block of `heap`, e.g. by an earlier
+    /// [`into_raw`][HeapBox::into_raw].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an initialized `T` in a block that was allocated from `heap` for
+    /// `Layout::new::<T>()`, and nothing else may access or free the block afterwards.
+    pub unsafe fn from_raw_in(ptr: NonNull<T>, heap: &'a H) -> Self {
+        HeapBox {
+            ptr,
+            heap,
+            _owned: PhantomData,
+        }
+    }
+
+    /// Consumes the box without dropping the value or freeing the block, and returns the
+    /// pointer to the value.
+    ///
+    /// The value can be put back into a box with [`from_raw_in`][HeapBox::from_raw_in] or
+    /// freed with `Layout::new::<T>()`.
+    pub fn into_raw(this: Self) -> NonNull<T> {
+        let ptr = this.ptr;
+        core::mem::forget(this);
+        ptr
+    }
+
+    /// Moves the value out of the box and frees the block.
+    pub fn into_inner(this: Self) -> T {
+        let heap = this.heap;
+        let ptr = HeapBox::into_raw(this);
+        // SAFETY: The box owned the value and doesn't drop it anymore, and the block was
+        // allocated from this heap for a `T`.
+        unsafe {
+            let value = ptr.as_ptr().read();
+            heap.deallocate(ptr.cast(), Layout::new::<T>());
+            value
+        }
+    }
+
+    /// Returns the heap that the value lives in.
+    pub fn heap(this: &Self) -> &'a H {
+        this.heap
+    }
+}
+
+impl<'a, T, H: SharedHeap + ?Sized> Deref for HeapBox<'a, T, H> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The box owns an initialized value.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<'a, T, H: SharedHeap + ?Sized> DerefMut for HeapBox<'a, T, H> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The box owns an initialized value.
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<'a, T, H: SharedHeap + ?Sized> Drop for HeapBox<'a, T, H> {
+    fn drop(&mut self) {
+        // SAFETY: The box owns the value, and the block was allocated from this heap for a
+        // `T`.
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+            self.heap.deallocate(self.ptr.cast(), Layout::new::<T>());
+        }
+    }
+}
+
+impl<'a, T: fmt::Debug, H: SharedHeap + ?Sized> fmt::Debug for HeapBox<'a, T, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: fmt::Display, H: SharedHeap + ?Sized> fmt::Display for HeapBox<'a, T, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HeapBox;
+    use crate::test::Chonk;
+    use crate::Heap;
+    use core::cell::{Cell, RefCell};
+
+    /// Counts how often it was dropped.
+    struct Dropped<'a>(&'a Cell<usize>);
+
+    impl<'a> Drop for Dropped<'a> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn drops_and_frees() {
+        let (chonk, data) = Chonk::<512>::new();
+        let heap = RefCell::new(unsafe { Heap::new(data, 512) });
+        let dropped = Cell::new(0);
+
+        let mut value = HeapBox::new_in([1u64; 4], &heap).unwrap();
+        value[3] = 5;
+        assert_eq!(*value, [1, 1, 1, 5]);
+        let used = heap.borrow().used();
+        assert!(used >= 32);
+
+        let first = HeapBox::new_in(Dropped(&dropped), &heap).unwrap();
+        let second = HeapBox::new_in(Dropped(&dropped), &heap).unwrap();
+        drop(first);
+        assert_eq!(dropped.get(), 1);
+        let inner = HeapBox::into_inner(second);
+        assert_eq!(dropped.get(), 1);
+        drop(inner);
+        assert_eq!(dropped.get(), 2);
+        assert_eq!(heap.borrow().used(), used);
+
+        let ptr = HeapBox::into_raw(value);
+        assert_eq!(heap.borrow().used(), used);
+        let value = unsafe { HeapBox::from_raw_in(ptr, &heap) };
+        assert_eq!(value[3], 5);
+        drop(value);
+        assert_eq!(heap.borrow().used(), 0);
+
+        // zero-sized values don't take memory
+        let unit = HeapBox::new_in((), &heap).unwrap();
+        assert_eq!(heap.borrow().used(), 0);
+        drop(unit);
+
+        let too_large = HeapBox::new_in([0u8; 1024], &heap);
+        assert!(too_large.is_err());
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    #[cfg(feature = "use_spin")]
+    fn locked_heap() {
+        use crate::LockedHeap;
+
+        let (chonk, data) = Chonk::<512>::new();
+        let heap = unsafe { LockedHeap::new(data, 512) };
+        let value = HeapBox::new_in(42u32, &heap).unwrap();
+        assert_eq!(*value, 42);
+        assert_eq!(heap.stats().allocations, 1);
+        drop(value);
+        assert_eq!(heap.stats().used, 0);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+}
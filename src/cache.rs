@@ -0,0 +1,49 @@
+/// The cache line settings of a heap, see [`Heap::set_cache_line`][crate::Heap::set_cache_line].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CacheLine {
+    /// The size that allocations are rounded up to, or 1 if they aren't rounded.
+    size: usize,
+    /// The number of cache lines that successive allocations are staggered across.
+    colors: usize,
+    /// The color of the next allocation.
+    next: usize,
+}
+
+impl CacheLine {
+    pub const fn new() -> Self {
+        CacheLine {
+            size: 1,
+            colors: 1,
+            next: 0,
+        }
+    }
+
+    pub fn set(&mut self, size: usize, colors: usize) {
+        *self = CacheLine {
+            size,
+            colors,
+            next: 0,
+        };
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the alignment and the offset that place the next allocation with the given
+    /// alignment at the next color, or `None` if coloring is disabled or the alignment
+    /// already determines the color.
+    ///
+    /// The address of the allocation plus the offset must be aligned to the returned
+    /// alignment.
+    pub fn next_color(&mut self, align: usize) -> Option<(usize, usize)> {
+        let span = self.size * self.colors;
+        if align >= span {
+            return None;
+        }
+        // the span is a power of two, so wrapping doesn't change the result modulo the span
+        let color = self.next.wrapping_mul(align) % span;
+        self.next = (self.next + 1) % self.colors;
+        Some((span, (span - color) % span))
+    }
+}
@@ -0,0 +1,374 @@
+//! A wrapper that checks how a heap is used, see [`CheckedHeap`].
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr::NonNull;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::vec::Vec;
+
+#[cfg(feature = "backtrace")]
+use std::string::String;
+
+use crate::{AllocError, SharedHeap};
+
+/// A wrapper around a heap that panics when its allocations are freed wrongly, e.g. to
+/// catch such bugs in the unit tests of code that manages memory by hand.
+///
+/// Every live allocation is mirrored in a table on the `std` heap. A deallocation panics
+/// with the addresses and layouts involved if it frees a block twice, frees a pointer into
+/// the middle of an allocation, frees a pointer that was never allocated, or uses another
+/// layout than the allocation. The check happens before the block is passed to the heap, so
+/// the heap is not corrupted by the wrong deallocation. Zero-sized allocations are not
+/// checked.
+///
+/// Allocations that are still live at the end can be listed with
+/// [`report_leaks`][CheckedHeap::report_leaks]. With the `backtrace` feature,
+/// [`capture_backtraces`][CheckedHeap::capture_backtraces] adds a truncated backtrace of
+/// every allocation to the report.
+///
+/// The table allocates from the global allocator, so a `CheckedHeap` can't be the global
+/// allocator itself. It is only available with the `std` feature.
+///
+/// ```
+/// use std::cell::RefCell;
+/// use linked_list_allocator::{CheckedHeap, Heap, SharedHeap};
+/// # use std::alloc::Layout;
+/// # use std::mem::MaybeUninit;
+///
+/// # static mut MEM: [MaybeUninit<u8>; 1024] = [MaybeUninit::uninit(); 1024];
+/// # let mem = unsafe { &mut *std::ptr::addr_of_mut!(MEM) };
+/// let heap = CheckedHeap::new(RefCell::new(Heap::from_slice(mem)));
+/// let layout = Layout::new::<u64>();
+/// let ptr = heap.allocate(layout).unwrap();
+/// assert_eq!(heap.live(), 1);
+/// unsafe { heap.deallocate(ptr, layout) };
+/// ```
+pub struct CheckedHeap<H> {
+    heap: H,
+    table: Mutex<Table>,
+    #[cfg(feature = "backtrace")]
+    backtrace_depth: usize,
+}
+
+/// The allocations of a [`CheckedHeap`] by their address.
+#[derive(Default)]
+struct Table {
+    live: HashMap<usize, Layout>,
+    /// Freed allocations, until their address is allocated again.
+    freed: HashMap<usize, Layout>,
+    /// The instruction pointers of the backtraces of live allocations.
+    #[cfg(feature = "backtrace")]
+    backtraces: HashMap<usize, Vec<usize>>,
+}
+
+/// The number of frames of the `backtrace` crate and of the `CheckedHeap` itself that are
+/// captured in addition to the requested depth, since they are left out of the reports.
+#[cfg(feature = "backtrace")]
+const INTERNAL_FRAMES: usize = 6;
+
+impl<H> CheckedHeap<H> {
+    /// Wraps `heap`, which must not have live allocations yet.
+    pub fn new(heap: H) -> Self {
+        CheckedHeap {
+            heap,
+            table: Mutex::new(Table::default()),
+            #[cfg(feature = "backtrace")]
+            backtrace_depth: 0,
+        }
+    }
+
+    /// Captures a backtrace of at most `depth` frames for every following allocation, which
+    /// [`report_leaks`][CheckedHeap::report_leaks] prints for the allocations that are
+    /// still live. A depth of 0, the default, disables the backtraces.
+    ///
+    /// Only the instruction pointers are captured, which is fast enough to do for every
+    /// allocation. They are resolved to symbols and lines when the report is written.
+    #[cfg(feature = "backtrace")]
+    pub fn capture_backtraces(&mut self, depth: usize) {
+        self.backtrace_depth = depth;
+    }
+
+    /// Returns a reference to the wrapped heap.
+    pub fn heap(&self) -> &H {
+        &self.heap
+    }
+
+    /// Returns the number of live allocations, e.g. to check for leaks at the end of a
+    /// test.
+    pub fn live(&self) -> usize {
+        self.table().live.len()
+    }
+
+    /// Writes one line for every live allocation to `out`, in address order, followed by
+    /// its backtrace if one was [captured][CheckedHeap::capture_backtraces].
+    ///
+    /// A line looks like `leaked 48 bytes at 0x55d0c4a3e040`. This is meant to be called at
+    /// the end of a test, when all allocations should be freed.
+    pub fn report_leaks<W: fmt::Write + ?Sized>(&self, out: &mut W) -> fmt::Result {
+        let table = self.table();
+        let mut live: Vec<_> = table.live.iter().collect();
+        live.sort_unstable_by_key(|&(&addr, _)| addr);
+        for (&addr, layout) in live {
+            writeln!(out, "leaked {} bytes at {:#x}", layout.size(), addr)?;
+            #[cfg(feature = "backtrace")]
+            if let Some(ips) = table.backtraces.get(&addr) {
+                for frame in resolve(ips).into_iter().take(self.backtrace_depth) {
+                    writeln!(out, "    at {}", frame)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Unwraps the heap.
+    pub fn into_inner(self) -> H {
+        self.heap
+    }
+
+    fn table(&self) -> MutexGuard<'_, Table> {
+        // a failed check panics while the table is locked
+        self.table.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn record_allocation(&self, ptr: *mut u8, layout: Layout) {
+        if ptr.is_null() || layout.size() == 0 {
+            return;
+        }
+        #[cfg(feature = "backtrace")]
+        let ips = capture(self.backtrace_depth);
+        let mut table = self.table();
+        table.freed.remove(&(ptr as usize));
+        #[cfg(feature = "backtrace")]
+        if let Some(ips) = ips {
+            table.backtraces.insert(ptr as usize, ips);
+        }
+        if let Some(live) = table.live.insert(ptr as usize, layout) {
+            panic!(
+                "allocation at {:p} with {:?} overlaps the live allocation with {:?}",
+                ptr, layout, live
+            );
+        }
+    }
+
+    fn check_deallocation(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
+        let addr = ptr as usize;
+        let mut table = self.table();
+        match table.live.get(&addr) {
+            Some(&live) if live == layout => {
+                table.live.remove(&addr);
+                #[cfg(feature = "backtrace")]
+                table.backtraces.remove(&addr);
+                table.freed.insert(addr, layout);
+                return;
+            }
+            Some(live) => panic!(
+                "free of {:p} with {:?}, but it was allocated with {:?}",
+                ptr, layout, live
+            ),
+            None => {}
+        }
+        if let Some(freed) = table.freed.get(&addr) {
+            panic!(
+                "double free of {:p} with {:?}, it was already freed with {:?}",
+                ptr, layout, freed
+            );
+        }
+        let containing = table
+            .live
+            .iter()
+            .find(|&(&start, live)| addr.wrapping_sub(start) < live.size());
+        match containing {
+            Some((&start, live)) => panic!(
+                "free of {:p} with {:?}, which lies {} bytes into the allocation at {:#x} with {:?}",
+                ptr,
+                layout,
+                addr - start,
+                start,
+                live
+            ),
+            None => panic!(
+                "free of {:p} with {:?}, which was never allocated",
+                ptr, layout
+            ),
+        }
+    }
+}
+
+/// Returns the instruction pointers of the current backtrace, for at most `depth` frames
+/// after the internal ones, or `None` if `depth` is 0.
+#[cfg(feature = "backtrace")]
+fn capture(depth: usize) -> Option<Vec<usize>> {
+    if depth == 0 {
+        return None;
+    }
+    let mut ips = Vec::new();
+    backtrace::trace(|frame| {
+        ips.push(frame.ip() as usize);
+        ips.len() < depth + INTERNAL_FRAMES
+    });
+    Some(ips)
+}
+
+/// Resolves captured instruction pointers to `function (file:line)` strings, without the
+/// leading frames of the `backtrace` crate and of the `CheckedHeap`.
+#[cfg(feature = "backtrace")]
+fn resolve(ips: &[usize]) -> Vec<String> {
+    let mut frames = Vec::new();
+    for &ip in ips {
+        // except for the first frame, the pointers are return addresses, which may already
+        // belong to the next line
+        backtrace::resolve(ip.wrapping_sub(1) as *mut core::ffi::c_void, |symbol| {
+            let name = symbol.name().map_or_else(
+                || String::from("<unknown>"),
+                |name| std::format!("{:#}", name),
+            );
+            let frame = match (symbol.filename(), symbol.lineno()) {
+                (Some(file), Some(line)) => std::format!("{} ({}:{})", name, file.display(), line),
+                _ => name,
+            };
+            frames.push(frame);
+        });
+    }
+    let internal = frames
+        .iter()
+        .take_while(|frame| {
+            frame.starts_with("backtrace::")
+                || frame.contains("checked::capture")
+                || frame.contains("CheckedHeap")
+        })
+        .count();
+    frames.split_off(internal)
+}
+
+unsafe impl<H: SharedHeap> SharedHeap for CheckedHeap<H> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.heap.allocate(layout)?;
+        self.record_allocation(ptr.as_ptr(), layout);
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.check_deallocation(ptr.as_ptr(), layout);
+        self.heap.deallocate(ptr, layout)
+    }
+}
+
+unsafe impl<H: GlobalAlloc> GlobalAlloc for CheckedHeap<H> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.heap.alloc(layout);
+        self.record_allocation(ptr, layout);
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.heap.alloc_zeroed(layout);
+        self.record_allocation(ptr, layout);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.check_deallocation(ptr, layout);
+        self.heap.dealloc(ptr, layout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CheckedHeap;
+    use crate::test::Chonk;
+    use crate::{Heap, SharedHeap};
+    use core::alloc::Layout;
+    use core::cell::RefCell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::string::String;
+    #[cfg(all(feature = "backtrace", not(miri)))]
+    use std::vec::Vec;
+
+    /// Runs `f` and returns the message it panics with.
+    fn panic_message(f: impl FnOnce()) -> String {
+        let payload = catch_unwind(AssertUnwindSafe(f)).unwrap_err();
+        *payload.downcast::<String>().unwrap()
+    }
+
+    #[test]
+    fn catches_wrong_frees() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let heap = CheckedHeap::new(RefCell::new(unsafe { Heap::new(data, 1024) }));
+
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let a = heap.allocate(layout).unwrap();
+        let b = heap.allocate(layout).unwrap();
+        assert_eq!(heap.live(), 2);
+
+        let wider = Layout::from_size_align(64, 8).unwrap();
+        let message = panic_message(|| unsafe { heap.deallocate(a, wider) });
+        assert!(message.contains("allocated with"), "{}", message);
+
+        let inner = unsafe { a.as_ptr().add(8) };
+        let message = panic_message(|| unsafe {
+            heap.deallocate(core::ptr::NonNull::new_unchecked(inner), layout)
+        });
+        assert!(
+            message.contains("8 bytes into the allocation"),
+            "{}",
+            message
+        );
+
+        unsafe { heap.deallocate(a, layout) };
+        let message = panic_message(|| unsafe { heap.deallocate(a, layout) });
+        assert!(message.starts_with("double free"), "{}", message);
+        assert_eq!(heap.live(), 1);
+
+        // the address can be allocated and freed again
+        let c = heap.allocate(layout).unwrap();
+        assert_eq!(c, a);
+        unsafe {
+            heap.deallocate(c, layout);
+            heap.deallocate(b, layout);
+        }
+        assert_eq!(heap.live(), 0);
+        assert_eq!(heap.heap().borrow().used(), 0);
+        let mut report = String::new();
+        heap.report_leaks(&mut report).unwrap();
+        assert_eq!(report, "");
+
+        drop(heap);
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    // Miri can't resolve the symbols of a backtrace
+    #[test]
+    #[cfg(all(feature = "backtrace", not(miri)))]
+    fn reports_leaks_with_backtraces() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let mut heap = CheckedHeap::new(RefCell::new(unsafe { Heap::new(data, 1024) }));
+        heap.capture_backtraces(4);
+
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        let leaked = heap.allocate(layout).unwrap();
+        let freed = heap.allocate(layout).unwrap();
+        unsafe { heap.deallocate(freed, layout) };
+
+        let mut report = String::new();
+        heap.report_leaks(&mut report).unwrap();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(
+            lines[0],
+            std::format!("leaked 48 bytes at {:p}", leaked.as_ptr())
+        );
+        assert!(lines.len() > 1 && lines.len() <= 5, "{}", report);
+        assert!(
+            lines[1].contains("reports_leaks_with_backtraces"),
+            "{}",
+            report
+        );
+
+        unsafe { heap.deallocate(leaked, layout) };
+        drop(heap);
+        unsafe { Chonk::unleak(chonk) };
+    }
+}
@@ -0,0 +1,166 @@
+//! Deallocations that are queued without taking the lock of a [`LockedHeap`], e.g. from
+//! interrupt handlers.
+
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::{Heap, LockedHeap, RawMutex};
+
+/// The minimum size of the allocations that can be freed with
+/// [`LockedHeap::deallocate_deferred`].
+pub const MIN_DEFERRED_SIZE: usize = size_of::<Deferred>();
+
+/// A queued deallocation, which is written into the memory of the allocation itself.
+#[repr(C)]
+struct Deferred {
+    next: *mut Deferred,
+    layout: Layout,
+}
+
+/// A lock-free stack of queued deallocations, which any number of contexts can push to and
+/// the holder of the heap lock takes as a whole.
+///
+/// Since the stack is only ever emptied at once, a node can't be popped and pushed again
+/// while another context pushes, so the stack doesn't suffer from the ABA problem.
+pub(crate) struct DeferredFrees {
+    head: AtomicPtr<Deferred>,
+}
+
+impl DeferredFrees {
+    pub const fn new() -> Self {
+        DeferredFrees {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Queues the deallocation of `ptr` with the given layout.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for writes of [`MIN_DEFERRED_SIZE`] bytes, which are overwritten.
+    unsafe fn push(&self, ptr: NonNull<u8>, layout: Layout) {
+        // the node is written unaligned, since the allocation might have a smaller alignment
+        let node = ptr.as_ptr().cast::<Deferred>();
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            node.write_unaligned(Deferred { next: head, layout });
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Frees all queued deallocations in `heap` and returns their number.
+    pub fn drain(&self, heap: &mut Heap) -> usize {
+        if self.head.load(Ordering::Relaxed).is_null() {
+            return 0;
+        }
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut count = 0;
+        while let Some(ptr) = NonNull::new(node) {
+            // SAFETY: The node was written by `push` before it was published.
+            let Deferred { next, layout } = unsafe { node.read_unaligned() };
+            // SAFETY: The caller of `deallocate_deferred` guarantees that the allocation
+            // belongs to the heap.
+            unsafe { heap.deallocate(ptr.cast(), layout) };
+            node = next;
+            count += 1;
+        }
+        count
+    }
+}
+
+impl<R: RawMutex> LockedHeap<R> {
+    /// Queues the deallocation of `ptr` without taking the lock of the heap, e.g. in an
+    /// interrupt handler that frees the buffer of a completed DMA transfer.
+    ///
+    /// The allocation is freed by the next allocation through this `LockedHeap` or by
+    /// [`drain_deferred`][Self::drain_deferred]. Until then, its memory holds the entry
+    /// in the queue, so the allocation must be at least [`MIN_DEFERRED_SIZE`] bytes large.
+    /// Queuing takes a bounded number of atomic operations unless other contexts keep
+    /// queuing at the same time, and never waits for the lock.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the size of `layout` is smaller than [`MIN_DEFERRED_SIZE`].
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`Heap::deallocate`] apply.
+    pub unsafe fn deallocate_deferred(&self, ptr: NonNull<u8>, layout: Layout) {
+        assert!(
+            layout.size() >= MIN_DEFERRED_SIZE,
+            "deferred deallocations need at least {} bytes",
+            MIN_DEFERRED_SIZE
+        );
+        self.2.push(ptr, layout);
+    }
+
+    /// Frees the deallocations that were queued with
+    /// [`deallocate_deferred`][Self::deallocate_deferred] and returns their number.
+    ///
+    /// This takes the lock, so it must not be called in a context that might interrupt
+    /// the holder of the lock.
+    pub fn drain_deferred(&self) -> usize {
+        let mut heap = self.lock();
+        let used = heap.used();
+        let count = self.2.drain(&mut heap);
+        let freed = used - heap.used();
+        drop(heap);
+        self.1.record_deallocations(count, freed);
+        count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MIN_DEFERRED_SIZE;
+    use crate::test::Chonk;
+    use crate::{Heap, LockedHeap};
+    use core::alloc::Layout;
+
+    #[test]
+    fn drains_deferred_deallocations() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let heap: LockedHeap = LockedHeap::from_heap(unsafe { Heap::new(data, 1024) });
+        let layout = Layout::from_size_align(MIN_DEFERRED_SIZE, 1).unwrap();
+        let a = heap.allocate_first_fit(layout).unwrap();
+        let b = heap.allocate_first_fit(layout).unwrap();
+        let used = heap.counters().used;
+
+        // like an interrupt handler that interrupted the holder of the lock
+        heap.with_heap(|_| unsafe {
+            heap.deallocate_deferred(a, layout);
+            heap.deallocate_deferred(b, layout);
+        });
+        assert_eq!(heap.counters().used, used);
+        assert_eq!(heap.drain_deferred(), 2);
+        assert_eq!(heap.drain_deferred(), 0);
+        let counters = heap.counters();
+        assert_eq!((counters.used, counters.deallocations), (0, 2));
+
+        // the next allocation frees the queued deallocations first
+        let a = heap.allocate_first_fit(layout).unwrap();
+        unsafe { heap.deallocate_deferred(a, layout) };
+        let b = heap.allocate_first_fit(layout).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(heap.stats().used, heap.counters().used);
+        unsafe { heap.deallocate(b, layout) };
+        assert_eq!(heap.stats().used, 0);
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    #[should_panic(expected = "deferred deallocations need at least")]
+    fn rejects_small_deferred_deallocations() {
+        let heap: LockedHeap = LockedHeap::empty();
+        let layout = Layout::new::<u8>();
+        unsafe { heap.deallocate_deferred(core::ptr::NonNull::dangling(), layout) };
+    }
+}
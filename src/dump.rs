@@ -0,0 +1,184 @@
+//! A text dump of the heap state for panic handlers and crash reports.
+
+use core::fmt;
+
+use crate::Heap;
+#[cfg(feature = "use_spin")]
+use crate::{LockedHeap, RawMutex};
+
+/// The maximum number of free blocks that [`Heap::emergency_dump`] lists.
+const DUMP_HOLES: usize = 16;
+
+impl Heap {
+    /// Writes the bounds, the statistics and the first free blocks of the heap to `out`,
+    /// e.g. from a panic handler into a crash report.
+    ///
+    /// Unlike [`stats`][Self::stats], this is safe to call when the heap might be in a bad
+    /// state: it doesn't allocate, lists at most 16 free blocks, and stops at a corrupted
+    /// free block instead of panicking. A corrupted block is reported with its address.
+    pub fn emergency_dump<W: fmt::Write + ?Sized>(&self, out: &mut W) -> fmt::Result {
+        writeln!(
+            out,
+            "heap {:p}..{:p}: size {}, used {}, free {}, peak {}",
+            self.bottom(),
+            self.top(),
+            self.size(),
+            self.used,
+            self.free(),
+            self.counters.peak_used
+        )?;
+        writeln!(
+            out,
+            "{} allocations, {} deallocations, {} failed",
+            self.counters.allocations,
+            self.counters.deallocations,
+            self.counters.failed_allocations
+        )?;
+        let mut holes = self.holes.checked_holes();
+        for hole in holes.by_ref().take(DUMP_HOLES) {
+            match hole {
+                Ok((addr, size)) => writeln!(out, "  free block at {:#x}: {} bytes", addr, size)?,
+                Err(addr) => return writeln!(out, "  corrupted free block at {:#x}", addr),
+            }
+        }
+        match holes.next() {
+            Some(_) => writeln!(out, "  further free blocks left out"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "use_spin")]
+impl<R: RawMutex> LockedHeap<R> {
+    /// Writes the state of the heap to `out` if its lock is free, see
+    /// [`Heap::emergency_dump`].
+    ///
+    /// If the lock is held, e.g. because the panic happened within the allocator, only the
+    /// [counters][Self::counters] are written, which don't need the lock.
+    /// [`emergency_dump_unlocked`][Self::emergency_dump_unlocked] reads the heap anyway.
+    pub fn emergency_dump<W: fmt::Write + ?Sized>(&self, out: &mut W) -> fmt::Result {
+        match self.try_lock() {
+            Some(heap) => heap.emergency_dump(out),
+            None => {
+                let counters = self.counters();
+                writeln!(
+                    out,
+                    "heap is locked: used {}, peak {}, {} allocations, {} deallocations, {} failed",
+                    counters.used,
+                    counters.peak_used,
+                    counters.allocations,
+                    counters.deallocations,
+                    counters.failed_allocations
+                )
+            }
+        }
+    }
+
+    /// Writes the state of the heap to `out` without taking its lock, see
+    /// [`Heap::emergency_dump`].
+    ///
+    /// This is the escape hatch for a panic handler that runs while the lock is held. The
+    /// heap might be in the middle of an update, but the dump stops at free blocks that
+    /// are inconsistent, so it never reads outside of the heap.
+    ///
+    /// # Safety
+    ///
+    /// The heap must not be changed while the dump is written, i.e. the holder of the lock
+    /// must not run concurrently, e.g. because it was interrupted by the panic on the same
+    /// core or because the other cores were halted.
+    #[cfg(not(loom))]
+    pub unsafe fn emergency_dump_unlocked<W: fmt::Write + ?Sized>(
+        &self,
+        out: &mut W,
+    ) -> fmt::Result {
+        (*self.0.data_ptr()).emergency_dump(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::{new_heap, Chonk};
+    use crate::Heap;
+    use core::alloc::Layout;
+    use std::string::String;
+    use std::vec::Vec;
+
+    #[test]
+    fn dumps_heap_state() {
+        let (chonk, data) = Chonk::<4096>::new();
+        let mut heap = unsafe { Heap::new(data, 4096) };
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        let mut ptrs = Vec::new();
+        for _ in 0..40 {
+            ptrs.push(heap.allocate_first_fit(layout).unwrap());
+        }
+        // free every other allocation, which leaves more free blocks than are listed
+        for &ptr in ptrs.iter().step_by(2) {
+            unsafe { heap.deallocate(ptr, layout) };
+        }
+
+        let mut dump = String::new();
+        heap.emergency_dump(&mut dump).unwrap();
+        let mut lines = dump.lines();
+        let expected = std::format!(
+            "heap {:p}..{:p}: size {}, used {}, free {}, peak {}",
+            heap.bottom(),
+            heap.top(),
+            heap.size(),
+            heap.used(),
+            heap.free(),
+            heap.stats().peak_used
+        );
+        assert_eq!(lines.next(), Some(&*expected));
+        assert_eq!(
+            lines.next(),
+            Some("40 allocations, 20 deallocations, 0 failed")
+        );
+        let (hole, size) = heap.holes.holes().next().unwrap();
+        let first = std::format!("  free block at {:p}: {} bytes", hole, size);
+        assert_eq!(lines.next(), Some(&*first));
+        assert_eq!(lines.clone().count(), 16);
+        assert_eq!(lines.last(), Some("  further free blocks left out"));
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    fn stops_at_corrupted_block() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = heap.allocate_first_fit(layout).unwrap();
+        let _b = heap.allocate_first_fit(layout).unwrap();
+        unsafe { heap.deallocate(a, layout) };
+        let (hole, _) = heap.holes.holes().next().unwrap();
+        // link the first free block to an address below it
+        unsafe {
+            hole.cast::<usize>()
+                .add(1)
+                .write(heap.bottom() as usize - 64)
+        };
+
+        let mut dump = String::new();
+        heap.emergency_dump(&mut dump).unwrap();
+        let expected = std::format!("  corrupted free block at {:p}\n", hole);
+        assert!(dump.ends_with(&expected), "{}", dump);
+    }
+
+    #[test]
+    #[cfg(all(feature = "use_spin", not(loom)))]
+    fn dumps_locked_heap() {
+        let heap: crate::LockedHeap = crate::LockedHeap::empty();
+        let guard = heap.lock();
+        let mut dump = String::new();
+        heap.emergency_dump(&mut dump).unwrap();
+        assert!(dump.starts_with("heap is locked: used 0"), "{}", dump);
+
+        let mut unlocked = String::new();
+        unsafe { heap.emergency_dump_unlocked(&mut unlocked) }.unwrap();
+        assert!(
+            unlocked.starts_with("heap 0x0..0x0: size 0"),
+            "{}",
+            unlocked
+        );
+        drop(guard);
+    }
+}
@@ -0,0 +1,185 @@
+//! A heap behind a blocking mutex of `embassy-sync`, enabled by the `embassy_sync` feature.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::RefCell;
+use core::mem::MaybeUninit;
+#[cfg(feature = "call_sites")]
+use core::panic::Location;
+use core::ptr::NonNull;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::{AllocError, Heap, HeapStats, Owns, SharedHeap};
+
+/// A [`Heap`] behind a blocking mutex of [`embassy_sync`], which can be used as a global
+/// allocator.
+///
+/// The raw mutex `M` decides how the heap is protected. With
+/// [`CriticalSectionRawMutex`][embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex],
+/// the heap is used within a critical section, so interrupt handlers can allocate without
+/// deadlocking against the code they interrupted. Unlike the spinlock of a
+/// [`LockedHeap`][crate::LockedHeap], this also works on targets without atomic
+/// compare-and-swap, such as `thumbv6m`:
+///
+/// ```ignore
+/// use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+/// use linked_list_allocator::EmbassyHeap;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: EmbassyHeap<CriticalSectionRawMutex> = EmbassyHeap::empty();
+///
+/// #[embassy_executor::main]
+/// async fn main(spawner: Spawner) {
+///     static mut HEAP: [MaybeUninit<u8>; 4096] = [MaybeUninit::uninit(); 4096];
+///     ALLOCATOR.init_from_slice(unsafe { &mut *core::ptr::addr_of_mut!(HEAP) });
+///     // ...
+/// }
+/// ```
+///
+/// Using the heap again while it is in use, e.g. from a [`with_heap`][Self::with_heap]
+/// closure or with a mutex that doesn't mask interrupts, panics instead of deadlocking.
+pub struct EmbassyHeap<M: RawMutex>(Mutex<M, RefCell<Heap>>);
+
+impl<M: RawMutex> EmbassyHeap<M> {
+    /// Creates an empty heap. All allocations fail until it is initialized.
+    pub const fn empty() -> Self {
+        EmbassyHeap::from_heap(Heap::empty())
+    }
+
+    /// Puts the given heap behind a mutex of type `M`.
+    pub const fn from_heap(heap: Heap) -> Self {
+        EmbassyHeap(Mutex::new(RefCell::new(heap)))
+    }
+
+    /// Initializes an empty heap, see [`Heap::init`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the heap is already initialized.
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`Heap::init`] apply.
+    pub unsafe fn init(&self, heap_bottom: *mut u8, heap_size: usize) {
+        self.with_heap(|heap| {
+            assert!(
+                heap.bottom().is_null(),
+                "The heap has already been initialized."
+            );
+            heap.init(heap_bottom, heap_size);
+        })
+    }
+
+    /// Initializes an empty heap with a slice of raw memory, see
+    /// [`Heap::init_from_slice`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the heap is already initialized.
+    pub fn init_from_slice(&self, mem: &'static mut [MaybeUninit<u8>]) {
+        self.with_heap(move |heap| heap.init_from_slice(mem))
+    }
+
+    /// Allocates a block for `layout`, see [`Heap::allocate_first_fit`].
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_first_fit(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        #[cfg(feature = "call_sites")]
+        let site = Location::caller();
+        self.with_heap(|heap| {
+            // the closure would be recorded as the call site otherwise
+            #[cfg(feature = "call_sites")]
+            {
+                heap.caller = Some(site);
+            }
+            heap.allocate_first_fit(layout)
+        })
+    }
+
+    /// Frees the given allocation, see [`Heap::deallocate`].
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`Heap::deallocate`] apply.
+    pub unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.with_heap(|heap| heap.deallocate(ptr, layout))
+    }
+
+    /// Returns a snapshot of the statistics of the heap, see [`Heap::stats`].
+    pub fn stats(&self) -> HeapStats {
+        self.with_heap(|heap| heap.stats())
+    }
+
+    /// Runs `f` with the locked heap, e.g. to install hooks or to extend the heap.
+    ///
+    /// The mutex is held until `f` returns, so `f` should be short and must not allocate
+    /// from this heap.
+    pub fn with_heap<T>(&self, f: impl FnOnce(&mut Heap) -> T) -> T {
+        self.0.lock(|heap| f(&mut heap.borrow_mut()))
+    }
+}
+
+unsafe impl<M: RawMutex> GlobalAlloc for EmbassyHeap<M> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with_heap(|heap| heap.allocate_first_fit(layout))
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new_unchecked(ptr), layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.with_heap(|heap| heap.allocate_zeroed(layout))
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+}
+
+unsafe impl<M: RawMutex> SharedHeap for EmbassyHeap<M> {
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.allocate_first_fit(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        EmbassyHeap::deallocate(self, ptr, layout)
+    }
+}
+
+unsafe impl<M: RawMutex> Owns for EmbassyHeap<M> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.with_heap(|heap| heap.owns(ptr))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EmbassyHeap;
+    use crate::test::Chonk;
+    use crate::{Heap, HeapBox};
+    use core::alloc::{GlobalAlloc, Layout};
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+
+    #[test]
+    fn embassy_heap() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let heap =
+            EmbassyHeap::<CriticalSectionRawMutex>::from_heap(unsafe { Heap::new(data, 1024) });
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = unsafe { heap.alloc(layout) };
+        assert!(!a.is_null());
+        let b = HeapBox::new_in([1u8; 32], &heap).unwrap();
+        assert_eq!(heap.stats().allocations, 2);
+        unsafe { heap.dealloc(a, layout) };
+        drop(b);
+        assert_eq!(heap.stats().used, 0);
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn embassy_heap_reentrancy() {
+        let heap = EmbassyHeap::<CriticalSectionRawMutex>::empty();
+        heap.with_heap(|_| heap.stats());
+    }
+}
@@ -0,0 +1,91 @@
+use core::fmt;
+
+/// The reason why an allocation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum AllocError {
+    /// The heap has less free memory in total than the allocation requires.
+    ///
+    /// The allocation can only succeed after memory was freed or the heap was
+    /// [extended][crate::Heap::extend].
+    OutOfMemory,
+    /// The heap has enough free memory in total, but no free block is large enough to hold
+    /// the allocation with the required alignment.
+    Fragmented {
+        /// The size of the largest free block in bytes.
+        largest_hole: usize,
+    },
+    /// The layout can't be served by the heap, e.g. because its size overflows after
+    /// adding the required padding and metadata.
+    InvalidLayout,
+    /// A bookkeeping resource other than memory is exhausted, e.g. all slots of a
+    /// [`HandleHeap`][crate::handle::HandleHeap] are in use.
+    Exhausted,
+    /// The heap has enough free memory, but the allocation would use memory that is
+    /// [reserved][crate::Heap::set_reserve] for allocations with a higher priority.
+    Reserved,
+    /// The allocation was given up after examining the maximum number of free blocks, see
+    /// [`Heap::allocate_first_fit_bounded`][crate::Heap::allocate_first_fit_bounded].
+    ///
+    /// A free block that is large enough might still exist further up in the heap.
+    SearchLimit,
+    /// The heap was [frozen][crate::Heap::freeze], so no further allocations are allowed.
+    Frozen,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocError::OutOfMemory => f.write_str("out of memory"),
+            AllocError::Fragmented { largest_hole } => write!(
+                f,
+                "no free block is large enough, the largest has {} bytes",
+                largest_hole
+            ),
+            AllocError::InvalidLayout => f.write_str("invalid layout"),
+            AllocError::Exhausted => f.write_str("allocator resources exhausted"),
+            AllocError::Reserved => f.write_str("memory is reserved for higher priorities"),
+            AllocError::SearchLimit => f.write_str("search limit for free blocks reached"),
+            AllocError::Frozen => f.write_str("heap is frozen"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AllocError {}
+
+/// The reason why [`Heap::adopt`][crate::Heap::adopt] rejected a memory region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum AdoptError {
+    /// The region doesn't start with the record of a persistent heap, e.g. because it was
+    /// never initialized with [`Heap::init_persistent`][crate::Heap::init_persistent].
+    NotFound,
+    /// The record at the start of the region belongs to a heap at another address, or to
+    /// a heap that is larger than the region.
+    Mismatch,
+    /// A free block is misaligned, out of bounds or out of order, or its header doesn't
+    /// match its checksum.
+    Corrupted {
+        /// The address of the free block whose header is invalid or links to an invalid
+        /// block.
+        addr: usize,
+    },
+}
+
+impl fmt::Display for AdoptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdoptError::NotFound => f.write_str("no persistent heap found"),
+            AdoptError::Mismatch => f.write_str("persistent heap doesn't match the region"),
+            AdoptError::Corrupted { addr } => {
+                write!(f, "free block at {:#x} is corrupted", addr)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AdoptError {}
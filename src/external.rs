@@ -0,0 +1,286 @@
+//! A heap that keeps its bookkeeping outside of the managed memory, see [`ExternalHeap`].
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::AllocError;
+
+/// A free range of the memory managed by an [`ExternalHeap`], as stored in its side table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeRange {
+    start: *mut u8,
+    size: usize,
+}
+
+impl FreeRange {
+    /// An unused table entry, e.g. to initialize a static table.
+    pub const EMPTY: FreeRange = FreeRange {
+        start: core::ptr::null_mut(),
+        size: 0,
+    };
+
+    /// Returns the start address of the range.
+    pub fn start(&self) -> *mut u8 {
+        self.start
+    }
+
+    /// Returns the size of the range in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn end(&self) -> *mut u8 {
+        self.start.wrapping_add(self.size)
+    }
+}
+
+/// A heap that records its free ranges in a table that is provided by the user, instead of
+/// in the managed memory itself.
+///
+/// The managed memory is never read or written by the heap, so it can be memory that is slow
+/// or forbidden to access from the CPU in small pieces, e.g. a write-combining framebuffer
+/// or a memory area that is scrubbed for ECC errors. It doesn't even need to be mapped.
+///
+/// The free ranges are kept sorted by address in the table, and adjacent ranges are merged
+/// when an allocation is freed. Since every free range is followed by an allocation or the
+/// end of the heap, a table of `n` entries can describe the free memory next to `n - 1`
+/// allocations, so allocations beyond that fail with [`AllocError::Exhausted`]. Allocations
+/// aren't padded, except that zero-sized allocations take one byte to get a unique address.
+///
+/// ```ignore
+/// use linked_list_allocator::{ExternalHeap, FreeRange};
+///
+/// static mut TABLE: [FreeRange; 64] = [FreeRange::EMPTY; 64];
+///
+/// let mut heap = unsafe { ExternalHeap::new(framebuffer, framebuffer_size, &mut TABLE) };
+/// let layout = Layout::from_size_align(1920 * 4, 64).unwrap();
+/// let line = heap.allocate_first_fit(layout).unwrap();
+/// ```
+pub struct ExternalHeap<'a> {
+    bottom: *mut u8,
+    size: usize,
+    table: &'a mut [FreeRange],
+    /// The number of entries at the start of the table that are in use.
+    len: usize,
+    used: usize,
+    allocations: usize,
+}
+
+unsafe impl<'a> Send for ExternalHeap<'a> {}
+
+impl<'a> ExternalHeap<'a> {
+    /// Creates a heap that manages the `[heap_bottom, heap_bottom + heap_size)` range and
+    /// records its free ranges in `table`. The previous contents of the table are ignored.
+    ///
+    /// Panics if the table is empty.
+    ///
+    /// # Safety
+    ///
+    /// The memory range must not be used for anything else while the heap or any of its
+    /// allocations are live, and `heap_bottom + heap_size` must not overflow.
+    pub unsafe fn new(heap_bottom: *mut u8, heap_size: usize, table: &'a mut [FreeRange]) -> Self {
+        assert!(!table.is_empty(), "the table needs at least one entry");
+        let len = if heap_size > 0 {
+            table[0] = FreeRange {
+                start: heap_bottom,
+                size: heap_size,
+            };
+            1
+        } else {
+            0
+        };
+        ExternalHeap {
+            bottom: heap_bottom,
+            size: heap_size,
+            table,
+            len,
+            used: 0,
+            allocations: 0,
+        }
+    }
+
+    /// Returns the bottom address of the heap.
+    pub fn bottom(&self) -> *mut u8 {
+        self.bottom
+    }
+
+    /// Returns the size of the heap.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the top address of the heap.
+    pub fn top(&self) -> *mut u8 {
+        self.bottom.wrapping_add(self.size)
+    }
+
+    /// Returns the size of the used part of the heap.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Returns the size of the free part of the heap.
+    pub fn free(&self) -> usize {
+        self.size - self.used
+    }
+
+    /// Returns the free ranges of the heap, sorted by address.
+    pub fn free_ranges(&self) -> &[FreeRange] {
+        &self.table[..self.len]
+    }
+
+    /// Returns whether `ptr` lies within the heap memory.
+    pub fn contains(&self, ptr: NonNull<u8>) -> bool {
+        let ptr = ptr.as_ptr();
+        ptr >= self.bottom() && ptr < self.top()
+    }
+
+    /// Allocates a block with the given layout from the first free range that can hold it.
+    ///
+    /// The runtime is in `O(n)` where n is the number of entries in the table.
+    pub fn allocate_first_fit(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if self.allocations + 1 >= self.table.len() {
+            return Err(AllocError::Exhausted);
+        }
+        let size = layout.size().max(1);
+        let mut largest = 0;
+        for i in 0..self.len {
+            let range = self.table[i];
+            let padding = range.start.align_offset(layout.align());
+            let end = match padding.checked_add(size) {
+                Some(end) if end <= range.size => end,
+                _ => {
+                    largest = largest.max(range.size);
+                    continue;
+                }
+            };
+            let ptr = range.start.wrapping_add(padding);
+            let rest = FreeRange {
+                start: ptr.wrapping_add(size),
+                size: range.size - end,
+            };
+            match (padding, rest.size) {
+                (0, 0) => self.remove(i),
+                (0, _) => self.table[i] = rest,
+                (_, 0) => self.table[i].size = padding,
+                _ => {
+                    self.table[i].size = padding;
+                    self.insert(i + 1, rest);
+                }
+            }
+            self.used += size;
+            self.allocations += 1;
+            // SAFETY: The block lies within a free range, which doesn't contain null.
+            return Ok(unsafe { NonNull::new_unchecked(ptr) });
+        }
+        if self.free() < size {
+            Err(AllocError::OutOfMemory)
+        } else {
+            Err(AllocError::Fragmented {
+                largest_hole: largest,
+            })
+        }
+    }
+
+    /// Frees the given allocation and merges it with the adjacent free ranges.
+    ///
+    /// The runtime is in `O(n)` where n is the number of entries in the table.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a pointer returned by a call to
+    /// [`allocate_first_fit`][ExternalHeap::allocate_first_fit] of this heap with identical
+    /// layout that wasn't freed yet.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let freed = FreeRange {
+            start: ptr.as_ptr(),
+            size: layout.size().max(1),
+        };
+        let i = self
+            .free_ranges()
+            .partition_point(|range| range.start < freed.start);
+        let merge_prev = i > 0 && self.table[i - 1].end() == freed.start;
+        let merge_next = i < self.len && self.table[i].start == freed.end();
+        match (merge_prev, merge_next) {
+            (true, true) => {
+                self.table[i - 1].size += freed.size + self.table[i].size;
+                self.remove(i);
+            }
+            (true, false) => self.table[i - 1].size += freed.size,
+            (false, true) => {
+                self.table[i] = FreeRange {
+                    start: freed.start,
+                    size: freed.size + self.table[i].size,
+                }
+            }
+            (false, false) => self.insert(i, freed),
+        }
+        self.used -= freed.size;
+        self.allocations -= 1;
+    }
+
+    fn insert(&mut self, index: usize, range: FreeRange) {
+        // there are at most as many free ranges as allocations plus one, which the number of
+        // allocations is limited to
+        debug_assert!(self.len < self.table.len());
+        self.table.copy_within(index..self.len, index + 1);
+        self.table[index] = range;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.table.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExternalHeap, FreeRange};
+    use crate::AllocError;
+    use core::alloc::Layout;
+    use std::vec::Vec;
+
+    #[test]
+    fn keeps_bookkeeping_in_table() {
+        // the heap never touches the managed memory, so it doesn't need to exist
+        let bottom = core::ptr::null_mut::<u8>().wrapping_add(0x1000);
+        let mut table = [FreeRange::EMPTY; 4];
+        let mut heap = unsafe { ExternalHeap::new(bottom, 0x1000, &mut table) };
+
+        let small = Layout::from_size_align(0x10, 0x10).unwrap();
+        let aligned = Layout::from_size_align(0x100, 0x100).unwrap();
+        let a = heap.allocate_first_fit(small).unwrap();
+        let b = heap.allocate_first_fit(aligned).unwrap();
+        let c = heap.allocate_first_fit(small).unwrap();
+        assert_eq!(a.as_ptr() as usize, 0x1000);
+        assert_eq!(b.as_ptr() as usize, 0x1100);
+        assert_eq!(c.as_ptr() as usize, 0x1010);
+        assert_eq!(heap.used(), 0x120);
+        let ranges: Vec<_> = heap
+            .free_ranges()
+            .iter()
+            .map(|range| (range.start() as usize, range.size()))
+            .collect();
+        assert_eq!(ranges, [(0x1020, 0xe0), (0x1200, 0xe00)]);
+
+        // three allocations use up the table of four entries
+        assert_eq!(heap.allocate_first_fit(small), Err(AllocError::Exhausted));
+
+        unsafe {
+            heap.deallocate(a, small);
+            heap.deallocate(b, aligned);
+        }
+        assert_eq!(heap.free_ranges().len(), 2);
+        unsafe { heap.deallocate(c, small) };
+        assert_eq!(heap.used(), 0);
+        assert_eq!(heap.free_ranges().len(), 1);
+        assert_eq!(heap.free_ranges()[0].size(), 0x1000);
+
+        let too_large = Layout::from_size_align(0x1001, 1).unwrap();
+        assert_eq!(
+            heap.allocate_first_fit(too_large),
+            Err(AllocError::OutOfMemory)
+        );
+    }
+}
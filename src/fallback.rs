@@ -0,0 +1,139 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+#[cfg(feature = "use_spin")]
+use crate::{LockedHeap, RawMutex};
+
+/// An allocator that can tell whether an allocation belongs to it.
+///
+/// This is used by [`FallbackHeap`] to route deallocations to the allocator that made the
+/// allocation.
+///
+/// # Safety
+///
+/// `owns` must return `true` for all pointers returned by allocations of `self` that were
+/// not freed yet, and `false` for all pointers that were allocated by other allocators.
+pub unsafe trait Owns {
+    /// Returns whether `ptr` points into memory that is managed by this allocator.
+    fn owns(&self, ptr: NonNull<u8>) -> bool;
+}
+
+#[cfg(feature = "use_spin")]
+unsafe impl<R: RawMutex> Owns for LockedHeap<R> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.lock().owns(ptr)
+    }
+}
+
+/// An allocator that tries to allocate from a `primary` allocator first and falls back to a
+/// `secondary` allocator if that fails.
+///
+/// Deallocations are routed to the primary allocator if it [owns][Owns] the freed pointer,
+/// and to the secondary allocator otherwise. A typical use is to prefer a small, fast memory
+/// (e.g. TCM) and fall back to a large, slow memory (e.g. SDRAM):
+///
+/// ```ignore
+/// use linked_list_allocator::{FallbackHeap, LockedHeap};
+///
+/// #[global_allocator]
+/// static ALLOCATOR: FallbackHeap<LockedHeap, LockedHeap> =
+///     FallbackHeap::new(LockedHeap::empty(), LockedHeap::empty());
+/// ```
+///
+/// Fallback heaps can be nested to chain more than two allocators.
+pub struct FallbackHeap<A, B> {
+    primary: A,
+    secondary: B,
+}
+
+impl<A, B> FallbackHeap<A, B> {
+    /// Creates a new allocator that prefers `primary` over `secondary`.
+    pub const fn new(primary: A, secondary: B) -> Self {
+        FallbackHeap { primary, secondary }
+    }
+
+    /// Returns a reference to the primary allocator.
+    pub fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    /// Returns a reference to the secondary allocator.
+    pub fn secondary(&self) -> &B {
+        &self.secondary
+    }
+}
+
+unsafe impl<A: Owns, B: Owns> Owns for FallbackHeap<A, B> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.primary.owns(ptr) || self.secondary.owns(ptr)
+    }
+}
+
+unsafe impl<A: GlobalAlloc + Owns, B: GlobalAlloc> GlobalAlloc for FallbackHeap<A, B> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.primary.alloc(layout);
+        if ptr.is_null() {
+            self.secondary.alloc(layout)
+        } else {
+            ptr
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.primary.alloc_zeroed(layout);
+        if ptr.is_null() {
+            self.secondary.alloc_zeroed(layout)
+        } else {
+            ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.primary.owns(NonNull::new_unchecked(ptr)) {
+            self.primary.dealloc(ptr, layout)
+        } else {
+            self.secondary.dealloc(ptr, layout)
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "use_spin",
+    not(all(
+        any(feature = "call_sites", feature = "timestamps"),
+        feature = "redundant"
+    ))
+))]
+mod test {
+    use super::FallbackHeap;
+    use crate::test::Chonk;
+    use crate::LockedHeap;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn falls_back_and_routes_deallocations() {
+        let (primary_chonk, primary_data) = Chonk::<256>::new();
+        let (secondary_chonk, secondary_data) = Chonk::<1024>::new();
+        let primary = unsafe { LockedHeap::new(primary_data, 256) };
+        let secondary = unsafe { LockedHeap::new(secondary_data, 1024) };
+        let heap = FallbackHeap::new(primary, secondary);
+        let layout = Layout::from_size_align(150, 8).unwrap();
+
+        let a = unsafe { heap.alloc(layout) };
+        let b = unsafe { heap.alloc(layout) };
+        assert!(!a.is_null() && !b.is_null());
+        assert_ne!(heap.primary().lock().used(), 0);
+        assert_ne!(heap.secondary().lock().used(), 0);
+
+        unsafe { heap.dealloc(b, layout) };
+        assert_eq!(heap.secondary().lock().used(), 0);
+        unsafe { heap.dealloc(a, layout) };
+        assert_eq!(heap.primary().lock().used(), 0);
+
+        unsafe {
+            Chonk::unleak(primary_chonk);
+            Chonk::unleak(secondary_chonk);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! The heap functions of FreeRTOS on top of a [`LockedHeap`], enabled by the `freertos`
+//! feature.
+
+use core::alloc::Layout;
+use core::ptr::{self, NonNull};
+
+use crate::{LockedHeap, RawMutex};
+
+/// The alignment of the allocations of [`pv_port_malloc`], which matches the default
+/// `portBYTE_ALIGNMENT` of FreeRTOS.
+pub const FREERTOS_ALIGNMENT: usize = 8;
+
+/// Defines the heap functions of FreeRTOS, i.e. `pvPortMalloc`, `vPortFree`,
+/// `xPortGetFreeHeapSize`, `xPortGetMinimumEverFreeHeapSize` and `vPortGetHeapStats`,
+/// on top of a [`LockedHeap`], so that the C code of a firmware allocates from the same
+/// heap as the Rust code.
+///
+/// The first argument is the `LockedHeap`, usually the global allocator. FreeRTOS must be
+/// built without one of its `heap_n.c` files. Like those, the functions suspend the
+/// scheduler while they use the heap, so that a task that holds the lock is never
+/// preempted by another one that waits for it. `pvPortMalloc` calls
+/// `vApplicationMallocFailedHook` when an allocation fails if the hook is passed as the
+/// second argument, which can also be a closure that takes the requested size:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: LockedHeap = LockedHeap::empty();
+///
+/// linked_list_allocator::freertos_heap!(ALLOCATOR, vApplicationMallocFailedHook);
+/// ```
+#[macro_export]
+macro_rules! freertos_heap {
+    ($heap:expr) => {
+        $crate::freertos_heap!($heap, |_: usize| {});
+    };
+    ($heap:expr, vApplicationMallocFailedHook) => {
+        extern "C" {
+            fn vApplicationMallocFailedHook();
+        }
+
+        $crate::freertos_heap!($heap, |_: usize| unsafe { vApplicationMallocFailedHook() });
+    };
+    ($heap:expr, $malloc_failed:expr) => {
+        extern "C" {
+            fn vTaskSuspendAll();
+            fn xTaskResumeAll() -> ::core::ffi::c_long;
+        }
+
+        /// Allocates `size` bytes from the heap of the Rust code.
+        #[no_mangle]
+        pub unsafe extern "C" fn pvPortMalloc(size: usize) -> *mut ::core::ffi::c_void {
+            vTaskSuspendAll();
+            let ptr = $crate::pv_port_malloc(&$heap, size);
+            xTaskResumeAll();
+            if ptr.is_null() && size != 0 {
+                ($malloc_failed)(size);
+            }
+            ptr.cast()
+        }
+
+        /// Frees an allocation of `pvPortMalloc`.
+        #[no_mangle]
+        pub unsafe extern "C" fn vPortFree(ptr: *mut ::core::ffi::c_void) {
+            vTaskSuspendAll();
+            $crate::v_port_free(&$heap, ptr.cast());
+            xTaskResumeAll();
+        }
+
+        /// Returns the number of free bytes of the heap.
+        #[no_mangle]
+        pub extern "C" fn xPortGetFreeHeapSize() -> usize {
+            $crate::FreeRtosHeapStats::of(&$heap).xAvailableHeapSpaceInBytes
+        }
+
+        /// Returns the lowest number of free bytes since the heap was initialized.
+        #[no_mangle]
+        pub extern "C" fn xPortGetMinimumEverFreeHeapSize() -> usize {
+            $crate::FreeRtosHeapStats::of(&$heap).xMinimumEverFreeBytesRemaining
+        }
+
+        /// Fills in the statistics of the heap.
+        #[no_mangle]
+        pub unsafe extern "C" fn vPortGetHeapStats(stats: *mut $crate::FreeRtosHeapStats) {
+            stats.write($crate::FreeRtosHeapStats::of(&$heap));
+        }
+
+        /// Does nothing, the heap is initialized through the `LockedHeap`.
+        #[no_mangle]
+        pub extern "C" fn vPortInitialiseBlocks() {}
+    };
+}
+
+/// Allocates `size` bytes with an alignment of [`FREERTOS_ALIGNMENT`], like `pvPortMalloc`
+/// of FreeRTOS. Returns a null pointer if the allocation fails or `size` is zero.
+///
+/// The layout is stored in the header of the allocation, so that it can be freed with
+/// [`v_port_free`].
+pub fn pv_port_malloc<R: RawMutex>(heap: &LockedHeap<R>, size: usize) -> *mut u8 {
+    if size == 0 {
+        return ptr::null_mut();
+    }
+    match Layout::from_size_align(size, FREERTOS_ALIGNMENT) {
+        Ok(layout) => heap
+            .allocate_first_fit(layout)
+            .map_or(ptr::null_mut(), NonNull::as_ptr),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Frees an allocation of [`pv_port_malloc`], like `vPortFree` of FreeRTOS. A null pointer
+/// is ignored.
+///
+/// # Safety
+///
+/// `ptr` must be null or a live allocation of [`pv_port_malloc`] with the same heap.
+pub unsafe fn v_port_free<R: RawMutex>(heap: &LockedHeap<R>, ptr: *mut u8) {
+    if let Some(ptr) = NonNull::new(ptr) {
+        let locked = heap.lock();
+        let layout = locked.allocation_layout(ptr);
+        heap.deallocate_locked(locked, ptr, layout);
+    }
+}
+
+/// The statistics of the heap in the layout of the `HeapStats_t` struct of FreeRTOS, which
+/// `vPortGetHeapStats` fills in.
+///
+/// The values are taken from the same [`Heap::stats`][crate::Heap::stats] as those of the
+/// Rust code, so both see the fragmentation caused by the other.
+#[repr(C)]
+#[allow(non_snake_case)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FreeRtosHeapStats {
+    /// The number of free bytes.
+    pub xAvailableHeapSpaceInBytes: usize,
+    /// The size of the largest free block in bytes.
+    pub xSizeOfLargestFreeBlockInBytes: usize,
+    /// The size of the smallest free block in bytes.
+    pub xSizeOfSmallestFreeBlockInBytes: usize,
+    /// The number of free blocks.
+    pub xNumberOfFreeBlocks: usize,
+    /// The lowest number of free bytes since the heap was initialized.
+    pub xMinimumEverFreeBytesRemaining: usize,
+    /// The number of successful allocations.
+    pub xNumberOfSuccessfulAllocations: usize,
+    /// The number of deallocations.
+    pub xNumberOfSuccessfulFrees: usize,
+}
+
+impl FreeRtosHeapStats {
+    /// Returns the statistics of `heap`.
+    ///
+    /// This takes the lock and walks the list of free blocks.
+    pub fn of<R: RawMutex>(heap: &LockedHeap<R>) -> Self {
+        let heap = heap.lock();
+        let stats = heap.stats();
+        let smallest = heap.holes.holes().map(|(_, size)| size).min();
+        FreeRtosHeapStats {
+            xAvailableHeapSpaceInBytes: stats.free,
+            xSizeOfLargestFreeBlockInBytes: stats.largest_hole,
+            xSizeOfSmallestFreeBlockInBytes: smallest.unwrap_or(0),
+            xNumberOfFreeBlocks: stats.holes,
+            xMinimumEverFreeBytesRemaining: stats.size - stats.peak_used,
+            xNumberOfSuccessfulAllocations: stats.allocations,
+            xNumberOfSuccessfulFrees: stats.deallocations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pv_port_malloc, v_port_free, FreeRtosHeapStats, FREERTOS_ALIGNMENT};
+    use crate::test::Chonk;
+    use crate::{Heap, LockedHeap};
+    use core::alloc::Layout;
+
+    #[test]
+    fn port_malloc_and_free() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let heap: LockedHeap = LockedHeap::from_heap(unsafe { Heap::new(data, 1024) });
+        assert!(pv_port_malloc(&heap, 0).is_null());
+        assert!(pv_port_malloc(&heap, 4096).is_null());
+
+        let a = pv_port_malloc(&heap, 13);
+        assert_eq!(a as usize % FREERTOS_ALIGNMENT, 0);
+        // the Rust code allocates from the same heap
+        let b = heap
+            .allocate_first_fit(Layout::from_size_align(64, 8).unwrap())
+            .unwrap();
+        let c = pv_port_malloc(&heap, 100);
+        unsafe { v_port_free(&heap, a) };
+
+        let stats = FreeRtosHeapStats::of(&heap);
+        assert_eq!(stats.xNumberOfSuccessfulAllocations, 3);
+        assert_eq!(stats.xNumberOfSuccessfulFrees, 1);
+        assert_eq!(stats.xNumberOfFreeBlocks, 2);
+        assert_eq!(stats.xAvailableHeapSpaceInBytes, heap.stats().free);
+        assert!(stats.xSizeOfSmallestFreeBlockInBytes < stats.xSizeOfLargestFreeBlockInBytes);
+        assert!(stats.xMinimumEverFreeBytesRemaining < stats.xAvailableHeapSpaceInBytes);
+
+        unsafe {
+            v_port_free(&heap, c);
+            v_port_free(&heap, core::ptr::null_mut());
+            heap.deallocate(b, Layout::from_size_align(64, 8).unwrap());
+        }
+        assert_eq!(heap.counters().used, 0);
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    // the functions are exported from a static heap, which loom can't create
+    #[cfg(not(loom))]
+    mod exported {
+        use crate::test::Chonk;
+        use crate::{FreeRtosHeapStats, LockedHeap};
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static HEAP: LockedHeap = LockedHeap::empty();
+        static FAILED: AtomicUsize = AtomicUsize::new(0);
+
+        crate::freertos_heap!(HEAP, |size| FAILED.store(size, Ordering::Relaxed));
+
+        /// The scheduler of FreeRTOS, which the functions suspend.
+        mod scheduler {
+            #[no_mangle]
+            extern "C" fn vTaskSuspendAll() {}
+
+            #[no_mangle]
+            extern "C" fn xTaskResumeAll() -> core::ffi::c_long {
+                0
+            }
+        }
+
+        #[test]
+        fn freertos_heap_functions() {
+            let (chonk, data) = Chonk::<1024>::new();
+            unsafe { HEAP.init(data, 1024) };
+            let free = xPortGetFreeHeapSize();
+            let a = unsafe { pvPortMalloc(100) };
+            assert!(!a.is_null());
+            assert!(xPortGetFreeHeapSize() < free);
+            assert!(unsafe { pvPortMalloc(4096) }.is_null());
+            assert_eq!(FAILED.load(Ordering::Relaxed), 4096);
+
+            let mut stats = FreeRtosHeapStats::default();
+            unsafe { vPortGetHeapStats(&mut stats) };
+            assert_eq!(stats.xNumberOfSuccessfulAllocations, 1);
+            assert_eq!(
+                stats.xMinimumEverFreeBytesRemaining,
+                xPortGetMinimumEverFreeHeapSize()
+            );
+            unsafe { vPortFree(a) };
+            assert_eq!(xPortGetFreeHeapSize(), free);
+            assert!(xPortGetMinimumEverFreeHeapSize() < free);
+            unsafe { Chonk::unleak(chonk) };
+        }
+    }
+}
@@ -0,0 +1,11 @@
+/// What allocations do after the heap was frozen, see
+/// [`Heap::freeze`][crate::Heap::freeze].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FreezeMode {
+    /// Allocations fail with [`AllocError::Frozen`][crate::AllocError::Frozen].
+    Fail,
+    /// Allocations panic, e.g. so that a violation of the allocation phase can't be
+    /// handled and ignored by the caller.
+    Panic,
+}
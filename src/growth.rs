@@ -0,0 +1,159 @@
+//! Growing a heap when an allocation fails, see [`Heap::set_growth`][crate::Heap::set_growth].
+
+use core::ptr::NonNull;
+
+/// Provides the memory that a heap grows into when an allocation fails, e.g. by mapping
+/// pages or by moving a program break.
+///
+/// # Safety
+///
+/// The memory that [`grow`][GrowthSource::grow] reports must directly follow `top`, be
+/// valid for reads and writes for the `'static` lifetime and not be used for anything else,
+/// like the memory passed to [`Heap::extend`][crate::Heap::extend].
+pub unsafe trait GrowthSource: Sync {
+    /// Makes up to `by` bytes directly behind `top` available to the heap and returns how
+    /// many bytes were made available, or 0 if there is no more memory.
+    fn grow(&self, top: NonNull<u8>, by: usize) -> usize;
+}
+
+/// Decides how many bytes a heap requests from its [`GrowthSource`] when an allocation
+/// fails.
+///
+/// [`FixedIncrement`], [`Doubling`] and [`Capped`] cover the common strategies.
+pub trait GrowthPolicy: Sync {
+    /// Returns the number of bytes to request for an allocation that needs at least
+    /// `needed` more bytes on a heap of `size` bytes. A result below `needed` lets the
+    /// allocation fail without growing the heap.
+    fn grow_by(&self, needed: usize, size: usize) -> usize;
+}
+
+/// Grows the heap by the smallest multiple of the increment that fits the allocation, so
+/// that the heap grows in steps of a fixed size, e.g. whole pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedIncrement(pub usize);
+
+impl GrowthPolicy for FixedIncrement {
+    fn grow_by(&self, needed: usize, _size: usize) -> usize {
+        match self.0 {
+            0 => needed,
+            increment => needed
+                .checked_add(increment - 1)
+                .map_or(usize::MAX, |n| n / increment * increment),
+        }
+    }
+}
+
+/// Doubles the size of the heap, or grows it by the size of the allocation if that is
+/// larger. This keeps the number of extensions logarithmic in the final heap size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Doubling;
+
+impl GrowthPolicy for Doubling {
+    fn grow_by(&self, needed: usize, size: usize) -> usize {
+        needed.max(size)
+    }
+}
+
+/// Limits another policy so that the heap never grows beyond `max_size` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capped<P> {
+    policy: P,
+    max_size: usize,
+}
+
+impl<P: GrowthPolicy> Capped<P> {
+    /// Wraps `policy` so that the heap grows to at most `max_size` bytes.
+    pub const fn new(policy: P, max_size: usize) -> Self {
+        Capped { policy, max_size }
+    }
+}
+
+impl<P: GrowthPolicy> GrowthPolicy for Capped<P> {
+    fn grow_by(&self, needed: usize, size: usize) -> usize {
+        let room = self.max_size.saturating_sub(size);
+        self.policy.grow_by(needed, size).min(room)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Capped, Doubling, FixedIncrement, GrowthPolicy, GrowthSource};
+    use crate::test::Chonk;
+    use crate::{AllocError, Heap};
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::boxed::Box;
+
+    #[test]
+    fn policies() {
+        assert_eq!(FixedIncrement(4096).grow_by(1, 0), 4096);
+        assert_eq!(FixedIncrement(4096).grow_by(4097, 0), 8192);
+        assert_eq!(FixedIncrement(0).grow_by(100, 0), 100);
+        assert_eq!(Doubling.grow_by(100, 1000), 1000);
+        assert_eq!(Doubling.grow_by(2000, 1000), 2000);
+        let capped = Capped::new(Doubling, 1500);
+        assert_eq!(capped.grow_by(100, 1000), 500);
+        assert_eq!(capped.grow_by(100, 1500), 0);
+    }
+
+    /// Hands out the memory up to `limit` and counts the calls.
+    struct Limit {
+        limit: usize,
+        calls: AtomicUsize,
+    }
+
+    unsafe impl GrowthSource for Limit {
+        fn grow(&self, top: NonNull<u8>, by: usize) -> usize {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            by.min(self.limit - top.as_ptr() as usize)
+        }
+    }
+
+    #[test]
+    fn grows_on_failed_allocation() {
+        const HEAP_SIZE: usize = 4096;
+        let (chonk, data) = Chonk::<HEAP_SIZE>::new();
+        let raw = Box::into_raw(Box::new(Limit {
+            limit: data as usize + HEAP_SIZE,
+            calls: AtomicUsize::new(0),
+        }));
+        // freed through `raw`, which the shared reference is derived from
+        let source: &'static Limit = unsafe { &*raw };
+        static POLICY: Capped<Doubling> = Capped::new(Doubling, 1536);
+        let mut heap = unsafe { Heap::new(data, 256) };
+        heap.set_growth(Some(source), &POLICY);
+
+        // fits without growing
+        let small = Layout::from_size_align(64, 8).unwrap();
+        heap.allocate_first_fit(small).unwrap();
+        assert_eq!(source.calls.load(Ordering::Relaxed), 0);
+
+        // the allocation needs more than doubling the heap would add
+        let large = Layout::from_size_align(600, 8).unwrap();
+        heap.allocate_first_fit(large).unwrap();
+        assert_eq!(source.calls.load(Ordering::Relaxed), 1);
+        let size = heap.size();
+        assert!((256 + 600..1024).contains(&size));
+
+        // doubling is limited by the cap
+        let medium = Layout::from_size_align(400, 8).unwrap();
+        heap.allocate_first_fit(medium).unwrap();
+        assert_eq!(heap.size(), 1536);
+
+        // the allocation doesn't fit below the cap
+        let huge = Layout::from_size_align(1500, 8).unwrap();
+        assert!(matches!(
+            heap.allocate_first_fit(huge),
+            Err(AllocError::Fragmented { .. }) | Err(AllocError::OutOfMemory)
+        ));
+        assert_eq!(heap.size(), 1536);
+        assert_eq!(source.calls.load(Ordering::Relaxed), 2);
+
+        unsafe {
+            heap.set_growth(None, &POLICY);
+            drop(Box::from_raw(raw));
+            Chonk::unleak(chonk);
+        }
+    }
+}
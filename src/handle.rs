@@ -0,0 +1,312 @@
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::{AllocError, Heap};
+
+/// A stable reference to an allocation of a [`HandleHeap`].
+///
+/// Unlike a pointer, a handle stays valid when the allocation is moved by
+/// [`HandleHeap::compact`]. Handles carry a generation counter, so a handle whose allocation
+/// was freed is rejected even if its slot has been reused since.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    generation: u32,
+    allocation: Option<(NonNull<u8>, Layout)>,
+}
+
+const EMPTY_SLOT: Slot = Slot {
+    generation: 0,
+    allocation: None,
+};
+
+/// A heap whose allocations are referenced through an indirection table of `N` slots.
+///
+/// Since the table knows the location and layout of every allocation, the heap is free
+/// to move them around. This allows [`compact`][HandleHeap::compact] to reduce
+/// fragmentation, which is impossible for allocations referenced by raw pointers.
+pub struct HandleHeap<const N: usize> {
+    heap: Heap,
+    slots: [Slot; N],
+}
+
+unsafe impl<const N: usize> Send for HandleHeap<N> {}
+
+impl<const N: usize> HandleHeap<N> {
+    /// Creates an empty handle heap. All allocate calls will return an error.
+    pub const fn empty() -> Self {
+        Self::new(Heap::empty())
+    }
+
+    /// Creates a handle heap that allocates from the given heap.
+    ///
+    /// Allocations made on the heap before are left in place and never moved.
+    pub const fn new(heap: Heap) -> Self {
+        HandleHeap {
+            heap,
+            slots: [EMPTY_SLOT; N],
+        }
+    }
+
+    /// Initializes an empty handle heap, see [`Heap::init`].
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`Heap::init`] apply.
+    pub unsafe fn init(&mut self, heap_bottom: *mut u8, heap_size: usize) {
+        self.heap.init(heap_bottom, heap_size);
+    }
+
+    /// Returns a reference to the underlying heap.
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// Returns a mutable reference to the underlying heap.
+    ///
+    /// Allocations made on it directly are not referenced by a slot, so they are left in
+    /// place by [`compact`][HandleHeap::compact].
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// Allocates a block with the given layout and returns a handle to it.
+    ///
+    /// Fails if the heap has no suitable free block, or with [`AllocError::Exhausted`] if all
+    /// `N` slots are in use.
+    pub fn allocate(&mut self, layout: Layout) -> Result<Handle, AllocError> {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| slot.allocation.is_none())
+            .ok_or(AllocError::Exhausted)?;
+        let ptr = self.heap.allocate_first_fit(layout)?;
+
+        let slot = &mut self.slots[index];
+        slot.allocation = Some((ptr, layout));
+        Ok(Handle {
+            index: index as u32,
+            generation: slot.generation,
+        })
+    }
+
+    /// Returns the current location of the allocation referenced by `handle`.
+    ///
+    /// The returned pointer is only valid until the next call to
+    /// [`compact`][HandleHeap::compact]. Returns `None` if the handle is stale.
+    pub fn get(&self, handle: Handle) -> Option<NonNull<u8>> {
+        self.slot(handle).map(|(ptr, _)| ptr)
+    }
+
+    /// Returns the layout the allocation referenced by `handle` was made with.
+    pub fn layout(&self, handle: Handle) -> Option<Layout> {
+        self.slot(handle).map(|(_, layout)| layout)
+    }
+
+    /// Frees the allocation referenced by `handle`.
+    ///
+    /// Returns `false` if the handle is stale, i.e. its allocation was already freed.
+    pub fn deallocate(&mut self, handle: Handle) -> bool {
+        let (ptr, layout) = match self.slot(handle) {
+            Some(allocation) => allocation,
+            None => return false,
+        };
+        let slot = &mut self.slots[handle.index as usize];
+        slot.allocation = None;
+        slot.generation = slot.generation.wrapping_add(1);
+        // SAFETY: The slot table only contains live allocations of `self.heap`.
+        unsafe { self.heap.deallocate(ptr, layout) };
+        true
+    }
+
+    /// Moves allocations towards the bottom of the heap to coalesce the free memory.
+    ///
+    /// The allocations are visited in address order and each one is moved into the first
+    /// free block below it that is large enough. Pointers returned by
+    /// [`get`][HandleHeap::get] are invalidated, the handles themselves stay valid.
+    ///
+    /// Returns the number of allocations that were moved.
+    pub fn compact(&mut self) -> usize {
+        let mut moved = 0;
+
+        // Visit the slots in ascending address order. Each allocation only moves into
+        // memory below it, which was already visited, so the order stays valid.
+        let mut order = [0; N];
+        for (index, entry) in order.iter_mut().enumerate() {
+            *entry = index;
+        }
+        let slots = &self.slots;
+        order.sort_unstable_by_key(|&index| slots[index].allocation.map(|(ptr, _)| ptr));
+
+        for index in order {
+            let slot = &mut self.slots[index];
+            let (ptr, layout) = match slot.allocation {
+                Some(allocation) => allocation,
+                None => continue,
+            };
+            // SAFETY: The slot table only contains live allocations of `self.heap`.
+            if let Some(new_ptr) = unsafe { self.heap.move_down(ptr, layout) } {
+                slot.allocation = Some((new_ptr, layout));
+                moved += 1;
+            }
+        }
+        moved
+    }
+
+    fn slot(&self, handle: Handle) -> Option<(NonNull<u8>, Layout)> {
+        let slot = self.slots.get(handle.index as usize)?;
+        if slot.generation == handle.generation {
+            slot.allocation
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HandleHeap;
+    use crate::test::Chonk;
+    use crate::{AllocError, Heap};
+    use core::alloc::Layout;
+
+    #[test]
+    fn compact_moves_allocations_down() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let mut heap = HandleHeap::<4>::new(unsafe { Heap::new(data, 1024) });
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        let a = heap.allocate(layout).unwrap();
+        let b = heap.allocate(layout).unwrap();
+        let c = heap.allocate(layout).unwrap();
+        unsafe { heap.get(c).unwrap().as_ptr().write_bytes(0xc3, 64) };
+        let old_a = heap.get(a).unwrap();
+        let old_c = heap.get(c).unwrap();
+
+        assert!(heap.deallocate(b));
+        assert!(!heap.deallocate(b));
+        assert_eq!(heap.get(b), None);
+
+        assert_eq!(heap.compact(), 1);
+        let new_c = heap.get(c).unwrap();
+        assert!(new_c < old_c);
+        assert_eq!(heap.get(a).unwrap(), old_a);
+        let contents = unsafe { core::slice::from_raw_parts(new_c.as_ptr(), 64) };
+        assert!(contents.iter().all(|&byte| byte == 0xc3));
+
+        assert!(heap.deallocate(a));
+        assert!(heap.deallocate(c));
+        assert_eq!(heap.heap().used(), 0);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    fn compact_padded_allocations() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let mut heap = HandleHeap::<4>::new(Heap::with_min_block_size(64));
+        unsafe { heap.init(data, 1024) };
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        let a = heap.allocate(layout).unwrap();
+        let b = heap.allocate(layout).unwrap();
+        let c = heap.allocate(layout).unwrap();
+        let used = heap.heap().used();
+        assert!(heap.deallocate(a));
+
+        // the allocations move by whole padded blocks
+        assert_eq!(heap.compact(), 2);
+        let (new_b, new_c) = (heap.get(b).unwrap(), heap.get(c).unwrap());
+        assert!(new_c.as_ptr() as usize - new_b.as_ptr() as usize >= 64);
+        assert_eq!(heap.heap().used(), used * 2 / 3);
+        heap.heap().holes.check_invariants();
+
+        assert!(heap.deallocate(b));
+        assert!(heap.deallocate(c));
+        assert_eq!(heap.heap().used(), 0);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    fn compact_with_guard_gap() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let mut heap = HandleHeap::<4>::empty();
+        unsafe {
+            heap.heap_mut().set_guard_gap(32);
+            heap.init(data, 1024);
+        }
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        let a = heap.allocate(layout).unwrap();
+        let b = heap.allocate(layout).unwrap();
+        let used = heap.heap().used();
+        assert!(heap.deallocate(a));
+
+        // the gap moves along with the allocation
+        assert_eq!(heap.compact(), 1);
+        assert_eq!(heap.heap().used(), used / 2);
+        heap.heap().holes.check_invariants();
+
+        assert!(heap.deallocate(b));
+        assert_eq!(heap.heap().used(), 0);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    fn slots_exhausted() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let mut heap = HandleHeap::<2>::new(unsafe { Heap::new(data, 1024) });
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        let a = heap.allocate(layout).unwrap();
+        let _b = heap.allocate(layout).unwrap();
+        assert_eq!(heap.allocate(layout), Err(AllocError::Exhausted));
+
+        // a freed slot is reused, but stale handles are still rejected
+        assert!(heap.deallocate(a));
+        let c = heap.allocate(layout).unwrap();
+        assert_ne!(a, c);
+        assert_eq!(heap.get(a), None);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    fn init_empty() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let mut heap = HandleHeap::<8>::empty();
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        assert!(heap.allocate(layout).is_err());
+        unsafe { heap.init(data, 1024) };
+
+        // allocations made on the heap directly are not moved
+        let pinned = heap.heap_mut().allocate_first_fit(layout).unwrap();
+        let handles: std::vec::Vec<_> = (0..8).map(|_| heap.allocate(layout).unwrap()).collect();
+        for (i, &handle) in handles.iter().enumerate() {
+            unsafe { heap.get(handle).unwrap().as_ptr().write_bytes(i as u8, 32) };
+        }
+        // free every other allocation, in an order unrelated to the addresses
+        for &i in &[6, 0, 4, 2] {
+            assert!(heap.deallocate(handles[i]));
+        }
+        unsafe { heap.heap_mut().deallocate(pinned, layout) };
+
+        assert_eq!(heap.compact(), 4);
+        for &i in &[1, 3, 5, 7] {
+            let ptr = heap.get(handles[i]).unwrap();
+            let contents = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), 32) };
+            assert!(contents.iter().all(|&byte| byte == i as u8));
+        }
+        assert_eq!(heap.heap().holes.holes().count(), 1);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+}
@@ -0,0 +1,426 @@
+//! Optional per-allocation metadata.
+//!
+//! With the `headers` feature enabled, every allocation is preceded by a [`Header`] that
+//! records the layout of the allocation and the extent of the block that contains it. This
+//! makes it possible to walk the live allocations of a heap, which is required for
+//! operations such as [`Heap::defragment`][crate::Heap::defragment].
+//!
+//! A block has the following structure:
+//!
+//! ```text
+//! block                                  payload
+//! v                                      v
+//! [ Header | padding | offset: usize ] [ allocation ... ]
+//! ```
+//!
+//! The `offset` of the payload from the start of the block is always stored in the word
+//! right in front of the payload. If the allocation has no alignment requirement beyond
+//! that of the header, this word is the last field of the header itself.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::AllocError;
+
+#[cfg(feature = "headers")]
+use core::marker::PhantomData;
+#[cfg(feature = "headers")]
+use core::mem::{align_of, size_of};
+#[cfg(feature = "call_sites")]
+use core::panic::Location;
+
+#[cfg(feature = "headers")]
+use crate::checked_align_up_size;
+#[cfg(feature = "headers")]
+use crate::hole::{Hole, HoleList, LinkKey};
+
+/// Metadata stored at the start of every block.
+#[cfg(feature = "headers")]
+#[repr(C)]
+pub(crate) struct Header {
+    /// Size of the whole block, including the header. Since block sizes are a multiple of
+    /// the word size, the lowest bits are used for the [`PURGEABLE`] and [`PINNED`] flags.
+    size_and_flags: usize,
+    /// The layout that was requested for the allocation.
+    pub layout: Layout,
+    /// The tag passed to [`Heap::allocate_tagged`][crate::Heap::allocate_tagged], or 0.
+    pub tag: usize,
+    /// The call site of the allocation, with the `call_sites` feature.
+    #[cfg(feature = "call_sites")]
+    pub site: Option<&'static Location<'static>>,
+    /// The time of the allocation, with the `timestamps` feature.
+    #[cfg(feature = "timestamps")]
+    pub created: u64,
+    /// Offset of the payload from the start of the block. Must be the last field.
+    pub offset: usize,
+}
+
+/// The flag of allocations that the heap may purge, see
+/// [`Heap::allocate_purgeable`][crate::Heap::allocate_purgeable].
+#[cfg(feature = "headers")]
+pub(crate) const PURGEABLE: usize = 1;
+
+/// The flag of blocks whose contents must not be accessed or moved, e.g. the pages that
+/// were removed by [`Heap::inflate`][crate::Heap::inflate].
+#[cfg(feature = "headers")]
+pub(crate) const PINNED: usize = 2;
+
+#[cfg(feature = "headers")]
+impl Header {
+    /// Returns the header of the block starting at `block`.
+    ///
+    /// # Safety
+    ///
+    /// `block` must be the start of a live block.
+    pub unsafe fn of_block<'a>(block: *mut u8) -> &'a Header {
+        &*block.cast::<Header>()
+    }
+
+    /// Returns the start of the block that contains the payload at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a pointer returned by an allocation of the heap.
+    pub unsafe fn block_of(ptr: NonNull<u8>) -> *mut u8 {
+        let offset = ptr.as_ptr().sub(size_of::<usize>()).cast::<usize>().read();
+        ptr.as_ptr().sub(offset)
+    }
+
+    /// Returns the size of the whole block, including the header.
+    pub fn block_size(&self) -> usize {
+        self.size_and_flags & !(PURGEABLE | PINNED)
+    }
+
+    /// Returns whether the heap may purge the allocation.
+    pub fn is_purgeable(&self) -> bool {
+        self.size_and_flags & PURGEABLE != 0
+    }
+
+    /// Returns whether the block must stay where it is and is not a regular allocation.
+    pub fn is_pinned(&self) -> bool {
+        self.size_and_flags & PINNED != 0
+    }
+
+    /// Returns the layout of this block, as passed to the hole list.
+    pub fn block_layout(&self) -> Layout {
+        Layout::from_size_align(
+            self.block_size(),
+            self.layout.align().max(align_of::<Header>()),
+        )
+        .unwrap()
+    }
+
+    /// Returns the payload of the block starting at `block`.
+    pub fn payload(&self, block: *mut u8) -> NonNull<u8> {
+        unsafe { NonNull::new_unchecked(block.add(self.offset)) }
+    }
+}
+
+/// Returns the layout of the block that is needed for an allocation with the given layout,
+/// and the offset of the payload from the start of the block.
+#[cfg(feature = "headers")]
+pub(crate) fn block_layout(layout: Layout) -> Result<(Layout, usize), AllocError> {
+    block_layout_with_offset(layout, 0).map(|(block_layout, offset, _)| (block_layout, offset))
+}
+
+/// Like [`block_layout`], but for an allocation whose address plus `offset` must be aligned.
+///
+/// Also returns the offset that must be passed to the hole list, so that the payload is
+/// placed correctly. `offset` must be smaller than the alignment of `layout` and a multiple
+/// of the word size.
+#[cfg(feature = "headers")]
+pub(crate) fn block_layout_with_offset(
+    layout: Layout,
+    offset: usize,
+) -> Result<(Layout, usize, usize), AllocError> {
+    if offset != 0 {
+        return block_layout_placed(layout, offset);
+    }
+    let payload_offset = checked_align_up_size(size_of::<Header>(), layout.align())
+        .ok_or(AllocError::InvalidLayout)?;
+    block_layout_at(layout, payload_offset, offset)
+}
+
+/// Like [`block_layout_with_offset`], but lets the payload follow the header directly even
+/// if `offset` is zero. The hole list then places the block so that the payload ends up at
+/// the right position, which needs less padding than aligning the payload within the block
+/// if the alignment is large.
+#[cfg(feature = "headers")]
+pub(crate) fn block_layout_placed(
+    layout: Layout,
+    offset: usize,
+) -> Result<(Layout, usize, usize), AllocError> {
+    block_layout_at(layout, size_of::<Header>(), offset)
+}
+
+#[cfg(feature = "headers")]
+fn block_layout_at(
+    layout: Layout,
+    payload_offset: usize,
+    offset: usize,
+) -> Result<(Layout, usize, usize), AllocError> {
+    let size = payload_offset
+        .checked_add(layout.size())
+        .ok_or(AllocError::InvalidLayout)?;
+    let align = layout.align().max(align_of::<Header>());
+    let block_layout =
+        Layout::from_size_align(size, align).map_err(|_| AllocError::InvalidLayout)?;
+    let block_offset = (payload_offset + offset) % align;
+    Ok((block_layout, payload_offset, block_offset))
+}
+
+#[cfg(not(feature = "headers"))]
+pub(crate) fn block_layout(layout: Layout) -> Result<(Layout, usize), AllocError> {
+    Ok((layout, 0))
+}
+
+#[cfg(not(feature = "headers"))]
+pub(crate) fn block_layout_with_offset(
+    layout: Layout,
+    offset: usize,
+) -> Result<(Layout, usize, usize), AllocError> {
+    Ok((layout, 0, offset))
+}
+
+#[cfg(not(feature = "headers"))]
+pub(crate) fn block_layout_placed(
+    layout: Layout,
+    offset: usize,
+) -> Result<(Layout, usize, usize), AllocError> {
+    Ok((layout, 0, offset))
+}
+
+/// The offset of the payload from the start of a block that is placed at a given address,
+/// see [`Heap::inflate`][crate::Heap::inflate].
+#[cfg(feature = "headers")]
+pub(crate) const FIXED_OFFSET: usize = size_of::<Header>();
+
+#[cfg(not(feature = "headers"))]
+pub(crate) const FIXED_OFFSET: usize = 0;
+
+/// Writes the header for a new allocation into the given block and returns the payload.
+///
+/// # Safety
+///
+/// `block` must point to a newly allocated block of `block_size` bytes that was allocated
+/// for a layout returned by [`block_layout`].
+#[cfg(feature = "headers")]
+pub(crate) unsafe fn write(
+    block: NonNull<u8>,
+    block_size: usize,
+    layout: Layout,
+    offset: usize,
+) -> NonNull<u8> {
+    block.as_ptr().cast::<Header>().write(Header {
+        size_and_flags: block_size,
+        layout,
+        tag: 0,
+        #[cfg(feature = "call_sites")]
+        site: None,
+        #[cfg(feature = "timestamps")]
+        created: 0,
+        offset,
+    });
+    let payload = block.as_ptr().add(offset);
+    payload
+        .sub(size_of::<usize>())
+        .cast::<usize>()
+        .write(offset);
+    NonNull::new_unchecked(payload)
+}
+
+#[cfg(not(feature = "headers"))]
+pub(crate) unsafe fn write(
+    block: NonNull<u8>,
+    _block_size: usize,
+    _layout: Layout,
+    _offset: usize,
+) -> NonNull<u8> {
+    block
+}
+
+/// Returns the block and block layout of the allocation at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be a pointer returned by an allocation of the heap with the given `layout`.
+#[cfg(feature = "headers")]
+pub(crate) unsafe fn block(ptr: NonNull<u8>, layout: Layout) -> (NonNull<u8>, Layout) {
+    let block = Header::block_of(ptr);
+    let header = Header::of_block(block);
+    // allocations that return their usable size may be freed with any size up to it
+    let matches = header.layout.align() == layout.align() && layout.size() <= header.layout.size();
+    #[cfg(feature = "call_sites")]
+    debug_assert!(
+        matches,
+        "deallocation with {:?} does not match the allocation with {:?} made at {}",
+        layout,
+        header.layout,
+        crate::site::Site(header.site)
+    );
+    #[cfg(not(feature = "call_sites"))]
+    debug_assert!(
+        matches,
+        "deallocation layout does not match the allocation layout"
+    );
+    (NonNull::new_unchecked(block), header.block_layout())
+}
+
+/// Returns the layout that the allocation at `ptr` was made with.
+///
+/// # Safety
+///
+/// `ptr` must be an untagged pointer returned by an allocation of the heap.
+#[cfg(feature = "headers")]
+pub(crate) unsafe fn layout(ptr: NonNull<u8>) -> Layout {
+    Header::of_block(Header::block_of(ptr)).layout
+}
+
+/// Sets the tag of the allocation at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be an untagged pointer returned by an allocation of the heap.
+#[cfg(feature = "headers")]
+pub(crate) unsafe fn set_tag(ptr: NonNull<u8>, tag: usize) {
+    (*Header::block_of(ptr).cast::<Header>()).tag = tag;
+}
+
+/// Sets the call site of the allocation at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be an untagged pointer returned by an allocation of the heap.
+#[cfg(feature = "call_sites")]
+pub(crate) unsafe fn set_site(ptr: NonNull<u8>, site: &'static Location<'static>) {
+    (*Header::block_of(ptr).cast::<Header>()).site = Some(site);
+}
+
+/// Sets the time of the allocation at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be an untagged pointer returned by an allocation of the heap.
+#[cfg(feature = "timestamps")]
+pub(crate) unsafe fn set_created(ptr: NonNull<u8>, time: u64) {
+    (*Header::block_of(ptr).cast::<Header>()).created = time;
+}
+
+/// Sets the given flags of the allocation at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` must be an untagged pointer returned by an allocation of the heap.
+#[cfg(feature = "headers")]
+pub(crate) unsafe fn set_flags(ptr: NonNull<u8>, flags: usize) {
+    (*Header::block_of(ptr).cast::<Header>()).size_and_flags |= flags;
+}
+
+#[cfg(not(feature = "headers"))]
+pub(crate) unsafe fn block(ptr: NonNull<u8>, layout: Layout) -> (NonNull<u8>, Layout) {
+    (ptr, layout)
+}
+
+/// An iterator over the live allocations of a [`Heap`][crate::Heap], in address order.
+///
+/// Created by [`Heap::allocations`][crate::Heap::allocations]. Yields the pointer and the
+/// layout of every allocation.
+#[cfg(feature = "headers")]
+pub struct Allocations<'a> {
+    pos: *mut u8,
+    next_hole: Option<NonNull<Hole>>,
+    /// The last hole below `pos`, see [`last_hole`][Self::last_hole].
+    last_hole: Option<NonNull<Hole>>,
+    top: *mut u8,
+    key: LinkKey,
+    _holes: PhantomData<&'a HoleList>,
+}
+
+#[cfg(feature = "headers")]
+impl<'a> Allocations<'a> {
+    /// Creates an iterator over all blocks at or after `from`.
+    ///
+    /// `from` must either be the start of a block or lie within a hole.
+    pub(crate) fn new(holes: &'a HoleList, from: *mut u8) -> Self {
+        Self::after_hole(holes, from, None)
+    }
+
+    /// Like [`new`][Self::new], but starts walking the holes at `hint` instead of at the
+    /// start of the list, which must be a hole at or below `from`.
+    pub(crate) fn after_hole(
+        holes: &'a HoleList,
+        from: *mut u8,
+        hint: Option<NonNull<Hole>>,
+    ) -> Self {
+        let mut pos = from.max(holes.bottom);
+        let mut next_hole = hint.or_else(|| holes.first());
+        let mut last_hole = None;
+        while let Some(hole) = next_hole {
+            let start = hole.as_ptr().cast::<u8>();
+            let end = start.wrapping_add(unsafe { hole.as_ref() }.size);
+            if start > pos {
+                break;
+            }
+            if end > pos {
+                // `pos` lies within this hole
+                pos = end;
+            }
+            last_hole = Some(hole);
+            next_hole = unsafe { hole.as_ref() }.next(holes.key);
+        }
+        Allocations {
+            pos,
+            next_hole,
+            last_hole,
+            top: holes.top,
+            key: holes.key,
+            _holes: PhantomData,
+        }
+    }
+
+    /// Returns the start of the next live block.
+    pub(crate) fn next_block(&mut self) -> Option<*mut u8> {
+        loop {
+            match self.next_hole {
+                Some(hole) if hole.as_ptr().cast::<u8>() == self.pos => {
+                    self.last_hole = Some(hole);
+                    let hole = unsafe { hole.as_ref() };
+                    self.pos = self.pos.wrapping_add(hole.size);
+                    self.next_hole = hole.next(self.key);
+                }
+                // a cleared word in front of a block is padding that was too small for a
+                // hole, while the first word of a header is the non-zero block size
+                _ if self.pos < self.top && unsafe { self.pos.cast::<usize>().read() } == 0 => {
+                    self.pos = self.pos.wrapping_add(size_of::<usize>());
+                }
+                _ => break,
+            }
+        }
+        if self.pos >= self.top {
+            return None;
+        }
+        let block = self.pos;
+        let header = unsafe { Header::of_block(block) };
+        self.pos = block.wrapping_add(header.block_size());
+        Some(block)
+    }
+
+    /// Returns the last hole below the blocks that are yet to be returned. As long as the
+    /// list of holes doesn't change, it can be passed as the hint of
+    /// [`after_hole`][Self::after_hole] to continue the walk without starting over.
+    pub(crate) fn last_hole(&self) -> Option<NonNull<Hole>> {
+        self.last_hole
+    }
+}
+
+#[cfg(feature = "headers")]
+impl<'a> Iterator for Allocations<'a> {
+    type Item = (NonNull<u8>, Layout);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_block().map(|block| {
+            let header = unsafe { Header::of_block(block) };
+            (header.payload(block), header.layout)
+        })
+    }
+}
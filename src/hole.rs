@@ -1,31 +1,534 @@
 use core::alloc::{Layout, LayoutError};
+use core::marker::PhantomData;
 use core::mem;
 use core::mem::{align_of, size_of};
 use core::ptr::null_mut;
 use core::ptr::NonNull;
 
-use crate::{align_down_size, align_up_size};
+use crate::pages::Pages;
+#[cfg(feature = "hooks")]
+use crate::HeapHooks;
+#[cfg(feature = "pmem")]
+use crate::PersistHooks;
+use crate::{align_down_size, checked_align_up_size, sanitizer, AdoptError, AllocError};
 
 use super::align_up;
 
+/// The result of a step of the incremental heap check, see
+/// [`Heap::scan_step`][crate::Heap::scan_step].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ScanState {
+    /// The step checked its budget of free blocks without finding a problem. The next step
+    /// continues with the following blocks.
+    InProgress,
+    /// The step reached the end of the list of free blocks without finding a problem. The
+    /// next step starts a new pass at the start of the list.
+    Complete,
+    /// A free block is misaligned, out of bounds or out of order, or its header doesn't
+    /// match its checksum and can't be repaired. The next step starts a new pass.
+    Corrupted {
+        /// The address of the free block whose header is invalid or links to an invalid
+        /// block.
+        addr: usize,
+    },
+}
+
 /// A sorted list of holes. It uses the the holes itself to store its nodes.
 pub struct HoleList {
     pub(crate) first: Hole, // dummy
     pub(crate) bottom: *mut u8,
     pub(crate) top: *mut u8,
     pub(crate) pending_extend: u8,
+    /// All free memory at or above this address is known to contain zeros, except for the
+    /// headers of the holes that start there.
+    pub(crate) zeroed_from: *mut u8,
+    pub(crate) key: LinkKey,
+    /// The hooks that decommit the pages of large holes, see [`Pages`].
+    pub(crate) pages: Option<Pages>,
+    /// The hole that the last release started merging at, which is used as the hint for the
+    /// next release if that lies above it. Any other change to the list clears it, since it
+    /// might remove or move the hole.
+    last_release: Option<NonNull<Hole>>,
+    /// The last hole that [`scan_step`][Self::scan_step] checked, or `None` if the next scan
+    /// starts at the head of the list. A change that removes or moves this hole sets it to
+    /// a hole in front of it.
+    scan: Option<NonNull<Hole>>,
+    /// The record at the start of a persistent heap, which holds the dummy hole instead of
+    /// `first`, see [`Anchor`].
+    anchor: Option<NonNull<Anchor>>,
 }
 
+/// The record at the start of the memory of a persistent heap, see
+/// [`Heap::init_persistent`][crate::Heap::init_persistent].
+///
+/// It holds the dummy hole at the start of the list, so that the whole list lives in the
+/// heap memory and can be [adopted][HoleList::adopt] after the `Heap` itself was lost,
+/// e.g. across a warm reboot.
+#[repr(C)]
+struct Anchor {
+    magic: usize,
+    /// The address of the anchor itself, which tells that the memory wasn't moved.
+    base: usize,
+    top: *mut u8,
+    pending_extend: usize,
+    head: Hole,
+}
+
+/// Identifies the [`Anchor`] of a persistent heap, `LLAH` in ASCII.
+const ANCHOR_MAGIC: usize = 0x4c4c_4148;
+
 pub(crate) struct Cursor {
     prev: NonNull<Hole>,
     hole: NonNull<Hole>,
     top: *mut u8,
+    key: LinkKey,
 }
 
 /// A block containing free memory. It points to the next hole and thus forms a linked list.
+// the fields must come first, so that they don't overlap the mirror in the smallest holes
+#[cfg_attr(feature = "redundant", repr(C))]
 pub(crate) struct Hole {
     pub size: usize,
-    pub next: Option<NonNull<Hole>>,
+    /// The link to the next hole, encoded with the [`LinkKey`] of the list.
+    next: Option<NonNull<Hole>>,
+    /// The checksum of the size and the encoded link, see [`LinkKey::checksum`].
+    #[cfg(feature = "checksum")]
+    check: usize,
+    /// A second copy of the size, so that the size can be recovered by a majority vote
+    /// before the mirror at the end of the hole is located.
+    #[cfg(feature = "redundant")]
+    size_copy: usize,
+    /// Keeps the size of a hole a power of two, which the placement of front padding that is
+    /// too small for a hole relies on. With the `redundant` feature, it also makes room for
+    /// the [`Mirror`] at the end of the smallest holes.
+    #[cfg(feature = "checksum")]
+    _reserved: [u8; RESERVED],
+}
+
+/// The size of the fields of a [`Hole`]. Links are counted with the size of a pointer,
+/// which is larger than a `usize` where pointers are capabilities, e.g. on CHERI.
+#[cfg(feature = "checksum")]
+const HOLE_FIELDS: usize =
+    size_of::<*mut Hole>() + size_of::<usize>() * if cfg!(feature = "redundant") { 3 } else { 2 };
+
+/// The padding that rounds the size of a [`Hole`] up to the next power of two, together
+/// with its [`Mirror`] with the `redundant` feature.
+#[cfg(feature = "checksum")]
+const RESERVED: usize = (HOLE_FIELDS + MIRROR_SIZE).next_power_of_two() - HOLE_FIELDS;
+
+// front padding that is smaller than a hole is placed at an address that a hole can't have
+const _: () = assert!(size_of::<Hole>().is_power_of_two());
+
+/// The number of bytes at the end of every hole that are taken by its [`Mirror`].
+#[cfg(not(feature = "redundant"))]
+pub(crate) const MIRROR_SIZE: usize = 0;
+#[cfg(feature = "redundant")]
+pub(crate) const MIRROR_SIZE: usize = size_of::<Mirror>();
+
+impl Hole {
+    /// A hole without size and link, e.g. for the dummy node at the start of the list.
+    const EMPTY: Hole = Hole {
+        size: 0,
+        next: None,
+        #[cfg(feature = "checksum")]
+        check: 0,
+        #[cfg(feature = "redundant")]
+        size_copy: 0,
+        #[cfg(feature = "checksum")]
+        _reserved: [0; RESERVED],
+    };
+
+    /// Returns the next hole.
+    ///
+    /// With the `safe_linking` feature, this panics if the stored link was corrupted. With
+    /// the `checksum` feature, this panics if the size or the link of the next hole don't
+    /// match its checksum, unless the `redundant` feature can repair the hole.
+    pub(crate) fn next(&self, key: LinkKey) -> Option<NonNull<Hole>> {
+        self.next.map(|link| {
+            let next = key.decode(link);
+            // SAFETY: The link was decoded successfully, so it points to a hole.
+            unsafe { key.verify(next) };
+            next
+        })
+    }
+
+    /// Links the given hole to `next`.
+    ///
+    /// The hole is passed as a pointer instead of a reference, since the `redundant` feature
+    /// also writes to the end of the hole.
+    pub(crate) unsafe fn set_next(
+        mut hole: NonNull<Hole>,
+        next: Option<NonNull<Hole>>,
+        key: LinkKey,
+    ) {
+        hole.as_mut().next = next.map(|next| key.encode(next));
+        Hole::update_check(hole, key);
+        Hole::persist(hole, key);
+    }
+
+    /// Sets the size of the given hole.
+    unsafe fn set_size(mut hole: NonNull<Hole>, size: usize, key: LinkKey) {
+        #[cfg(feature = "redundant")]
+        if size > hole.as_ref().size {
+            // the old mirror is left in the middle of the hole, where it must not be taken
+            // for a valid one. When the hole shrinks, the old end belongs to someone else.
+            Mirror::clear(hole, key);
+        }
+        hole.as_mut().size = size;
+        Hole::update_check(hole, key);
+        Hole::persist(hole, key);
+    }
+
+    /// Writes the header of the given hole, and its [`Mirror`] with the `redundant` feature,
+    /// back to persistent memory with the persistence hooks of `key`, if there are any.
+    unsafe fn persist(hole: NonNull<Hole>, key: LinkKey) {
+        key.flush(hole.as_ptr().cast(), size_of::<Hole>());
+        #[cfg(feature = "redundant")]
+        if hole.as_ref().size >= size_of::<Hole>() {
+            let mirror = Mirror::locate(hole, hole.as_ref().size);
+            key.flush(mirror.cast(), MIRROR_SIZE);
+        }
+        key.fence();
+    }
+
+    /// Unlinks the given hole from its next hole and returns the latter.
+    unsafe fn take_next(hole: NonNull<Hole>, key: LinkKey) -> Option<NonNull<Hole>> {
+        let next = hole.as_ref().next(key);
+        Hole::set_next(hole, None, key);
+        next
+    }
+
+    #[cfg(feature = "checksum")]
+    unsafe fn update_check(mut hole: NonNull<Hole>, key: LinkKey) {
+        let check = key.checksum(hole.as_ref().size, hole.as_ref().next);
+        hole.as_mut().check = check;
+        #[cfg(feature = "redundant")]
+        {
+            hole.as_mut().size_copy = hole.as_ref().size;
+            Mirror::write(hole);
+        }
+    }
+
+    #[cfg(not(feature = "checksum"))]
+    unsafe fn update_check(_hole: NonNull<Hole>, _key: LinkKey) {}
+}
+
+/// A copy of the header of a hole that the `redundant` feature keeps in the last bytes of
+/// the hole.
+///
+/// If the header of a hole doesn't match its checksum, e.g. because of a bit flip, it is
+/// restored from the mirror, see [`LinkKey::repair`].
+#[cfg(feature = "redundant")]
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Mirror {
+    size: usize,
+    next: Option<NonNull<Hole>>,
+    check: usize,
+}
+
+#[cfg(feature = "redundant")]
+impl Mirror {
+    /// Returns the address of the mirror of the given hole if it had `size` bytes.
+    fn locate(hole: NonNull<Hole>, size: usize) -> *mut Mirror {
+        let end = hole.as_ptr().cast::<u8>().wrapping_add(size);
+        end.wrapping_sub(MIRROR_SIZE).cast()
+    }
+
+    /// Copies the header of the given hole to its end. The dummy hole at the start of the
+    /// list has no room for a mirror.
+    unsafe fn write(hole: NonNull<Hole>) {
+        let header = hole.as_ref();
+        if header.size < size_of::<Hole>() {
+            return;
+        }
+        let mirror = Mirror::locate(hole, header.size);
+        sanitizer::unpoison(mirror.cast(), MIRROR_SIZE);
+        mirror.write(Mirror {
+            size: header.size,
+            next: header.next,
+            check: header.check,
+        });
+    }
+
+    /// Zeroes the mirror of the given hole, e.g. before the hole grows.
+    unsafe fn clear(hole: NonNull<Hole>, key: LinkKey) {
+        let size = hole.as_ref().size;
+        if size >= size_of::<Hole>() {
+            let mirror = Mirror::locate(hole, size).cast::<u8>();
+            mirror.write_bytes(0, MIRROR_SIZE);
+            key.flush(mirror, MIRROR_SIZE);
+            key.fence();
+        }
+    }
+
+    /// Returns whether the mirror of the given hole matches its header.
+    unsafe fn matches(hole: NonNull<Hole>) -> bool {
+        let header = hole.as_ref();
+        let mirror = Mirror::locate(hole, header.size).read();
+        mirror.size == header.size && mirror.next == header.next && mirror.check == header.check
+    }
+}
+
+/// The secret that the links between holes are encoded with.
+///
+/// With the `safe_linking` feature, the address of the next hole is XOR-ed with the key
+/// before it is stored, similar to the safe linking of glibc. Overwriting a link without
+/// knowing the key most likely results in a misaligned pointer, which is detected when the
+/// link is followed. Without the feature, links are stored as they are.
+///
+/// With the `checksum` feature, the key is also mixed into the checksum of every hole and
+/// carries the hooks that are told about corrupted holes. With the `pmem` feature, it
+/// carries the [`PersistHooks`] that every write to the header of a hole is flushed with.
+#[derive(Clone, Copy)]
+pub(crate) struct LinkKey {
+    #[cfg(any(feature = "safe_linking", feature = "checksum"))]
+    secret: usize,
+    #[cfg(all(feature = "checksum", feature = "hooks"))]
+    hooks: Option<&'static dyn HeapHooks>,
+    #[cfg(feature = "pmem")]
+    persist: Option<&'static dyn PersistHooks>,
+}
+
+impl LinkKey {
+    pub(crate) const fn new(key: usize) -> LinkKey {
+        let _ = key;
+        LinkKey {
+            // the lowest bit is always set, so that encoded links are never null and links
+            // written without the key decode to a misaligned pointer
+            #[cfg(any(feature = "safe_linking", feature = "checksum"))]
+            secret: key | 1,
+            #[cfg(all(feature = "checksum", feature = "hooks"))]
+            hooks: None,
+            #[cfg(feature = "pmem")]
+            persist: None,
+        }
+    }
+
+    /// Returns a key with the given secret and the hooks of this key.
+    #[cfg(any(feature = "safe_linking", feature = "checksum"))]
+    pub(crate) fn with_secret(self, key: usize) -> LinkKey {
+        LinkKey {
+            #[cfg(all(feature = "checksum", feature = "hooks"))]
+            hooks: self.hooks,
+            #[cfg(feature = "pmem")]
+            persist: self.persist,
+            ..LinkKey::new(key)
+        }
+    }
+
+    /// Returns the secret of this key, or 0 without the `safe_linking` and `checksum`
+    /// features.
+    #[cfg(any(feature = "safe_linking", feature = "checksum"))]
+    pub(crate) fn secret(self) -> usize {
+        self.secret
+    }
+
+    #[cfg(not(any(feature = "safe_linking", feature = "checksum")))]
+    pub(crate) fn secret(self) -> usize {
+        0
+    }
+
+    /// Returns a key with the secret of this key and the given hooks, which are only used
+    /// with the `checksum` feature.
+    #[cfg(all(feature = "checksum", feature = "hooks"))]
+    pub(crate) fn with_hooks(self, hooks: Option<&'static dyn HeapHooks>) -> LinkKey {
+        LinkKey { hooks, ..self }
+    }
+
+    #[cfg(all(not(feature = "checksum"), feature = "hooks"))]
+    pub(crate) fn with_hooks(self, _hooks: Option<&'static dyn HeapHooks>) -> LinkKey {
+        self
+    }
+
+    /// Returns a key with the secret and the hooks of this key and the given persistence
+    /// hooks.
+    #[cfg(feature = "pmem")]
+    pub(crate) fn with_persist(mut self, persist: Option<&'static dyn PersistHooks>) -> LinkKey {
+        self.persist = persist;
+        self
+    }
+
+    /// Starts writing the `len` bytes at `ptr` back to persistent memory, if persistence
+    /// hooks are installed.
+    #[cfg(feature = "pmem")]
+    fn flush(self, ptr: *mut u8, len: usize) {
+        if let (Some(persist), Some(ptr)) = (self.persist, NonNull::new(ptr)) {
+            persist.flush(ptr, len);
+        }
+    }
+
+    #[cfg(not(feature = "pmem"))]
+    fn flush(self, _ptr: *mut u8, _len: usize) {}
+
+    /// Waits until the flushed bytes have reached persistent memory.
+    #[cfg(feature = "pmem")]
+    fn fence(self) {
+        if let Some(persist) = self.persist {
+            persist.fence();
+        }
+    }
+
+    #[cfg(not(feature = "pmem"))]
+    fn fence(self) {}
+
+    /// Reports the corrupted hole at `addr` to the hooks and panics.
+    #[cfg(any(feature = "safe_linking", feature = "checksum"))]
+    #[cold]
+    fn corrupted(self, addr: *mut u8, what: &str) -> ! {
+        #[cfg(all(feature = "checksum", feature = "hooks"))]
+        if let Some(hooks) = self.hooks {
+            if let Some(addr) = NonNull::new(addr) {
+                hooks.on_corruption(addr);
+            }
+        }
+        let _ = addr;
+        panic!("heap corruption detected: {}", what)
+    }
+
+    /// Returns the checksum of a hole with the given size and encoded link.
+    #[cfg(feature = "checksum")]
+    fn checksum(self, size: usize, link: Option<NonNull<Hole>>) -> usize {
+        let link = link.map_or(0, |link| link.as_ptr() as usize);
+        size ^ link ^ self.secret
+    }
+
+    /// Returns whether the size and the link of the given hole match its checksum, and with
+    /// the `redundant` feature, whether both copies of the size agree.
+    #[cfg(feature = "checksum")]
+    unsafe fn is_intact(self, hole: NonNull<Hole>) -> bool {
+        let hole = hole.as_ref();
+        #[cfg(feature = "redundant")]
+        if hole.size_copy != hole.size {
+            return false;
+        }
+        hole.check == self.checksum(hole.size, hole.next)
+    }
+
+    /// Panics if the size or the link of the given hole don't match its checksum. With the
+    /// `redundant` feature, it tries to [`repair`][LinkKey::repair] the hole first.
+    #[cfg(feature = "checksum")]
+    unsafe fn verify(self, hole: NonNull<Hole>) {
+        if self.is_intact(hole) {
+            return;
+        }
+        #[cfg(feature = "redundant")]
+        if self.repair(hole) {
+            return;
+        }
+        self.corrupted(hole.as_ptr().cast(), "checksum mismatch of a free block");
+    }
+
+    #[cfg(not(feature = "checksum"))]
+    unsafe fn verify(self, _hole: NonNull<Hole>) {}
+
+    /// Like [`verify`][LinkKey::verify], but returns `false` instead of panicking if the
+    /// hole can't be repaired.
+    #[cfg(feature = "checksum")]
+    unsafe fn check(self, hole: NonNull<Hole>) -> bool {
+        #[cfg(feature = "redundant")]
+        if !self.is_intact(hole) {
+            return self.repair(hole);
+        }
+        self.is_intact(hole)
+    }
+
+    #[cfg(not(feature = "checksum"))]
+    unsafe fn check(self, _hole: NonNull<Hole>) -> bool {
+        true
+    }
+
+    /// Restores the header of the given hole, which isn't intact, from its mirror. Returns
+    /// `false` if the hole can't be repaired.
+    ///
+    /// The size is taken by majority vote of its two copies in the header and the size that
+    /// the checksum implies for the link of the header. That locates the mirror, which then
+    /// provides the link if it matches its own checksum. This survives the corruption of
+    /// any single field of the header or the mirror.
+    #[cfg(feature = "redundant")]
+    unsafe fn repair(self, mut hole: NonNull<Hole>) -> bool {
+        let header = hole.as_ref();
+        // the checksum is a plain XOR, so it also turns the checksum and the link into the size
+        let implied = self.checksum(header.check, header.next);
+        let size = if header.size == header.size_copy || header.size == implied {
+            header.size
+        } else if header.size_copy == implied {
+            implied
+        } else {
+            return false;
+        };
+        let end = (hole.as_ptr() as usize).checked_add(size);
+        if size < size_of::<Hole>() || size % align_of::<Hole>() != 0 || end.is_none() {
+            return false;
+        }
+        let mirror = Mirror::locate(hole, size).read();
+        if mirror.size != size || mirror.check != self.checksum(size, mirror.next) {
+            return false;
+        }
+        let header = hole.as_mut();
+        header.size = size;
+        header.size_copy = size;
+        header.next = mirror.next;
+        header.check = mirror.check;
+        Hole::persist(hole, self);
+        self.repaired(hole);
+        true
+    }
+
+    /// Reports the repaired hole to the hooks.
+    #[cfg(feature = "redundant")]
+    fn repaired(self, hole: NonNull<Hole>) {
+        #[cfg(feature = "hooks")]
+        if let Some(hooks) = self.hooks {
+            hooks.on_repair(hole.cast());
+        }
+        let _ = hole;
+    }
+
+    #[cfg(feature = "safe_linking")]
+    fn encode(self, next: NonNull<Hole>) -> NonNull<Hole> {
+        let ptr = next.as_ptr().cast::<u8>();
+        let addr = ptr as usize;
+        // offset the pointer instead of creating it from an integer to keep its provenance
+        let encoded = ptr.wrapping_add((addr ^ self.secret).wrapping_sub(addr));
+        // SAFETY: Hole addresses are aligned, so flipping the lowest bit can't result in 0.
+        unsafe { NonNull::new_unchecked(encoded.cast()) }
+    }
+
+    #[cfg(not(feature = "safe_linking"))]
+    fn encode(self, next: NonNull<Hole>) -> NonNull<Hole> {
+        next
+    }
+
+    #[cfg(feature = "safe_linking")]
+    fn decode(self, link: NonNull<Hole>) -> NonNull<Hole> {
+        let decoded = self.unmask(link);
+        if decoded.is_null() || decoded.align_offset(align_of::<Hole>()) != 0 {
+            self.corrupted(decoded.cast(), "invalid link between free blocks");
+        }
+        // SAFETY: The pointer was checked to be non-null above.
+        unsafe { NonNull::new_unchecked(decoded) }
+    }
+
+    #[cfg(not(feature = "safe_linking"))]
+    fn decode(self, link: NonNull<Hole>) -> NonNull<Hole> {
+        link
+    }
+
+    /// Returns the address that the given link points to, without checking it.
+    #[cfg(feature = "safe_linking")]
+    fn unmask(self, link: NonNull<Hole>) -> *mut Hole {
+        let ptr = link.as_ptr().cast::<u8>();
+        let addr = ptr as usize;
+        ptr.wrapping_add((addr ^ self.secret).wrapping_sub(addr))
+            .cast()
+    }
+
+    #[cfg(not(feature = "safe_linking"))]
+    fn unmask(self, link: NonNull<Hole>) -> *mut Hole {
+        link.as_ptr()
+    }
 }
 
 /// Basic information about a hole.
@@ -38,10 +541,11 @@ struct HoleInfo {
 impl Cursor {
     fn next(mut self) -> Option<Self> {
         unsafe {
-            self.hole.as_mut().next.map(|nhole| Cursor {
+            self.hole.as_mut().next(self.key).map(|nhole| Cursor {
                 prev: self.hole,
                 hole: nhole,
                 top: self.top,
+                key: self.key,
             })
         }
     }
@@ -54,11 +558,41 @@ impl Cursor {
         unsafe { self.prev.as_ref() }
     }
 
-    // On success, it returns the new allocation, and the linked list has been updated
-    // to accomodate any new holes and allocation. On error, it returns the cursor
-    // unmodified, and has made no changes to the linked list of holes.
-    fn split_current(self, required_layout: Layout) -> Result<(*mut u8, usize), Self> {
+    /// Commits the pages of the current hole that an allocation of `layout` might touch,
+    /// including the header of the hole that is left behind it.
+    fn commit_for(&self, pages: Option<Pages>, layout: Layout) {
+        let pages = match pages {
+            Some(pages) => pages,
+            None => return,
+        };
+        let size = self.current().size;
+        if size < layout.size() {
+            return;
+        }
+        // the front padding is smaller than two alignment steps
+        let len = layout
+            .size()
+            .saturating_add(2 * layout.align())
+            .saturating_add(size_of::<Hole>())
+            .min(size);
+        unsafe { pages.commit(self.hole.as_ptr().cast(), len) };
+    }
+
+    // The allocation is placed so that its address plus `offset` is aligned to the alignment
+    // of the layout.
+    //
+    // On success, it returns the new allocation and the size of the padding in front of and
+    // behind it that was too small for a hole, and the linked list has been updated to accomodate any new holes
+    // and allocation. On error, it returns the cursor unmodified, and has made no changes to
+    // the linked list of holes.
+    fn split_current(
+        self,
+        required_layout: Layout,
+        offset: usize,
+    ) -> Result<(*mut u8, usize, usize), Self> {
         let front_padding;
+        let stranded;
+        let tail;
         let alloc_ptr;
         let alloc_size;
         let back_padding;
@@ -85,64 +619,82 @@ impl Cursor {
             // First, figure out if front padding is necessary. This would be necessary if the new
             // allocation has a larger alignment requirement than the current hole, and we didn't get
             // lucky that the current position was well-aligned enough for the new item.
-            let aligned_addr = if hole_addr_u8 == align_up(hole_addr_u8, required_align) {
-                // hole has already the required alignment, no front padding is needed.
-                front_padding = None;
-                hole_addr_u8
-            } else {
-                // Unfortunately, we did not get lucky. Instead: Push the "starting location" FORWARD the size
-                // of a hole node, to guarantee there is at least enough room for the hole header, and
-                // potentially additional space.
-                let new_start = hole_addr_u8.wrapping_add(HoleList::min_size());
-
-                let aligned_addr = align_up(new_start, required_align);
-                front_padding = Some(HoleInfo {
-                    // Our new front padding will exist at the same location as the previous hole,
-                    // it will just have a smaller size after we have chopped off the "tail" for
-                    // the allocation.
-                    addr: hole_addr_u8,
-                    size: (aligned_addr as usize) - (hole_addr_u8 as usize),
-                });
-                aligned_addr
+            //
+            // All sizes are computed as offsets from the start of the hole with checked
+            // arithmetic, so that huge alignments or sizes can't wrap around the address space.
+            debug_assert_eq!(offset % align_of::<Hole>(), 0);
+            let front_padding_size = match padding_before(hole_addr_u8, offset, required_align) {
+                Some(size) => size,
+                None => return Err(self),
             };
 
             // Okay, now that we found space, we need to see if the decisions we just made
             // ACTUALLY fit in the previous hole space
-            let allocation_end = aligned_addr.wrapping_add(required_size);
-            let hole_end = hole_addr_u8.wrapping_add(hole_size);
-
-            if allocation_end > hole_end {
+            let back_padding_size = match hole_size
+                .checked_sub(front_padding_size)
+                .and_then(|rest| rest.checked_sub(required_size))
+            {
+                Some(size) => size,
                 // hole is too small
-                return Err(self);
-            }
+                None => return Err(self),
+            };
 
             // Yes! We have successfully placed our allocation as well.
+            // safety: the allocation lies within the hole, as checked above
+            let aligned_addr = unsafe { hole_addr_u8.add(front_padding_size) };
             alloc_ptr = aligned_addr;
             alloc_size = required_size;
 
+            front_padding = if front_padding_size < size_of::<Hole>() {
+                // Either no front padding is needed, or it is too small for a hole. In the
+                // latter case the padding is left unused until this allocation or the one in
+                // front of it is freed, which merges it into the new hole again.
+                stranded = front_padding_size;
+                None
+            } else {
+                stranded = 0;
+                Some(HoleInfo {
+                    // Our new front padding will exist at the same location as the previous hole,
+                    // it will just have a smaller size after we have chopped off the "tail" for
+                    // the allocation.
+                    addr: hole_addr_u8,
+                    size: front_padding_size,
+                })
+            };
+
             // Okay, time to move onto the back padding.
-            let back_padding_size = hole_end as usize - allocation_end as usize;
-            back_padding = if back_padding_size == 0 {
+            let hole_end = aligned_addr.wrapping_add(required_size + back_padding_size);
+            back_padding = if back_padding_size == 0
+                || back_padding_size < size_of::<Hole>()
+                    && hole_end.align_offset(size_of::<Hole>()) == 0
+            {
+                // The allocation fits exactly or the rest is too small for a hole, so the
+                // whole hole is handed out. Like stranded front padding, the rest is unused
+                // until this allocation or the one behind it is freed. Because it ends at an
+                // address aligned to the size of a hole, it never directly precedes
+                // stranded front padding, see `padding_before`.
+                tail = back_padding_size;
                 None
             } else {
+                tail = 0;
                 // NOTE: Because we always use `HoleList::align_layout`, the size of
                 // the new allocation is always "rounded up" to cover any partial gaps that
                 // would have occurred. For this reason, we DON'T need to "round up"
                 // to account for an unaligned hole spot.
-                let hole_layout = Layout::new::<Hole>();
-                let back_padding_start = align_up(allocation_end, hole_layout.align());
-                let back_padding_end = back_padding_start.wrapping_add(hole_layout.size());
-
+                //
                 // Will the proposed new back padding actually fit in the old hole slot?
-                if back_padding_end <= hole_end {
+                if back_padding_size >= size_of::<Hole>() {
                     // Yes, it does! Place a back padding node
                     Some(HoleInfo {
-                        addr: back_padding_start,
+                        // safety: the back padding lies within the hole
+                        addr: unsafe { aligned_addr.add(required_size) },
                         size: back_padding_size,
                     })
                 } else {
-                    // No, it does not. We don't want to leak any heap bytes, so we
-                    // consider this hole unsuitable for the requested allocation.
+                    // No, it does not. The rest could directly precede the stranded front
+                    // padding of the block behind the hole, and the two together could be
+                    // as large as a hole. Freeing the neighbours would then no longer merge
+                    // them, so we consider this hole unsuitable for the requested allocation.
                     return Err(self);
                 }
             };
@@ -152,24 +704,44 @@ impl Cursor {
         // This is where we actually perform surgery on the linked list.
         ////////////////////////////////////////////////////////////////////////////
         let Cursor {
-            mut prev, mut hole, ..
+            prev, hole, key, ..
         } = self;
         // Remove the current location from the previous node
         unsafe {
-            prev.as_mut().next = None;
+            Hole::set_next(prev, None, key);
         }
         // Take the next node out of our current node
-        let maybe_next_addr: Option<NonNull<Hole>> = unsafe { hole.as_mut().next.take() };
+        let maybe_next_addr: Option<NonNull<Hole>> = unsafe { Hole::take_next(hole, key) };
 
         // As of now, the old `Hole` is no more. We are about to replace it with one or more of
         // the front padding, the allocation, and the back padding.
+        if stranded != 0 {
+            // The padding is smaller than a hole and a multiple of the word size. Clear it, so
+            // that it doesn't look like the size of a hole or a block header, and so that it
+            // is zero once it becomes part of a hole again.
+            debug_assert_eq!(stranded % size_of::<usize>(), 0);
+            let padding = hole.as_ptr().cast::<u8>();
+            unsafe {
+                sanitizer::unpoison(padding, stranded);
+                padding.write_bytes(0, stranded);
+            }
+        }
+        if tail != 0 {
+            // the same goes for the rest behind the allocation
+            debug_assert_eq!(tail % size_of::<usize>(), 0);
+            let rest = alloc_ptr.wrapping_add(alloc_size);
+            unsafe {
+                sanitizer::unpoison(rest, tail);
+                rest.write_bytes(0, tail);
+            }
+        }
 
         match (front_padding, back_padding) {
             (None, None) => {
                 // No padding at all, how lucky! We still need to connect the PREVIOUS node
                 // to the NEXT node, if there was one
                 unsafe {
-                    prev.as_mut().next = maybe_next_addr;
+                    Hole::set_next(prev, maybe_next_addr, key);
                 }
             }
             (None, Some(singlepad)) | (Some(singlepad), None) => unsafe {
@@ -177,52 +749,44 @@ impl Cursor {
                 //
                 // Replace the old node with the new single node. We need to stitch the new node
                 // into the linked list. Start by writing the padding into the proper location
-                let singlepad_ptr = singlepad.addr.cast::<Hole>();
-                singlepad_ptr.write(Hole {
-                    size: singlepad.size,
-                    // If the old hole had a next pointer, the single padding now takes
-                    // "ownership" of that link
-                    next: maybe_next_addr,
-                });
+                let singlepad_ptr = make_hole(singlepad.addr, singlepad.size, key);
+                // If the old hole had a next pointer, the single padding now takes
+                // "ownership" of that link
+                Hole::set_next(singlepad_ptr, maybe_next_addr, key);
 
                 // Then connect the OLD previous to the NEW single padding
-                prev.as_mut().next = Some(NonNull::new_unchecked(singlepad_ptr));
+                Hole::set_next(prev, Some(singlepad_ptr), key);
             },
             (Some(frontpad), Some(backpad)) => unsafe {
                 // We have front padding AND back padding.
                 //
                 // We need to stich them together as two nodes where there used to
                 // only be one. Start with the back padding.
-                let backpad_ptr = backpad.addr.cast::<Hole>();
-                backpad_ptr.write(Hole {
-                    size: backpad.size,
-                    // If the old hole had a next pointer, the BACK padding now takes
-                    // "ownership" of that link
-                    next: maybe_next_addr,
-                });
+                let backpad_ptr = make_hole(backpad.addr, backpad.size, key);
+                // If the old hole had a next pointer, the BACK padding now takes
+                // "ownership" of that link
+                Hole::set_next(backpad_ptr, maybe_next_addr, key);
 
                 // Now we emplace the front padding, and link it to both the back padding,
                 // and the old previous
-                let frontpad_ptr = frontpad.addr.cast::<Hole>();
-                frontpad_ptr.write(Hole {
-                    size: frontpad.size,
-                    // We now connect the FRONT padding to the BACK padding
-                    next: Some(NonNull::new_unchecked(backpad_ptr)),
-                });
+                let frontpad_ptr = make_hole(frontpad.addr, frontpad.size, key);
+                // We now connect the FRONT padding to the BACK padding
+                Hole::set_next(frontpad_ptr, Some(backpad_ptr), key);
 
                 // Then connect the OLD previous to the NEW FRONT padding
-                prev.as_mut().next = Some(NonNull::new_unchecked(frontpad_ptr));
+                Hole::set_next(prev, Some(frontpad_ptr), key);
             },
         }
 
         // Well that went swimmingly! Hand off the allocation, with surgery performed successfully!
-        Ok((alloc_ptr, alloc_size))
+        Ok((alloc_ptr, alloc_size, stranded + tail))
     }
 }
 
 // See if we can extend this hole towards the end of the allocation region
 // If so: increase the size of the node. If no: keep the node as-is
-fn check_merge_top(mut node: NonNull<Hole>, top: *mut u8) {
+// Returns the number of bytes that were added to the node.
+fn check_merge_top(node: NonNull<Hole>, top: *mut u8, key: LinkKey) -> usize {
     let node_u8 = node.as_ptr().cast::<u8>();
     let node_sz = unsafe { node.as_ref().size };
 
@@ -233,25 +797,36 @@ fn check_merge_top(mut node: NonNull<Hole>, top: *mut u8) {
         let next_hole_end = align_up(end, hole_layout.align()).wrapping_add(hole_layout.size());
 
         if next_hole_end > top {
-            let offset = (top as usize) - (end as usize);
             unsafe {
-                node.as_mut().size += offset;
+                let offset = top.offset_from(end) as usize;
+                let size = node.as_ref().size + offset;
+                Hole::set_size(node, size, key);
+                return offset;
             }
         }
     }
+    0
 }
 
 // See if we can scoot this hole back to the bottom of the allocation region
 // If so: create and return the new hole. If not: return the existing hole
-fn check_merge_bottom(node: NonNull<Hole>, bottom: *mut u8) -> NonNull<Hole> {
-    debug_assert_eq!(bottom as usize % align_of::<Hole>(), 0);
+// Also returns the number of bytes that were added in front of the node.
+fn check_merge_bottom(
+    node: NonNull<Hole>,
+    bottom: *mut u8,
+    key: LinkKey,
+) -> (NonNull<Hole>, usize) {
+    debug_assert_eq!(bottom.align_offset(align_of::<Hole>()), 0);
 
     if bottom.wrapping_add(core::mem::size_of::<Hole>()) > node.as_ptr().cast::<u8>() {
-        let offset = (node.as_ptr() as usize) - (bottom as usize);
+        let offset = unsafe { node.as_ptr().cast::<u8>().offset_from(bottom) as usize };
         let size = unsafe { node.as_ref() }.size + offset;
-        unsafe { make_hole(bottom, size) }
+        unsafe {
+            sanitizer::poison(node.as_ptr().cast(), size_of::<Hole>());
+            (make_hole(bottom, size, key), offset)
+        }
     } else {
-        node
+        (node, 0)
     }
 }
 
@@ -259,22 +834,53 @@ impl HoleList {
     /// Creates an empty `HoleList`.
     pub const fn empty() -> HoleList {
         HoleList {
-            first: Hole {
-                size: 0,
-                next: None,
-            },
+            first: Hole::EMPTY,
             bottom: null_mut(),
             top: null_mut(),
             pending_extend: 0,
+            zeroed_from: null_mut(),
+            key: LinkKey::new(0),
+            pages: None,
+            last_release: None,
+            scan: None,
+            anchor: None,
+        }
+    }
+
+    /// Returns the dummy hole at the start of the list.
+    fn head(&self) -> &Hole {
+        match self.anchor {
+            // SAFETY: The anchor lives in the heap memory, which the list owns.
+            Some(anchor) => unsafe { &(*anchor.as_ptr()).head },
+            None => &self.first,
         }
     }
 
+    /// Returns a pointer to the dummy hole at the start of the list, to change its link.
+    fn head_mut(&mut self) -> NonNull<Hole> {
+        match self.anchor {
+            // SAFETY: The anchor lives in the heap memory, which the list owns.
+            Some(anchor) => unsafe {
+                NonNull::new_unchecked(core::ptr::addr_of_mut!((*anchor.as_ptr()).head))
+            },
+            None => NonNull::from(&mut self.first),
+        }
+    }
+
+    /// Returns the first hole of the list.
+    pub(crate) fn first(&self) -> Option<NonNull<Hole>> {
+        self.head().next(self.key)
+    }
+
     pub(crate) fn cursor(&mut self) -> Option<Cursor> {
-        if let Some(hole) = self.first.next {
+        // the cursor might be used to change any hole
+        self.last_release = None;
+        if let Some(hole) = self.first() {
             Some(Cursor {
                 hole,
-                prev: NonNull::new(&mut self.first)?,
+                prev: self.head_mut(),
                 top: self.top,
+                key: self.key,
             })
         } else {
             None
@@ -314,12 +920,13 @@ impl HoleList {
     /// The given `hole_size` must be large enough to store the required
     /// metadata, otherwise this function will panic. Depending on the
     /// alignment of the `hole_addr` pointer, the minimum size is between
-    /// `2 * size_of::<usize>` and `3 * size_of::<usize>`.
+    /// [`min_allocation_size`][crate::Heap::min_allocation_size] and one
+    /// [`allocation_granularity`][crate::Heap::allocation_granularity] more.
     ///
-    /// The usable size for allocations will be truncated to the nearest
-    /// alignment of `align_of::<usize>`. Any extra bytes left at the end
-    /// will be reclaimed once sufficient additional space is given to
-    /// [`extend`][crate::Heap::extend].
+    /// The usable size for allocations will be truncated to a multiple of
+    /// [`allocation_granularity`][crate::Heap::allocation_granularity]. Any extra bytes left
+    /// at the end will be reclaimed once sufficient additional space is
+    /// given to [`extend`][crate::Heap::extend].
     ///
     /// # Safety
     ///
@@ -331,29 +938,258 @@ impl HoleList {
         assert!(hole_size >= size_of::<Hole>());
 
         let aligned_hole_addr = align_up(hole_addr, align_of::<Hole>());
-        let requested_hole_size = hole_size - ((aligned_hole_addr as usize) - (hole_addr as usize));
+        let requested_hole_size = hole_size - aligned_hole_addr.offset_from(hole_addr) as usize;
         let aligned_hole_size = align_down_size(requested_hole_size, align_of::<Hole>());
         assert!(aligned_hole_size >= size_of::<Hole>());
 
-        let ptr = aligned_hole_addr as *mut Hole;
-        ptr.write(Hole {
-            size: aligned_hole_size,
-            next: None,
-        });
+        sanitizer::poison(aligned_hole_addr, requested_hole_size);
+        let key = LinkKey::new(0);
+        let ptr = make_hole(aligned_hole_addr, aligned_hole_size, key);
 
         assert_eq!(
             hole_addr.wrapping_add(hole_size),
             aligned_hole_addr.wrapping_add(requested_hole_size)
         );
 
-        HoleList {
-            first: Hole {
-                size: 0,
-                next: Some(NonNull::new_unchecked(ptr)),
-            },
+        let mut list = HoleList {
+            first: Hole::EMPTY,
             bottom: aligned_hole_addr,
             top: aligned_hole_addr.wrapping_add(aligned_hole_size),
             pending_extend: (requested_hole_size - aligned_hole_size) as u8,
+            zeroed_from: hole_addr.wrapping_add(hole_size),
+            key,
+            pages: None,
+            last_release: None,
+            scan: None,
+            anchor: None,
+        };
+        Hole::set_next(NonNull::from(&mut list.first), Some(ptr), list.key);
+        list
+    }
+
+    /// Creates a persistent `HoleList` that keeps its dummy hole in an [`Anchor`] at the
+    /// start of the given memory and manages the rest of it like [`new`][HoleList::new].
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`new`][HoleList::new] apply.
+    pub(crate) unsafe fn new_persistent(hole_addr: *mut u8, hole_size: usize) -> HoleList {
+        let anchor = align_up(hole_addr, align_of::<Anchor>()).cast::<Anchor>();
+        let rest = anchor.wrapping_add(1).cast::<u8>();
+        let offset = rest as usize - hole_addr as usize;
+        assert!(hole_size >= offset, "the heap is too small for the anchor");
+        let mut list = HoleList::new(rest, hole_size - offset);
+
+        let first = list.first();
+        sanitizer::unpoison(anchor.cast(), size_of::<Anchor>());
+        anchor.write(Anchor {
+            magic: ANCHOR_MAGIC,
+            base: anchor as usize,
+            top: list.top,
+            pending_extend: list.pending_extend as usize,
+            head: Hole::EMPTY,
+        });
+        list.anchor = Some(NonNull::new_unchecked(anchor));
+        Hole::set_next(list.head_mut(), first, list.key);
+        list
+    }
+
+    /// Creates a `HoleList` for the heap memory from `bottom` to `bottom + size` whose
+    /// holes are already in place, starting with `first`.
+    ///
+    /// # Safety
+    ///
+    /// The holes must form a valid list whose links are encoded with `key`.
+    pub(crate) unsafe fn from_raw_parts(
+        bottom: *mut u8,
+        size: usize,
+        pending_extend: usize,
+        first: Option<NonNull<Hole>>,
+        key: LinkKey,
+    ) -> HoleList {
+        let top = bottom.add(size);
+        let mut list = HoleList {
+            first: Hole::EMPTY,
+            bottom,
+            top,
+            pending_extend: pending_extend as u8,
+            // nothing is known about the contents of the free memory
+            zeroed_from: top.add(pending_extend),
+            key,
+            pages: None,
+            last_release: None,
+            scan: None,
+            anchor: None,
+        };
+        Hole::set_next(NonNull::from(&mut list.first), first, key);
+        list
+    }
+
+    /// Resumes using the list of a persistent heap that was created by
+    /// [`new_persistent`][HoleList::new_persistent] with the same `hole_addr`, e.g. before a
+    /// warm reboot. The links must be encoded with `key`.
+    ///
+    /// Returns the list and its number of free bytes, or an error if the memory doesn't
+    /// start with an intact anchor or the holes are misaligned, out of bounds, unsorted, or
+    /// don't match their checksums. The holes are only read.
+    ///
+    /// # Safety
+    ///
+    /// The `[hole_addr, hole_addr + hole_size)` range must be valid for reads and writes
+    /// and must not be used for anything else.
+    pub(crate) unsafe fn adopt(
+        hole_addr: *mut u8,
+        hole_size: usize,
+        key: LinkKey,
+    ) -> Result<(HoleList, usize), AdoptError> {
+        let end = hole_addr as usize + hole_size;
+        let anchor = align_up(hole_addr, align_of::<Anchor>()).cast::<Anchor>();
+        let bottom = anchor.wrapping_add(1).cast::<u8>();
+        if bottom as usize > end || (*anchor).magic != ANCHOR_MAGIC {
+            return Err(AdoptError::NotFound);
+        }
+        let Anchor {
+            base,
+            top,
+            pending_extend,
+            ..
+        } = anchor.read();
+        if base != anchor as usize
+            || top < bottom
+            || top as usize > end
+            || top.align_offset(align_of::<Hole>()) != 0
+            || pending_extend >= Self::min_size()
+            || pending_extend > end - top as usize
+        {
+            return Err(AdoptError::Mismatch);
+        }
+
+        // the holes must be sorted by address, which also ensures that the walk ends
+        let mut free = 0;
+        let mut prev = core::ptr::addr_of_mut!((*anchor).head);
+        let mut prev_end = bottom;
+        #[cfg(feature = "checksum")]
+        if !key.is_intact(NonNull::new_unchecked(prev)) {
+            return Err(AdoptError::Corrupted {
+                addr: prev as usize,
+            });
+        }
+        while let Some(link) = (*prev).next {
+            let hole = key.unmask(link);
+            let addr = hole.cast::<u8>();
+            if addr < prev_end
+                || addr > top
+                || addr.align_offset(align_of::<Hole>()) != 0
+                || (top as usize - addr as usize) < Self::min_size()
+            {
+                return Err(AdoptError::Corrupted {
+                    addr: prev as usize,
+                });
+            }
+            #[cfg(feature = "checksum")]
+            let intact = key.is_intact(NonNull::new_unchecked(hole));
+            #[cfg(not(feature = "checksum"))]
+            let intact = true;
+            let size = (*hole).size;
+            if !intact
+                || size < Self::min_size()
+                || size % align_of::<Hole>() != 0
+                || size > top as usize - addr as usize
+            {
+                return Err(AdoptError::Corrupted {
+                    addr: hole as usize,
+                });
+            }
+            free += size;
+            prev = hole;
+            prev_end = addr.wrapping_add(size);
+        }
+
+        let list = HoleList {
+            first: Hole::EMPTY,
+            bottom,
+            top,
+            pending_extend: pending_extend as u8,
+            // nothing is known about the contents of the free memory
+            zeroed_from: top.wrapping_add(pending_extend),
+            key,
+            pages: None,
+            last_release: None,
+            scan: None,
+            anchor: Some(NonNull::new_unchecked(anchor)),
+        };
+        Ok((list, free))
+    }
+
+    /// Moves the memory of the list, including the anchor of a persistent list, to
+    /// `new_bottom` and shifts the links between the holes and the bounds by the distance.
+    ///
+    /// # Safety
+    ///
+    /// The list must not be empty and `new_bottom` must be aligned for a `Hole`. The memory
+    /// at the new location must be valid for writes and unused, except that it may overlap
+    /// the old location.
+    pub(crate) unsafe fn relocate(&mut self, new_bottom: *mut u8) {
+        self.last_release = None;
+        self.scan = None;
+        let old_bottom = self.bottom;
+        // derive the new addresses from `new_bottom` to give them its provenance
+        let moved = |ptr: *mut u8| {
+            if ptr >= old_bottom {
+                new_bottom.wrapping_add(ptr as usize - old_bottom as usize)
+            } else {
+                new_bottom.wrapping_sub(old_bottom as usize - ptr as usize)
+            }
+        };
+
+        // the links are rewritten in the old location, which is copied afterwards
+        let mut prev = self.head_mut();
+        while let Some(hole) = prev.as_ref().next(self.key) {
+            sanitizer::unpoison(hole.as_ptr().cast(), hole.as_ref().size);
+            let target = NonNull::new_unchecked(moved(hole.as_ptr().cast()).cast());
+            Hole::set_next(prev, Some(target), self.key);
+            prev = hole;
+        }
+        let pending = self.pending_extend as usize;
+        sanitizer::unpoison(self.top, pending);
+
+        let start = self
+            .anchor
+            .map_or(old_bottom, |anchor| anchor.as_ptr().cast());
+        let len = self.top as usize + pending - start as usize;
+        core::ptr::copy(start, moved(start), len);
+        self.key.flush(moved(start), len);
+        self.key.fence();
+
+        self.bottom = new_bottom;
+        self.top = moved(self.top);
+        self.zeroed_from = moved(self.zeroed_from);
+        if let Some(anchor) = self.anchor {
+            let anchor = moved(anchor.as_ptr().cast()).cast::<Anchor>();
+            (*anchor).base = anchor as usize;
+            self.anchor = Some(NonNull::new_unchecked(anchor));
+        }
+        self.update_anchor();
+
+        for (addr, size) in self.holes() {
+            let header = size_of::<Hole>();
+            sanitizer::poison(
+                addr.wrapping_add(header),
+                size.saturating_sub(header + MIRROR_SIZE),
+            );
+        }
+        sanitizer::poison(self.top, pending);
+    }
+
+    /// Records the bounds of a persistent heap in its anchor after they changed.
+    fn update_anchor(&mut self) {
+        if let Some(mut anchor) = self.anchor {
+            // SAFETY: The anchor lives in the heap memory, which the list owns.
+            let record = unsafe { anchor.as_mut() };
+            record.top = self.top;
+            record.pending_extend = self.pending_extend as usize;
+            self.key.flush(anchor.as_ptr().cast(), size_of::<Anchor>());
+            self.key.fence();
         }
     }
 
@@ -370,7 +1206,8 @@ impl HoleList {
         if size < Self::min_size() {
             size = Self::min_size();
         }
-        let size = align_up_size(size, mem::align_of::<Hole>());
+        // an overflowing size is rejected by `Layout::from_size_align`
+        let size = checked_align_up_size(size, mem::align_of::<Hole>()).unwrap_or(usize::MAX);
         Layout::from_size_align(size, layout.align())
     }
 
@@ -384,23 +1221,333 @@ impl HoleList {
     ///
     /// This function uses the “first fit” strategy, so it uses the first hole that is big
     /// enough. Thus the runtime is in O(n) but it should be reasonably fast for small allocations.
-    //
-    // NOTE: We could probably replace this with an `Option` instead of a `Result` in a later
-    // release to remove this clippy warning
-    #[allow(clippy::result_unit_err)]
-    pub fn allocate_first_fit(&mut self, layout: Layout) -> Result<(NonNull<u8>, Layout), ()> {
-        let aligned_layout = Self::align_layout(layout).map_err(|_| ())?;
-        let mut cursor = self.cursor().ok_or(())?;
+    pub fn allocate_first_fit(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, Layout), AllocError> {
+        self.allocate_first_fit_below(layout, 0, self.top)
+            .map(|(ptr, layout, _)| (ptr, layout))
+    }
+
+    /// Like [`allocate_first_fit`][HoleList::allocate_first_fit], but only considers holes
+    /// that start below `limit`, and places the block so that its address plus `offset` is
+    /// aligned to `layout.align()`. The `offset` must be a multiple of the alignment of a
+    /// [`Hole`].
+    ///
+    /// Since holes never overlap allocations, an allocation returned by this function lies
+    /// completely below any allocation that starts at or after `limit`. This is used to move
+    /// existing allocations towards the bottom of the heap.
+    ///
+    /// Also returns the size of the padding in front of the block that was too small for a
+    /// hole and is thus no longer free until the block is deallocated.
+    pub(crate) fn allocate_first_fit_below(
+        &mut self,
+        layout: Layout,
+        offset: usize,
+        limit: *mut u8,
+    ) -> Result<(NonNull<u8>, Layout, usize), AllocError> {
+        self.allocate_first_fit_after(&mut None, layout, offset, limit, usize::MAX)
+    }
+
+    /// Like [`allocate_first_fit_below`][HoleList::allocate_first_fit_below], but starts the
+    /// search behind the hole `start` if it is set, and sets it to the hole in front of the
+    /// one that the block was taken from.
+    ///
+    /// Holes in front of that one are too small for the block, so they are also too small
+    /// for another block with the same layout. This makes it possible to allocate many such
+    /// blocks with a single pass over the list.
+    ///
+    /// Fails with [`AllocError::SearchLimit`] instead of examining more than `max_holes`
+    /// holes.
+    pub(crate) fn allocate_first_fit_after(
+        &mut self,
+        start: &mut Option<NonNull<Hole>>,
+        layout: Layout,
+        offset: usize,
+        limit: *mut u8,
+        max_holes: usize,
+    ) -> Result<(NonNull<u8>, Layout, usize), AllocError> {
+        let aligned_layout = Self::align_layout(layout).map_err(|_| AllocError::InvalidLayout)?;
+        self.last_release = None;
+        let cursor = match *start {
+            Some(prev) => unsafe { prev.as_ref() }.next(self.key).map(|hole| Cursor {
+                prev,
+                hole,
+                top: self.top,
+                key: self.key,
+            }),
+            None => self.cursor(),
+        };
+        let mut cursor = match cursor {
+            Some(cursor) => cursor,
+            None => return Err(self.alloc_error(aligned_layout.size())),
+        };
+
+        let mut examined = 0;
+        loop {
+            if cursor.hole.as_ptr().cast::<u8>() >= limit {
+                break;
+            }
+            if examined == max_holes {
+                return Err(AllocError::SearchLimit);
+            }
+            examined += 1;
+            let prev = cursor.prev;
+            let hole = cursor.hole;
+            cursor.commit_for(self.pages, aligned_layout);
+            match cursor.split_current(aligned_layout, offset) {
+                Ok((ptr, _len, stranded)) => {
+                    self.scan_removed(hole, prev);
+                    if let Some(ptr) = NonNull::new(ptr) {
+                        // splitting the hole doesn't touch the hole in front of it, but a
+                        // pointer to the head doesn't outlive the borrow of the list
+                        let head = self.head_mut();
+                        *start = Some(prev).filter(|&prev| prev != head);
+                        self.mark_used(ptr, aligned_layout.size());
+                        return Ok((ptr, aligned_layout, stranded));
+                    }
+                    break;
+                }
+                Err(curs) => match curs.next() {
+                    Some(next) => cursor = next,
+                    None => break,
+                },
+            }
+        }
+        Err(self.alloc_error(aligned_layout.size()))
+    }
+
+    /// Returns the size of the largest block with the given alignment that can currently be
+    /// allocated, or `None` if no hole can hold such a block.
+    pub(crate) fn largest_block(&self, align: usize) -> Option<usize> {
+        self.holes()
+            .filter_map(|(addr, size)| available_size(addr, size, align))
+            .max()
+    }
+
+    /// Like [`allocate_first_fit`][HoleList::allocate_first_fit], but enlarges the allocation
+    /// to up to `max` bytes if the chosen hole has enough space.
+    ///
+    /// Returns the pointer and the layout of the allocated block, whose size lies between the
+    /// aligned size of `layout` and `max`, unless `max` is smaller than the aligned size, and
+    /// the size of the padding in front of it, like
+    /// [`allocate_first_fit_below`][HoleList::allocate_first_fit_below].
+    pub(crate) fn allocate_first_fit_within(
+        &mut self,
+        layout: Layout,
+        max: usize,
+    ) -> Result<(NonNull<u8>, Layout, usize), AllocError> {
+        let aligned_layout = Self::align_layout(layout).map_err(|_| AllocError::InvalidLayout)?;
+        let min = aligned_layout.size();
+        let max = align_down_size(max, align_of::<Hole>()).max(min);
+        let mut cursor = match self.cursor() {
+            Some(cursor) => cursor,
+            None => return Err(AllocError::OutOfMemory),
+        };
+
+        loop {
+            let hole_addr = cursor.hole.as_ptr().cast::<u8>();
+            let hole_size = cursor.current().size;
+            let size = available_size(hole_addr, hole_size, aligned_layout.align())
+                .filter(|&available| available >= min)
+                .and_then(|available| {
+                    let size = available.min(max);
+                    let rest = available - size;
+                    if rest == 0 || rest >= Self::min_size() {
+                        Some(size)
+                    } else {
+                        // the rest is too small for a hole, so leave a larger rest behind
+                        Some(size - (Self::min_size() - rest)).filter(|&size| size >= min)
+                    }
+                });
+            if let Some(size) = size {
+                let layout = Layout::from_size_align(size, aligned_layout.align())
+                    .map_err(|_| AllocError::InvalidLayout)?;
+                // the block fits into the hole and leaves no rest that is too small for a
+                // hole behind, so splitting can't fail
+                let (prev, hole) = (cursor.prev, cursor.hole);
+                cursor.commit_for(self.pages, layout);
+                if let Ok((ptr, _, stranded)) = cursor.split_current(layout, 0) {
+                    self.scan_removed(hole, prev);
+                    if let Some(ptr) = NonNull::new(ptr) {
+                        self.mark_used(ptr, size);
+                        return Ok((ptr, layout, stranded));
+                    }
+                }
+                break;
+            }
+            match cursor.next() {
+                Some(next) => cursor = next,
+                None => break,
+            }
+        }
+        Err(self.alloc_error(min))
+    }
 
+    /// Allocates the block of `size` bytes at `addr`, which must be aligned for a [`Hole`].
+    ///
+    /// Returns `false` if the block doesn't lie within a single hole, or if the rest of the
+    /// hole in front of or behind the block would be too small for a hole.
+    pub(crate) fn allocate_at(&mut self, addr: *mut u8, size: usize) -> bool {
+        let end = addr.wrapping_add(size);
+        let mut cursor = match self.cursor() {
+            Some(cursor) => cursor,
+            None => return false,
+        };
         loop {
-            match cursor.split_current(aligned_layout) {
-                Ok((ptr, _len)) => {
-                    return Ok((NonNull::new(ptr).ok_or(())?, aligned_layout));
+            let hole_addr = cursor.hole.as_ptr().cast::<u8>();
+            if hole_addr > addr {
+                return false;
+            }
+            if hole_addr.wrapping_add(cursor.current().size) >= end {
+                break;
+            }
+            cursor = match cursor.next() {
+                Some(next) => next,
+                None => return false,
+            };
+        }
+
+        let hole_addr = cursor.hole.as_ptr().cast::<u8>();
+        let front = addr as usize - hole_addr as usize;
+        let back = hole_addr as usize + cursor.current().size - end as usize;
+        let fits = |rest: usize| rest == 0 || rest >= Self::min_size();
+        if !fits(front) || !fits(back) {
+            return false;
+        }
+
+        unsafe {
+            let Cursor { prev, hole, .. } = cursor;
+            let mut next = Hole::take_next(hole, self.key);
+            if back != 0 {
+                if let Some(pages) = self.pages {
+                    pages.commit(end, size_of::<Hole>());
                 }
-                Err(curs) => {
-                    cursor = curs.next().ok_or(())?;
+                let back_hole = make_hole(end, back, self.key);
+                Hole::set_next(back_hole, next, self.key);
+                next = Some(back_hole);
+            }
+            if front != 0 {
+                #[cfg(feature = "redundant")]
+                if let Some(pages) = self.pages {
+                    // the mirror of the front part might lie in a decommitted page
+                    pages.commit(addr.wrapping_sub(MIRROR_SIZE), MIRROR_SIZE);
                 }
+                Hole::set_size(hole, front, self.key);
+                Hole::set_next(hole, next, self.key);
+            } else {
+                Hole::set_next(prev, next, self.key);
             }
+            self.scan_removed(hole, prev);
+            self.mark_used(NonNull::new_unchecked(addr), size);
+        }
+        true
+    }
+
+    /// Allocates a block for `layout` as close to `target` as possible, so that the address
+    /// `offset` bytes into the block is aligned to `layout.align()`.
+    ///
+    /// Unlike the first fit allocations, the block may be taken from the middle or the end
+    /// of a hole if that brings it closer to `target`. Returns the block, its aligned layout
+    /// and the size of the rest behind it that was too small for a hole and was handed out
+    /// with it, like in `split_current`.
+    pub(crate) fn allocate_near(
+        &mut self,
+        target: *mut u8,
+        layout: Layout,
+        offset: usize,
+    ) -> Result<(NonNull<u8>, Layout, usize), AllocError> {
+        let aligned_layout = Self::align_layout(layout).map_err(|_| AllocError::InvalidLayout)?;
+        let size = aligned_layout.size();
+        let align = layout.align().max(align_of::<Hole>());
+        let min = Self::min_size();
+        let target = target as usize;
+
+        // the closest start address, the hole and the offset into it that the block is
+        // derived from, so that it keeps the provenance of the hole, and the stranded rest
+        let mut best: Option<(usize, *mut u8, usize, usize)> = None;
+        for (hole_addr, hole_size) in self.holes() {
+            if hole_size < size {
+                continue;
+            }
+            let lo = hole_addr as usize;
+            let hi = lo + hole_size - size;
+            // a rest behind the block that is too small for a hole is handed out with it if
+            // it can't precede stranded front padding, see `split_current`
+            let end_aligned = (lo + hole_size) % size_of::<Hole>() == 0;
+            // the rest in front of and behind the block must be empty or large enough for a
+            // hole, which leaves these positions as the closest ones to the target
+            let bases = [
+                target.max(lo).min(hi),
+                lo,
+                lo + min,
+                hi,
+                hi.saturating_sub(min),
+            ];
+            for base in bases {
+                let down = align_down_size(base.wrapping_add(offset), align).wrapping_sub(offset);
+                for start in [down, down.wrapping_add(align)] {
+                    if start < lo || start > hi {
+                        continue;
+                    }
+                    let fits = |rest: usize| rest == 0 || rest >= min;
+                    let back = hi - start;
+                    let tail = if fits(back) { 0 } else { back };
+                    let valid =
+                        fits(start - lo) && (tail == 0 || tail < size_of::<Hole>() && end_aligned);
+                    let closer = best.map_or(true, |(best, _, _, _)| {
+                        start.abs_diff(target) < best.abs_diff(target)
+                    });
+                    if valid && closer {
+                        best = Some((start, hole_addr, start - lo, tail));
+                    }
+                }
+            }
+        }
+
+        let (block, tail) = match best {
+            Some((_, hole, offset, tail)) => (hole.wrapping_add(offset), tail),
+            None => return Err(self.alloc_error(size)),
+        };
+        if let Some(pages) = self.pages {
+            // the block might lie in decommitted pages in the middle of the hole
+            unsafe { pages.commit(block, size + tail) };
+        }
+        let allocated = self.allocate_at(block, size + tail);
+        debug_assert!(allocated);
+        if tail != 0 {
+            // clear the rest like `split_current` does, so that it is zero once it becomes
+            // part of a hole again
+            unsafe { block.add(size).write_bytes(0, tail) };
+        }
+        // SAFETY: The block lies within a hole, which is not at address zero.
+        Ok((
+            unsafe { NonNull::new_unchecked(block) },
+            aligned_layout,
+            tail,
+        ))
+    }
+
+    /// Moves the start of the known-zero memory behind a block that was just allocated.
+    fn mark_used(&mut self, ptr: NonNull<u8>, size: usize) {
+        unsafe { sanitizer::unpoison(ptr.as_ptr(), size) };
+        let end = ptr.as_ptr().wrapping_add(size);
+        if end > self.zeroed_from {
+            self.zeroed_from = end;
+        }
+    }
+
+    /// Determines why an allocation of `size` bytes failed.
+    fn alloc_error(&self, size: usize) -> AllocError {
+        let (free, largest_hole) = self
+            .holes()
+            .fold((0, 0), |(free, largest), (_, hole_size)| {
+                (free + hole_size, largest.max(hole_size))
+            });
+        if free < size {
+            AllocError::OutOfMemory
+        } else {
+            AllocError::Fragmented { largest_hole }
         }
     }
 
@@ -420,27 +1567,378 @@ impl HoleList {
     /// returns the aligned layout.
     pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) -> Layout {
         let aligned_layout = Self::align_layout(layout).unwrap();
-        deallocate(self, ptr.as_ptr(), aligned_layout.size());
+        self.release(ptr, aligned_layout);
         aligned_layout
     }
 
+    /// Frees the block given by `ptr` and its aligned layout, like
+    /// [`deallocate`][HoleList::deallocate].
+    ///
+    /// Returns the number of bytes that became free, which includes any adjacent padding
+    /// that was too small for a hole on its own.
+    pub(crate) unsafe fn release(&mut self, ptr: NonNull<u8>, aligned_layout: Layout) -> usize {
+        self.release_after(None, ptr, aligned_layout).0
+    }
+
+    /// Like [`release`][Self::release], but starts the search for the place of the block at
+    /// the hole `hint` instead of at the start of the list, which must lie below the block.
+    ///
+    /// Also returns a hole that lies below the block, which can be passed as `hint` when
+    /// releasing a block at a higher address, so that freeing many blocks in address order
+    /// only walks the list once.
+    pub(crate) unsafe fn release_after(
+        &mut self,
+        hint: Option<NonNull<Hole>>,
+        ptr: NonNull<u8>,
+        aligned_layout: Layout,
+    ) -> (usize, NonNull<Hole>) {
+        sanitizer::poison(ptr.as_ptr(), aligned_layout.size());
+        // blocks are often freed in ascending order, so the search can continue where the
+        // last one ended
+        let hint = hint.or_else(|| {
+            self.last_release
+                .filter(|hole| hole.as_ptr().cast::<u8>() < ptr.as_ptr())
+        });
+        let (merged, hint) = deallocate(self, hint, ptr.as_ptr(), aligned_layout.size());
+        self.last_release = Some(hint);
+        if let Some(scan) = self.scan {
+            // the holes that were merged into the block lie within the hole it became part
+            // of, and the hint lies in front of them
+            let hole = self.released_hole(hint, ptr);
+            let start = hole.as_ptr() as usize;
+            if (start + 1..start + hole.as_ref().size).contains(&(scan.as_ptr() as usize)) {
+                self.scan = Some(hint);
+            }
+        }
+        if let Some(pages) = self.pages {
+            let hole = self.released_hole(hint, ptr);
+            // memory above `zeroed_from` is never decommitted, so that it stays zero
+            pages.decommit_hole(hole.as_ptr().cast(), hole.as_ref().size, self.zeroed_from);
+        }
+        (aligned_layout.size() + merged, hint)
+    }
+
+    /// Returns the hole that the block at `ptr` became part of, given the hole that
+    /// [`release_after`][Self::release_after] returned for it.
+    pub(crate) unsafe fn released_hole(
+        &self,
+        hint: NonNull<Hole>,
+        ptr: NonNull<u8>,
+    ) -> NonNull<Hole> {
+        // the block was merged into the hole that merging started at or became the hole
+        // behind it
+        if hint.as_ptr().cast::<u8>().wrapping_add(hint.as_ref().size) <= ptr.as_ptr() {
+            hint.as_ref().next(self.key).unwrap()
+        } else {
+            hint
+        }
+    }
+
     /// Returns the minimal allocation size. Smaller allocations or deallocations are not allowed.
     pub fn min_size() -> usize {
-        size_of::<usize>() * 2
+        size_of::<Hole>()
+    }
+
+    /// Returns an iterator over the address and size of all holes, in address order.
+    pub(crate) fn holes(&self) -> Holes<'_> {
+        Holes {
+            next: self.first(),
+            key: self.key,
+            _list: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the address and size of the holes that yields the address
+    /// of the first corrupted hole as an error and stops there, instead of panicking.
+    ///
+    /// The checks are the same as those of [`scan_step`][Self::scan_step], but nothing is
+    /// repaired, so the list is only read.
+    pub(crate) fn checked_holes(&self) -> CheckedHoles<'_> {
+        CheckedHoles {
+            prev: Some(NonNull::from(self.head())),
+            prev_end: self.bottom as usize,
+            top: self.top as usize,
+            key: self.key,
+            _list: PhantomData,
+        }
+    }
+
+    /// Checks the invariants of the list and returns the number of free bytes.
+    ///
+    /// The holes must be sorted by address, properly aligned, at least
+    /// [`min_size`][HoleList::min_size] bytes large and located within the heap bounds.
+    /// Neighboring holes must be separated by at least [`min_size`][HoleList::min_size] bytes
+    /// of used memory, as they would have been merged otherwise.
+    #[cfg(any(test, kani))]
+    pub(crate) fn check_invariants(&self) -> usize {
+        let mut free = 0;
+        let mut prev_end: Option<*mut u8> = None;
+        for (addr, size) in self.holes() {
+            let end = addr.wrapping_add(size);
+            assert_eq!(
+                addr.align_offset(align_of::<Hole>()),
+                0,
+                "hole is unaligned"
+            );
+            assert!(size >= Self::min_size(), "hole is too small");
+            assert!(addr >= self.bottom, "hole starts below the heap");
+            assert!(end <= self.top, "hole ends above the heap");
+            if let Some(prev_end) = prev_end {
+                assert!(
+                    addr >= prev_end.wrapping_add(Self::min_size()),
+                    "holes are unsorted, overlapping or not merged"
+                );
+            }
+            prev_end = Some(end);
+            free += size;
+        }
+        free
     }
 
     /// Returns information about the first hole for test purposes.
     #[cfg(test)]
     pub fn first_hole(&self) -> Option<(*const u8, usize)> {
-        self.first.next.as_ref().map(|hole| {
+        self.first().map(|hole| {
             (hole.as_ptr() as *mut u8 as *const u8, unsafe {
                 hole.as_ref().size
             })
         })
     }
 
+    /// Splits the list at `at`, which must be aligned for a `Hole` and lie strictly between
+    /// `bottom` and `top`.
+    ///
+    /// The holes above `at` are moved into the returned list, which manages the memory from
+    /// `at` to the end of the heap, including any pending extension. Returns `None` without
+    /// modifying the list if `at` might lie within an allocation, or if splitting a hole at
+    /// `at` would leave a part that is too small to hold a hole.
+    pub(crate) fn split_off(&mut self, at: *mut u8) -> Option<HoleList> {
+        self.last_release = None;
+        self.scan = None;
+        let mut prev: NonNull<Hole> = self.head_mut();
+        let upper_first = loop {
+            let hole = unsafe { prev.as_ref() }.next(self.key)?;
+            let hole_u8 = hole.as_ptr().cast::<u8>();
+            let hole_size = unsafe { hole.as_ref() }.size;
+            let hole_end = hole_u8.wrapping_add(hole_size);
+
+            if hole_end < at {
+                prev = hole;
+            } else if hole_end == at {
+                // the hole ends right at the split point
+                break unsafe { Hole::take_next(hole, self.key) };
+            } else if hole_u8 == at {
+                // the hole starts right at the split point
+                unsafe { Hole::set_next(prev, None, self.key) };
+                break Some(hole);
+            } else if hole_u8 < at {
+                // the split point lies within the hole, so cut it in two
+                let front_size = unsafe { at.offset_from(hole_u8) as usize };
+                let back_size = hole_size - front_size;
+                if front_size < Self::min_size() || back_size < Self::min_size() {
+                    return None;
+                }
+                unsafe {
+                    let back = make_hole(at, back_size, self.key);
+                    Hole::set_next(back, Hole::take_next(hole, self.key), self.key);
+                    Hole::set_size(hole, front_size, self.key);
+                    break Some(back);
+                }
+            } else {
+                // the split point lies in used memory that is not directly behind a hole,
+                // so it might be in the middle of an allocation
+                return None;
+            }
+        };
+
+        let mut upper = HoleList {
+            first: Hole::EMPTY,
+            bottom: at,
+            top: self.top,
+            pending_extend: self.pending_extend,
+            zeroed_from: self.zeroed_from.max(at),
+            #[cfg(feature = "hooks")]
+            key: self.key.with_hooks(None),
+            #[cfg(not(feature = "hooks"))]
+            key: self.key,
+            pages: self.pages,
+            last_release: None,
+            scan: None,
+            anchor: None,
+        };
+        unsafe { Hole::set_next(NonNull::from(&mut upper.first), upper_first, self.key) };
+        self.top = at;
+        self.pending_extend = 0;
+        self.zeroed_from = self.zeroed_from.min(at);
+        self.update_anchor();
+        Some(upper)
+    }
+
+    /// Returns the start of the memory of the list, which is the anchor of a persistent list.
+    pub(crate) fn start(&self) -> *mut u8 {
+        self.anchor
+            .map_or(self.bottom, |anchor| anchor.as_ptr().cast())
+    }
+
+    /// Appends the holes of `upper`, which must manage the memory from [`start`][Self::start]
+    /// to its top, where `start` lies at the top of this list or at the next address that is
+    /// aligned for a hole. The memory in between is turned into a hole if it is large
+    /// enough, and the holes at the border are merged.
+    pub(crate) fn merge(&mut self, upper: HoleList) {
+        self.last_release = None;
+        self.scan = None;
+        let mut last = self.head_mut();
+        while let Some(hole) = unsafe { last.as_ref() }.next(self.key) {
+            last = hole;
+        }
+
+        // the anchor of a persistent upper list is part of the gap
+        let gap_start = self.top;
+        let gap = upper.bottom as usize - gap_start as usize;
+        let mut next = upper.first();
+        if gap >= Self::min_size() {
+            unsafe {
+                sanitizer::poison(gap_start, gap);
+                let hole = make_hole(gap_start, gap, self.key);
+                Hole::set_next(hole, next, self.key);
+                next = Some(hole);
+            }
+        }
+        unsafe { Hole::set_next(last, next, self.key) };
+
+        if upper.zeroed_from > upper.bottom {
+            self.zeroed_from = upper.zeroed_from;
+        } else {
+            self.zeroed_from = self.zeroed_from.min(upper.bottom);
+        }
+        self.top = upper.top;
+        self.pending_extend = upper.pending_extend;
+        self.update_anchor();
+
+        // merge the last hole of this list with the gap and the first hole of `upper`
+        let first = if last == self.head_mut() {
+            next
+        } else {
+            Some(last)
+        };
+        if let Some(hole) = first {
+            Cursor {
+                prev: hole,
+                hole,
+                top: self.top,
+                key: self.key,
+            }
+            .try_merge_next_n(2);
+        }
+    }
+
+    /// Re-encodes all links between the holes and their checksums with a new key.
+    pub(crate) fn set_key(&mut self, key: LinkKey) {
+        let mut prev = self.head_mut();
+        while let Some(hole) = unsafe { prev.as_ref() }.next(self.key) {
+            unsafe { Hole::set_next(prev, Some(hole), key) };
+            prev = hole;
+        }
+        // the last hole has no link, but its checksum covers the key
+        unsafe { Hole::set_next(prev, None, key) };
+        self.key = key;
+        // the new key might flush the anchor, which was written without it
+        self.update_anchor();
+    }
+
+    /// Verifies the header and the mirror of every hole and repairs the copies that were
+    /// corrupted. Returns the number of repaired copies.
+    #[cfg(feature = "redundant")]
+    pub(crate) fn scrub(&mut self) -> usize {
+        let key = self.key;
+        let mut repaired = 0;
+        let mut hole = self.head_mut();
+        // the links are followed without `Hole::next`, which would repair the headers
+        // before they are counted
+        while let Some(link) = unsafe { hole.as_ref() }.next {
+            hole = key.decode(link);
+            unsafe {
+                if !key.is_intact(hole) {
+                    if !key.repair(hole) {
+                        key.corrupted(hole.as_ptr().cast(), "checksum mismatch of a free block");
+                    }
+                    repaired += 1;
+                }
+                if !Mirror::matches(hole) {
+                    Mirror::write(hole);
+                    key.repaired(hole);
+                    repaired += 1;
+                }
+            }
+        }
+        repaired
+    }
+
+    /// Checks up to `budget` holes, continuing after the hole that the last call checked,
+    /// see [`Heap::scan_step`][crate::Heap::scan_step].
+    ///
+    /// The checks are the same as those of [`adopt`][Self::adopt]. A link is only followed
+    /// after the address that it points to was checked, so a corrupted list can't make the
+    /// scan read outside of the heap.
+    pub(crate) fn scan_step(&mut self, budget: usize) -> ScanState {
+        let key = self.key;
+        let top = self.top as usize;
+        let (mut prev, mut prev_end) = match self.scan {
+            Some(hole) => (hole, hole.as_ptr() as usize + unsafe { hole.as_ref() }.size),
+            None => (self.head_mut(), self.bottom as usize),
+        };
+        for _ in 0..budget {
+            // SAFETY: `prev` is the head or a hole whose address was checked.
+            if !unsafe { key.check(prev) } {
+                self.scan = None;
+                return ScanState::Corrupted {
+                    addr: prev.as_ptr() as usize,
+                };
+            }
+            let link = match unsafe { prev.as_ref() }.next {
+                Some(link) => link,
+                None => {
+                    self.scan = None;
+                    return ScanState::Complete;
+                }
+            };
+            let addr = key.unmask(link) as usize;
+            if addr < prev_end
+                || addr > top
+                || addr % align_of::<Hole>() != 0
+                || top - addr < Self::min_size()
+            {
+                self.scan = None;
+                return ScanState::Corrupted {
+                    addr: prev.as_ptr() as usize,
+                };
+            }
+            // SAFETY: The hole lies within the heap.
+            let hole = unsafe { NonNull::new_unchecked(key.unmask(link)) };
+            let size = unsafe { hole.as_ref() }.size;
+            if size < Self::min_size() || size % align_of::<Hole>() != 0 || size > top - addr {
+                self.scan = None;
+                return ScanState::Corrupted { addr };
+            }
+            prev = hole;
+            prev_end = addr + size;
+            self.scan = Some(hole);
+        }
+        ScanState::InProgress
+    }
+
+    /// Moves the [scan cursor][Self::scan] off `hole`, which was just removed from the list
+    /// or replaced, to the hole `prev` in front of it.
+    fn scan_removed(&mut self, hole: NonNull<Hole>, prev: NonNull<Hole>) {
+        if self.scan == Some(hole) {
+            let head = self.head_mut();
+            self.scan = Some(prev).filter(|&prev| prev != head);
+        }
+    }
+
     pub(crate) unsafe fn extend(&mut self, by: usize) {
         assert!(!self.top.is_null(), "tried to extend an empty heap");
+        self.last_release = None;
 
         let top = self.top;
 
@@ -458,10 +1956,12 @@ impl HoleList {
 
         // join this extend request with any pending (but not yet acted on) extension
         let extend_by = self.pending_extend as usize + by;
+        sanitizer::poison(top, extend_by);
 
         let minimum_extend = Self::min_size();
         if extend_by < minimum_extend {
             self.pending_extend = extend_by as u8;
+            self.update_anchor();
             return;
         }
 
@@ -470,27 +1970,120 @@ impl HoleList {
         let layout = Layout::from_size_align(new_hole_size, 1).unwrap();
 
         // instantiate the hole by forcing a deallocation on the new memory
-        self.deallocate(NonNull::new_unchecked(top as *mut u8), layout);
+        self.deallocate(NonNull::new_unchecked(top), layout);
         self.top = top.add(new_hole_size);
 
         // save extra bytes given to extend that weren't aligned to the hole size
         self.pending_extend = (extend_by - new_hole_size) as u8;
+        self.update_anchor();
+    }
+}
+
+/// An iterator over the holes of a [`HoleList`], created by [`HoleList::holes`].
+pub(crate) struct Holes<'a> {
+    next: Option<NonNull<Hole>>,
+    key: LinkKey,
+    _list: PhantomData<&'a HoleList>,
+}
+
+impl<'a> Iterator for Holes<'a> {
+    type Item = (*mut u8, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next.map(|hole| {
+            let addr = hole.as_ptr().cast::<u8>();
+            let hole = unsafe { hole.as_ref() };
+            self.next = hole.next(self.key);
+            (addr, hole.size)
+        })
     }
 }
 
-unsafe fn make_hole(addr: *mut u8, size: usize) -> NonNull<Hole> {
+/// An iterator over the holes of a [`HoleList`] that stops at the first corrupted hole,
+/// created by [`HoleList::checked_holes`].
+pub(crate) struct CheckedHoles<'a> {
+    prev: Option<NonNull<Hole>>,
+    prev_end: usize,
+    top: usize,
+    key: LinkKey,
+    _list: PhantomData<&'a HoleList>,
+}
+
+impl<'a> Iterator for CheckedHoles<'a> {
+    type Item = Result<(usize, usize), usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let prev = self.prev.take()?;
+        // SAFETY: `prev` is the head or a hole whose address was checked.
+        #[cfg(feature = "checksum")]
+        if !unsafe { self.key.is_intact(prev) } {
+            return Some(Err(prev.as_ptr() as usize));
+        }
+        let link = unsafe { prev.as_ref() }.next?;
+        let addr = self.key.unmask(link) as usize;
+        if addr < self.prev_end
+            || addr > self.top
+            || addr % align_of::<Hole>() != 0
+            || self.top - addr < HoleList::min_size()
+        {
+            return Some(Err(prev.as_ptr() as usize));
+        }
+        // SAFETY: The hole lies within the heap.
+        let hole = unsafe { NonNull::new_unchecked(self.key.unmask(link)) };
+        let size = unsafe { hole.as_ref() }.size;
+        if size < HoleList::min_size() || size % align_of::<Hole>() != 0 || size > self.top - addr {
+            return Some(Err(addr));
+        }
+        self.prev = Some(hole);
+        self.prev_end = addr + size;
+        Some(Ok((addr, size)))
+    }
+}
+
+/// Returns the size of the padding in front of a block in the hole at `addr`, so that the
+/// address of the block plus `offset` is aligned to `align`. Returns `None` if the padding
+/// overflows.
+fn padding_before(addr: *mut u8, offset: usize, align: usize) -> Option<usize> {
+    let mut padding = addr.wrapping_add(offset).align_offset(align);
+    // Padding that is too small for a hole is only left in front of blocks that are aligned
+    // to the size of a hole. Such padding always starts in the middle of a hole-sized unit,
+    // so it never directly follows the padding of another allocation, which would make the
+    // two indistinguishable from an allocation when they are merged again. Skip to the next
+    // aligned addresses instead, until there is enough room for a hole.
+    while padding != 0
+        && padding < size_of::<Hole>()
+        && addr.wrapping_add(padding).align_offset(size_of::<Hole>()) != 0
+    {
+        padding = padding.checked_add(align)?;
+    }
+    Some(padding)
+}
+
+/// Returns the number of bytes that are available for a block with the given alignment in
+/// the hole at `addr`, or `None` if the hole can't hold such a block.
+fn available_size(addr: *mut u8, size: usize, align: usize) -> Option<usize> {
+    size.checked_sub(padding_before(addr, 0, align)?)
+        .filter(|&available| available >= HoleList::min_size())
+}
+
+unsafe fn make_hole(addr: *mut u8, size: usize, key: LinkKey) -> NonNull<Hole> {
     let hole_addr = addr.cast::<Hole>();
     debug_assert_eq!(
-        addr as usize % align_of::<Hole>(),
+        addr.align_offset(align_of::<Hole>()),
         0,
         "Hole address not aligned!",
     );
-    hole_addr.write(Hole { size, next: None });
-    NonNull::new_unchecked(hole_addr)
+    sanitizer::unpoison(addr, size_of::<Hole>());
+    hole_addr.write(Hole::EMPTY);
+    let hole = NonNull::new_unchecked(hole_addr);
+    Hole::set_size(hole, size, key);
+    hole
 }
 
 impl Cursor {
-    fn try_insert_back(self, node: NonNull<Hole>, bottom: *mut u8) -> Result<Self, Self> {
+    // On success, also returns the number of bytes that were merged into the node from the
+    // bottom of the heap.
+    fn try_insert_back(self, node: NonNull<Hole>, bottom: *mut u8) -> Result<(Self, usize), Self> {
         // Covers the case where the new hole exists BEFORE the current pointer,
         // which only happens when previous is the stub pointer
         if node < self.hole {
@@ -505,32 +2098,37 @@ impl Cursor {
             debug_assert_eq!(self.previous().size, 0);
 
             let Cursor {
-                mut prev,
+                prev,
                 hole,
                 top,
+                key,
             } = self;
+            let (node, merged) = check_merge_bottom(node, bottom, key);
             unsafe {
-                let mut node = check_merge_bottom(node, bottom);
-                prev.as_mut().next = Some(node);
-                node.as_mut().next = Some(hole);
+                Hole::set_next(prev, Some(node), key);
+                Hole::set_next(node, Some(hole), key);
             }
-            Ok(Cursor {
-                prev,
-                hole: node,
-                top,
-            })
+            Ok((
+                Cursor {
+                    prev,
+                    hole: node,
+                    top,
+                    key,
+                },
+                merged,
+            ))
         } else {
             Err(self)
         }
     }
 
-    fn try_insert_after(&mut self, mut node: NonNull<Hole>) -> Result<(), ()> {
+    fn try_insert_after(&mut self, node: NonNull<Hole>) -> Result<(), ()> {
         let node_u8 = node.as_ptr().cast::<u8>();
         let node_size = unsafe { node.as_ref().size };
 
         // If we have a next, does the node overlap next?
-        if let Some(next) = self.current().next.as_ref() {
-            if node < *next {
+        if let Some(next) = self.current().next(self.key) {
+            if node < next {
                 let node_u8 = node_u8 as *const u8;
                 assert!(
                     node_u8.wrapping_add(node_size) <= next.as_ptr().cast::<u8>(),
@@ -561,33 +2159,35 @@ impl Cursor {
 
         // All good! Let's insert that after.
         unsafe {
-            let maybe_next = self.hole.as_mut().next.replace(node);
-            node.as_mut().next = maybe_next;
+            let maybe_next = Hole::take_next(self.hole, self.key);
+            Hole::set_next(self.hole, Some(node), self.key);
+            Hole::set_next(node, maybe_next, self.key);
         }
 
         Ok(())
     }
 
     // Merge the current node with up to n following nodes
-    fn try_merge_next_n(self, max: usize) {
+    // Returns the number of bytes between the nodes that were merged into them.
+    fn try_merge_next_n(self, max: usize) -> usize {
         let Cursor {
             prev: _,
             mut hole,
             top,
-            ..
+            key,
         } = self;
+        let mut merged = 0;
 
         for _ in 0..max {
             // Is there a next node?
-            let mut next = if let Some(next) = unsafe { hole.as_mut() }.next.as_ref() {
-                *next
+            let next = if let Some(next) = unsafe { hole.as_mut() }.next(key) {
+                next
             } else {
                 // Since there is no NEXT node, we need to check whether the current
                 // hole SHOULD extend to the end, but doesn't. This would happen when
                 // there isn't enough remaining space to place a hole after the current
                 // node's placement.
-                check_merge_top(hole, top);
-                return;
+                return merged + check_merge_top(hole, top, key);
             };
 
             // Can we directly merge these? e.g. are they touching?
@@ -596,26 +2196,28 @@ impl Cursor {
             // the new hole is always "rounded up" to cover any partial gaps that
             // would have occurred. For this reason, we DON'T need to "round up"
             // to account for an unaligned hole spot.
+            //
+            // A gap that is smaller than a hole can't contain an allocation, so it is
+            // front padding that `split_current` couldn't turn into a hole. Merge it, too.
             let hole_u8 = hole.as_ptr().cast::<u8>();
             let hole_sz = unsafe { hole.as_ref().size };
             let next_u8 = next.as_ptr().cast::<u8>();
             let end = hole_u8.wrapping_add(hole_sz);
+            let gap = (next_u8 as usize).wrapping_sub(end as usize);
 
-            let touching = end == next_u8;
+            let touching = gap < size_of::<Hole>();
 
             if touching {
-                let next_sz;
-                let next_next;
                 unsafe {
-                    let next_mut = next.as_mut();
-                    next_sz = next_mut.size;
-                    next_next = next_mut.next.take();
-                }
-                unsafe {
-                    let hole_mut = hole.as_mut();
-                    hole_mut.next = next_next;
-                    hole_mut.size += next_sz;
+                    let next_sz = next.as_ref().size;
+                    let next_next = Hole::take_next(next, key);
+                    Hole::set_next(hole, next_next, key);
+                    // the gap and the header of the merged hole are free memory now
+                    sanitizer::poison(end, gap + size_of::<Hole>());
+                    let size = hole_sz + gap + next_sz;
+                    Hole::set_size(hole, size, key);
                 }
+                merged += gap;
                 // Okay, we just merged the next item. DON'T move the cursor, as we can
                 // just try to merge the next_next, which is now our next.
             } else {
@@ -623,65 +2225,181 @@ impl Cursor {
                 hole = next;
             }
         }
+        merged
     }
 }
 
-/// Frees the allocation given by `(addr, size)`. It starts at the given hole and walks the list to
-/// find the correct place (the list is sorted by address).
-fn deallocate(list: &mut HoleList, addr: *mut u8, size: usize) {
+/// Frees the allocation given by `(addr, size)`. It starts at the given hole, or at the start
+/// of the list, and walks the list to find the correct place (the list is sorted by address).
+///
+/// Returns the number of bytes around the allocation that were too small for a hole and are
+/// now merged into the freed block, and the hole that the merging started at. That hole lies
+/// below the next allocations and is not merged into another hole by freeing them.
+fn deallocate(
+    list: &mut HoleList,
+    hint: Option<NonNull<Hole>>,
+    addr: *mut u8,
+    size: usize,
+) -> (usize, NonNull<Hole>) {
     // Start off by just making this allocation a hole where it stands.
     // We'll attempt to merge it with other nodes once we figure out where
     // it should live
-    let hole = unsafe { make_hole(addr, size) };
-
-    // Now, try to get a cursor to the list - this only works if we have at least
-    // one non-"dummy" hole in the list
-    let cursor = if let Some(cursor) = list.cursor() {
-        cursor
+    let hole = unsafe { make_hole(addr, size, list.key) };
+
+    let (cursor, n, merged) = if let Some(hint) = hint {
+        // The hole lies somewhere after the hint, so there is no need to check the front
+        // of the list.
+        let cursor = Cursor {
+            prev: hint,
+            hole: hint,
+            top: list.top,
+            key: list.key,
+        };
+        (insert_after(cursor, hole), 2, 0)
     } else {
-        // Oh hey, there are no "real" holes at all. That means this just
-        // becomes the only "real" hole! Check if this is touching the end
-        // or the beginning of the allocation range
-        let hole = check_merge_bottom(hole, list.bottom);
-        check_merge_top(hole, list.top);
-        list.first.next = Some(hole);
-        return;
-    };
-
-    // First, check if we can just insert this node at the top of the list. If the
-    // insertion succeeded, then our cursor now points to the NEW node, behind the
-    // previous location the cursor was pointing to.
-    //
-    // Otherwise, our cursor will point at the current non-"dummy" head of the list
-    let (cursor, n) = match cursor.try_insert_back(hole, list.bottom) {
-        Ok(cursor) => {
-            // Yup! It lives at the front of the list. Hooray! Attempt to merge
-            // it with just ONE next node, since it is at the front of the list
-            (cursor, 1)
-        }
-        Err(mut cursor) => {
-            // Nope. It lives somewhere else. Advance the list until we find its home
-            while let Err(()) = cursor.try_insert_after(hole) {
-                cursor = cursor
-                    .next()
-                    .expect("Reached end of holes without finding deallocation hole!");
-            }
-            // Great! We found a home for it, our cursor is now JUST BEFORE the new
-            // node we inserted, so we need to try to merge up to twice: One to combine
-            // the current node to the new node, then once more to combine the new node
-            // with the node after that.
-            (cursor, 2)
+        // Now, try to get a cursor to the list - this only works if we have at least
+        // one non-"dummy" hole in the list
+        let cursor = if let Some(cursor) = list.cursor() {
+            cursor
+        } else {
+            // Oh hey, there are no "real" holes at all. That means this just
+            // becomes the only "real" hole! Check if this is touching the end
+            // or the beginning of the allocation range
+            let (hole, merged) = check_merge_bottom(hole, list.bottom, list.key);
+            let merged = merged + check_merge_top(hole, list.top, list.key);
+            unsafe { Hole::set_next(list.head_mut(), Some(hole), list.key) };
+            return (merged, hole);
+        };
+
+        // First, check if we can just insert this node at the top of the list. If the
+        // insertion succeeded, then our cursor now points to the NEW node, behind the
+        // previous location the cursor was pointing to.
+        //
+        // Otherwise, our cursor will point at the current non-"dummy" head of the list
+        match cursor.try_insert_back(hole, list.bottom) {
+            Ok((cursor, merged)) => {
+                // Yup! It lives at the front of the list. Hooray! Attempt to merge
+                // it with just ONE next node, since it is at the front of the list
+                (cursor, 1, merged)
+            }
+            Err(cursor) => {
+                // Nope. It lives somewhere else. Advance the list until we find its home.
+                // Our cursor is now JUST BEFORE the new node we inserted, so we need to try
+                // to merge up to twice: One to combine the current node to the new node,
+                // then once more to combine the new node with the node after that.
+                (insert_after(cursor, hole), 2, 0)
+            }
         }
     };
 
     // We now need to merge up to two times to combine the current node with the next
     // two nodes.
-    cursor.try_merge_next_n(n);
+    let start = cursor.hole;
+    (merged + cursor.try_merge_next_n(n), start)
+}
+
+/// Advances the cursor until `hole` can be inserted after it and inserts it there.
+fn insert_after(mut cursor: Cursor, hole: NonNull<Hole>) -> Cursor {
+    while let Err(()) = cursor.try_insert_after(hole) {
+        cursor = cursor
+            .next()
+            .expect("Reached end of holes without finding deallocation hole!");
+    }
+    cursor
+}
+
+/// Bounded proofs of the hole list invariants, run with `cargo kani`.
+#[cfg(kani)]
+mod verification {
+    use super::HoleList;
+    use core::alloc::Layout;
+    use core::mem::size_of;
+
+    const HEAP_WORDS: usize = 16;
+
+    fn any_layout() -> Layout {
+        let size: usize = kani::any();
+        let align_shift: u32 = kani::any();
+        kani::assume(size > 0 && size <= 4 * size_of::<usize>());
+        kani::assume(align_shift <= 4);
+        Layout::from_size_align(size, 1 << align_shift).unwrap()
+    }
+
+    unsafe fn any_hole_list(heap: &mut [usize; HEAP_WORDS]) -> HoleList {
+        let offset: usize = kani::any();
+        let size: usize = kani::any();
+        kani::assume(offset < size_of::<usize>());
+        kani::assume(size >= 3 * size_of::<usize>());
+        kani::assume(offset + size <= HEAP_WORDS * size_of::<usize>());
+        HoleList::new(heap.as_mut_ptr().cast::<u8>().add(offset), size)
+    }
+
+    #[kani::proof]
+    #[kani::unwind(3)]
+    fn new_is_valid() {
+        let mut heap = [0; HEAP_WORDS];
+        let list = unsafe { any_hole_list(&mut heap) };
+        let free = list.check_invariants();
+        assert_eq!(free, unsafe { list.top.offset_from(list.bottom) as usize });
+    }
+
+    #[kani::proof]
+    #[kani::unwind(5)]
+    fn allocate_deallocate_round_trip() {
+        let mut heap = [0; HEAP_WORDS];
+        let mut list = unsafe { any_hole_list(&mut heap) };
+        let free = list.check_invariants();
+
+        let layout = any_layout();
+        if let Ok((ptr, aligned_layout)) = list.allocate_first_fit(layout) {
+            assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+            assert!(ptr.as_ptr() >= list.bottom);
+            assert!(ptr.as_ptr().wrapping_add(aligned_layout.size()) <= list.top);
+            assert_eq!(list.check_invariants(), free - aligned_layout.size());
+
+            unsafe { list.deallocate(ptr, layout) };
+            assert_eq!(list.check_invariants(), free);
+        } else {
+            assert_eq!(list.check_invariants(), free);
+        }
+    }
+
+    #[kani::proof]
+    #[kani::unwind(6)]
+    fn deallocate_in_any_order_merges() {
+        let mut heap = [0; HEAP_WORDS];
+        let mut list = unsafe { any_hole_list(&mut heap) };
+        let free = list.check_invariants();
+
+        let first = any_layout();
+        let second = any_layout();
+        let a = list.allocate_first_fit(first);
+        let b = list.allocate_first_fit(second);
+        kani::assume(a.is_ok() && b.is_ok());
+        let (a, _) = a.unwrap();
+        let (b, _) = b.unwrap();
+        list.check_invariants();
+
+        // exercises merging with the previous hole, the next hole and both at once
+        unsafe {
+            if kani::any() {
+                list.deallocate(a, first);
+                list.check_invariants();
+                list.deallocate(b, second);
+            } else {
+                list.deallocate(b, second);
+                list.check_invariants();
+                list.deallocate(a, first);
+            }
+        }
+        assert_eq!(list.check_invariants(), free);
+        assert_eq!(list.holes().count(), 1);
+    }
 }
 
 #[cfg(test)]
 pub mod test {
-    use super::HoleList;
+    use super::{Hole, HoleList};
     use crate::{align_down_size, test::new_heap};
     use core::mem::size_of;
     use std::{alloc::Layout, convert::TryInto, prelude::v1::*, ptr::NonNull};
@@ -708,26 +2426,69 @@ pub mod test {
         let _ = heap.allocate_first_fit(reqd).unwrap();
     }
 
+    #[test]
+    fn check_invariants() {
+        let mut heap = new_heap();
+        let free = heap.holes.check_invariants();
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        let a = heap.allocate_first_fit(layout).unwrap();
+        let b = heap.allocate_first_fit(layout).unwrap();
+        heap.holes.check_invariants();
+        unsafe { heap.deallocate(a, layout) };
+        heap.holes.check_invariants();
+        unsafe { heap.deallocate(b, layout) };
+        assert_eq!(heap.holes.check_invariants(), free);
+    }
+
+    #[test]
+    fn release_resumes_at_last_release() {
+        let mut heap = new_heap();
+        let free = heap.holes.check_invariants();
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptrs: Vec<_> = (0..8)
+            .map(|_| heap.allocate_first_fit(layout).unwrap())
+            .collect();
+        assert!(heap.holes.last_release.is_none());
+
+        // the hole of each freed block lies below the next one, so the list isn't walked
+        for ptr in ptrs.iter().step_by(2) {
+            unsafe { heap.deallocate(*ptr, layout) };
+            heap.holes.check_invariants();
+        }
+        // freeing the last block merged it into the top hole, so merging started at the
+        // hole of the block before it
+        let last = heap.holes.last_release.unwrap().as_ptr().cast::<u8>();
+        assert!(ptrs[3].as_ptr() < last && last <= ptrs[4].as_ptr());
+
+        // allocations might remove the hole
+        let ptr = heap.allocate_first_fit(layout).unwrap();
+        assert!(heap.holes.last_release.is_none());
+        unsafe { heap.deallocate(ptr, layout) };
+
+        // blocks below the last release are freed from the start of the list
+        for ptr in ptrs.iter().skip(1).step_by(2).rev() {
+            unsafe { heap.deallocate(*ptr, layout) };
+            heap.holes.check_invariants();
+        }
+        assert_eq!(heap.holes.check_invariants(), free);
+        assert_eq!(heap.holes.holes().count(), 1);
+    }
+
     /// Tests `HoleList::new` with the minimal allowed `hole_size`.
     #[test]
     fn hole_list_new_min_size() {
         // define an array of `u64` instead of `u8` for alignment
-        static mut HEAP: [u64; 2] = [0; 2];
-        let heap_start = unsafe { HEAP.as_ptr() as usize };
+        static mut HEAP: [u64; size_of::<Hole>() / 8] = [0; size_of::<Hole>() / 8];
+        let heap_start = core::ptr::addr_of!(HEAP) as usize;
         let heap =
-            unsafe { HoleList::new(HEAP.as_mut_ptr().cast(), 2 * core::mem::size_of::<usize>()) };
+            unsafe { HoleList::new(core::ptr::addr_of_mut!(HEAP).cast(), size_of::<Hole>()) };
         assert_eq!(heap.bottom as usize, heap_start);
-        assert_eq!(heap.top as usize, heap_start + 2 * size_of::<usize>());
+        assert_eq!(heap.top as usize, heap_start + size_of::<Hole>());
         assert_eq!(heap.first.size, 0); // dummy
-        assert_eq!(
-            heap.first.next,
-            Some(NonNull::new(heap.bottom.cast())).unwrap()
-        );
-        assert_eq!(
-            unsafe { heap.first.next.as_ref().unwrap().as_ref() }.size,
-            2 * core::mem::size_of::<usize>()
-        );
-        assert_eq!(unsafe { &*(heap.first.next.unwrap().as_ptr()) }.next, None);
+        let first = heap.first.next(heap.key);
+        assert_eq!(first, NonNull::new(heap.bottom.cast()));
+        assert_eq!(unsafe { first.unwrap().as_ref() }.size, size_of::<Hole>());
+        assert_eq!(unsafe { first.unwrap().as_ref() }.next(heap.key), None);
     }
 
     /// Tests that `HoleList::new` aligns the `hole_addr` correctly and adjusts the size
@@ -735,31 +2496,29 @@ pub mod test {
     #[test]
     fn hole_list_new_align() {
         // define an array of `u64` instead of `u8` for alignment
-        static mut HEAP: [u64; 3] = [0; 3];
+        static mut HEAP: [u64; size_of::<Hole>() / 8 + 1] = [0; size_of::<Hole>() / 8 + 1];
 
-        let heap_start: *mut u8 = unsafe { HEAP.as_mut_ptr().add(1) }.cast();
+        let heap_start: *mut u8 =
+            unsafe { core::ptr::addr_of_mut!(HEAP).cast::<u64>().add(1) }.cast();
         // initialize the HoleList with a hole_addr one byte before `heap_start`
         // -> the function should align it up to `heap_start`
-        let heap =
-            unsafe { HoleList::new(heap_start.sub(1), 2 * core::mem::size_of::<usize>() + 1) };
+        let heap = unsafe { HoleList::new(heap_start.sub(1), size_of::<Hole>() + 1) };
         assert_eq!(heap.bottom, heap_start);
         assert_eq!(heap.top.cast(), unsafe {
             // one byte less than the `hole_size` given to `new` because of alignment
-            heap_start.add(2 * core::mem::size_of::<usize>())
+            heap_start.add(size_of::<Hole>())
         });
 
         assert_eq!(heap.first.size, 0); // dummy
+        let first = heap.first.next(heap.key);
+        assert_eq!(first, NonNull::new(heap.bottom.cast()));
         assert_eq!(
-            heap.first.next,
-            Some(NonNull::new(heap.bottom.cast())).unwrap()
-        );
-        assert_eq!(
-            unsafe { &*(heap.first.next.unwrap().as_ptr()) }.size,
+            unsafe { first.unwrap().as_ref() }.size,
             unsafe { heap.top.offset_from(heap.bottom) }
                 .try_into()
                 .unwrap()
         );
-        assert_eq!(unsafe { &*(heap.first.next.unwrap().as_ptr()) }.next, None);
+        assert_eq!(unsafe { first.unwrap().as_ref() }.next(heap.key), None);
     }
 
     #[test]
@@ -768,7 +2527,8 @@ pub mod test {
         // define an array of `u64` instead of `u8` for alignment
         static mut HEAP: [u64; 3] = [0; 3];
 
-        let heap_start: *mut u8 = unsafe { HEAP.as_mut_ptr().add(1) }.cast();
+        let heap_start: *mut u8 =
+            unsafe { core::ptr::addr_of_mut!(HEAP).cast::<u64>().add(1) }.cast();
         // initialize the HoleList with a hole_addr one byte before `heap_start`
         // -> the function should align it up to `heap_start`, but then the
         // available size is too small to store a hole -> it should panic
@@ -0,0 +1,90 @@
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+#[cfg(feature = "pressure")]
+use crate::Pressure;
+
+/// Callbacks that are invoked on heap events, e.g. to feed them into a tracing subsystem.
+///
+/// Hooks are installed with [`Heap::set_hooks`][crate::Heap::set_hooks]. All methods have
+/// empty default implementations, so implementors only need to override the events they
+/// are interested in.
+///
+/// The hooks are called while the heap is borrowed mutably, i.e. while the lock of a
+/// [`LockedHeap`][crate::LockedHeap] is held. They must not allocate from the same heap,
+/// as this would deadlock.
+pub trait HeapHooks: Sync {
+    /// Called after a successful allocation of `layout` at `ptr`.
+    fn on_alloc(&self, ptr: NonNull<u8>, layout: Layout, context: &HookContext) {
+        let _ = (ptr, layout, context);
+    }
+
+    /// Called after the allocation of `layout` at `ptr` was freed.
+    fn on_dealloc(&self, ptr: NonNull<u8>, layout: Layout, context: &HookContext) {
+        let _ = (ptr, layout, context);
+    }
+
+    /// Called after an allocation of `layout` failed.
+    fn on_fail(&self, layout: Layout, context: &HookContext) {
+        let _ = (layout, context);
+    }
+
+    /// Called after the heap was extended by `by` bytes.
+    fn on_extend(&self, by: usize, context: &HookContext) {
+        let _ = (by, context);
+    }
+
+    /// Called after the memory of the heap changed to `[bottom, top)`, i.e. after the heap
+    /// was initialized, extended, split, merged or relocated, and when the hooks are
+    /// installed on an initialized heap.
+    ///
+    /// The range covers all memory the heap may access, including bytes that are pending
+    /// for a future extension and the record at the start of a
+    /// [persistent][crate::Heap::init_persistent] heap. This is the place to program an MPU
+    /// or PMP region that faults on accesses just outside the heap, which catches overruns
+    /// of the first and the last allocation in hardware.
+    fn on_bounds(&self, bottom: *mut u8, top: *mut u8, context: &HookContext) {
+        let _ = (bottom, top, context);
+    }
+
+    /// Called after the [pressure level][crate::Heap::set_pressure_thresholds] of the heap
+    /// changed to `level`.
+    ///
+    /// This is the place to shrink caches when memory gets scarce. Since the heap can't be
+    /// used from within the hook, it should only signal the owners of the caches to free
+    /// memory once they get to it.
+    #[cfg(feature = "pressure")]
+    fn on_pressure(&self, level: Pressure, context: &HookContext) {
+        let _ = (level, context);
+    }
+
+    /// Called with the `checksum` feature when the free block at `addr` doesn't match its
+    /// checksum or links to an invalid address, right before the heap panics.
+    ///
+    /// The heap is in an inconsistent state, so this is the place to record the error,
+    /// e.g. in memory that survives a reset, and not to continue. A hook that doesn't
+    /// return, e.g. because it resets the system, prevents the panic.
+    fn on_corruption(&self, addr: NonNull<u8>) {
+        let _ = addr;
+    }
+
+    /// Called with the `redundant` feature after a corrupted copy of the metadata of the
+    /// free block at `addr` was repaired, e.g. to count bit flips in RAM.
+    fn on_repair(&self, addr: NonNull<u8>) {
+        let _ = addr;
+    }
+}
+
+/// The state of the heap after the event that a [`HeapHooks`] method is called for.
+///
+/// The context only contains values that the heap tracks anyway, so it can be created
+/// without walking the list of free blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct HookContext {
+    /// The number of allocated bytes.
+    pub used: usize,
+    /// The usable size of the heap in bytes.
+    pub size: usize,
+}
@@ -0,0 +1,133 @@
+//! Serving large allocations outside of the heap, see
+//! [`Heap::set_large_allocator`][crate::Heap::set_large_allocator].
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Serves large allocations directly, e.g. with whole pages from a frame allocator, so that
+/// they don't split up the free memory of the heap.
+///
+/// It is installed with [`Heap::set_large_allocator`][crate::Heap::set_large_allocator]
+/// and called while the heap is borrowed mutably, i.e. while the lock of a
+/// [`LockedHeap`][crate::LockedHeap] is held. It must not allocate from or free to the same
+/// heap.
+///
+/// # Safety
+///
+/// A block returned by [`allocate`][LargeAllocator::allocate] must be valid for the
+/// layout, must not be used by anything else until it is passed to
+/// [`deallocate`][LargeAllocator::deallocate], and must not overlap the memory of the
+/// heap. [`owns`][LargeAllocator::owns] must return `true` for all pointers into such a
+/// block and `false` for all pointers into the heap.
+pub unsafe trait LargeAllocator: Sync {
+    /// Allocates a block for `layout`, or returns `None` to let the heap serve the
+    /// allocation itself.
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>>;
+
+    /// Frees a block that was returned by [`allocate`][LargeAllocator::allocate].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by `self` for `layout` and not freed yet.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Returns whether `ptr` points into a block that was allocated by `self`.
+    fn owns(&self, ptr: NonNull<u8>) -> bool;
+}
+
+#[cfg(test)]
+mod test {
+    use super::LargeAllocator;
+    use crate::test::{new_heap, Chonk};
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::boxed::Box;
+
+    /// Bump allocates from a separate region and counts the live blocks.
+    struct Pages {
+        base: *mut u8,
+        len: usize,
+        next: AtomicUsize,
+        live: AtomicUsize,
+    }
+
+    unsafe impl Sync for Pages {}
+
+    unsafe impl LargeAllocator for Pages {
+        fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+            let next = self.next.load(Ordering::Relaxed);
+            let start = next + self.base.wrapping_add(next).align_offset(layout.align());
+            if start + layout.size() > self.len {
+                return None;
+            }
+            self.next.store(start + layout.size(), Ordering::Relaxed);
+            self.live.fetch_add(1, Ordering::Relaxed);
+            NonNull::new(self.base.wrapping_add(start))
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+            assert!(self.owns(ptr));
+            self.live.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        fn owns(&self, ptr: NonNull<u8>) -> bool {
+            (ptr.as_ptr() as usize).wrapping_sub(self.base as usize) < self.len
+        }
+    }
+
+    #[test]
+    fn passes_large_allocations_through() {
+        let (chonk, data) = Chonk::<2048>::new();
+        let raw = Box::into_raw(Box::new(Pages {
+            base: data,
+            len: 1280,
+            next: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+        }));
+        // freed through `raw`, which the shared reference is derived from
+        let pages: &'static Pages = unsafe { &*raw };
+        let mut heap = new_heap();
+        heap.set_large_allocator(Some(pages), 256);
+
+        let small = Layout::from_size_align(64, 8).unwrap();
+        let large = Layout::from_size_align(1024, 64).unwrap();
+        let a = heap.allocate_first_fit(small).unwrap();
+        let used = heap.used();
+        let b = heap.allocate_first_fit(large).unwrap();
+        assert!(pages.owns(b) && !heap.contains(b));
+        assert_eq!(b.as_ptr() as usize % 64, 0);
+        assert_eq!(heap.used(), used);
+        assert_eq!(heap.stats().allocations, 2);
+
+        // the heap serves the allocation if the large allocator fails
+        let medium = Layout::from_size_align(512, 8).unwrap();
+        let c = heap.allocate_first_fit(medium).unwrap();
+        assert!(heap.contains(c));
+        assert_eq!(pages.live.load(Ordering::Relaxed), 1);
+
+        unsafe { heap.deallocate(c, medium) };
+
+        // allocations near an address are always served by the heap
+        let near = Layout::from_size_align(256, 8).unwrap();
+        let bottom = heap.bottom();
+        let d = heap.allocate_near(bottom, near).unwrap();
+        assert!(heap.contains(d));
+        assert_eq!(pages.live.load(Ordering::Relaxed), 1);
+
+        unsafe {
+            heap.deallocate(b, large);
+            assert_eq!(pages.live.load(Ordering::Relaxed), 0);
+            heap.deallocate(d, near);
+            heap.deallocate(a, small);
+        }
+        assert_eq!(heap.used(), 0);
+        assert_eq!(heap.stats().deallocations, 4);
+
+        unsafe {
+            heap.set_large_allocator(None, 0);
+            drop(Box::from_raw(raw));
+            Chonk::unleak(chonk);
+        }
+    }
+}
@@ -1,10 +1,28 @@
-#![cfg_attr(feature = "alloc_ref", feature(allocator_api, alloc_layout_extra))]
+#![cfg_attr(feature = "alloc_ref", feature(allocator_api))]
 #![no_std]
 
-#[cfg(any(test, fuzzing))]
+#[cfg(any(test, fuzzing, feature = "std"))]
 #[macro_use]
 extern crate std;
 
+#[cfg(any(
+    feature = "critical_section",
+    // provides the critical section of `std` for the tests
+    all(test, feature = "embassy_sync")
+))]
+extern crate critical_section;
+#[cfg(feature = "defmt")]
+extern crate defmt;
+#[cfg(feature = "embassy_sync")]
+extern crate embassy_sync;
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(all(feature = "use_spin", loom))]
+extern crate loom;
+#[cfg(test)]
+extern crate proptest;
+#[cfg(feature = "uefi")]
+extern crate r_efi;
 #[cfg(feature = "use_spin")]
 extern crate spinning_top;
 
@@ -12,25 +30,219 @@ extern crate spinning_top;
 use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
 #[cfg(feature = "alloc_ref")]
-use core::alloc::{AllocError, Allocator};
-use core::mem::MaybeUninit;
+use core::alloc::{AllocError as CoreAllocError, Allocator};
+use core::mem::{align_of, size_of, MaybeUninit};
 #[cfg(feature = "use_spin")]
-use core::ops::Deref;
+use core::ops::DerefMut;
+use core::ops::RangeInclusive;
+#[cfg(feature = "call_sites")]
+use core::panic::Location;
 use core::ptr::NonNull;
-#[cfg(test)]
 use hole::Hole;
 use hole::HoleList;
+pub use hole::ScanState;
+use hole::MIRROR_SIZE;
+#[cfg(all(feature = "use_spin", feature = "critical_section"))]
+pub use sync::CriticalSectionLock;
+#[cfg(all(
+    feature = "use_spin",
+    feature = "riscv_machine_mode",
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+pub use sync::MachineModeLock;
+#[cfg(feature = "use_spin")]
+use sync::Mutex;
+#[cfg(feature = "use_spin")]
+pub use sync::{
+    BackoffSpinlock, CheckedSpinlock, ExecutionContext, RawMutex, RawSpinlock, SingleContext,
+    TicketLock,
+};
+
+#[cfg(feature = "timestamps")]
+pub use age::{AgeGroup, Clock};
+pub use balloon::FreePages;
+#[cfg(feature = "boot_phase")]
+use boot::BootRegion;
+pub use boxed::{HeapBox, SharedHeap};
+use cache::CacheLine;
+#[cfg(feature = "std")]
+pub use checked::CheckedHeap;
+#[cfg(feature = "use_spin")]
+use deferred::DeferredFrees;
 #[cfg(feature = "use_spin")]
-use spinning_top::Spinlock;
+pub use deferred::MIN_DEFERRED_SIZE;
+#[cfg(feature = "embassy_sync")]
+pub use embassy::EmbassyHeap;
+pub use error::{AdoptError, AllocError};
+pub use external::{ExternalHeap, FreeRange};
+pub use fallback::{FallbackHeap, Owns};
+#[cfg(feature = "freertos")]
+pub use freertos::{pv_port_malloc, v_port_free, FreeRtosHeapStats, FREERTOS_ALIGNMENT};
+pub use freeze::FreezeMode;
+#[cfg(feature = "growth")]
+pub use growth::{Capped, Doubling, FixedIncrement, GrowthPolicy, GrowthSource};
+#[cfg(feature = "headers")]
+pub use header::Allocations;
+#[cfg(feature = "hooks")]
+pub use hooks::{HeapHooks, HookContext};
+#[cfg(feature = "large_alloc")]
+pub use large::LargeAllocator;
+#[cfg(all(feature = "alloc_error_handler", feature = "use_spin"))]
+pub use oom::handle_alloc_error;
+#[cfg(feature = "alloc_error_handler")]
+pub use oom::write_alloc_error_report;
+pub use pages::PageHooks;
+use pages::Pages;
+#[cfg(feature = "pmem")]
+pub use persist::PersistHooks;
+pub use pool::Pool;
+#[cfg(feature = "pressure")]
+use pressure::PressureState;
+#[cfg(feature = "pressure")]
+pub use pressure::{Pressure, PressureThreshold};
+pub use priority::Priority;
+use priority::Reserves;
+#[cfg(feature = "headers")]
+pub use purge::Purger;
+pub use raw::RawHeap;
+#[cfg(all(feature = "use_spin", not(loom)))]
+pub use registry::HeapRegistry;
+#[cfg(all(feature = "use_spin", not(loom)))]
+pub use sharded::ShardedHeap;
+#[cfg(feature = "call_sites")]
+pub use site::{LeakReport, SiteStats};
+pub use split::SplitHeap;
+#[cfg(feature = "use_spin")]
+use stats::SharedCounters;
+use stats::{AlignHistogram, Counters, ALIGN_CLASSES};
+pub use stats::{AlignStats, HeapCounters, HeapStats, HeapStatsDiff};
+#[cfg(feature = "tagging")]
+pub use tagging::MemoryTagger;
+#[cfg(all(feature = "mte", target_arch = "aarch64"))]
+pub use tagging::Mte;
+#[cfg(feature = "uefi")]
+pub use uefi::{UefiRegion, UEFI_PAGE_SIZE};
+#[cfg(all(feature = "use_spin", not(loom)))]
+pub use wake::{AllocateFuture, AsyncHeap};
+#[cfg(feature = "watchpoints")]
+use watch::Watchpoints;
+#[cfg(feature = "watchpoints")]
+pub use watch::{WatchEvent, WatchId, WatchKind, Watcher, MAX_WATCHPOINTS};
 
+#[cfg(feature = "timestamps")]
+mod age;
+mod balloon;
+#[cfg(feature = "boot_phase")]
+mod boot;
+mod boxed;
+mod cache;
+#[cfg(feature = "std")]
+mod checked;
+#[cfg(feature = "use_spin")]
+mod deferred;
+mod dump;
+#[cfg(feature = "embassy_sync")]
+mod embassy;
+mod error;
+mod external;
+mod fallback;
+#[cfg(feature = "freertos")]
+mod freertos;
+mod freeze;
+#[cfg(feature = "growth")]
+mod growth;
+pub mod handle;
+mod header;
 pub mod hole;
+#[cfg(feature = "hooks")]
+mod hooks;
+#[cfg(feature = "large_alloc")]
+mod large;
+mod map;
+#[cfg(feature = "alloc_error_handler")]
+mod oom;
+mod pages;
+#[cfg(feature = "pmem")]
+mod persist;
+mod pool;
+#[cfg(feature = "pressure")]
+mod pressure;
+mod priority;
+#[cfg(feature = "headers")]
+mod purge;
+mod raw;
+#[cfg(all(feature = "use_spin", not(loom)))]
+mod registry;
+mod sanitizer;
+mod scatter;
+#[cfg(all(feature = "use_spin", not(loom)))]
+mod sharded;
+#[cfg(feature = "call_sites")]
+mod site;
+pub mod snapshot;
+mod split;
+mod stats;
+#[cfg(feature = "use_spin")]
+mod sync;
+#[cfg(feature = "tagging")]
+mod tagging;
 #[cfg(test)]
 mod test;
+#[cfg(feature = "hooks")]
+pub mod trace;
+#[cfg(feature = "uefi")]
+mod uefi;
+#[cfg(all(feature = "use_spin", not(loom)))]
+mod wake;
+#[cfg(feature = "watchpoints")]
+mod watch;
 
 /// A fixed size heap backed by a linked list of free memory blocks.
 pub struct Heap {
     used: usize,
     holes: HoleList,
+    counters: Counters,
+    /// The counters since the last call of [`Heap::take_stats`].
+    #[cfg(feature = "interval_stats")]
+    interval: Counters,
+    aligns: AlignHistogram,
+    #[cfg(feature = "hooks")]
+    hooks: Option<&'static dyn HeapHooks>,
+    #[cfg(feature = "tagging")]
+    tagger: Option<&'static dyn MemoryTagger>,
+    cache_line: CacheLine,
+    /// The size that smaller allocations are padded to, see [`Heap::with_min_block_size`].
+    min_block: usize,
+    /// The unused bytes behind every allocation, see [`Heap::set_guard_gap`].
+    guard_gap: usize,
+    reserves: Reserves,
+    #[cfg(feature = "pressure")]
+    pressure: PressureState,
+    #[cfg(feature = "growth")]
+    growth: Option<(&'static dyn GrowthSource, &'static dyn GrowthPolicy)>,
+    /// Set by [`Heap::freeze`].
+    frozen: Option<FreezeMode>,
+    #[cfg(feature = "boot_phase")]
+    boot: BootRegion,
+    /// The allocator for large allocations and the size from which on it is used, see
+    /// [`Heap::set_large_allocator`].
+    #[cfg(feature = "large_alloc")]
+    large: Option<(&'static dyn LargeAllocator, usize)>,
+    #[cfg(feature = "headers")]
+    purger: Option<&'static dyn Purger>,
+    /// The table of [`Heap::set_site_stats`], which the heap borrows mutably. It is kept as
+    /// a pointer, since `Heap::empty` can't hold a mutable reference in a const fn on the
+    /// minimum supported Rust version.
+    #[cfg(feature = "call_sites")]
+    site_stats: Option<NonNull<[SiteStats]>>,
+    /// The call site of the next allocation, set by wrappers that call the heap from a
+    /// closure.
+    #[cfg(feature = "call_sites")]
+    caller: Option<&'static Location<'static>>,
+    #[cfg(feature = "timestamps")]
+    clock: Option<&'static dyn Clock>,
+    #[cfg(feature = "watchpoints")]
+    watchpoints: Watchpoints,
 }
 
 #[cfg(fuzzing)]
@@ -49,12 +261,79 @@ impl Heap {
 
 unsafe impl Send for Heap {}
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Heap {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Heap {{ bottom: {=usize:#x}, top: {=usize:#x}, size: {=usize}, used: {=usize}, free: {=usize} }}",
+            self.bottom() as usize,
+            self.top() as usize,
+            self.size(),
+            self.used,
+            self.free(),
+        )
+    }
+}
+
 impl Heap {
     /// Creates an empty heap. All allocate calls will return `None`.
     pub const fn empty() -> Heap {
         Heap {
             used: 0,
             holes: HoleList::empty(),
+            counters: Counters::new(),
+            #[cfg(feature = "interval_stats")]
+            interval: Counters::new(),
+            aligns: AlignHistogram::new(),
+            #[cfg(feature = "hooks")]
+            hooks: None,
+            #[cfg(feature = "tagging")]
+            tagger: None,
+            cache_line: CacheLine::new(),
+            min_block: 0,
+            guard_gap: 0,
+            reserves: Reserves::new(),
+            #[cfg(feature = "pressure")]
+            pressure: PressureState::new(),
+            #[cfg(feature = "growth")]
+            growth: None,
+            frozen: None,
+            #[cfg(feature = "boot_phase")]
+            boot: BootRegion::new(),
+            #[cfg(feature = "large_alloc")]
+            large: None,
+            #[cfg(feature = "headers")]
+            purger: None,
+            #[cfg(feature = "call_sites")]
+            site_stats: None,
+            #[cfg(feature = "call_sites")]
+            caller: None,
+            #[cfg(feature = "timestamps")]
+            clock: None,
+            #[cfg(feature = "watchpoints")]
+            watchpoints: Watchpoints::new(),
+        }
+    }
+
+    /// Creates an empty heap that pads all allocations that are smaller than `size` bytes
+    /// to `size` bytes. Like a heap created by [`empty`][Heap::empty], it has to be
+    /// initialized before use.
+    ///
+    /// This trades memory for fewer, larger free blocks, e.g. to guarantee that freed
+    /// blocks can be reused for allocations up to `size` bytes or to keep small allocations
+    /// on separate cache lines. To also align allocations to cache lines, use
+    /// [`set_cache_line`][Heap::set_cache_line]. Blocks are never smaller than
+    /// [`HoleList::min_size`] bytes, since a freed block has to hold the metadata of a free
+    /// block, so smaller sizes have no effect. The recorded layout of an allocation, see
+    /// [`allocation_layout`][Heap::allocation_layout], includes the padding.
+    ///
+    /// The minimum block size can't be changed afterwards, since allocations must be freed
+    /// with the size that they were padded to.
+    pub const fn with_min_block_size(size: usize) -> Heap {
+        Heap {
+            min_block: size,
+            ..Heap::empty()
         }
     }
 
@@ -66,12 +345,13 @@ impl Heap {
     /// The given `heap_size` must be large enough to store the required
     /// metadata, otherwise this function will panic. Depending on the
     /// alignment of the `hole_addr` pointer, the minimum size is between
-    /// `2 * size_of::<usize>` and `3 * size_of::<usize>`.
+    /// [`min_allocation_size`][Heap::min_allocation_size] and one
+    /// [`allocation_granularity`][Heap::allocation_granularity] more.
     ///
-    /// The usable size for allocations will be truncated to the nearest
-    /// alignment of `align_of::<usize>`. Any extra bytes left at the end
-    /// will be reclaimed once sufficient additional space is given to
-    /// [`extend`][Heap::extend].
+    /// The usable size for allocations will be truncated to a multiple of
+    /// [`allocation_granularity`][Heap::allocation_granularity]. Any extra bytes left
+    /// at the end will be reclaimed once sufficient additional space is
+    /// given to [`extend`][Heap::extend].
     ///
     /// # Safety
     ///
@@ -85,8 +365,92 @@ impl Heap {
     ///
     /// The provided memory range must be valid for the `'static` lifetime.
     pub unsafe fn init(&mut self, heap_bottom: *mut u8, heap_size: usize) {
+        let key = self.holes.key;
+        let pages = self.holes.pages;
         self.used = 0;
         self.holes = HoleList::new(heap_bottom, heap_size);
+        self.holes.set_key(key);
+        self.holes.pages = pages;
+        self.reset_state();
+        self.bounds_changed();
+    }
+
+    /// Like [`init`][Heap::init], but additionally declares that the given memory is
+    /// zeroed.
+    ///
+    /// The heap then keeps track of the free memory that was never handed out, so that
+    /// [`allocate_zeroed`][Heap::allocate_zeroed] can skip zeroing it again.
+    ///
+    /// # Safety
+    ///
+    /// All requirements of [`init`][Heap::init] apply. In addition, all bytes in the
+    /// `[heap_bottom, heap_bottom + heap_size)` range must be zero.
+    pub unsafe fn init_zeroed(&mut self, heap_bottom: *mut u8, heap_size: usize) {
+        self.init(heap_bottom, heap_size);
+        self.holes.zeroed_from = self.holes.bottom;
+    }
+
+    /// Like [`init`][Heap::init], but keeps the list of free memory blocks entirely in the
+    /// given memory, so that it can be [adopted][Heap::adopt] by another `Heap` later.
+    ///
+    /// This is meant for memory whose contents survive a warm reboot, e.g. battery-backed
+    /// SRAM or MRAM, while the `Heap` itself doesn't. A small record at the start of the
+    /// memory is taken for the start of the list and the bounds of the heap, so
+    /// [`bottom`][Self::bottom] lies behind it.
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`init`][Heap::init] apply.
+    pub unsafe fn init_persistent(&mut self, heap_bottom: *mut u8, heap_size: usize) {
+        let key = self.holes.key;
+        let pages = self.holes.pages;
+        self.used = 0;
+        self.holes = HoleList::new_persistent(heap_bottom, heap_size);
+        self.holes.set_key(key);
+        self.holes.pages = pages;
+        self.reset_state();
+        self.bounds_changed();
+    }
+
+    /// Resumes using a heap that was initialized with
+    /// [`init_persistent`][Heap::init_persistent] at the same `heap_bottom`, e.g. before a
+    /// warm reboot, instead of initializing it again.
+    ///
+    /// The list of free memory blocks is validated before it is used: the memory must
+    /// start with the record of a persistent heap that lies within
+    /// `[heap_bottom, heap_bottom + heap_size)`, and the free blocks must be aligned,
+    /// sorted by address and within the bounds of the heap. With the `checksum` feature,
+    /// their headers must also match their checksums. If any check fails, an
+    /// [`AdoptError`] is returned and the heap is left unchanged, so that it can be
+    /// initialized from scratch instead.
+    ///
+    /// All memory that isn't part of a free block is considered allocated, so the
+    /// allocations that were live before stay valid. The statistics start from zero. With
+    /// the `safe_linking` or `checksum` feature, the heap must have the same
+    /// [link key][Heap::set_link_key] as the heap that wrote the list.
+    ///
+    /// This only catches corruption of the list itself, e.g. after the reboot interrupted
+    /// an allocation. It can't tell whether the allocations are intact.
+    ///
+    /// # Safety
+    ///
+    /// This function must only be used on an empty heap. The memory in the
+    /// `[heap_bottom, heap_bottom + heap_size)` range must be valid for reads and writes
+    /// and must not be used for anything else, except for the allocations of the adopted
+    /// heap. It must be valid for the `'static` lifetime.
+    pub unsafe fn adopt(
+        &mut self,
+        heap_bottom: *mut u8,
+        heap_size: usize,
+    ) -> Result<(), AdoptError> {
+        let (holes, free) = HoleList::adopt(heap_bottom, heap_size, self.holes.key)?;
+        let pages = self.holes.pages;
+        self.holes = holes;
+        self.holes.pages = pages;
+        self.used = self.size() - free;
+        self.reset_state();
+        self.bounds_changed();
+        Ok(())
     }
 
     /// Initialize an empty heap with provided memory.
@@ -100,10 +464,10 @@ impl Heap {
     /// deallocation (e.g. a simple bump allocator). Then the overlaid linked-list-allocator can
     /// provide memory reclamation.
     ///
-    /// The usable size for allocations will be truncated to the nearest
-    /// alignment of `align_of::<usize>`. Any extra bytes left at the end
-    /// will be reclaimed once sufficient additional space is given to
-    /// [`extend`][Heap::extend].
+    /// The usable size for allocations will be truncated to a multiple of
+    /// [`allocation_granularity`][Heap::allocation_granularity]. Any extra bytes left
+    /// at the end will be reclaimed once sufficient additional space is
+    /// given to [`extend`][Heap::extend].
     ///
     /// # Panics
     ///
@@ -111,7 +475,8 @@ impl Heap {
     ///
     /// It also panics when the length of the given `mem` slice is not large enough to
     /// store the required metadata. Depending on the alignment of the slice, the minimum
-    /// size is between `2 * size_of::<usize>` and `3 * size_of::<usize>`.
+    /// size is between [`min_allocation_size`][Heap::min_allocation_size] and one
+    /// [`allocation_granularity`][Heap::allocation_granularity] more.
     pub fn init_from_slice(&mut self, mem: &'static mut [MaybeUninit<u8>]) {
         assert!(
             self.bottom().is_null(),
@@ -135,12 +500,13 @@ impl Heap {
     /// The given `heap_size` must be large enough to store the required
     /// metadata, otherwise this function will panic. Depending on the
     /// alignment of the `hole_addr` pointer, the minimum size is between
-    /// `2 * size_of::<usize>` and `3 * size_of::<usize>`.
+    /// [`min_allocation_size`][Heap::min_allocation_size] and one
+    /// [`allocation_granularity`][Heap::allocation_granularity] more.
     ///
-    /// The usable size for allocations will be truncated to the nearest
-    /// alignment of `align_of::<usize>`. Any extra bytes left at the end
-    /// will be reclaimed once sufficient additional space is given to
-    /// [`extend`][Heap::extend].
+    /// The usable size for allocations will be truncated to a multiple of
+    /// [`allocation_granularity`][Heap::allocation_granularity]. Any extra bytes left
+    /// at the end will be reclaimed once sufficient additional space is
+    /// given to [`extend`][Heap::extend].
     ///
     /// # Safety
     ///
@@ -152,8 +518,8 @@ impl Heap {
     /// The provided memory range must be valid for the `'static` lifetime.
     pub unsafe fn new(heap_bottom: *mut u8, heap_size: usize) -> Heap {
         Heap {
-            used: 0,
             holes: HoleList::new(heap_bottom, heap_size),
+            ..Heap::empty()
         }
     }
 
@@ -171,21 +537,647 @@ impl Heap {
     }
 
     /// Allocates a chunk of the given size with the given alignment. Returns a pointer to the
-    /// beginning of that chunk if it was successful. Else it returns `None`.
+    /// beginning of that chunk if it was successful. Else it returns an [`AllocError`] that
+    /// describes why the allocation failed.
     /// This function scans the list of free memory blocks and uses the first block that is big
     /// enough. The runtime is in O(n) where n is the number of free blocks, but it should be
     /// reasonably fast for small allocations.
-    //
-    // NOTE: We could probably replace this with an `Option` instead of a `Result` in a later
-    // release to remove this clippy warning
-    #[allow(clippy::result_unit_err)]
-    pub fn allocate_first_fit(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
-        match self.holes.allocate_first_fit(layout) {
-            Ok((ptr, aligned_layout)) => {
-                self.used += aligned_layout.size();
+    ///
+    /// Zero-sized layouts don't take any memory. They return an aligned dangling pointer,
+    /// which is passed to [`deallocate`][Heap::deallocate] like any other allocation. This
+    /// applies to all variants of this method.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_first_fit(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let result = self.allocate_block(layout, 0, Priority::Normal);
+        self.record_allocation(layout, result)
+    }
+
+    /// Like [`allocate_first_fit`][Heap::allocate_first_fit], but gives up with
+    /// [`AllocError::SearchLimit`] after examining `max_holes` free blocks.
+    ///
+    /// This bounds the runtime of the allocation independently of the fragmentation of the
+    /// heap, e.g. for code with hard deadlines that prefers a deterministic failure over an
+    /// unbounded search. [Purgeable][Heap::allocate_purgeable] allocations are not purged
+    /// to make room for the allocation.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_first_fit_bounded(
+        &mut self,
+        layout: Layout,
+        max_holes: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let result = self.allocate_block_after(&mut None, layout, 0, Priority::Normal, max_holes);
+        self.record_allocation(layout, result)
+    }
+
+    /// Like [`allocate_first_fit`][Heap::allocate_first_fit], but places the allocation as
+    /// close to `addr` as possible, e.g. to keep related buffers in the same memory region.
+    ///
+    /// All free blocks are considered and the allocation may be taken from the middle or
+    /// the end of a free block, so the runtime is always in O(n) where n is the number of
+    /// free blocks. [Purgeable][Heap::allocate_purgeable] allocations are not purged for
+    /// the allocation, and the [large allocator][Heap::set_large_allocator] is never used
+    /// for it, since it can't place the allocation near `addr`.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_near(
+        &mut self,
+        addr: *const u8,
+        layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let result = self.allocate_block_near(addr as *mut u8, layout);
+        self.record_allocation(layout, result)
+    }
+
+    /// Allocates `n` blocks with the same layout and adds them to `out`.
+    ///
+    /// The blocks are placed exactly like `n` calls to
+    /// [`allocate_first_fit`][Heap::allocate_first_fit] would place them, but the list of
+    /// free blocks is only scanned once, so the runtime is in O(n + m) where m is the number
+    /// of free blocks. If an allocation fails, its error is returned and the blocks that were
+    /// allocated before stay allocated and in `out`.
+    ///
+    /// `out` must not allocate from this heap while it is locked, e.g. a `Vec` should have
+    /// enough capacity reserved beforehand.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_many<E: Extend<NonNull<u8>>>(
+        &mut self,
+        layout: Layout,
+        n: usize,
+        out: &mut E,
+    ) -> Result<(), AllocError> {
+        let mut start = None;
+        for _ in 0..n {
+            let result =
+                self.allocate_block_after(&mut start, layout, 0, Priority::Normal, usize::MAX);
+            out.extend(Some(self.record_allocation(layout, result)?));
+        }
+        Ok(())
+    }
+
+    /// Like [`allocate_first_fit`][Heap::allocate_first_fit], but with the given priority.
+    ///
+    /// Fails with [`AllocError::Reserved`] if the allocation would leave less free memory
+    /// behind than is [reserved][Heap::set_reserve] for the priority.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_with_priority(
+        &mut self,
+        layout: Layout,
+        priority: Priority,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let result = self.allocate_block(layout, 0, priority);
+        self.record_allocation(layout, result)
+    }
+
+    /// Like [`allocate_first_fit`][Heap::allocate_first_fit], but aligns the address `offset`
+    /// bytes after the returned pointer instead of the pointer itself.
+    ///
+    /// This is useful for buffers that start with a header that is followed by an aligned
+    /// payload, as the block is placed so that no padding is needed between the two. The
+    /// allocation is freed with [`deallocate`][Heap::deallocate] and the same `layout`.
+    ///
+    /// Only `offset % layout.align()` is relevant, and it must be a multiple of
+    /// `align_of::<usize>()`, otherwise [`AllocError::InvalidLayout`] is returned. The same
+    /// happens if a [`MemoryTagger`] or a [cache line size][Heap::set_cache_line] is set,
+    /// since allocations must start at a tag granule or cache line then.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_first_fit_with_offset(
+        &mut self,
+        layout: Layout,
+        offset: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let offset = offset % layout.align();
+        let result = if offset % align_of::<usize>() != 0 || (offset != 0 && self.granule() > 1) {
+            Err(AllocError::InvalidLayout)
+        } else {
+            self.allocate_block(layout, offset, Priority::Normal)
+        };
+        self.record_allocation(layout, result)
+    }
+
+    /// Like [`allocate_first_fit`][Heap::allocate_first_fit], but returns the whole usable
+    /// part of the allocated block.
+    ///
+    /// The length of the returned slice is at least `layout.size()`, but includes any bytes
+    /// that were added by rounding up the allocation. All of these bytes may be used by the
+    /// caller. The allocation can be freed with the original layout or with a layout whose
+    /// size is any value between `layout.size()` and the length of the returned slice.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_first_fit_slice(
+        &mut self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let result = self.allocate_block_within(layout, layout.size());
+        let len = result.map_or(0, |(_, actual)| actual.size());
+        self.record_allocation(layout, result.map(|(ptr, _)| ptr))
+            .map(|ptr| {
+                let slice = core::ptr::slice_from_raw_parts_mut(ptr.as_ptr(), len);
+                // SAFETY: The slice starts at a non-null pointer.
+                unsafe { NonNull::new_unchecked(slice) }
+            })
+    }
+
+    /// Allocates a block of at least `*size.start()` and up to `*size.end()` bytes with the
+    /// given alignment.
+    ///
+    /// The first free block that can hold the minimum size is used, and the allocation is
+    /// enlarged to take as much of that block as possible, up to the maximum size. Returns the
+    /// pointer and the actual size of the allocation, which must be used for freeing it.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_within(
+        &mut self,
+        size: RangeInclusive<usize>,
+        align: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocError> {
+        let (min, max) = size.into_inner();
+        if min > max {
+            return Err(AllocError::InvalidLayout);
+        }
+        let layout = Layout::from_size_align(min, align).map_err(|_| AllocError::InvalidLayout)?;
+        let result = self.allocate_block_within(layout, max);
+        let actual = result.map_or(layout, |(_, actual)| actual);
+        self.record_allocation(actual, result.map(|(ptr, _)| ptr))
+            .map(|ptr| (ptr, actual.size()))
+    }
+
+    /// Like [`allocate_first_fit`][Heap::allocate_first_fit], but the returned memory is
+    /// zeroed.
+    ///
+    /// If the heap was initialized with [`init_zeroed`][Heap::init_zeroed], memory that was
+    /// never allocated before is known to be zero already, so only the bookkeeping data
+    /// that the heap stored in it is cleared. All other memory is zeroed explicitly.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_zeroed(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let zeroed_from = self.holes.zeroed_from;
+        let ptr = self.allocate_first_fit(layout)?;
+        // the `redundant` feature leaves copies of the bookkeeping data all over free memory
+        let len = if ptr.as_ptr() >= zeroed_from && !cfg!(feature = "redundant") {
+            // only the header of the hole that the block was taken from might be non-zero
+            layout.size().min(HoleList::min_size())
+        } else {
+            layout.size()
+        };
+        // SAFETY: The allocation is valid for `layout.size()` bytes.
+        unsafe {
+            ptr.as_ptr().write_bytes(0, len);
+            sanitizer::mark_initialized(ptr.as_ptr(), layout.size());
+        }
+        Ok(ptr)
+    }
+
+    /// Updates the counters, calls the hooks and logs the result of an allocation. With the
+    /// `call_sites` feature, the caller is recorded as the call site of the allocation.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    fn record_allocation(
+        &mut self,
+        layout: Layout,
+        result: Result<NonNull<u8>, AllocError>,
+    ) -> Result<NonNull<u8>, AllocError> {
+        // the layout is only needed by the hooks and the other optional features
+        let _ = layout;
+        #[cfg(feature = "call_sites")]
+        let site = match self.caller.take() {
+            Some(site) => site,
+            None => Location::caller(),
+        };
+        match result {
+            Ok(ptr) => {
+                #[cfg(feature = "call_sites")]
+                self.record_site(ptr, layout, site);
+                #[cfg(feature = "timestamps")]
+                self.record_time(ptr, layout);
+                self.counters.record_allocation(self.used);
+                #[cfg(feature = "interval_stats")]
+                self.interval.record_allocation(self.used);
+                #[cfg(feature = "hooks")]
+                if let Some(hooks) = self.hooks {
+                    hooks.on_alloc(ptr, layout, &self.hook_context());
+                }
+                #[cfg(feature = "watchpoints")]
+                self.notify_watchers(WatchKind::Allocate, ptr, layout);
+                self.update_pressure();
+                #[cfg(feature = "log")]
+                log::trace!(
+                    "allocated {:?} at {:p}, used: {}, holes: {}",
+                    layout,
+                    ptr,
+                    self.used,
+                    self.holes.holes().count()
+                );
                 Ok(ptr)
             }
-            Err(err) => Err(err),
+            Err(err) => {
+                self.counters.record_failure();
+                #[cfg(feature = "interval_stats")]
+                self.interval.record_failure();
+                #[cfg(feature = "hooks")]
+                if let Some(hooks) = self.hooks {
+                    hooks.on_fail(layout, &self.hook_context());
+                }
+                #[cfg(feature = "log")]
+                log::debug!(
+                    "failed to allocate {:?}: {}, free: {}, holes: {}",
+                    layout,
+                    err,
+                    self.free(),
+                    self.holes.holes().count()
+                );
+                Err(err)
+            }
+        }
+    }
+
+    /// Allocates a block for `layout` from the hole list and writes its header. The payload
+    /// is placed so that its address plus `offset` is aligned.
+    fn allocate_block(
+        &mut self,
+        layout: Layout,
+        offset: usize,
+        priority: Priority,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.allocate_block_after(&mut None, layout, offset, priority, usize::MAX)
+    }
+
+    /// Like [`allocate_block`][Self::allocate_block], but continues the search of a previous
+    /// allocation and examines at most `max_holes` holes, see
+    /// [`HoleList::allocate_first_fit_after`]. Purgeable allocations are only purged if the
+    /// search is unbounded.
+    fn allocate_block_after(
+        &mut self,
+        start: &mut Option<NonNull<Hole>>,
+        layout: Layout,
+        offset: usize,
+        priority: Priority,
+        max_holes: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.check_frozen()?;
+        if layout.size() == 0 {
+            return Ok(dangling(layout.align()));
+        }
+        if offset == 0 {
+            if let Some(ptr) = self.allocate_large(layout) {
+                return Ok(ptr);
+            }
+        }
+        #[cfg(feature = "boot_phase")]
+        if self.boot.is_active() {
+            return self.allocate_boot(layout, offset);
+        }
+        let requested = layout;
+        let layout = self.padded_layout(layout)?;
+        let (block_layout, offset, block_offset) = match self.cache_line.next_color(layout.align())
+        {
+            // the caller's offset is zero, since offsets are rejected with a cache line size
+            Some((align, color_offset)) => {
+                let colored = Layout::from_size_align(layout.size(), align)
+                    .map_err(|_| AllocError::InvalidLayout)?;
+                header::block_layout_placed(colored, color_offset)?
+            }
+            None => header::block_layout_with_offset(layout, offset)?,
+        };
+        let aligned_layout =
+            HoleList::align_layout(block_layout).map_err(|_| AllocError::InvalidLayout)?;
+        let (block, aligned_layout, stranded) = loop {
+            let result = self
+                .check_reserve(aligned_layout.size(), priority)
+                .and_then(|()| {
+                    self.holes.allocate_first_fit_after(
+                        start,
+                        block_layout,
+                        block_offset,
+                        self.holes.top,
+                        max_holes,
+                    )
+                });
+            match result {
+                Err(err)
+                    if err != AllocError::InvalidLayout
+                        && max_holes == usize::MAX
+                        && (self.purge_one() || self.grow(aligned_layout)) =>
+                {
+                    // purging merges free blocks, which might include the one to start at
+                    *start = None;
+                }
+                result => break result?,
+            }
+        };
+        // padding in front of the block that is too small for a hole counts as used
+        self.charge(requested, aligned_layout.size() + stranded);
+        // SAFETY: The block was just allocated for `block_layout`.
+        Ok(unsafe { self.write_block(block, aligned_layout.size(), layout, offset) })
+    }
+
+    /// Like [`allocate_block`][Self::allocate_block], but places the block as close to
+    /// `target` as possible, see [`HoleList::allocate_near`].
+    fn allocate_block_near(
+        &mut self,
+        target: *mut u8,
+        layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.check_frozen()?;
+        if layout.size() == 0 {
+            return Ok(dangling(layout.align()));
+        }
+        #[cfg(feature = "boot_phase")]
+        if self.boot.is_active() {
+            return self.allocate_boot(layout, 0);
+        }
+        let requested = layout;
+        let layout = self.padded_layout(layout)?;
+        let (block_layout, offset, block_offset) = header::block_layout_with_offset(layout, 0)?;
+        let aligned_layout =
+            HoleList::align_layout(block_layout).map_err(|_| AllocError::InvalidLayout)?;
+        self.check_reserve(aligned_layout.size(), Priority::Normal)?;
+        let (block, aligned_layout, stranded) =
+            self.holes
+                .allocate_near(target, block_layout, block_offset)?;
+        // a rest behind the block that is too small for a hole counts as used
+        self.charge(requested, aligned_layout.size() + stranded);
+        // SAFETY: The block was just allocated for `block_layout`.
+        Ok(unsafe { self.write_block(block, aligned_layout.size(), layout, offset) })
+    }
+
+    /// Allocates `layout` from the [large allocator][Heap::set_large_allocator] if one is
+    /// installed and the allocation is large enough. Returns `None` if the heap has to serve
+    /// the allocation itself.
+    #[cfg(feature = "large_alloc")]
+    fn allocate_large(&self, layout: Layout) -> Option<NonNull<u8>> {
+        match self.large {
+            Some((large, threshold)) if layout.size() >= threshold => large.allocate(layout),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(feature = "large_alloc"))]
+    fn allocate_large(&self, _layout: Layout) -> Option<NonNull<u8>> {
+        None
+    }
+
+    /// Returns whether the [large allocator][Heap::set_large_allocator] owns `ptr`.
+    #[cfg(feature = "large_alloc")]
+    fn is_large(&self, ptr: NonNull<u8>) -> bool {
+        self.large
+            .map_or(false, |(large, _)| large.owns(self.strip_tag(ptr)))
+    }
+
+    #[cfg(not(feature = "large_alloc"))]
+    fn is_large(&self, _ptr: NonNull<u8>) -> bool {
+        false
+    }
+
+    /// Frees `ptr` through the [large allocator][Heap::set_large_allocator] if it owns it.
+    /// Returns `false` if the allocation belongs to the heap memory.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation of this heap with the given `layout`.
+    #[cfg(feature = "large_alloc")]
+    unsafe fn deallocate_large(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        match self.large {
+            Some((large, _)) if large.owns(self.strip_tag(ptr)) => {
+                large.deallocate(ptr, layout);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    #[cfg(not(feature = "large_alloc"))]
+    unsafe fn deallocate_large(&self, _ptr: NonNull<u8>, _layout: Layout) -> bool {
+        false
+    }
+
+    /// Returns whether `ptr` belongs to the heap memory or to the
+    /// [large allocator][Heap::set_large_allocator], so that wrappers route its
+    /// deallocation to this heap.
+    #[cfg(any(feature = "use_spin", feature = "embassy_sync"))]
+    pub(crate) fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.contains(ptr) || self.is_large(ptr)
+    }
+
+    /// Fails with [`AllocError::Frozen`] or panics if the heap was [frozen][Heap::freeze].
+    fn check_frozen(&self) -> Result<(), AllocError> {
+        match self.frozen {
+            None => Ok(()),
+            Some(FreezeMode::Fail) => Err(AllocError::Frozen),
+            Some(FreezeMode::Panic) => panic!("allocation from a frozen heap"),
+        }
+    }
+
+    /// Writes the header of a newly allocated block of `size` bytes and returns the payload,
+    /// tagged if a [`MemoryTagger`] is installed.
+    ///
+    /// # Safety
+    ///
+    /// The block must have been allocated for the block layout of `layout` and `offset`.
+    unsafe fn write_block(
+        &self,
+        block: NonNull<u8>,
+        size: usize,
+        layout: Layout,
+        offset: usize,
+    ) -> NonNull<u8> {
+        // the recorded layout leaves out the guard gap, which freeing with it adds again
+        let payload = header::write(block, size, self.without_guard_gap(layout), offset);
+        #[cfg(feature = "tagging")]
+        if let Some(tagger) = self.tagger {
+            // SAFETY: The payload is aligned to and padded to whole granules.
+            return tagger.tag(payload, layout.size());
+        }
+        payload
+    }
+
+    /// Frees the first [purgeable][Heap::allocate_purgeable] allocation after calling the
+    /// installed [`Purger`] for it. Returns `false` if there is none or no purger is
+    /// installed.
+    #[cfg(feature = "headers")]
+    fn purge_one(&mut self) -> bool {
+        let purger = match self.purger {
+            Some(purger) => purger,
+            None => return false,
+        };
+        let mut blocks = Allocations::new(&self.holes, self.bottom());
+        let purgeable = loop {
+            match blocks.next_block() {
+                Some(block) => {
+                    // SAFETY: The iterator only yields live blocks.
+                    let header = unsafe { header::Header::of_block(block) };
+                    if header.is_purgeable() {
+                        break (header.payload(block), header.layout);
+                    }
+                }
+                None => return false,
+            }
+        };
+        let (ptr, layout) = purgeable;
+        purger.purge(ptr, layout);
+        // SAFETY: The allocation is live and was made with the recorded layout, which is
+        // already rounded up to whole granules.
+        unsafe { self.deallocate(ptr, layout) };
+        true
+    }
+
+    #[cfg(not(feature = "headers"))]
+    fn purge_one(&mut self) -> bool {
+        false
+    }
+
+    /// Extends the heap from the installed [`GrowthSource`] by the amount that the
+    /// [`GrowthPolicy`] decides for a block with the given layout. Returns `false` if the
+    /// heap didn't grow.
+    #[cfg(feature = "growth")]
+    fn grow(&mut self, block_layout: Layout) -> bool {
+        let (source, policy) = match self.growth {
+            Some(growth) if !self.bottom().is_null() => growth,
+            _ => return false,
+        };
+        // the block might need padding in front of it to be aligned
+        let padding = block_layout.align().saturating_sub(align_of::<Hole>());
+        let needed = block_layout.size().saturating_add(padding);
+        let by = policy.grow_by(needed, self.size());
+        if by < needed {
+            return false;
+        }
+        // SAFETY: The top of an initialized heap is not null.
+        let grown = source.grow(unsafe { NonNull::new_unchecked(self.top()) }, by);
+        if grown == 0 {
+            return false;
+        }
+        // SAFETY: The source guarantees that the memory follows the heap and is unused.
+        unsafe { self.extend(grown) };
+        true
+    }
+
+    #[cfg(not(feature = "growth"))]
+    fn grow(&mut self, _block_layout: Layout) -> bool {
+        false
+    }
+
+    /// Returns the size that allocations are aligned to and padded to, which is the larger
+    /// one of the tag granule of the installed [`MemoryTagger`] and the
+    /// [cache line size][Heap::set_cache_line], or 1 if neither is set.
+    fn granule(&self) -> usize {
+        #[cfg(feature = "tagging")]
+        let tag_granule = self.tagger.map_or(1, |tagger| tagger.granule_size());
+        #[cfg(not(feature = "tagging"))]
+        let tag_granule = 1;
+        tag_granule.max(self.cache_line.size())
+    }
+
+    /// Pads the layout to the [minimum block size][Self::with_min_block_size], appends the
+    /// [guard gap][Self::set_guard_gap] and rounds it up to whole [granules][Self::granule].
+    fn padded_layout(&self, layout: Layout) -> Result<Layout, AllocError> {
+        let granule = self.granule();
+        if granule == 1 && self.guard_gap == 0 && layout.size() >= self.min_block {
+            return Ok(layout);
+        }
+        let size = layout
+            .size()
+            .max(self.min_block)
+            .checked_add(self.guard_gap)
+            .and_then(|size| checked_align_up_size(size, granule))
+            .ok_or(AllocError::InvalidLayout)?;
+        Layout::from_size_align(size, layout.align().max(granule))
+            .map_err(|_| AllocError::InvalidLayout)
+    }
+
+    /// Removes the [guard gap][Self::set_guard_gap] from a layout that was returned by
+    /// [`padded_layout`][Self::padded_layout].
+    fn without_guard_gap(&self, layout: Layout) -> Layout {
+        // SAFETY: The padded size includes the gap, and the alignment is unchanged.
+        unsafe { Layout::from_size_align_unchecked(layout.size() - self.guard_gap, layout.align()) }
+    }
+
+    /// Calls the [watchers][Self::watch] whose range overlaps the allocation at `ptr`.
+    #[cfg(feature = "watchpoints")]
+    fn notify_watchers(&self, kind: WatchKind, ptr: NonNull<u8>, layout: Layout) {
+        let addr = self.strip_tag(ptr).as_ptr() as usize;
+        self.watchpoints.notify(kind, ptr, addr, layout);
+    }
+
+    /// Removes the memory tag from `ptr` if a [`MemoryTagger`] is installed.
+    fn strip_tag(&self, ptr: NonNull<u8>) -> NonNull<u8> {
+        #[cfg(feature = "tagging")]
+        if let Some(tagger) = self.tagger {
+            return tagger.strip_tag(ptr);
+        }
+        ptr
+    }
+
+    /// Returns whether the new allocation at `ptr` with the given layout has a header of its
+    /// own, which zero-sized, large and boot allocations don't.
+    #[cfg(any(feature = "call_sites", feature = "timestamps"))]
+    pub(crate) fn has_own_header(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        #[cfg(feature = "boot_phase")]
+        if self.boot.is_active() {
+            return false;
+        }
+        layout.size() != 0 && !self.is_large(ptr)
+    }
+
+    /// Allocates a block for at least `layout` and up to `max` bytes from the hole list and
+    /// writes its header. Returns the payload and its actual layout.
+    fn allocate_block_within(
+        &mut self,
+        layout: Layout,
+        max: usize,
+    ) -> Result<(NonNull<u8>, Layout), AllocError> {
+        self.check_frozen()?;
+        if max == 0 {
+            return Ok((dangling(layout.align()), layout));
+        }
+        if let Some(ptr) = self.allocate_large(layout) {
+            return Ok((ptr, layout));
+        }
+        #[cfg(feature = "boot_phase")]
+        if self.boot.is_active() {
+            return self.allocate_boot(layout, 0).map(|ptr| (ptr, layout));
+        }
+        if self.granule() > 1 || self.guard_gap != 0 {
+            // an enlarged block might end within a granule that it shares with the next block,
+            // or take the guard gap
+            return self
+                .allocate_block(layout, 0, Priority::Normal)
+                .map(|ptr| (ptr, layout));
+        }
+        let layout = self.padded_layout(layout)?;
+        let (block_layout, offset) = header::block_layout(layout)?;
+        let min_block = HoleList::align_layout(block_layout)
+            .map_err(|_| AllocError::InvalidLayout)?
+            .size();
+        self.check_reserve(min_block, Priority::Normal)?;
+        // don't enlarge the block into the reserved memory
+        let unreserved = self
+            .free()
+            .saturating_sub(self.reserves.get(Priority::Normal));
+        let max_block = max.saturating_add(offset).min(unreserved);
+        let (block, aligned_layout, stranded) = self
+            .holes
+            .allocate_first_fit_within(block_layout, max_block)?;
+        // SAFETY: The payload lies within the block, whose size is a valid layout size, and the
+        // alignment was taken from a valid layout.
+        let actual = unsafe {
+            Layout::from_size_align_unchecked(aligned_layout.size() - offset, layout.align())
+        };
+        self.charge(actual, aligned_layout.size() + stranded);
+        // SAFETY: The block was just allocated for a block layout with the same alignment and
+        // header offset as `block_layout`.
+        let payload = unsafe { header::write(block, aligned_layout.size(), actual, offset) };
+        Ok((payload, actual))
+    }
+
+    /// Counts `size` bytes that were taken from the heap for an allocation with the given
+    /// layout as used.
+    fn charge(&mut self, layout: Layout, size: usize) {
+        self.used += size;
+        self.aligns
+            .record_allocation(layout.align(), size.saturating_sub(layout.size()));
+    }
+
+    /// Fails with [`AllocError::Reserved`] if allocating a block of `size` bytes would leave
+    /// less free memory behind than is reserved for `priority`.
+    fn check_reserve(&self, size: usize, priority: Priority) -> Result<(), AllocError> {
+        match self.free().checked_sub(size) {
+            Some(rest) if rest < self.reserves.get(priority) => Err(AllocError::Reserved),
+            _ => Ok(()),
         }
     }
 
@@ -195,19 +1187,333 @@ impl Heap {
     /// This function walks the list of free memory blocks and inserts the freed block at the
     /// correct place. If the freed block is adjacent to another free block, the blocks are merged
     /// again. This operation is in `O(n)` since the list needs to be sorted by address.
+    /// Freeing a zero-sized allocation only updates the counters and calls the hooks.
     ///
     /// # Safety
     ///
     /// `ptr` must be a pointer returned by a call to the [`allocate_first_fit`] function with
     /// identical layout. Undefined behavior may occur for invalid arguments.
     pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
-        self.used -= self.holes.deallocate(ptr, layout).size();
+        self.deallocate_after(None, ptr, layout);
+    }
+
+    /// Allocates a block for a `T` and moves `value` into it.
+    ///
+    /// This computes the layout from the type, so the allocation can't be freed with a
+    /// mismatching one by [`dealloc_value`][Heap::dealloc_value]. If the allocation fails,
+    /// `value` is dropped. To free the value automatically, use a [`HeapBox`] instead.
+    pub fn alloc_value<T>(&mut self, value: T) -> Result<NonNull<T>, AllocError> {
+        let ptr = self.allocate_first_fit(Layout::new::<T>())?.cast::<T>();
+        // SAFETY: The block was just allocated for a `T`.
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(ptr)
+    }
+
+    /// Drops the value at `ptr` and frees its block.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`alloc_value`][Heap::alloc_value] of this heap and
+    /// not been freed yet.
+    pub unsafe fn dealloc_value<T>(&mut self, ptr: NonNull<T>) {
+        core::ptr::drop_in_place(ptr.as_ptr());
+        self.deallocate(ptr.cast(), Layout::new::<T>());
+    }
+
+    /// Allocates a block for `len` values of type `T` and returns it as a slice.
+    ///
+    /// The elements are uninitialized. Fails with [`AllocError::InvalidLayout`] if the size
+    /// of the slice overflows. The block is freed with
+    /// [`deallocate_slice`][Heap::deallocate_slice], which derives the layout from the
+    /// slice again.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_slice<T>(&mut self, len: usize) -> Result<NonNull<[T]>, AllocError> {
+        let layout = Layout::array::<T>(len).map_err(|_| AllocError::InvalidLayout)?;
+        let ptr = self.allocate_first_fit(layout)?.cast::<T>();
+        let slice = core::ptr::slice_from_raw_parts_mut(ptr.as_ptr(), len);
+        // SAFETY: The slice starts at the non-null allocation.
+        Ok(unsafe { NonNull::new_unchecked(slice) })
+    }
+
+    /// Frees a slice that was allocated by [`allocate_slice`][Heap::allocate_slice].
+    ///
+    /// The elements are not dropped.
+    ///
+    /// # Safety
+    ///
+    /// `slice` must have been returned by [`allocate_slice`][Heap::allocate_slice] of this
+    /// heap and not been freed yet.
+    pub unsafe fn deallocate_slice<T>(&mut self, slice: NonNull<[T]>) {
+        // the elements might be uninitialized, so they are only referenced as such
+        let len = (&*(slice.as_ptr() as *const [MaybeUninit<T>])).len();
+        // the layout was valid when the slice was allocated
+        let layout = Layout::array::<T>(len).unwrap();
+        self.deallocate(slice.cast(), layout);
+    }
+
+    /// Allocates `n` whole pages of `page_size` bytes, aligned to the page size.
+    ///
+    /// The free memory in front of the pages and behind them stays in the list of free
+    /// blocks, so unlike an allocation of one more page that is aligned by hand, this doesn't
+    /// waste up to a page of memory. Fails with [`AllocError::InvalidLayout`] if
+    /// `page_size` is not a power of two or the size of the pages overflows. The pages are
+    /// freed with [`deallocate_pages`][Heap::deallocate_pages].
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_pages(
+        &mut self,
+        n: usize,
+        page_size: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let layout = page_layout(n, page_size)?;
+        self.allocate_first_fit(layout)
+    }
+
+    /// Frees pages that were allocated by [`allocate_pages`][Heap::allocate_pages].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`allocate_pages`][Heap::allocate_pages] of this heap
+    /// with the same `n` and `page_size`, and not been freed yet.
+    pub unsafe fn deallocate_pages(&mut self, ptr: NonNull<u8>, n: usize, page_size: usize) {
+        // the layout was valid when the pages were allocated
+        let layout = page_layout(n, page_size).unwrap();
+        self.deallocate(ptr, layout);
+    }
+
+    /// Frees a batch of allocations.
+    ///
+    /// The batch is sorted by address in place and the allocations are then returned to the
+    /// list of free blocks in a single pass over it. This takes O(m log m + n) time for m
+    /// allocations and n free blocks, instead of O(m · n) for separate calls to
+    /// [`deallocate`][Heap::deallocate].
+    ///
+    /// # Safety
+    ///
+    /// Every entry must be a pointer and layout that could be passed to
+    /// [`deallocate`][Heap::deallocate], and no pointer may appear twice.
+    pub unsafe fn deallocate_batch(&mut self, batch: &mut [(NonNull<u8>, Layout)]) {
+        batch.sort_unstable_by_key(|&(ptr, _)| self.strip_tag(ptr));
+        let mut hint = None;
+        for &(ptr, layout) in batch.iter() {
+            hint = self.deallocate_after(hint, ptr, layout);
+        }
+    }
+
+    /// Like [`deallocate`][Heap::deallocate], but returns the size of the free block that the
+    /// allocation became part of.
+    #[cfg(feature = "use_spin")]
+    pub(crate) unsafe fn deallocate_merged(&mut self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        match self.deallocate_after(None, ptr, layout) {
+            Some(hint) => {
+                let block = self.strip_tag(ptr);
+                self.holes.released_hole(hint, block).as_ref().size
+            }
+            None => 0,
+        }
+    }
+
+    /// Like [`deallocate`][Heap::deallocate], but starts searching the hole list at `hint`,
+    /// see [`HoleList::release_after`]. Returns the hint for the next deallocation at a
+    /// higher address, which is `hint` itself for a zero-sized allocation.
+    unsafe fn deallocate_after(
+        &mut self,
+        hint: Option<NonNull<Hole>>,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> Option<NonNull<Hole>> {
+        #[cfg(feature = "boot_phase")]
+        if self.boot.contains(self.bottom(), self.strip_tag(ptr)) {
+            // the boot region is never freed
+            return hint;
+        }
+        let (size, hint) = if layout.size() == 0 {
+            // zero-sized allocations don't take any memory
+            (0, hint)
+        } else if self.deallocate_large(ptr, layout) {
+            (0, hint)
+        } else {
+            let padded_layout = self.padded_layout(layout).unwrap();
+            #[cfg(feature = "tagging")]
+            let ptr = match self.tagger {
+                Some(tagger) => tagger.untag(ptr, padded_layout.size()),
+                None => ptr,
+            };
+            // a header records the layout without the guard gap and the size of the block
+            let (block, block_layout) = if cfg!(feature = "headers") {
+                header::block(ptr, self.without_guard_gap(padded_layout))
+            } else {
+                header::block(ptr, padded_layout)
+            };
+            #[cfg(feature = "call_sites")]
+            self.record_site_release(block);
+            let (size, hint) = self.free_block_after(hint, block, block_layout);
+            (size, Some(hint))
+        };
+        self.used = self.used.saturating_sub(size);
+        if layout.size() != 0 {
+            self.aligns
+                .record_deallocation(layout.align(), size.saturating_sub(layout.size()));
+        }
+        self.counters.record_deallocation();
+        #[cfg(feature = "interval_stats")]
+        self.interval.record_deallocation();
+        #[cfg(feature = "hooks")]
+        if let Some(hooks) = self.hooks {
+            hooks.on_dealloc(ptr, layout, &self.hook_context());
+        }
+        #[cfg(feature = "watchpoints")]
+        self.notify_watchers(WatchKind::Deallocate, ptr, layout);
+        self.update_pressure();
+        #[cfg(feature = "log")]
+        log::trace!(
+            "freed {:?} at {:p}, used: {}, holes: {}",
+            layout,
+            ptr,
+            self.used,
+            self.holes.holes().count()
+        );
+        hint
+    }
+
+    /// Moves the given allocation into the first free block that lies below it and copies
+    /// its contents over. Returns the new location, or `None` if no free block below `ptr`
+    /// is large enough, in which case the allocation is left untouched. Zero-sized, large
+    /// and boot allocations are never moved.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation of this heap with the given `layout`. On success,
+    /// `ptr` is deallocated and must no longer be used.
+    pub(crate) unsafe fn move_down(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> Option<NonNull<u8>> {
+        if layout.size() == 0 || self.is_large(ptr) {
+            return None;
+        }
+        #[cfg(feature = "boot_phase")]
+        if self.boot.contains(self.bottom(), self.strip_tag(ptr)) {
+            return None;
+        }
+        // the block is derived like in `deallocate_after`
+        let padded_layout = self.padded_layout(layout).ok()?;
+        let untagged = self.strip_tag(ptr);
+        let (block, block_layout) = if cfg!(feature = "headers") {
+            header::block(untagged, self.without_guard_gap(padded_layout))
+        } else {
+            header::block(untagged, padded_layout)
+        };
+        let offset = untagged.as_ptr().offset_from(block.as_ptr()) as usize;
+        let (new_block, charged) = self.allocate_below(block, block_layout)?;
+        // the contents are copied through untagged pointers, so the tag of the old memory is
+        // reset first and the new memory is tagged afterwards
+        #[cfg(feature = "tagging")]
+        if let Some(tagger) = self.tagger {
+            tagger.untag(ptr, padded_layout.size());
+        }
+        self.finish_move(
+            block,
+            new_block,
+            block_layout,
+            offset + layout.size(),
+            charged,
+        );
+        let payload = NonNull::new_unchecked(new_block.as_ptr().add(offset));
+        #[cfg(feature = "tagging")]
+        if let Some(tagger) = self.tagger {
+            // SAFETY: The payload is aligned to and padded to whole granules.
+            return Some(tagger.tag(payload, padded_layout.size()));
+        }
+        Some(payload)
+    }
+
+    /// Moves the block at `block` into the first free block that lies below it and copies
+    /// the first `len` bytes over.
+    #[cfg(feature = "headers")]
+    unsafe fn move_block_down(
+        &mut self,
+        block: NonNull<u8>,
+        block_layout: Layout,
+        len: usize,
+    ) -> Option<(NonNull<u8>, NonNull<Hole>)> {
+        let (new_block, charged) = self.allocate_below(block, block_layout)?;
+        let hole = self.finish_move(block, new_block, block_layout, len, charged);
+        Some((new_block, hole))
+    }
+
+    /// Allocates a block for `block_layout` in the first free block that lies below `block`.
+    /// Returns the new block and the number of bytes that it takes from the heap.
+    fn allocate_below(
+        &mut self,
+        block: NonNull<u8>,
+        block_layout: Layout,
+    ) -> Option<(NonNull<u8>, usize)> {
+        // The new block lies completely below the old one, so the hole surgery performed by
+        // the allocation cannot touch the contents of the old block. It keeps the position of
+        // the old block relative to its alignment, which might have been chosen for an
+        // allocation with an offset.
+        let block_offset = block.as_ptr().align_offset(block_layout.align());
+        let (new_block, aligned_layout, stranded) = self
+            .holes
+            .allocate_first_fit_below(block_layout, block_offset, block.as_ptr())
+            .ok()?;
+        Some((new_block, aligned_layout.size() + stranded))
+    }
+
+    /// Copies the first `len` bytes of `block` to the `new_block` that was allocated for it
+    /// by [`allocate_below`][Self::allocate_below], which took `charged` bytes, and frees
+    /// the old block. Returns the hole that the old block became part of.
+    unsafe fn finish_move(
+        &mut self,
+        block: NonNull<u8>,
+        new_block: NonNull<u8>,
+        block_layout: Layout,
+        len: usize,
+        charged: usize,
+    ) -> NonNull<Hole> {
+        core::ptr::copy_nonoverlapping(block.as_ptr(), new_block.as_ptr(), len);
+        let (freed, hint) = self.free_block_after(None, block, block_layout);
+        // the block keeps its size, but the padding around the old and the new block differs
+        self.used = self.used + charged - freed;
+        self.holes.released_hole(hint, block)
+    }
+
+    /// Returns the given block to the hole list, wiping its contents first if the
+    /// `zeroize_on_free` feature is enabled. Returns the number of bytes that became free.
+    unsafe fn free_block(&mut self, block: NonNull<u8>, block_layout: Layout) -> usize {
+        self.free_block_after(None, block, block_layout).0
+    }
+
+    /// Like [`free_block`][Self::free_block], but starts searching the hole list at `hint`,
+    /// see [`HoleList::release_after`].
+    unsafe fn free_block_after(
+        &mut self,
+        hint: Option<NonNull<Hole>>,
+        block: NonNull<u8>,
+        block_layout: Layout,
+    ) -> (usize, NonNull<Hole>) {
+        let aligned_layout = HoleList::align_layout(block_layout).unwrap();
+        #[cfg(feature = "zeroize_on_free")]
+        {
+            // blocks are aligned for a hole and their aligned size is a multiple of it
+            let words = block.as_ptr().cast::<usize>();
+            for i in 0..aligned_layout.size() / core::mem::size_of::<usize>() {
+                core::ptr::write_volatile(words.add(i), 0);
+            }
+            // make sure that the wipe is not reordered after the block is reused
+            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+        }
+        self.holes.release_after(hint, block, aligned_layout)
     }
 
     /// Returns the bottom address of the heap.
     ///
     /// The bottom pointer is automatically aligned, so the returned pointer
     /// might be larger than the bottom pointer used for initialization.
+    ///
+    /// The returned pointer is derived from the pointer passed on initialization without
+    /// any integer round-trips, so it carries its provenance.
     pub fn bottom(&self) -> *mut u8 {
         self.holes.bottom
     }
@@ -226,6 +1532,7 @@ impl Heap {
     /// Note: The heap may choose to not use bytes at the end for allocations
     /// until there is enough room for metadata, but it still retains ownership
     /// over memory from [`bottom`][Self::bottom] to the address returned.
+    /// Like `bottom`, the returned pointer carries the provenance of the heap memory.
     pub fn top(&self) -> *mut u8 {
         unsafe { self.holes.top.add(self.holes.pending_extend as usize) }
     }
@@ -240,55 +1547,1014 @@ impl Heap {
         self.size() - self.used
     }
 
-    /// Extends the size of the heap by creating a new hole at the end.
-    ///
-    /// Small extensions are not guaranteed to grow the usable size of
-    /// the heap. In order to grow the Heap most effectively, extend by
-    /// at least `2 * size_of::<usize>`, keeping the amount a multiple of
-    /// `size_of::<usize>`.
-    ///
-    /// Calling this method on an uninitialized Heap will panic.
+    /// Returns whether `ptr` points into the memory managed by this heap, i.e. into the
+    /// range from [`bottom`][Self::bottom] to [`top`][Self::top].
     ///
-    /// # Safety
+    /// This is a cheap range check that doesn't tell whether `ptr` belongs to a live
+    /// allocation. It is meant for wrapper allocators that need to route a deallocation to
+    /// the heap it came from; [`FallbackHeap`] does so for several heaps through [`Owns`].
+    pub fn contains(&self, ptr: NonNull<u8>) -> bool {
+        let ptr = ptr.as_ptr();
+        ptr >= self.bottom() && ptr < self.top()
+    }
+
+    /// Returns the number of bytes of metadata that every allocation takes in front of its
+    /// payload with the active feature set, which is the size of the header with the
+    /// `headers` feature and zero otherwise.
     ///
-    /// The amount of data given in `by` MUST exist directly after the original
-    /// range of data provided when constructing the [Heap]. The additional data
-    /// must have the same lifetime of the original range of data.
+    /// Allocations that are aligned to more than a word can need more, since the payload is
+    /// aligned within the block.
+    pub const fn per_allocation_overhead() -> usize {
+        header::FIXED_OFFSET
+    }
+
+    /// Returns the smallest block that an allocation takes from the heap, including its
+    /// metadata. Smaller blocks are padded to this size, since they must be able to hold
+    /// the bookkeeping of a free block after they are freed.
     ///
-    /// Even if this operation doesn't increase the [usable size][`Self::size`]
-    /// by exactly `by` bytes, those bytes are still owned by the Heap for
-    /// later use.
-    pub unsafe fn extend(&mut self, by: usize) {
-        self.holes.extend(by);
+    /// Zero-sized allocations don't take any memory.
+    pub const fn min_allocation_size() -> usize {
+        size_of::<Hole>()
     }
-}
 
-#[cfg(all(feature = "alloc_ref", feature = "use_spin"))]
-unsafe impl Allocator for LockedHeap {
-    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        if layout.size() == 0 {
-            return Ok(NonNull::slice_from_raw_parts(layout.dangling(), 0));
-        }
-        match self.0.lock().allocate_first_fit(layout) {
-            Ok(ptr) => Ok(NonNull::slice_from_raw_parts(ptr, layout.size())),
-            Err(()) => Err(AllocError),
+    /// Returns the granularity of the heap: all blocks are aligned to it and their sizes
+    /// are rounded up to a multiple of it.
+    pub const fn allocation_granularity() -> usize {
+        align_of::<Hole>()
+    }
+
+    /// Returns the number of bytes that an allocation of `size` bytes with an alignment of
+    /// at most a word takes from the heap with the active feature set, including its
+    /// metadata, the [minimum size][Self::min_allocation_size] and the rounding to the
+    /// [granularity][Self::allocation_granularity].
+    ///
+    /// This is what [`used`][Self::used] grows by when the allocation is taken from the
+    /// middle of a large free block. Allocations with a larger alignment can take more,
+    /// and so can allocations that would leave a gap behind that is too small for a free
+    /// block. The settings of a heap instance, i.e. the
+    /// [minimum block size][Self::with_min_block_size], the
+    /// [guard gap][Self::set_guard_gap], the [cache line size][Self::set_cache_line] and the
+    /// granule of a [`MemoryTagger`], aren't taken into account.
+    pub const fn block_size(size: usize) -> usize {
+        if size == 0 {
+            return 0;
         }
+        let size = size.saturating_add(Self::per_allocation_overhead());
+        let size = if size < Self::min_allocation_size() {
+            Self::min_allocation_size()
+        } else {
+            size
+        };
+        let granularity = Self::allocation_granularity();
+        size.saturating_add(granularity - 1) & !(granularity - 1)
     }
 
-    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-        if layout.size() != 0 {
-            self.0.lock().deallocate(ptr, layout);
+    /// Returns a snapshot of the heap statistics.
+    ///
+    /// This walks the list of free memory blocks, so the runtime is in `O(n)` where n is
+    /// the number of free blocks.
+    pub fn stats(&self) -> HeapStats {
+        let (holes, largest_hole) = self
+            .holes
+            .holes()
+            .fold((0, 0), |(count, largest), (_, size)| {
+                (count + 1, largest.max(size))
+            });
+        HeapStats {
+            size: self.size(),
+            used: self.used,
+            free: self.free(),
+            peak_used: self.counters.peak_used,
+            allocations: self.counters.allocations,
+            deallocations: self.counters.deallocations,
+            failed_allocations: self.counters.failed_allocations,
+            holes,
+            largest_hole,
         }
     }
-}
 
-#[cfg(feature = "use_spin")]
-pub struct LockedHeap(Spinlock<Heap>);
+    /// Returns a histogram of the live allocations by their alignment.
+    ///
+    /// Every entry covers the allocations with one power-of-two alignment, from 1 byte up
+    /// to 2048 bytes, where the last entry also covers all larger alignments. Besides the
+    /// number of allocations, it records how many bytes they take beyond their requested
+    /// size, which shows whether a few large alignments are responsible for most of the
+    /// wasted memory. Padding in front of an allocation that is too small for a free
+    /// block is released together with one of its neighbours, so the numbers are
+    /// approximate.
+    pub fn align_stats(&self) -> [AlignStats; ALIGN_CLASSES] {
+        self.aligns.get()
+    }
 
-#[cfg(feature = "use_spin")]
+    /// Like [`stats`][Heap::stats], but the event counters and the peak usage only cover
+    /// the time since the last call of this method, or since the heap was initialized.
+    ///
+    /// They are reset afterwards, so that a telemetry task that calls this periodically
+    /// gets the activity of each interval without keeping copies of the totals. The totals
+    /// that [`stats`][Heap::stats] returns are not affected.
+    #[cfg(feature = "interval_stats")]
+    pub fn take_stats(&mut self) -> HeapStats {
+        let interval = core::mem::replace(&mut self.interval, Counters::new());
+        self.interval.peak_used = self.used;
+        HeapStats {
+            peak_used: interval.peak_used,
+            allocations: interval.allocations,
+            deallocations: interval.deallocations,
+            failed_allocations: interval.failed_allocations,
+            ..self.stats()
+        }
+    }
+
+    /// Returns the size of the largest allocation with the given alignment that would
+    /// currently succeed.
+    ///
+    /// This is useful to decide how much memory needs to be freed after an allocation
+    /// failed. Returns `None` if `align` is not a power of two or if no allocation with this
+    /// alignment fits into the heap. The runtime is in `O(n)` where n is the number of free
+    /// blocks.
+    ///
+    /// An allocation of exactly the returned size succeeds. Slightly smaller allocations can
+    /// still fail if they would leave a gap behind that is too small to be tracked as a free
+    /// block, i.e. smaller than [`HoleList::min_size`].
+    pub fn largest_allocation(&self, align: usize) -> Option<usize> {
+        let layout = Layout::from_size_align(0, align).ok()?;
+        let (block_layout, offset) = header::block_layout(layout).ok()?;
+        let block_size = self.holes.largest_block(block_layout.align())?;
+        // smaller allocations are padded to the minimum block size
+        block_size
+            .checked_sub(offset + self.guard_gap)
+            .filter(|&size| size >= self.min_block)
+    }
+
+    /// Allocates the largest block with the given alignment that is currently available.
+    ///
+    /// Returns the pointer to the block together with its size. The block must be freed
+    /// with a layout of the returned size and the given alignment. This is useful for
+    /// buffers that can use as much contiguous memory as the heap has to offer.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_largest(&mut self, align: usize) -> Result<(NonNull<u8>, usize), AllocError> {
+        if !align.is_power_of_two() {
+            return Err(AllocError::InvalidLayout);
+        }
+        let size = self
+            .largest_allocation(align)
+            .ok_or(AllocError::OutOfMemory)?;
+        let layout = Layout::from_size_align(size, align).map_err(|_| AllocError::InvalidLayout)?;
+        self.allocate_first_fit(layout).map(|ptr| (ptr, size))
+    }
+
+    /// Splits the heap into two independent heaps at `at` bytes from the
+    /// [bottom][Heap::bottom].
+    ///
+    /// After the call, this heap manages the memory below the split point and the returned
+    /// heap manages the memory above it, including any bytes that are pending for a future
+    /// [`extend`][Heap::extend]. Allocations above the split point belong to the returned
+    /// heap and must be freed there. The returned heap has no hooks installed and its
+    /// statistics start from zero. It keeps the
+    /// [minimum block size][Heap::with_min_block_size] of this heap.
+    ///
+    /// Returns `None` and leaves the heap unchanged if `at` is not a multiple of
+    /// [`allocation_granularity`][Heap::allocation_granularity], if one of the parts would
+    /// be smaller than
+    /// [`HoleList::min_size`], or if the split point might lie within a live allocation.
+    /// Since the heap only tracks free memory, the split point must lie within or at the
+    /// border of a free block.
+    pub fn split_off(&mut self, at: usize) -> Option<Heap> {
+        let max = self.size().checked_sub(HoleList::min_size())?;
+        if at % align_of::<Hole>() != 0 || at < HoleList::min_size() || at > max {
+            return None;
+        }
+        let split = self.bottom().wrapping_add(at);
+        let holes = self.holes.split_off(split)?;
+
+        let upper_free: usize = holes.holes().map(|(_, size)| size).sum();
+        let upper_size = unsafe { holes.top.offset_from(holes.bottom) as usize };
+        let upper_used = upper_size - upper_free;
+        self.used -= upper_used;
+        self.bounds_changed();
+        Some(Heap {
+            used: upper_used,
+            holes,
+            min_block: self.min_block,
+            // only the upper part can grow, since the lower part is followed by it
+            #[cfg(feature = "growth")]
+            growth: self.growth.take(),
+            frozen: self.frozen,
+            #[cfg(feature = "large_alloc")]
+            large: self.large,
+            ..Heap::empty()
+        })
+    }
+
+    /// Combines this heap with `other`, which manages the memory that directly follows or
+    /// precedes this heap, into a single heap. This is the reverse of
+    /// [`split_off`][Heap::split_off].
+    ///
+    /// The lists of free memory blocks are concatenated and the blocks at the border are
+    /// merged, so allocations of both heaps can be freed on the combined heap. The hooks
+    /// and other settings of this heap are kept, except for the
+    /// [growth source][Heap::set_growth], which is taken from the upper heap since only it
+    /// can grow. The event counters of both heaps are added up. A
+    /// [persistent][Heap::init_persistent] heap stays persistent if it is the lower one, and
+    /// the combined heap is [frozen][Heap::freeze] if one of the heaps was.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if one of the heaps is not initialized or if the memory of the
+    /// upper heap doesn't start at the [`top`][Heap::top] of the lower heap, or at the next
+    /// address that is aligned to [`allocation_granularity`][Heap::allocation_granularity].
+    /// It also panics if the upper heap has a [boot region][Heap::init_boot], whose
+    /// allocations would then lie in the middle of the combined heap, or if the heaps have
+    /// different [minimum block sizes][Heap::with_min_block_size].
+    pub fn merge(mut self, other: Heap) -> Heap {
+        assert!(
+            !self.bottom().is_null() && !other.bottom().is_null(),
+            "tried to merge an empty heap"
+        );
+        assert!(
+            self.min_block == other.min_block,
+            "tried to merge heaps with different minimum block sizes"
+        );
+        #[cfg(feature = "boot_phase")]
+        {
+            let (lower_boot, upper_boot) = if self.bottom() < other.bottom() {
+                (self.boot, other.boot)
+            } else {
+                (other.boot, self.boot)
+            };
+            assert!(
+                upper_boot.is_empty(),
+                "tried to merge a heap with a boot region on top"
+            );
+            self.boot = lower_boot;
+        }
+        let (lower, upper) = if self.bottom() < other.bottom() {
+            #[cfg(feature = "growth")]
+            {
+                self.growth = other.growth;
+            }
+            (&mut self.holes, other.holes)
+        } else {
+            let upper = core::mem::replace(&mut self.holes, other.holes);
+            (&mut self.holes, upper)
+        };
+        assert_eq!(
+            align_up(
+                lower.top.wrapping_add(lower.pending_extend as usize),
+                align_of::<Hole>()
+            ),
+            upper.start(),
+            "the heaps are not adjacent"
+        );
+        lower.merge(upper);
+
+        let free: usize = self.holes.holes().map(|(_, size)| size).sum();
+        self.used = self.size() - free;
+        self.counters = self.counters.merge(other.counters, self.used);
+        #[cfg(feature = "interval_stats")]
+        {
+            self.interval = self.interval.merge(other.interval, self.used);
+        }
+        self.aligns.merge(&other.aligns);
+        self.frozen = self.frozen.or(other.frozen);
+        #[cfg(feature = "large_alloc")]
+        {
+            self.large = self.large.or(other.large);
+        }
+        self.bounds_changed();
+        self.update_pressure();
+        self
+    }
+
+    /// Extends the size of the heap by creating a new hole at the end.
+    ///
+    /// Small extensions are not guaranteed to grow the usable size of
+    /// the heap. In order to grow the Heap most effectively, extend by
+    /// at least [`min_allocation_size`][Heap::min_allocation_size], keeping the amount a
+    /// multiple of [`allocation_granularity`][Heap::allocation_granularity].
+    ///
+    /// Calling this method on an uninitialized Heap will panic.
+    ///
+    /// The new memory isn't known to be zero, so afterwards
+    /// [`allocate_zeroed`][Heap::allocate_zeroed] zeroes all allocations explicitly. Use
+    /// [`extend_zeroed`][Heap::extend_zeroed] if the new memory is zeroed.
+    ///
+    /// # Safety
+    ///
+    /// The amount of data given in `by` MUST exist directly after the original
+    /// range of data provided when constructing the [Heap]. The additional data
+    /// must have the same lifetime of the original range of data.
+    ///
+    /// Even if this operation doesn't increase the [usable size][`Self::size`]
+    /// by exactly `by` bytes, those bytes are still owned by the Heap for
+    /// later use.
+    pub unsafe fn extend(&mut self, by: usize) {
+        self.extend_holes(by);
+        // the new memory isn't known to be zero
+        self.holes.zeroed_from = self.top();
+    }
+
+    /// Like [`extend`][Heap::extend], but additionally declares that the new memory is
+    /// zeroed, so that [`allocate_zeroed`][Heap::allocate_zeroed] doesn't need to zero it
+    /// again.
+    ///
+    /// # Safety
+    ///
+    /// All requirements of [`extend`][Heap::extend] apply. In addition, all bytes of the
+    /// new memory must be zero.
+    pub unsafe fn extend_zeroed(&mut self, by: usize) {
+        let old_top = self.holes.top;
+        let merges = self
+            .holes
+            .holes()
+            .any(|(addr, size)| addr.wrapping_add(size) == old_top);
+        self.extend_holes(by);
+        if merges && self.holes.top != old_top {
+            // the new hole was merged into the last hole, which left its header behind, but
+            // the mirror of the last hole might directly follow it
+            let len = HoleList::min_size() - MIRROR_SIZE;
+            sanitizer::unpoison(old_top, len);
+            old_top.write_bytes(0, len);
+            sanitizer::poison(old_top, len);
+        }
+    }
+
+    /// Extends the heap by the given memory, which must directly follow the heap.
+    ///
+    /// This is a safe variant of [`extend`][Heap::extend], which takes ownership of the new
+    /// memory instead of requiring the caller to vouch for it. All other notes of `extend`
+    /// apply to this function as well.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the heap is not initialized or if `mem` does not start at
+    /// [`top`][Self::top].
+    pub fn extend_from_slice(&mut self, mem: &'static mut [MaybeUninit<u8>]) {
+        assert!(
+            !self.bottom().is_null(),
+            "The heap has not been initialized yet."
+        );
+        assert_eq!(
+            mem.as_mut_ptr().cast::<u8>(),
+            self.top(),
+            "The memory does not directly follow the heap."
+        );
+        // SAFETY: The memory directly follows the heap, and the mutable reference handed to
+        // us by the caller guarantees that it is valid and unused for the `'static` lifetime.
+        unsafe { self.extend(mem.len()) }
+    }
+
+    /// Moves the heap memory to `new_bottom`, which becomes the new [`bottom`][Self::bottom]
+    /// of the heap, e.g. to make room for a kernel image that is loaded to the old location.
+    ///
+    /// The whole memory from `bottom` to [`top`][Self::top] is copied, including the live
+    /// allocations, and the links between the free memory blocks are rewritten for the new
+    /// location. The allocations keep their offset from `bottom`, so a pointer to an
+    /// allocation must be moved by the same distance as the heap to stay valid. The
+    /// record at the start of a [persistent][Heap::init_persistent] heap is moved as well.
+    ///
+    /// The runtime is in `O(n + m)` where n is the number of free blocks and m is the size
+    /// of the heap.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the heap is not initialized, if `new_bottom` is not aligned to
+    /// [`allocation_granularity`][Heap::allocation_granularity], or if
+    /// [page hooks][Heap::set_page_hooks] or a [memory tagger][Heap::set_tagger] are
+    /// installed, since free pages might not be readable and the tags would not move along
+    /// with the memory.
+    ///
+    /// # Safety
+    ///
+    /// The memory at the new location must be valid for the `'static` lifetime and must not
+    /// be used for anything else. It may overlap the old location, which is no longer used
+    /// by the heap afterwards. The distance between the old and the new location must be a
+    /// multiple of the alignment of every live allocation, and no pointer into the old
+    /// location may be used anymore.
+    pub unsafe fn relocate(&mut self, new_bottom: *mut u8) {
+        assert!(!self.bottom().is_null(), "tried to relocate an empty heap");
+        assert_eq!(
+            new_bottom.align_offset(align_of::<Hole>()),
+            0,
+            "the new bottom is unaligned"
+        );
+        #[cfg(feature = "tagging")]
+        let tagged = self.tagger.is_some();
+        #[cfg(not(feature = "tagging"))]
+        let tagged = false;
+        assert!(
+            self.holes.pages.is_none() && !tagged,
+            "page hooks and memory taggers don't support relocation"
+        );
+        self.holes.relocate(new_bottom);
+        self.bounds_changed();
+    }
+
+    unsafe fn extend_holes(&mut self, by: usize) {
+        self.holes.extend(by);
+        #[cfg(feature = "hooks")]
+        if let Some(hooks) = self.hooks {
+            hooks.on_extend(by, &self.hook_context());
+        }
+        self.bounds_changed();
+        self.update_pressure();
+        #[cfg(feature = "log")]
+        log::debug!(
+            "extended heap by {} bytes, size: {}, holes: {}",
+            by,
+            self.size(),
+            self.holes.holes().count()
+        );
+    }
+
+    /// Sets the secret that the links between free blocks are encoded with.
+    ///
+    /// With the `safe_linking` feature, every link in the list of free blocks is XOR-ed with
+    /// this key, so that an attacker who can overwrite free memory can't forge a valid link
+    /// without knowing the key. Corrupted links are detected when they are followed, which
+    /// causes a panic. The key should be random, e.g. taken from a hardware RNG at boot.
+    ///
+    /// With the `checksum` feature, the key is also mixed into the checksum of every free
+    /// block, so that a block that was overwritten without knowing the key is detected even
+    /// if its checksum was updated as well.
+    ///
+    /// The existing links are re-encoded with the new key. The key stays in place when the
+    /// heap is initialized, so it can be set up on an [empty][Heap::empty] heap.
+    #[cfg(any(feature = "safe_linking", feature = "checksum"))]
+    pub fn set_link_key(&mut self, key: usize) {
+        let key = self.holes.key.with_secret(key);
+        self.holes.set_key(key);
+    }
+
+    /// Checks up to `budget` free blocks, continuing after the block that the last call
+    /// checked.
+    ///
+    /// This performs the checks of [`adopt`][Heap::adopt] in small steps, so that an idle
+    /// task can audit the heap continuously without holding its lock for long. Every free
+    /// block must lie within the heap, be aligned, sorted by address and at least
+    /// [`HoleList::min_size`] bytes large. With the `checksum` feature, its header must also
+    /// match its checksum, and the `redundant` feature repairs it if it doesn't. A pass
+    /// over the whole list ends with [`ScanState::Complete`], after which the next call
+    /// starts a new pass.
+    ///
+    /// Allocations and deallocations between the steps don't restart the pass. If they
+    /// remove the block that the last step ended at, the next step continues at a block in
+    /// front of it instead. Other changes to the whole list, like
+    /// [`split_off`][Heap::split_off], restart the pass.
+    ///
+    /// Unlike the checks while the list is walked, a corrupted block doesn't cause a panic,
+    /// but is returned as [`ScanState::Corrupted`].
+    pub fn scan_step(&mut self, budget: usize) -> ScanState {
+        self.holes.scan_step(budget)
+    }
+
+    /// Verifies the metadata of every free block and its mirror, and repairs the copies that
+    /// were corrupted, e.g. by bit flips in RAM. Returns the number of repaired copies.
+    ///
+    /// With the `redundant` feature, a corrupted free block is only repaired as long as one
+    /// of its copies is intact. Corruptions are found when the block is visited anyway, but a
+    /// block that isn't visited for a long time can collect errors in both copies. Calling
+    /// this periodically, like a memory scrubber, repairs them before that happens.
+    ///
+    /// Panics if a free block can't be repaired, after reporting it to
+    /// [`HeapHooks::on_corruption`].
+    #[cfg(feature = "redundant")]
+    pub fn scrub(&mut self) -> usize {
+        self.holes.scrub()
+    }
+
+    /// Installs a [`MemoryTagger`] that assigns a fresh memory tag to every allocation and
+    /// resets it when the allocation is freed. Passing `None` removes the installed tagger.
+    ///
+    /// Allocations are aligned to and padded to whole tag granules, and
+    /// [`allocate_within`][Heap::allocate_within] no longer enlarges allocations. Moving
+    /// allocations with `defragment` is not supported while a tagger is installed.
+    ///
+    /// # Safety
+    ///
+    /// The tagger must not be changed while there are live allocations, since they must be
+    /// freed with the tagger that tagged them.
+    #[cfg(feature = "tagging")]
+    pub unsafe fn set_tagger(&mut self, tagger: Option<&'static dyn MemoryTagger>) {
+        self.tagger = tagger;
+    }
+
+    /// Rounds all allocations up to whole cache lines of `size` bytes and staggers them
+    /// across `colors` cache lines.
+    ///
+    /// Allocations are aligned to and padded to the cache line size, so that allocations
+    /// that are used by different cores never share a cache line. With more than one color,
+    /// successive allocations start at different offsets modulo `size * colors`, so that
+    /// allocations with the same layout don't all compete for the same cache sets. Like with
+    /// a [`MemoryTagger`], [`allocate_within`][Heap::allocate_within] no longer enlarges
+    /// allocations. A `size` of 1 and a single color disable both.
+    ///
+    /// Panics if `size` or `colors` is not a power of two, if `size` is smaller than
+    /// `align_of::<usize>()` and not 1, or if colors are requested with a `size` of 1.
+    ///
+    /// # Safety
+    ///
+    /// The cache line size must not be changed while there are live allocations, since they
+    /// must be freed with the size that they were padded to.
+    pub unsafe fn set_cache_line(&mut self, size: usize, colors: usize) {
+        assert!(
+            size.is_power_of_two() && (size == 1 || size >= align_of::<usize>()),
+            "the cache line size must be a power of two and at least the word size"
+        );
+        assert!(
+            colors.is_power_of_two() && (size > 1 || colors == 1),
+            "the number of colors must be a power of two and needs a cache line size"
+        );
+        assert!(
+            size.checked_mul(colors).is_some(),
+            "too many colors for the cache line size"
+        );
+        self.cache_line.set(size, colors);
+    }
+
+    /// Leaves `gap` unused bytes behind every allocation, so that no two allocations are
+    /// closer than `gap` bytes, e.g. for debugging overflows with hardware watchpoints or MPU
+    /// subregions, which need a few bytes of their own behind the buffer they watch.
+    ///
+    /// Unlike canaries, nothing is written to the gap and it is never checked; it only
+    /// keeps an overflowing write from reaching the next allocation right away. The gap
+    /// follows the allocation and its padding to the
+    /// [minimum block size][Heap::with_min_block_size]. It counts as used memory, it is not
+    /// included in the recorded layout of an allocation, and
+    /// [`allocate_within`][Heap::allocate_within] no longer enlarges allocations into it.
+    /// A `gap` of 0 disables it.
+    ///
+    /// # Safety
+    ///
+    /// The guard gap must not be changed while there are live allocations, since they must
+    /// be freed with the gap that they were allocated with.
+    pub unsafe fn set_guard_gap(&mut self, gap: usize) {
+        self.guard_gap = gap;
+    }
+
+    /// Installs [`PageHooks`] that give the pages of free blocks with at least `threshold`
+    /// bytes back to the system. Passing `None` removes the installed hooks.
+    ///
+    /// The free blocks are checked right away and after every deallocation. The pages are
+    /// committed again before the heap uses them. Free memory of a heap that was set up
+    /// with [`init_zeroed`][Heap::init_zeroed] and was never handed out is not decommitted,
+    /// since it is known to be zero.
+    ///
+    /// # Safety
+    ///
+    /// The hooks must not be removed or replaced while pages of the heap are decommitted,
+    /// since the heap would then use them without committing them first.
+    pub unsafe fn set_page_hooks(
+        &mut self,
+        hooks: Option<&'static dyn PageHooks>,
+        threshold: usize,
+    ) {
+        self.holes.pages = hooks.map(|hooks| Pages { hooks, threshold });
+        if let Some(pages) = self.holes.pages {
+            for (addr, size) in self.holes.holes() {
+                pages.decommit_hole(addr, size, self.holes.zeroed_from);
+            }
+        }
+    }
+
+    /// Installs [`PersistHooks`] that write every change to the list of free blocks back to
+    /// persistent memory before the next one is made. Passing `None` removes the installed
+    /// hooks.
+    ///
+    /// Together with [`init_persistent`][Heap::init_persistent] and [`adopt`][Heap::adopt],
+    /// this keeps a heap in persistent memory recoverable after a power loss, see the
+    /// [`PersistHooks`] for the guarantees. The headers of all free blocks are written back
+    /// right away. The hooks stay installed when the heap is initialized.
+    #[cfg(feature = "pmem")]
+    pub fn set_persist_hooks(&mut self, hooks: Option<&'static dyn PersistHooks>) {
+        let key = self.holes.key.with_persist(hooks);
+        self.holes.set_key(key);
+    }
+
+    /// Installs a [`GrowthSource`] that the heap is extended from when an allocation fails,
+    /// and the [`GrowthPolicy`] that decides how many bytes to request from it. Passing
+    /// `None` removes the installed source.
+    ///
+    /// The heap grows before an allocation fails for any reason other than an invalid
+    /// layout, after [purgeable][Heap::allocate_purgeable] allocations were purged. It
+    /// keeps growing until the allocation succeeds, the policy returns less than the
+    /// allocation needs, or the source has no more memory. Allocations with a bounded
+    /// search or a target address don't grow the heap.
+    ///
+    /// The source and the policy stay installed when the heap is initialized. When the heap
+    /// is [split][Heap::split_off], they move to the upper part.
+    #[cfg(feature = "growth")]
+    pub fn set_growth(
+        &mut self,
+        source: Option<&'static dyn GrowthSource>,
+        policy: &'static dyn GrowthPolicy,
+    ) {
+        self.growth = source.map(|source| (source, policy));
+    }
+
+    /// Installs a [`LargeAllocator`] that serves all allocations of at least `threshold`
+    /// bytes outside of the heap, e.g. with whole pages from a frame allocator. Passing
+    /// `None` removes the installed allocator.
+    ///
+    /// Large buffers would otherwise split up the free memory of the heap. If the large
+    /// allocator fails, the heap serves the allocation itself. Deallocations are routed to
+    /// the large allocator if it [owns][LargeAllocator::owns] the pointer, so the threshold
+    /// may change while large allocations are live, but the allocator must stay installed
+    /// until they are freed. Allocations with an offset or a target address are always
+    /// served by the heap.
+    ///
+    /// Large allocations are counted in the [statistics][Heap::stats] as allocations, but
+    /// don't count as used memory of the heap. With the `headers` feature, they have no
+    /// header, so they don't show up in [`allocations`][Heap::allocations] and must not be
+    /// passed to [`deallocate_unsized`][Heap::deallocate_unsized].
+    #[cfg(feature = "large_alloc")]
+    pub fn set_large_allocator(
+        &mut self,
+        allocator: Option<&'static dyn LargeAllocator>,
+        threshold: usize,
+    ) {
+        self.large = allocator.map(|allocator| (allocator, threshold));
+    }
+
+    /// Installs callbacks that are invoked on allocations, deallocations, failed
+    /// allocations and extensions of this heap. Passing `None` removes the installed hooks.
+    ///
+    /// The hooks stay installed when the heap is initialized, so they can be set up on an
+    /// [empty][Heap::empty] heap. On an initialized heap,
+    /// [`on_bounds`][HeapHooks::on_bounds] is called right away with the current bounds.
+    #[cfg(feature = "hooks")]
+    pub fn set_hooks(&mut self, hooks: Option<&'static dyn HeapHooks>) {
+        self.hooks = hooks;
+        // the hole list reports corrupted free blocks to them
+        self.holes.key = self.holes.key.with_hooks(hooks);
+        if !self.bottom().is_null() {
+            self.bounds_changed();
+        }
+    }
+
+    /// Reserves `bytes` of free memory for allocations with a higher priority than
+    /// `priority`.
+    ///
+    /// Allocations with the given priority fail with [`AllocError::Reserved`] once they would
+    /// leave less than `bytes` of free memory behind, while allocations with a higher
+    /// priority can still use it. This allows low-priority users such as caches to degrade
+    /// gracefully before memory runs out for the important ones. The reserve of each
+    /// priority should thus be larger than that of the next higher one. All reserves are
+    /// zero by default.
+    ///
+    /// The reserve is compared to the total amount of free memory, so allocations can still
+    /// fail because of fragmentation when less than the reserved amount is allocated.
+    pub fn set_reserve(&mut self, priority: Priority, bytes: usize) {
+        self.reserves.set(priority, bytes);
+    }
+
+    /// Freezes the heap, so that all further allocations fail with [`AllocError::Frozen`]
+    /// or panic, depending on `mode`. Deallocations keep working.
+    ///
+    /// This enforces that memory is only allocated during initialization, as required by
+    /// some safety standards. All allocation methods are affected, including those for
+    /// zero-sized layouts. A frozen heap can't be unfrozen, but calling `freeze` again
+    /// changes the mode. The part returned by [`split_off`][Heap::split_off] is frozen as
+    /// well.
+    pub fn freeze(&mut self, mode: FreezeMode) {
+        self.frozen = Some(mode);
+    }
+
+    /// Returns whether the heap was [frozen][Heap::freeze].
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    /// Sets the thresholds below which the heap enters the [`Elevated`][Pressure::Elevated]
+    /// and the [`Critical`][Pressure::Critical] pressure level.
+    ///
+    /// The level is determined after every allocation, deallocation and extension, and
+    /// changes are reported to [`HeapHooks::on_pressure`]. The thresholds for critical
+    /// pressure should thus be lower than those for elevated pressure. All thresholds are
+    /// zero by default, so the level stays [`Normal`][Pressure::Normal].
+    #[cfg(feature = "pressure")]
+    pub fn set_pressure_thresholds(
+        &mut self,
+        elevated: PressureThreshold,
+        critical: PressureThreshold,
+    ) {
+        self.pressure.set_thresholds(elevated, critical);
+        self.update_pressure();
+    }
+
+    /// Returns the current pressure level, see
+    /// [`set_pressure_thresholds`][Heap::set_pressure_thresholds].
+    #[cfg(feature = "pressure")]
+    pub fn pressure(&self) -> Pressure {
+        self.pressure.level()
+    }
+
+    /// Determines the pressure level and calls the hooks if it changed.
+    #[cfg(feature = "pressure")]
+    fn update_pressure(&mut self) {
+        let free = self.free();
+        let holes = &self.holes;
+        let changed = self.pressure.update(free, || {
+            holes.holes().map(|(_, size)| size).max().unwrap_or(0)
+        });
+        if let Some(level) = changed {
+            #[cfg(feature = "hooks")]
+            if let Some(hooks) = self.hooks {
+                hooks.on_pressure(level, &self.hook_context());
+            }
+            #[cfg(feature = "log")]
+            log::debug!("pressure level changed to {:?}, free: {}", level, free);
+            let _ = level;
+        }
+    }
+
+    #[cfg(not(feature = "pressure"))]
+    fn update_pressure(&mut self) {}
+
+    /// Announces the current bounds of the heap to the hooks.
+    fn bounds_changed(&self) {
+        #[cfg(feature = "hooks")]
+        if let Some(hooks) = self.hooks {
+            hooks.on_bounds(self.holes.start(), self.top(), &self.hook_context());
+        }
+    }
+
+    /// Resets the statistics and ends the boot phase when the heap is initialized.
+    fn reset_state(&mut self) {
+        self.counters = Counters::new();
+        #[cfg(feature = "interval_stats")]
+        {
+            self.interval = Counters::new();
+        }
+        self.aligns = AlignHistogram::new();
+        #[cfg(feature = "boot_phase")]
+        {
+            self.boot = BootRegion::new();
+        }
+    }
+
+    #[cfg(feature = "hooks")]
+    fn hook_context(&self) -> HookContext {
+        HookContext {
+            used: self.used,
+            size: self.size(),
+        }
+    }
+}
+
+/// Fixes up references to allocations that are moved by [`Heap::defragment`].
+///
+/// This trait is implemented for all closures that take the old pointer, the new pointer,
+/// and the layout of a moved allocation.
+#[cfg(feature = "headers")]
+pub trait Relocator {
+    /// Called after the allocation with the given `layout` was moved from `old` to `new`.
+    ///
+    /// The contents of the allocation were already copied to `new`, and `old` must no
+    /// longer be used.
+    fn relocate(&mut self, old: NonNull<u8>, new: NonNull<u8>, layout: Layout);
+}
+
+#[cfg(feature = "headers")]
+impl<F: FnMut(NonNull<u8>, NonNull<u8>, Layout)> Relocator for F {
+    fn relocate(&mut self, old: NonNull<u8>, new: NonNull<u8>, layout: Layout) {
+        self(old, new, layout)
+    }
+}
+
+#[cfg(feature = "headers")]
+impl Heap {
+    /// Returns an iterator over the pointers and layouts of all live allocations, in
+    /// address order.
+    pub fn allocations(&self) -> Allocations<'_> {
+        Allocations::new(&self.holes, self.bottom())
+    }
+
+    /// Returns the layout of the allocation at `ptr`, as recorded in its header.
+    ///
+    /// This is the layout that the allocation was made with, or its actual layout if it was
+    /// made by [`allocate_within`][Self::allocate_within] or
+    /// [`allocate_largest`][Self::allocate_largest]. If a [`MemoryTagger`] is installed,
+    /// the size is rounded up to whole tag granules. It can be used to learn the old size
+    /// when reallocating through an API that doesn't keep track of it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation of this heap. Zero-sized allocations have no header,
+    /// so their pointers must not be passed.
+    pub unsafe fn allocation_layout(&self, ptr: NonNull<u8>) -> Layout {
+        header::layout(self.strip_tag(ptr))
+    }
+
+    /// Like [`allocate_first_fit`][Self::allocate_first_fit], but stores the given `tag` in
+    /// the header of the allocation.
+    ///
+    /// All allocations with the same tag can be freed at once with
+    /// [`free_all_tagged`][Self::free_all_tagged], e.g. to release everything that belongs
+    /// to a terminated task. Allocations made by other methods have the tag 0. The tag is
+    /// unrelated to the memory tags of a [`MemoryTagger`].
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_tagged(
+        &mut self,
+        layout: Layout,
+        tag: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.allocate_first_fit(layout)?;
+        if layout.size() != 0 {
+            // SAFETY: The allocation was just made and its header belongs to the heap.
+            unsafe { header::set_tag(self.strip_tag(ptr), tag) };
+        }
+        Ok(ptr)
+    }
+
+    /// Like [`allocate_first_fit`][Self::allocate_first_fit], but the heap may free the
+    /// allocation on its own when another allocation would fail otherwise.
+    ///
+    /// The installed [`Purger`] is told about the allocation before it is freed, see
+    /// [`set_purger`][Self::set_purger]. Without a purger, the allocation behaves like a
+    /// regular one. It may still be freed with [`deallocate`][Self::deallocate] as usual.
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_purgeable(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.allocate_first_fit(layout)?;
+        if layout.size() != 0 {
+            // SAFETY: The allocation was just made and its header belongs to the heap.
+            unsafe { header::set_flags(self.strip_tag(ptr), header::PURGEABLE) };
+        }
+        Ok(ptr)
+    }
+
+    /// Installs a [`Purger`] that allows the heap to free
+    /// [purgeable][Self::allocate_purgeable] allocations when an allocation through
+    /// [`allocate_first_fit`][Self::allocate_first_fit] or one of its variants would fail
+    /// otherwise. Passing `None` removes the installed purger.
+    pub fn set_purger(&mut self, purger: Option<&'static dyn Purger>) {
+        self.purger = purger;
+    }
+
+    /// Frees all live allocations that were made by [`allocate_tagged`][Self::allocate_tagged]
+    /// with the given `tag` and returns how many were freed.
+    ///
+    /// The allocations are freed in address order in a single pass over the heap, so the
+    /// runtime is in `O(n)` where n is the number of live allocations and free blocks.
+    ///
+    /// # Safety
+    ///
+    /// None of the allocations with the given tag may be used afterwards.
+    pub unsafe fn free_all_tagged(&mut self, tag: usize) -> usize {
+        let mut freed = 0;
+        let mut pos = self.bottom();
+        let mut hint = None;
+
+        while let Some(block) = Allocations::after_hole(&self.holes, pos, hint).next_block() {
+            let header = header::Header::of_block(block);
+            pos = block.add(header.block_size());
+            if header.tag != tag || header.is_pinned() {
+                continue;
+            }
+            let ptr = header.payload(block);
+            // the recorded layout is already rounded up to whole granules, so rounding it
+            // again when it is freed doesn't change it
+            let layout = header.layout;
+            // the freed block and everything after it lies above the hint, so the rest of the
+            // heap doesn't need to be walked again
+            hint = self.deallocate_after(hint, ptr, layout);
+            freed += 1;
+        }
+        freed
+    }
+
+    /// Frees the allocation at `ptr` without knowing its layout, like `free` in C.
+    ///
+    /// The layout is read from the header of the allocation, see
+    /// [`allocation_layout`][Self::allocation_layout].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation of this heap that isn't zero-sized.
+    pub unsafe fn deallocate_unsized(&mut self, ptr: NonNull<u8>) {
+        let layout = self.allocation_layout(ptr);
+        self.deallocate(ptr, layout);
+    }
+
+    /// Returns the pointer and the layout of the live allocation that contains `ptr`.
+    ///
+    /// `ptr` may point anywhere into the allocation, so this can be used to classify
+    /// potential pointers, e.g. when scanning for roots in a conservative garbage collector.
+    /// Returns `None` if `ptr` points into free memory, into the metadata of a block or
+    /// outside of the heap. Zero-sized allocations don't take any memory, so they are
+    /// never returned.
+    ///
+    /// This walks the live allocations and free blocks below `ptr`, so the runtime is in
+    /// `O(n)`.
+    pub fn find_allocation_start(&self, ptr: *const u8) -> Option<(NonNull<u8>, Layout)> {
+        self.allocations()
+            .take_while(|(start, _)| start.as_ptr() as *const u8 <= ptr)
+            .find(|(start, layout)| ptr < start.as_ptr().wrapping_add(layout.size()))
+    }
+
+    /// Moves live allocations towards the bottom of the heap to coalesce the free memory.
+    ///
+    /// The allocations are visited in address order and each one is moved into the first
+    /// free block below it that is large enough. After an allocation was moved, its
+    /// contents are copied and `relocator` is called with the old and the new pointer, so
+    /// that the caller can update its references.
+    ///
+    /// Returns the number of allocations that were moved.
+    ///
+    /// # Safety
+    ///
+    /// Any allocation of this heap may be moved. The caller must ensure that all pointers
+    /// to moved allocations are updated by the `relocator` before they are used again.
+    pub unsafe fn defragment<R: Relocator>(&mut self, relocator: &mut R) -> usize {
+        let mut moved = 0;
+        let mut pos = self.bottom();
+        // a hole below `pos`, so that the walk continues there instead of at the bottom
+        let mut hint = None;
+
+        loop {
+            let mut blocks = Allocations::after_hole(&self.holes, pos, hint);
+            let block = match blocks.next_block() {
+                Some(block) => block,
+                None => break,
+            };
+            hint = blocks.last_hole();
+            let header = header::Header::of_block(block);
+            let block_layout = header.block_layout();
+            let layout = header.layout;
+            let offset = header.offset;
+            // If the block is moved, its old location becomes part of a hole, which is
+            // skipped when searching for the next block.
+            pos = block.add(header.block_size());
+            if header.is_pinned() {
+                continue;
+            }
+
+            let block = NonNull::new_unchecked(block);
+            if let Some((new_block, hole)) =
+                self.move_block_down(block, block_layout, offset + layout.size())
+            {
+                // the holes below the old block changed, but the old block is part of `hole`
+                hint = Some(hole);
+                let old = NonNull::new_unchecked(block.as_ptr().add(offset));
+                let new = NonNull::new_unchecked(new_block.as_ptr().add(offset));
+                relocator.relocate(old, new, layout);
+                moved += 1;
+            }
+        }
+        moved
+    }
+}
+
+#[cfg(all(feature = "alloc_ref", feature = "use_spin"))]
+unsafe impl<R: RawMutex> Allocator for LockedHeap<R> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, CoreAllocError> {
+        self.allocate_with(|heap| heap.allocate_first_fit_slice(layout))
+            .map_err(|_| CoreAllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.deallocate_locked(self.lock(), ptr, layout);
+    }
+}
+
+/// A [`Heap`] behind a lock, which can be used as a global allocator.
+///
+/// The raw lock `R` decides how cores wait for the lock. The default [`RawSpinlock`] retries
+/// as soon as the lock looks free. Under contention, [`BackoffSpinlock`] waits exponentially
+/// longer between attempts and [`TicketLock`] hands out the lock in the order it was
+/// requested, so that no core starves. During bring-up, [`CheckedSpinlock`] panics when an
+/// interrupt handler tries to take the lock that the interrupted code holds, instead of
+/// spinning forever. Heaps with a lock other than the default are created with
+/// [`from_heap`][LockedHeap::from_heap]:
+///
+/// ```
+/// use linked_list_allocator::{Heap, LockedHeap, TicketLock};
+///
+/// static ALLOCATOR: LockedHeap<TicketLock> = LockedHeap::from_heap(Heap::empty());
+/// ```
+///
+/// The lock itself isn't exposed, so no caller can hold it for longer than a single
+/// operation. Besides the allocator traits, the heap is used through methods like
+/// [`allocate_first_fit`][LockedHeap::allocate_first_fit] and
+/// [`stats`][LockedHeap::stats], and everything else runs in a closure passed to
+/// [`with_heap`][LockedHeap::with_heap].
+///
+/// The usage and event [counters][LockedHeap::counters] are also kept outside of the lock,
+/// so that they can be monitored without contending with allocations.
+#[cfg(feature = "use_spin")]
+pub struct LockedHeap<R: RawMutex = RawSpinlock>(Mutex<R, Heap>, SharedCounters, DeferredFrees);
+
+#[cfg(feature = "use_spin")]
 impl LockedHeap {
+    #[cfg(not(loom))]
     pub const fn empty() -> LockedHeap {
-        LockedHeap(Spinlock::new(Heap::empty()))
+        LockedHeap::from_heap(Heap::empty())
+    }
+
+    // loom's atomics can't be created in a const context
+    #[cfg(loom)]
+    pub fn empty() -> LockedHeap {
+        LockedHeap::from_heap(Heap::empty())
     }
 
     /// Creates a new heap with the given `bottom` and `size`.
@@ -299,7 +2565,8 @@ impl LockedHeap {
     /// The given `heap_size` must be large enough to store the required
     /// metadata, otherwise this function will panic. Depending on the
     /// alignment of the `hole_addr` pointer, the minimum size is between
-    /// `2 * size_of::<usize>` and `3 * size_of::<usize>`.
+    /// [`min_allocation_size`][Heap::min_allocation_size] and one
+    /// [`allocation_granularity`][Heap::allocation_granularity] more.
     ///
     /// # Safety
     ///
@@ -310,58 +2577,252 @@ impl LockedHeap {
     ///
     /// The provided memory range must be valid for the `'static` lifetime.
     pub unsafe fn new(heap_bottom: *mut u8, heap_size: usize) -> LockedHeap {
-        LockedHeap(Spinlock::new(Heap {
-            used: 0,
-            holes: HoleList::new(heap_bottom, heap_size),
-        }))
+        LockedHeap::from_heap(Heap::new(heap_bottom, heap_size))
+    }
+
+    /// Creates a new heap from a slice of raw memory.
+    ///
+    /// This is the locked equivalent of [`Heap::from_slice`], whose requirements apply to
+    /// this function as well.
+    pub fn from_slice(mem: &'static mut [MaybeUninit<u8>]) -> LockedHeap {
+        LockedHeap::from_heap(Heap::from_slice(mem))
     }
 }
 
 #[cfg(feature = "use_spin")]
-impl Deref for LockedHeap {
-    type Target = Spinlock<Heap>;
+impl<R: RawMutex> LockedHeap<R> {
+    /// Puts the given heap behind a lock of type `R`.
+    #[cfg(not(loom))]
+    pub const fn from_heap(heap: Heap) -> Self {
+        let counters = SharedCounters::of(&heap);
+        LockedHeap(Mutex::new(heap), counters, DeferredFrees::new())
+    }
+
+    // loom's atomics can't be created in a const context
+    #[cfg(loom)]
+    pub fn from_heap(heap: Heap) -> Self {
+        let counters = SharedCounters::of(&heap);
+        LockedHeap(Mutex::new(heap), counters, DeferredFrees::new())
+    }
+
+    /// Initializes an empty heap, see [`Heap::init`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the heap is already initialized.
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`Heap::init`] apply.
+    pub unsafe fn init(&self, heap_bottom: *mut u8, heap_size: usize) {
+        let mut heap = self.lock();
+        assert!(
+            heap.bottom().is_null(),
+            "The heap has already been initialized."
+        );
+        heap.init(heap_bottom, heap_size);
+    }
+
+    /// Initializes an empty heap with a slice of raw memory, see
+    /// [`Heap::init_from_slice`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the heap is already initialized.
+    pub fn init_from_slice(&self, mem: &'static mut [MaybeUninit<u8>]) {
+        self.lock().init_from_slice(mem);
+    }
+
+    /// Allocates a block for `layout`, see [`Heap::allocate_first_fit`].
+    #[cfg_attr(feature = "call_sites", track_caller)]
+    pub fn allocate_first_fit(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        #[cfg(feature = "call_sites")]
+        let site = Location::caller();
+        self.allocate_with(|heap| {
+            // the closure would be recorded as the call site otherwise
+            #[cfg(feature = "call_sites")]
+            {
+                heap.caller = Some(site);
+            }
+            heap.allocate_first_fit(layout)
+        })
+    }
+
+    /// Frees the given allocation, see [`Heap::deallocate`].
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`Heap::deallocate`] apply.
+    pub unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.deallocate_locked(self.lock(), ptr, layout);
+    }
 
-    fn deref(&self) -> &Spinlock<Heap> {
-        &self.0
+    /// Returns a snapshot of the statistics of the heap, see [`Heap::stats`].
+    ///
+    /// This takes the lock and walks the list of free blocks. Use
+    /// [`counters`][LockedHeap::counters] to sample the heap usage without the lock.
+    pub fn stats(&self) -> HeapStats {
+        self.lock().stats()
+    }
+
+    /// Runs `f` with the locked heap, e.g. to install hooks or to extend the heap.
+    ///
+    /// The lock is held until `f` returns, so `f` should be short and must not allocate
+    /// from this heap. Allocations that `f` makes directly on the [`Heap`] are not
+    /// reflected in the [`counters`][LockedHeap::counters].
+    pub fn with_heap<T>(&self, f: impl FnOnce(&mut Heap) -> T) -> T {
+        f(&mut self.lock())
+    }
+
+    /// Returns the usage and event counters of the heap without taking the lock.
+    ///
+    /// This is meant for monitoring code that samples the heap usage often, see
+    /// [`HeapCounters`] for the guarantees of the values.
+    pub fn counters(&self) -> HeapCounters {
+        self.1.load()
+    }
+
+    /// Locks the heap.
+    pub(crate) fn lock(&self) -> impl DerefMut<Target = Heap> + '_ {
+        self.0.lock()
+    }
+
+    /// Locks the heap if the lock is free.
+    pub(crate) fn try_lock(&self) -> Option<impl DerefMut<Target = Heap> + '_> {
+        self.0.try_lock()
+    }
+
+    /// Runs the allocation `f` on the locked heap and updates the counters after the lock
+    /// was released. The [deferred deallocations][Self::deallocate_deferred] are freed
+    /// first.
+    pub(crate) fn allocate_with<T>(
+        &self,
+        f: impl FnOnce(&mut Heap) -> Result<T, AllocError>,
+    ) -> Result<T, AllocError> {
+        let (result, grown, drained, freed) = {
+            let mut heap = self.lock();
+            let used = heap.used();
+            let drained = self.2.drain(&mut heap);
+            let freed = used - heap.used();
+            let used = heap.used();
+            let result = f(&mut heap);
+            (result, heap.used().wrapping_sub(used), drained, freed)
+        };
+        if drained != 0 {
+            self.1.record_deallocations(drained, freed);
+        }
+        self.1.record_allocation(result.is_ok(), grown);
+        result
+    }
+
+    /// Frees the allocation at `ptr` in `heap`, which must be locked from this
+    /// `LockedHeap`, and updates the counters after the lock was released. Returns the size
+    /// of the free block that the allocation became part of.
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`Heap::deallocate`] apply.
+    pub(crate) unsafe fn deallocate_locked(
+        &self,
+        mut heap: impl DerefMut<Target = Heap>,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> usize {
+        let used = heap.used();
+        let free_block = heap.deallocate_merged(ptr, layout);
+        let freed = used - heap.used();
+        drop(heap);
+        self.1.record_deallocation(freed);
+        free_block
     }
 }
 
 #[cfg(feature = "use_spin")]
-unsafe impl GlobalAlloc for LockedHeap {
+unsafe impl<R: RawMutex> GlobalAlloc for LockedHeap<R> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.0
-            .lock()
-            .allocate_first_fit(layout)
-            .ok()
+        self.allocate_with(|heap| heap.allocate_first_fit(layout))
             .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.0
-            .lock()
-            .deallocate(NonNull::new_unchecked(ptr), layout)
+        self.deallocate_locked(self.lock(), NonNull::new_unchecked(ptr), layout);
     }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.allocate_with(|heap| heap.allocate_zeroed(layout))
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+}
+
+/// Returns the layout of `n` pages of `page_size` bytes that are aligned to the page size.
+fn page_layout(n: usize, page_size: usize) -> Result<Layout, AllocError> {
+    n.checked_mul(page_size)
+        .and_then(|size| Layout::from_size_align(size, page_size).ok())
+        .ok_or(AllocError::InvalidLayout)
 }
 
 /// Align downwards. Returns the greatest x with alignment `align`
 /// so that x <= addr. The alignment must be a power of 2.
+///
+/// See [`checked_align_down_size`] for a variant that doesn't panic.
 pub fn align_down_size(size: usize, align: usize) -> usize {
-    if align.is_power_of_two() {
-        size & !(align - 1)
-    } else if align == 0 {
-        size
-    } else {
-        panic!("`align` must be a power of 2");
+    match checked_align_down_size(size, align) {
+        Some(size) => size,
+        None if align == 0 => size,
+        None => panic!("`align` must be a power of 2"),
     }
 }
 
+/// Align upwards. Returns the smallest x with alignment `align`
+/// so that x >= size. The alignment must be a power of 2.
+///
+/// See [`checked_align_up_size`] for a variant that doesn't panic or overflow.
 pub fn align_up_size(size: usize, align: usize) -> usize {
     align_down_size(size + align - 1, align)
 }
 
+/// Like [`align_down_size`], but returns `None` if `align` is not a power of 2.
+pub fn checked_align_down_size(size: usize, align: usize) -> Option<usize> {
+    if align.is_power_of_two() {
+        Some(size & !(align - 1))
+    } else {
+        None
+    }
+}
+
+/// Like [`align_up_size`], but returns `None` if `align` is not a power of 2 or if the
+/// result would overflow.
+pub fn checked_align_up_size(size: usize, align: usize) -> Option<usize> {
+    checked_align_down_size(size.checked_add(align.checked_sub(1)?)?, align)
+}
+
 /// Align upwards. Returns the smallest x with alignment `align`
 /// so that x >= addr. The alignment must be a power of 2.
+///
+/// See [`checked_align_up`] for a variant that doesn't panic or wrap around.
 pub fn align_up(addr: *mut u8, align: usize) -> *mut u8 {
     let offset = addr.align_offset(align);
     addr.wrapping_add(offset)
 }
+
+/// Like [`align_up`], but returns `None` if `align` is not a power of 2 or if the result
+/// would wrap around the end of the address space.
+pub fn checked_align_up(addr: *mut u8, align: usize) -> Option<*mut u8> {
+    if !align.is_power_of_two() {
+        return None;
+    }
+    let aligned = addr.wrapping_add(addr.align_offset(align));
+    if aligned < addr {
+        None
+    } else {
+        Some(aligned)
+    }
+}
+
+/// Returns the pointer for a zero-sized allocation with the given alignment, which is
+/// aligned and non-null, but doesn't point into any heap.
+fn dangling(align: usize) -> NonNull<u8> {
+    // SAFETY: Alignments are never zero. The pointer is offset from null instead of being
+    // created from an integer, like `ptr::invalid`, so that it is a valid capability on CHERI.
+    unsafe { NonNull::new_unchecked(core::ptr::null_mut::<u8>().wrapping_add(align)) }
+}
@@ -0,0 +1,126 @@
+use core::fmt;
+use core::iter::Peekable;
+
+use crate::hole::Holes;
+use crate::Heap;
+
+const MAP_USED: u8 = b'#';
+const MAP_MIXED: u8 = b':';
+const MAP_FREE: u8 = b'.';
+
+impl Heap {
+    /// Renders a coarse map of the heap with `width` characters into `out`.
+    ///
+    /// The usable size of the heap is divided into `width` sections of (almost) equal size.
+    /// Each section is rendered as `#` if it is completely allocated, as `.` if it is
+    /// completely free, and as `:` otherwise.
+    /// For example, `###...##..` shows a heap whose free memory is split into two blocks.
+    ///
+    /// The map is computed in a single pass over the list of free blocks and does not
+    /// allocate.
+    pub fn render_map<W: fmt::Write + ?Sized>(&self, out: &mut W, width: usize) -> fmt::Result {
+        for cell in self.map_cells(width) {
+            out.write_char(cell as char)?;
+        }
+        Ok(())
+    }
+
+    /// Renders a coarse map of the heap into `buf`, using one byte per section.
+    ///
+    /// This is equivalent to [`render_map`][Heap::render_map] with a width of `buf.len()`.
+    pub fn render_map_into(&self, buf: &mut [u8]) {
+        let width = buf.len();
+        for (byte, cell) in buf.iter_mut().zip(self.map_cells(width)) {
+            *byte = cell;
+        }
+    }
+
+    fn map_cells(&self, width: usize) -> MapCells<'_> {
+        MapCells {
+            holes: self.holes.holes().peekable(),
+            bottom: self.bottom() as usize,
+            size: self.size(),
+            width,
+            index: 0,
+        }
+    }
+}
+
+struct MapCells<'a> {
+    holes: Peekable<Holes<'a>>,
+    bottom: usize,
+    size: usize,
+    width: usize,
+    index: usize,
+}
+
+impl<'a> MapCells<'a> {
+    fn section_start(&self, index: usize) -> usize {
+        // use 128 bit arithmetic to avoid overflows for large heaps
+        self.bottom + (self.size as u128 * index as u128 / self.width as u128) as usize
+    }
+}
+
+impl<'a> Iterator for MapCells<'a> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.index >= self.width {
+            return None;
+        }
+        let start = self.section_start(self.index);
+        let end = self.section_start(self.index + 1);
+        self.index += 1;
+
+        let mut free = 0;
+        while let Some(&(addr, size)) = self.holes.peek() {
+            let hole_start = addr as usize;
+            let hole_end = hole_start + size;
+            if hole_start >= end {
+                break;
+            }
+            free += hole_end.min(end).saturating_sub(hole_start.max(start));
+            if hole_end > end {
+                // the hole continues in the next section
+                break;
+            }
+            self.holes.next();
+        }
+
+        Some(if free == 0 && start != end {
+            MAP_USED
+        } else if free == end - start {
+            MAP_FREE
+        } else {
+            MAP_MIXED
+        })
+    }
+}
+
+#[cfg(all(test, not(any(feature = "call_sites", feature = "timestamps"))))]
+mod test {
+    use crate::test::new_heap;
+    use core::alloc::Layout;
+    use std::string::String;
+
+    #[test]
+    fn render_map() {
+        let mut heap = new_heap();
+        let mut map = String::new();
+        heap.render_map(&mut map, 10).unwrap();
+        assert_eq!(map, "..........");
+
+        let layout = Layout::from_size_align(450, 8).unwrap();
+        let ptr = heap.allocate_first_fit(layout).unwrap();
+        let mut buf = [0; 10];
+        heap.render_map_into(&mut buf);
+        assert_eq!(&buf, b"####:.....");
+
+        let small = Layout::from_size_align(16, 8).unwrap();
+        let _small = heap.allocate_first_fit(small).unwrap();
+        unsafe { heap.deallocate(ptr, layout) };
+        let mut map = String::new();
+        heap.render_map(&mut map, 5).unwrap();
+        assert_eq!(map, "..:..");
+    }
+}
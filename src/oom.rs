@@ -0,0 +1,163 @@
+//! A ready-made handler for allocation errors, enabled by the `alloc_error_handler` feature.
+
+use core::alloc::Layout;
+use core::fmt;
+
+use crate::Heap;
+#[cfg(feature = "use_spin")]
+use crate::{LockedHeap, RawMutex};
+
+/// The number of size classes of free blocks in an allocation error report, one for every
+/// power of two.
+const SIZE_CLASSES: usize = usize::BITS as usize;
+
+/// Defines the `#[alloc_error_handler]` of the program, which writes a report of the heap
+/// state to a sink before it panics, see [`handle_alloc_error`].
+///
+/// The first argument is the [`LockedHeap`] that serves as the global allocator and the
+/// second one an expression that returns a [`fmt::Write`] sink, e.g. the writer of a UART.
+/// The sink is only created when an allocation fails. The crate that uses the macro must
+/// enable the unstable `alloc_error_handler` feature of Rust:
+///
+/// ```ignore
+/// #![feature(alloc_error_handler)]
+///
+/// #[global_allocator]
+/// static ALLOCATOR: LockedHeap = LockedHeap::empty();
+///
+/// linked_list_allocator::alloc_error_handler!(ALLOCATOR, Uart::steal());
+/// ```
+#[cfg(feature = "use_spin")]
+#[macro_export]
+macro_rules! alloc_error_handler {
+    ($heap:expr, $sink:expr) => {
+        #[alloc_error_handler]
+        fn linked_list_allocator_alloc_error(layout: ::core::alloc::Layout) -> ! {
+            let mut sink = $sink;
+            $crate::handle_alloc_error(&$heap, layout, &mut sink)
+        }
+    };
+}
+
+/// Writes a report of the failed allocation of `layout` and of the state of `heap` to
+/// `sink` and panics, for use in an `#[alloc_error_handler]`, see
+/// [`alloc_error_handler!`].
+///
+/// The report contains the layout, the [statistics][Heap::stats] of the heap and the
+/// number of free blocks per size class, which shows whether the heap was exhausted or
+/// fragmented. It is written without allocating. If the lock of the heap is held, e.g.
+/// because the allocation failed within a [`with_heap`][LockedHeap::with_heap] closure,
+/// only the [counters][LockedHeap::counters] are written.
+///
+/// The panic carries the same message as the default handler of the `alloc` crate, so
+/// with `panic = "abort"` the program aborts after the report was written.
+#[cfg(feature = "use_spin")]
+pub fn handle_alloc_error<R: RawMutex, W: fmt::Write + ?Sized>(
+    heap: &LockedHeap<R>,
+    layout: Layout,
+    sink: &mut W,
+) -> ! {
+    // the report is best-effort, the sink can't do anything about its own errors here
+    let _ = match heap.try_lock() {
+        Some(heap) => write_alloc_error_report(&heap, layout, sink),
+        None => writeln!(
+            sink,
+            "memory allocation of {:?} failed, the heap is locked: {:?}",
+            layout,
+            heap.counters()
+        ),
+    };
+    panic!("memory allocation of {} bytes failed", layout.size())
+}
+
+/// Writes a report of the failed allocation of `layout` and of the state of `heap` to
+/// `sink`, see [`handle_alloc_error`].
+///
+/// This walks the list of free blocks once, so the runtime is in `O(n)` where n is the
+/// number of free blocks.
+pub fn write_alloc_error_report<W: fmt::Write + ?Sized>(
+    heap: &Heap,
+    layout: Layout,
+    sink: &mut W,
+) -> fmt::Result {
+    let stats = heap.stats();
+    writeln!(
+        sink,
+        "memory allocation of {} bytes with alignment {} failed",
+        layout.size(),
+        layout.align()
+    )?;
+    writeln!(
+        sink,
+        "heap: size {}, used {}, free {}, peak {}, {} allocations, {} deallocations, {} failed",
+        stats.size,
+        stats.used,
+        stats.free,
+        stats.peak_used,
+        stats.allocations,
+        stats.deallocations,
+        stats.failed_allocations
+    )?;
+    writeln!(
+        sink,
+        "free blocks: {}, the largest has {} bytes",
+        stats.holes, stats.largest_hole
+    )?;
+    let mut classes = [0; SIZE_CLASSES];
+    for (_, size) in heap.holes.holes() {
+        if size != 0 {
+            classes[SIZE_CLASSES - 1 - size.leading_zeros() as usize] += 1;
+        }
+    }
+    for (class, &count) in classes.iter().enumerate().filter(|(_, &count)| count != 0) {
+        let min = 1usize << class;
+        writeln!(sink, "  {}..={} bytes: {}", min, min + (min - 1), count)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::write_alloc_error_report;
+    use crate::test::new_heap;
+    use core::alloc::Layout;
+    use std::string::String;
+
+    #[test]
+    fn reports_heap_state() {
+        let mut heap = new_heap();
+        let small = Layout::from_size_align(64, 8).unwrap();
+        let a = heap.allocate_first_fit(small).unwrap();
+        let _b = heap.allocate_first_fit(small).unwrap();
+        unsafe { heap.deallocate(a, small) };
+
+        let large = Layout::from_size_align(2000, 8).unwrap();
+        assert!(heap.allocate_first_fit(large).is_err());
+        let mut report = String::new();
+        write_alloc_error_report(&heap, large, &mut report).unwrap();
+        let stats = heap.stats();
+        let expected = std::format!(
+            "memory allocation of 2000 bytes with alignment 8 failed\n\
+             heap: size {}, used {}, free {}, peak {}, 2 allocations, 1 deallocations, 1 failed\n\
+             free blocks: 2, the largest has {} bytes\n",
+            stats.size,
+            stats.used,
+            stats.free,
+            stats.peak_used,
+            stats.largest_hole
+        );
+        assert!(report.starts_with(&expected), "{}", report);
+        assert!(report.contains("  64..=127 bytes: 1\n"), "{}", report);
+        assert!(report.contains("  512..=1023 bytes: 1\n"), "{}", report);
+    }
+
+    #[test]
+    #[cfg(feature = "use_spin")]
+    #[should_panic(expected = "memory allocation of 2000 bytes failed")]
+    fn handles_alloc_error() {
+        let heap: crate::LockedHeap = crate::LockedHeap::empty();
+        let layout = Layout::from_size_align(2000, 8).unwrap();
+        let mut report = String::new();
+        super::handle_alloc_error(&heap, layout, &mut report);
+    }
+}
@@ -0,0 +1,197 @@
+//! Support for giving the pages of large free blocks back to the system.
+//!
+//! [`PageHooks`] are installed with [`Heap::set_page_hooks`][crate::Heap::set_page_hooks].
+//! Whenever a deallocation leaves a free block behind that is at least as large as the
+//! configured threshold, the heap decommits the whole pages inside of it, except for the
+//! page that holds the bookkeeping data of the block. Before the heap writes to free memory
+//! again, e.g. to hand out a block or to store the bookkeeping data of a new free block, it
+//! commits the pages that it might touch.
+
+use core::mem::size_of;
+use core::ptr::NonNull;
+
+use crate::align_up;
+use crate::hole::{Hole, MIRROR_SIZE};
+
+/// Decommits and commits pages of free heap memory, e.g. with `madvise` or by unmapping
+/// them.
+///
+/// # Safety
+///
+/// After [`commit`][PageHooks::commit] returns, the given range must be readable and
+/// writable. It may be called for pages that are committed already, which must keep their
+/// contents.
+pub unsafe trait PageHooks: Sync {
+    /// Returns the size of a page in bytes, which must be a power of two.
+    fn page_size(&self) -> usize;
+
+    /// Gives the `len` bytes at `ptr` back to the system. Both are multiples of the page
+    /// size.
+    ///
+    /// # Safety
+    ///
+    /// The range is free memory of the heap, which is neither read nor written until it
+    /// is committed again.
+    unsafe fn decommit(&self, ptr: NonNull<u8>, len: usize);
+
+    /// Makes the `len` bytes at `ptr` accessible again. Both are multiples of the page size.
+    ///
+    /// The contents of pages that were decommitted before may be arbitrary afterwards.
+    ///
+    /// # Safety
+    ///
+    /// The range lies within the memory of the heap.
+    unsafe fn commit(&self, ptr: NonNull<u8>, len: usize);
+}
+
+/// The installed page hooks and the size from which on free blocks are decommitted.
+#[derive(Clone, Copy)]
+pub(crate) struct Pages {
+    pub hooks: &'static dyn PageHooks,
+    pub threshold: usize,
+}
+
+impl Pages {
+    /// Decommits the whole pages of the hole at `addr` that lie behind its header, in front
+    /// of its mirror and below `limit`, if the hole has at least `threshold` bytes.
+    pub unsafe fn decommit_hole(&self, addr: *mut u8, size: usize, limit: *mut u8) {
+        if size < self.threshold {
+            return;
+        }
+        let page = self.hooks.page_size();
+        let start = align_up(addr.wrapping_add(size_of::<Hole>()), page);
+        let end = addr.wrapping_add(size - MIRROR_SIZE).min(limit);
+        let end = end.wrapping_sub(end as usize % page);
+        if end > start {
+            self.hooks
+                .decommit(NonNull::new_unchecked(start), end as usize - start as usize);
+        }
+    }
+
+    /// Commits all pages that overlap the `len` bytes at `ptr`.
+    pub unsafe fn commit(&self, ptr: *mut u8, len: usize) {
+        let page = self.hooks.page_size();
+        let start = ptr.wrapping_sub(ptr as usize % page);
+        let end = align_up(ptr.wrapping_add(len), page);
+        if end > start {
+            self.hooks
+                .commit(NonNull::new_unchecked(start), end as usize - start as usize);
+        }
+    }
+}
+
+// the test hooks write to free memory, which AddressSanitizer doesn't allow
+#[cfg(all(
+    test,
+    not(feature = "asan"),
+    not(all(
+        any(feature = "call_sites", feature = "timestamps"),
+        feature = "redundant"
+    ))
+))]
+mod test {
+    use super::PageHooks;
+    use crate::test::new_heap;
+    use crate::Heap;
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use std::boxed::Box;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    const PAGE: usize = 64;
+    const PATTERN: u8 = 0xdd;
+
+    /// Fills decommitted pages with a pattern, so that writes to them can be detected.
+    struct TestPages {
+        decommitted: Mutex<Vec<usize>>,
+    }
+
+    impl TestPages {
+        fn is_committed(&self, ptr: *mut u8, len: usize) -> bool {
+            let start = ptr as usize / PAGE;
+            let end = (ptr as usize + len + PAGE - 1) / PAGE;
+            let decommitted = self.decommitted.lock().unwrap();
+            (start..end).all(|page| !decommitted.contains(&page))
+        }
+
+        fn check_untouched(&self, heap: &Heap) {
+            for &page in self.decommitted.lock().unwrap().iter() {
+                // derive the page from the heap memory to keep its provenance
+                let start = heap
+                    .bottom()
+                    .wrapping_add(page * PAGE - heap.bottom() as usize);
+                let bytes = unsafe { core::slice::from_raw_parts(start, PAGE) };
+                assert!(bytes.iter().all(|&byte| byte == PATTERN));
+            }
+        }
+    }
+
+    unsafe impl PageHooks for TestPages {
+        fn page_size(&self) -> usize {
+            PAGE
+        }
+
+        unsafe fn decommit(&self, ptr: NonNull<u8>, len: usize) {
+            assert_eq!(ptr.as_ptr() as usize % PAGE, 0);
+            assert_eq!(len % PAGE, 0);
+            ptr.as_ptr().write_bytes(PATTERN, len);
+            let mut decommitted = self.decommitted.lock().unwrap();
+            for page in 0..len / PAGE {
+                let page = ptr.as_ptr() as usize / PAGE + page;
+                if !decommitted.contains(&page) {
+                    decommitted.push(page);
+                }
+            }
+        }
+
+        unsafe fn commit(&self, ptr: NonNull<u8>, len: usize) {
+            let start = ptr.as_ptr() as usize / PAGE;
+            self.decommitted
+                .lock()
+                .unwrap()
+                .retain(|page| *page < start || *page >= start + len / PAGE);
+        }
+    }
+
+    #[test]
+    fn decommits_large_free_blocks() {
+        let raw = Box::into_raw(Box::new(TestPages {
+            decommitted: Mutex::new(Vec::new()),
+        }));
+        // freed through `raw`, which the shared reference is derived from
+        let pages: &'static TestPages = unsafe { &*raw };
+        let mut heap = new_heap();
+        unsafe { heap.set_page_hooks(Some(pages), 4 * PAGE) };
+        // all pages except for the one with the header of the hole
+        let size = heap.size();
+        assert_eq!(pages.decommitted.lock().unwrap().len(), size / PAGE - 1);
+
+        let mut seed = 1u32;
+        let mut live: Vec<(NonNull<u8>, Layout)> = Vec::new();
+        for _ in 0..500 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let random = (seed >> 8) as usize;
+            if random % 3 != 0 || live.is_empty() {
+                let layout = Layout::from_size_align(1 + random % 200, 1 << (random % 7)).unwrap();
+                if let Ok(ptr) = heap.allocate_first_fit(layout) {
+                    assert!(pages.is_committed(ptr.as_ptr(), layout.size()));
+                    unsafe { ptr.as_ptr().write_bytes(0xab, layout.size()) };
+                    live.push((ptr, layout));
+                }
+            } else {
+                let (ptr, layout) = live.swap_remove(random % live.len());
+                unsafe { heap.deallocate(ptr, layout) };
+            }
+            pages.check_untouched(&heap);
+        }
+
+        for (ptr, layout) in live {
+            unsafe { heap.deallocate(ptr, layout) };
+        }
+        assert_eq!(pages.decommitted.lock().unwrap().len(), size / PAGE - 1);
+        unsafe {
+            drop(Box::from_raw(raw));
+        }
+    }
+}
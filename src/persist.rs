@@ -0,0 +1,113 @@
+//! Keeping the list of free blocks recoverable in persistent memory, enabled by the `pmem`
+//! feature.
+
+use core::ptr::NonNull;
+
+/// Writes the contents of the cache back to persistent memory, e.g. NVDIMMs.
+///
+/// The hooks are installed with [`Heap::set_persist_hooks`][crate::Heap::set_persist_hooks].
+/// Every write to the header of a free block, and to the record at the start of a
+/// [persistent][crate::Heap::init_persistent] heap, is flushed and fenced before the heap
+/// writes the next one. The heap changes the list in an order in which every intermediate
+/// list is valid: a free block is unlinked before it is split, and a new free block is
+/// written before it is linked. So after a power loss in the middle of an operation,
+/// [`Heap::adopt`][crate::Heap::adopt] finds a valid list, which at worst misses the free
+/// memory that the operation was changing. The allocations themselves are not flushed.
+///
+/// On x86_64, [`flush`][PersistHooks::flush] would execute `clwb` for each cache line of
+/// the range and [`fence`][PersistHooks::fence] would execute `sfence`.
+pub trait PersistHooks: Sync {
+    /// Starts writing the cache lines that hold the `len` bytes at `ptr` back to persistent
+    /// memory. The write-back may still be in progress when this returns.
+    fn flush(&self, ptr: NonNull<u8>, len: usize);
+
+    /// Waits until all write-backs that were started before have reached persistent
+    /// memory.
+    fn fence(&self);
+}
+
+#[cfg(test)]
+mod test {
+    use super::PersistHooks;
+    use crate::test::Chonk;
+    use crate::Heap;
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use std::boxed::Box;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    /// Records the flushed ranges and checks that each of them is fenced.
+    struct TestPersist {
+        flushed: Mutex<Vec<(usize, usize)>>,
+        unfenced: Mutex<usize>,
+    }
+
+    impl TestPersist {
+        fn is_flushed(&self, addr: *mut u8, len: usize) -> bool {
+            let addr = addr as usize;
+            let flushed = self.flushed.lock().unwrap();
+            flushed
+                .iter()
+                .any(|&(start, size)| start <= addr && addr + len <= start + size)
+        }
+    }
+
+    impl PersistHooks for TestPersist {
+        fn flush(&self, ptr: NonNull<u8>, len: usize) {
+            self.flushed
+                .lock()
+                .unwrap()
+                .push((ptr.as_ptr() as usize, len));
+            *self.unfenced.lock().unwrap() += 1;
+        }
+
+        fn fence(&self) {
+            *self.unfenced.lock().unwrap() = 0;
+        }
+    }
+
+    #[test]
+    fn flushes_free_block_headers() {
+        let raw = Box::into_raw(Box::new(TestPersist {
+            flushed: Mutex::new(Vec::new()),
+            unfenced: Mutex::new(0),
+        }));
+        // freed through `raw`, which the shared reference is derived from
+        let persist: &'static TestPersist = unsafe { &*raw };
+        let (chonk, data) = Chonk::<1024>::new();
+        let mut heap = Heap::empty();
+        heap.set_persist_hooks(Some(persist));
+        unsafe { heap.init_persistent(data, 1024) };
+        // the record at the start of the heap
+        assert!(persist.is_flushed(data, 4 * core::mem::size_of::<usize>()));
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = heap.allocate_first_fit(layout).unwrap();
+        let b = heap.allocate_first_fit(layout).unwrap();
+        persist.flushed.lock().unwrap().clear();
+        unsafe { heap.deallocate(a, layout) };
+        // the freed block and the link to it from the first block, which lies in the record
+        let header = Heap::min_allocation_size();
+        let (hole, _) = heap.holes.holes().next().unwrap();
+        assert!(persist.is_flushed(hole, header));
+        let head = data.wrapping_add(4 * core::mem::size_of::<usize>());
+        assert!(persist.is_flushed(head, header));
+        assert_eq!(*persist.unfenced.lock().unwrap(), 0);
+
+        // the flushed list can be adopted
+        let mut adopted = Heap::empty();
+        unsafe { adopted.adopt(data, 1024) }.unwrap();
+        assert_eq!(adopted.used(), heap.used());
+        unsafe { heap.deallocate(b, layout) };
+
+        heap.set_persist_hooks(None);
+        persist.flushed.lock().unwrap().clear();
+        let _ = heap.allocate_first_fit(layout).unwrap();
+        assert!(persist.flushed.lock().unwrap().is_empty());
+        unsafe {
+            drop(Box::from_raw(raw));
+            Chonk::unleak(chonk);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Fixed-size pools of typed slots, see [`Heap::pool`].
+
+use core::alloc::Layout;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ptr::NonNull;
+
+use crate::{AllocError, Heap};
+
+/// A slot of a [`Pool`], which either holds a value or links to the next free slot.
+union Slot<T> {
+    next: Option<NonNull<Slot<T>>>,
+    value: ManuallyDrop<T>,
+}
+
+/// A fixed number of slots for values of type `T`, carved from a single block of a heap.
+///
+/// Created by [`Heap::pool`]. Taking and returning a slot with [`get`][Pool::get] and
+/// [`put`][Pool::put] takes constant time and never touches the heap, so a pool gives a
+/// bounded and deterministic allocation for one type while the heap serves everything
+/// else.
+///
+/// The pool doesn't borrow the heap, so its block must be returned explicitly with
+/// [`Heap::free_pool`]. A pool that is dropped instead leaks its block.
+pub struct Pool<T> {
+    slab: NonNull<Slot<T>>,
+    capacity: usize,
+    /// The first free slot, whose `next` field links to the next one.
+    free: Option<NonNull<Slot<T>>>,
+    available: usize,
+    _owned: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Send for Pool<T> {}
+
+impl<T> Pool<T> {
+    /// Returns the number of slots of the pool.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of free slots.
+    pub fn available(&self) -> usize {
+        self.available
+    }
+
+    /// Moves `value` into a free slot and returns a pointer to it, or gives `value` back if
+    /// all slots are taken.
+    pub fn get(&mut self, value: T) -> Result<NonNull<T>, T> {
+        let slot = match self.free {
+            Some(slot) => slot,
+            None => return Err(value),
+        };
+        // SAFETY: Free slots are within the slab and hold a link.
+        unsafe {
+            self.free = (*slot.as_ptr()).next;
+            slot.as_ptr().write(Slot {
+                value: ManuallyDrop::new(value),
+            });
+        }
+        self.available -= 1;
+        Ok(slot.cast())
+    }
+
+    /// Moves the value out of the slot at `ptr` and frees the slot.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`get`][Pool::get] of this pool and not been put
+    /// back yet.
+    pub unsafe fn put(&mut self, ptr: NonNull<T>) -> T {
+        debug_assert!(self.contains(ptr), "pointer doesn't belong to the pool");
+        let slot = ptr.cast::<Slot<T>>();
+        let value = ManuallyDrop::into_inner(slot.as_ptr().read().value);
+        slot.as_ptr().write(Slot { next: self.free });
+        self.free = Some(slot);
+        self.available += 1;
+        value
+    }
+
+    /// Returns whether `ptr` points to a slot of the pool.
+    pub fn contains(&self, ptr: NonNull<T>) -> bool {
+        let start = self.slab.as_ptr() as usize;
+        let offset = (ptr.as_ptr() as usize).wrapping_sub(start);
+        let slot_size = core::mem::size_of::<Slot<T>>();
+        offset < self.capacity * slot_size && offset % slot_size == 0
+    }
+
+    fn layout(capacity: usize) -> Result<Layout, AllocError> {
+        Layout::array::<Slot<T>>(capacity).map_err(|_| AllocError::InvalidLayout)
+    }
+}
+
+impl Heap {
+    /// Allocates a [`Pool`] of `capacity` slots for values of type `T`.
+    ///
+    /// The slots are allocated as one block, which is returned to the heap by
+    /// [`free_pool`][Heap::free_pool]. Every slot is at least as large as a pointer, since
+    /// free slots link to each other. The runtime is in `O(n)` where n is the number of
+    /// free blocks plus `capacity`.
+    pub fn pool<T>(&mut self, capacity: usize) -> Result<Pool<T>, AllocError> {
+        let slab = self
+            .allocate_first_fit(Pool::<T>::layout(capacity)?)?
+            .cast::<Slot<T>>();
+        // link the slots in address order, so that the first slot is taken first
+        let mut free = None;
+        for i in (0..capacity).rev() {
+            // SAFETY: The slot is within the block that was just allocated.
+            unsafe {
+                let slot = slab.as_ptr().add(i);
+                slot.write(Slot { next: free });
+                free = Some(NonNull::new_unchecked(slot));
+            }
+        }
+        Ok(Pool {
+            slab,
+            capacity,
+            free,
+            available: capacity,
+            _owned: PhantomData,
+        })
+    }
+
+    /// Frees the block of a [`Pool`].
+    ///
+    /// Values that are still in the pool are not dropped.
+    ///
+    /// # Safety
+    ///
+    /// The pool must have been allocated by [`pool`][Heap::pool] of this heap, and the
+    /// pointers to its slots must not be used anymore.
+    pub unsafe fn free_pool<T>(&mut self, pool: Pool<T>) {
+        let layout = Pool::<T>::layout(pool.capacity).unwrap();
+        self.deallocate(pool.slab.cast(), layout);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::new_heap;
+    use std::vec::Vec;
+
+    #[test]
+    fn get_and_put() {
+        let mut heap = new_heap();
+        let mut pool = heap.pool::<[u32; 3]>(4).unwrap();
+        let used = heap.used();
+        assert!(used >= 4 * 12);
+        assert_eq!(pool.capacity(), 4);
+
+        let slots: Vec<_> = (0..4).map(|i| pool.get([i; 3]).unwrap()).collect();
+        assert_eq!(pool.available(), 0);
+        assert_eq!(pool.get([9; 3]), Err([9; 3]));
+        assert!(slots[1] > slots[0]);
+        assert!(slots.iter().all(|&ptr| pool.contains(ptr)));
+        // the heap isn't used for the slots
+        assert_eq!(heap.used(), used);
+
+        assert_eq!(unsafe { pool.put(slots[2]) }, [2; 3]);
+        assert_eq!(pool.available(), 1);
+        // the freed slot is reused
+        let slot = pool.get([5; 3]).unwrap();
+        assert_eq!(slot, slots[2]);
+        assert_eq!(unsafe { *slot.as_ptr() }, [5; 3]);
+        assert_eq!(unsafe { *slots[3].as_ptr() }, [3; 3]);
+
+        unsafe { heap.free_pool(pool) };
+        assert_eq!(heap.used(), 0);
+    }
+}
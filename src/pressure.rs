@@ -0,0 +1,93 @@
+/// How scarce the free memory of a heap is, see
+/// [`Heap::set_pressure_thresholds`][crate::Heap::set_pressure_thresholds].
+///
+/// Changes of the level are reported to
+/// [`HeapHooks::on_pressure`][crate::HeapHooks::on_pressure], e.g. to shrink caches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Pressure {
+    /// The heap is below no threshold.
+    Normal,
+    /// The heap is below the threshold for elevated pressure.
+    Elevated,
+    /// The heap is below the threshold for critical pressure.
+    Critical,
+}
+
+/// The limits below which a heap enters a [`Pressure`] level.
+///
+/// The level is entered if either limit is undercut. A limit of zero is never undercut.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PressureThreshold {
+    /// The number of free bytes.
+    pub free: usize,
+    /// The size of the largest free block in bytes.
+    ///
+    /// Checking this limit walks the list of free blocks after every allocation and
+    /// deallocation, so it should only be set if fragmentation is a concern.
+    pub largest_hole: usize,
+}
+
+impl PressureThreshold {
+    fn is_undercut(&self, free: usize, largest_hole: usize) -> bool {
+        free < self.free || largest_hole < self.largest_hole
+    }
+}
+
+/// The thresholds and the current pressure level of a heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PressureState {
+    elevated: PressureThreshold,
+    critical: PressureThreshold,
+    level: Pressure,
+}
+
+impl PressureState {
+    pub const fn new() -> Self {
+        let none = PressureThreshold {
+            free: 0,
+            largest_hole: 0,
+        };
+        PressureState {
+            elevated: none,
+            critical: none,
+            level: Pressure::Normal,
+        }
+    }
+
+    pub fn level(&self) -> Pressure {
+        self.level
+    }
+
+    pub fn set_thresholds(&mut self, elevated: PressureThreshold, critical: PressureThreshold) {
+        self.elevated = elevated;
+        self.critical = critical;
+    }
+
+    /// Determines the level for the given amount of free memory and returns it if it
+    /// changed. `largest_hole` is only called if a threshold depends on it.
+    pub fn update(
+        &mut self,
+        free: usize,
+        largest_hole: impl FnOnce() -> usize,
+    ) -> Option<Pressure> {
+        let largest_hole = if self.elevated.largest_hole != 0 || self.critical.largest_hole != 0 {
+            largest_hole()
+        } else {
+            0
+        };
+        let level = if self.critical.is_undercut(free, largest_hole) {
+            Pressure::Critical
+        } else if self.elevated.is_undercut(free, largest_hole) {
+            Pressure::Elevated
+        } else {
+            Pressure::Normal
+        };
+        if level == self.level {
+            return None;
+        }
+        self.level = level;
+        Some(level)
+    }
+}
@@ -0,0 +1,36 @@
+/// The priority class of an allocation, see [`Heap::set_reserve`][crate::Heap::set_reserve].
+///
+/// Allocations through the regular methods of [`Heap`][crate::Heap] and through
+/// [`LockedHeap`][crate::LockedHeap] have [`Normal`][Priority::Normal] priority. Other
+/// priorities can be requested with
+/// [`Heap::allocate_with_priority`][crate::Heap::allocate_with_priority].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Priority {
+    /// For allocations that can be retried later or whose failure is harmless, e.g. caches.
+    Low,
+    /// The priority of regular allocations.
+    Normal,
+    /// For allocations that are required to make progress or to free memory again.
+    High,
+}
+
+/// The amount of free memory that each priority class must leave behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Reserves {
+    bytes: [usize; 3],
+}
+
+impl Reserves {
+    pub const fn new() -> Self {
+        Reserves { bytes: [0; 3] }
+    }
+
+    pub fn get(&self, priority: Priority) -> usize {
+        self.bytes[priority as usize]
+    }
+
+    pub fn set(&mut self, priority: Priority, bytes: usize) {
+        self.bytes[priority as usize] = bytes;
+    }
+}
@@ -0,0 +1,29 @@
+//! Support for allocations that the heap may free on its own when memory runs out.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Gives up [purgeable][crate::Heap::allocate_purgeable] allocations when memory runs out.
+///
+/// A purger is installed with [`Heap::set_purger`][crate::Heap::set_purger]. When an
+/// allocation would fail, the heap frees purgeable allocations in address order until the
+/// allocation succeeds, and calls [`purge`][Purger::purge] for each of them right before it
+/// is freed. This is useful for caches whose contents can be recreated.
+///
+/// The purger is called while the heap is borrowed mutably, i.e. while the lock of a
+/// [`LockedHeap`][crate::LockedHeap] is held. It must not allocate from or free to the same
+/// heap, as this would deadlock.
+pub trait Purger: Sync {
+    /// Called before the purgeable allocation of `layout` at `ptr` is freed. The owner of
+    /// the allocation must not use it afterwards.
+    ///
+    /// If a [`MemoryTagger`][crate::MemoryTagger] is installed, `ptr` doesn't carry the
+    /// memory tag of the allocation.
+    fn purge(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+impl<F: Fn(NonNull<u8>, Layout) + Sync> Purger for F {
+    fn purge(&self, ptr: NonNull<u8>, layout: Layout) {
+        self(ptr, layout)
+    }
+}
@@ -0,0 +1,128 @@
+//! Handing a live heap across an ABI boundary, see [`RawHeap`].
+
+use core::ptr::NonNull;
+
+use crate::hole::{HoleList, LinkKey};
+use crate::Heap;
+
+/// A plain description of a live [`Heap`], created by [`Heap::into_raw_parts`].
+///
+/// The list of free memory blocks lives in the heap memory itself, so the bounds of the
+/// heap and the location of the first free block are enough to reconstruct the heap with
+/// [`Heap::from_raw_parts`], e.g. after a bootloader handed its heap to the kernel. The
+/// layout of the struct is stable, so it can be passed between separately compiled
+/// binaries that use the same version of this crate.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawHeap {
+    /// The [bottom][Heap::bottom] of the heap.
+    pub bottom: *mut u8,
+    /// The [size][Heap::size] of the heap.
+    pub size: usize,
+    /// The bytes at the top of the heap that were too few to be used, see
+    /// [`Heap::top`].
+    pub pending_extend: usize,
+    /// The offset of the first free block from `bottom`, or [`NO_HOLE`][Self::NO_HOLE] if
+    /// the heap is full.
+    pub first_hole: usize,
+    /// The [used][Heap::used] bytes of the heap.
+    pub used: usize,
+    /// The secret of [`Heap::set_link_key`] that the links between the free blocks are
+    /// encoded with, or 0 without the `safe_linking` and `checksum` features.
+    pub link_key: usize,
+}
+
+impl RawHeap {
+    /// The value of [`first_hole`][Self::first_hole] if the heap has no free memory.
+    pub const NO_HOLE: usize = usize::MAX;
+}
+
+impl Heap {
+    /// Decomposes the heap into a [`RawHeap`] descriptor, which can be turned back into a
+    /// heap with [`from_raw_parts`][Heap::from_raw_parts].
+    ///
+    /// Only the list of free memory blocks is transported. The statistics, the hooks and
+    /// all other settings are lost, since they might refer to code and data that isn't
+    /// available on the other side. A [persistent][Heap::init_persistent] heap is
+    /// reconstructed as a regular heap. The same goes for the [boot region][Heap::init_boot],
+    /// so its allocations must not be freed on the reconstructed heap.
+    pub fn into_raw_parts(self) -> RawHeap {
+        RawHeap {
+            bottom: self.bottom(),
+            size: self.size(),
+            pending_extend: self.holes.pending_extend as usize,
+            first_hole: self.holes.first().map_or(RawHeap::NO_HOLE, |hole| {
+                hole.as_ptr() as usize - self.bottom() as usize
+            }),
+            used: self.used,
+            link_key: self.holes.key.secret(),
+        }
+    }
+
+    /// Reconstructs a heap from a descriptor that was created by
+    /// [`into_raw_parts`][Heap::into_raw_parts].
+    ///
+    /// # Safety
+    ///
+    /// The descriptor must be unchanged, and the heap memory must not have been touched
+    /// since it was created, except through the live allocations of the heap. The
+    /// requirements of [`Heap::init`] apply to the heap memory.
+    pub unsafe fn from_raw_parts(raw: RawHeap) -> Heap {
+        let first = match raw.first_hole {
+            RawHeap::NO_HOLE => None,
+            offset => Some(NonNull::new_unchecked(raw.bottom.add(offset).cast())),
+        };
+        let mut heap = Heap::empty();
+        heap.holes = HoleList::from_raw_parts(
+            raw.bottom,
+            raw.size,
+            raw.pending_extend,
+            first,
+            LinkKey::new(raw.link_key),
+        );
+        heap.used = raw.used;
+        heap
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RawHeap;
+    use crate::test::Chonk;
+    use crate::Heap;
+    use core::alloc::Layout;
+
+    #[test]
+    fn raw_parts_round_trip() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let mut heap = unsafe { Heap::new(data, 1000) };
+        #[cfg(any(feature = "safe_linking", feature = "checksum"))]
+        heap.set_link_key(0x5eed_1234);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = heap.allocate_first_fit(layout).unwrap();
+        let b = heap.allocate_first_fit(layout).unwrap();
+        unsafe { heap.deallocate(a, layout) };
+        let (top, stats) = (heap.top(), heap.stats());
+
+        let raw = heap.into_raw_parts();
+        assert_eq!(raw.first_hole, 0);
+        let mut heap = unsafe { Heap::from_raw_parts(raw) };
+        assert_eq!(heap.top(), top);
+        assert_eq!(heap.used(), stats.used);
+        assert_eq!(heap.stats().holes, stats.holes);
+        unsafe { heap.deallocate(b, layout) };
+        assert_eq!(heap.used(), 0);
+        assert_eq!(heap.holes.check_invariants(), heap.size());
+
+        // a full heap has no free block
+        let (ptr, size) = heap.allocate_largest(8).unwrap();
+        let all = Layout::from_size_align(size, 8).unwrap();
+        let raw = heap.into_raw_parts();
+        assert_eq!(raw.first_hole, RawHeap::NO_HOLE);
+        let mut heap = unsafe { Heap::from_raw_parts(raw) };
+        unsafe { heap.deallocate(ptr, all) };
+        assert_eq!(heap.used(), 0);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+}
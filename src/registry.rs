@@ -0,0 +1,411 @@
+//! An allocator over several separate heaps, see [`HeapRegistry`].
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+use crate::fallback::Owns;
+use crate::hole::{Hole, HoleList};
+use crate::{align_down_size, align_up, AllocError, Heap, LockedHeap};
+use core::mem::align_of;
+
+/// An allocator that owns `N` heaps with separate memory, e.g. one per memory bank.
+///
+/// Allocations try the heaps in order and use the first one that can serve them. A
+/// selector can be given to pick the heap to start with for every layout, e.g. to keep
+/// large buffers out of a small, fast memory; the other heaps are still tried after it.
+/// Deallocations are routed to the heap whose memory contains the pointer, or whose
+/// [large allocator][crate::Heap::set_large_allocator] owns it, so a heap may be extended
+/// or directly follow another heap without confusing the routing.
+///
+/// ```ignore
+/// use linked_list_allocator::{HeapRegistry, LockedHeap};
+///
+/// #[global_allocator]
+/// static ALLOCATOR: HeapRegistry<2> =
+///     HeapRegistry::new([LockedHeap::empty(), LockedHeap::empty()]);
+///
+/// unsafe {
+///     ALLOCATOR.heaps()[0].init(sram_bottom, sram_size);
+///     ALLOCATOR.heaps()[1].init(sdram_bottom, sdram_size);
+/// }
+/// ```
+pub struct HeapRegistry<const N: usize> {
+    heaps: [LockedHeap; N],
+    select: Option<fn(Layout) -> usize>,
+}
+
+impl<const N: usize> HeapRegistry<N> {
+    /// Creates a registry of the given heaps, which are tried in order for every allocation.
+    pub const fn new(heaps: [LockedHeap; N]) -> Self {
+        HeapRegistry {
+            heaps,
+            select: None,
+        }
+    }
+
+    /// Creates a registry of the given heaps, which are tried starting at the index that
+    /// `select` returns for the layout of the allocation, modulo `N`.
+    pub const fn with_selector(heaps: [LockedHeap; N], select: fn(Layout) -> usize) -> Self {
+        HeapRegistry {
+            heaps,
+            select: Some(select),
+        }
+    }
+
+    /// Returns the heaps of the registry, e.g. to initialize them or to inspect their
+    /// statistics.
+    pub fn heaps(&self) -> &[LockedHeap; N] {
+        &self.heaps
+    }
+
+    /// Initializes the heaps from the usable regions of a memory map, e.g. one that was
+    /// passed by a bootloader, UEFI, or multiboot, and returns the number of bytes given to
+    /// the heaps.
+    ///
+    /// Every region is given as its start address and size. A region that directly follows
+    /// the memory of an initialized heap [extends][Heap::extend] that heap, as memory maps
+    /// often split contiguous memory into several entries. Every other region initializes
+    /// the next uninitialized heap, aligned like in [`Heap::init`]. Regions that are too
+    /// small for a heap after aligning them and regions for which no uninitialized heap is
+    /// left are skipped. Heaps that are already initialized keep their memory.
+    ///
+    /// ```ignore
+    /// let usable = memory_regions
+    ///     .iter()
+    ///     .filter(|region| region.kind == MemoryRegionKind::Usable)
+    ///     .map(|region| {
+    ///         let start = (physical_memory_offset + region.start) as *mut u8;
+    ///         (start, (region.end - region.start) as usize)
+    ///     });
+    /// unsafe { ALLOCATOR.init_from_regions(usable) };
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// The regions must not overlap each other or the memory of the heaps, and must satisfy
+    /// the requirements of [`Heap::init`].
+    pub unsafe fn init_from_regions<I>(&self, regions: I) -> usize
+    where
+        I: IntoIterator<Item = (*mut u8, usize)>,
+    {
+        let mut added = 0;
+        for (start, size) in regions {
+            let adjacent = self.heaps.iter().find(|heap| {
+                let heap = heap.lock();
+                let end = heap.top().wrapping_add(heap.holes.pending_extend as usize);
+                !heap.bottom().is_null() && end == start
+            });
+            if let Some(heap) = adjacent {
+                heap.lock().extend(size);
+                added += size;
+                continue;
+            }
+
+            let padding = (start as usize).wrapping_neg() % align_of::<Hole>();
+            let usable = align_down_size(size.saturating_sub(padding), align_of::<Hole>());
+            if usable < HoleList::min_size() {
+                continue;
+            }
+            let empty = self
+                .heaps
+                .iter()
+                .find(|heap| heap.lock().bottom().is_null());
+            if let Some(heap) = empty {
+                heap.lock().init(start, size);
+                added += size;
+            }
+        }
+        added
+    }
+
+    /// Removes the memory region that starts at `base` from the heaps, e.g. so that its
+    /// memory bank can be powered down or unplugged, and returns the number of bytes from
+    /// `base` to the end of the removed memory. This is the reverse of
+    /// [`init_from_regions`][HeapRegistry::init_from_regions].
+    ///
+    /// If `base` is the start of the memory of a heap, the whole heap is removed and left
+    /// uninitialized, so that it can be initialized with other memory later. It keeps its
+    /// [large allocator][Heap::set_large_allocator] though, so that its large allocations
+    /// can still be freed. Otherwise, `base` must lie within the memory of a heap, usually
+    /// at the start of a region that [extended][Heap::extend] it, and the memory from `base`
+    /// to the end of the heap is [split off][Heap::split_off] and dropped.
+    ///
+    /// Returns `None` and leaves the heaps unchanged if no heap contains `base`, if the
+    /// removed memory still contains live allocations, or if the rest of the heap would be
+    /// too small. The allocations have to be freed before the region can be removed.
+    pub fn remove_region(&self, base: *mut u8) -> Option<usize> {
+        let mut heap = self.heaps.iter().map(|heap| heap.lock()).find(|heap| {
+            let end = heap.top().wrapping_add(heap.holes.pending_extend as usize);
+            !heap.bottom().is_null()
+                && base < end
+                && align_up(base, align_of::<Hole>()) >= heap.bottom()
+        })?;
+        let end = heap.top().wrapping_add(heap.holes.pending_extend as usize);
+        let removed = end as usize - base as usize;
+
+        if align_up(base, align_of::<Hole>()) == heap.bottom() {
+            if heap.used() != 0 {
+                return None;
+            }
+            *heap = Heap {
+                #[cfg(feature = "large_alloc")]
+                large: heap.large,
+                ..Heap::empty()
+            };
+            return Some(removed);
+        }
+
+        // the split only succeeds if `base` doesn't lie within an allocation, but the memory
+        // above it must be free entirely
+        let free: usize = heap
+            .holes
+            .holes()
+            .map(|(addr, size)| {
+                let hole_end = addr.wrapping_add(size).min(heap.top());
+                (hole_end as usize).saturating_sub(addr.max(base) as usize)
+            })
+            .sum();
+        if free != (heap.top() as usize).saturating_sub(base as usize) {
+            return None;
+        }
+        let at = base as usize - heap.bottom() as usize;
+        heap.split_off(at).map(|_| removed)
+    }
+
+    /// Returns the index of the heap that is tried first for `layout`.
+    fn first(&self, layout: Layout) -> usize {
+        self.select.map_or(0, |select| select(layout)) % N
+    }
+
+    /// Allocates from the heaps in order, starting at the selected one.
+    fn allocate_with(
+        &self,
+        layout: Layout,
+        allocate: impl Fn(&mut Heap) -> Result<NonNull<u8>, AllocError>,
+    ) -> *mut u8 {
+        if N == 0 {
+            return core::ptr::null_mut();
+        }
+        let first = self.first(layout);
+        (0..N)
+            .find_map(|i| self.heaps[(first + i) % N].allocate_with(&allocate).ok())
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+}
+
+unsafe impl<const N: usize> Owns for HeapRegistry<N> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.heaps.iter().any(|heap| heap.owns(ptr))
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for HeapRegistry<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocate_with(layout, |heap| heap.allocate_first_fit(layout))
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.allocate_with(layout, |heap| heap.allocate_zeroed(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let ptr = NonNull::new_unchecked(ptr);
+        if layout.size() == 0 {
+            // zero-sized allocations don't point into any heap, but are counted by the
+            // heap that was tried first
+            self.heaps[self.first(layout)].deallocate(ptr, layout);
+            return;
+        }
+        for heap in &self.heaps {
+            let locked = heap.lock();
+            if locked.owns(ptr) {
+                heap.deallocate_locked(locked, ptr, layout);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HeapRegistry;
+    use crate::test::Chonk;
+    use crate::LockedHeap;
+    use core::alloc::{GlobalAlloc, Layout};
+    use std::vec::Vec;
+
+    #[test]
+    fn routes_by_address() {
+        // both heaps live in one chunk of memory, so they are directly adjacent
+        let (chonk, data) = Chonk::<1024>::new();
+        let registry = unsafe {
+            HeapRegistry::new([
+                LockedHeap::new(data, 512),
+                LockedHeap::new(data.add(512), 512),
+            ])
+        };
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let upper = unsafe { data.add(512) };
+
+        // allocations go to the first heap until it is full
+        let mut ptrs = Vec::new();
+        let last = loop {
+            let ptr = unsafe { registry.alloc(layout) };
+            assert!(!ptr.is_null());
+            if ptr >= upper {
+                break ptr;
+            }
+            ptrs.push(ptr);
+        };
+        assert!(ptrs.len() > 2);
+
+        unsafe { registry.dealloc(last, layout) };
+        assert_eq!(registry.heaps()[1].stats().used, 0);
+        assert_ne!(registry.heaps()[0].stats().used, 0);
+        for ptr in ptrs {
+            unsafe { registry.dealloc(ptr, layout) };
+        }
+        assert_eq!(registry.heaps()[0].stats().used, 0);
+
+        let unit = Layout::new::<()>();
+        let ptr = unsafe { registry.alloc(unit) };
+        unsafe { registry.dealloc(ptr, unit) };
+        let stats = registry.heaps()[0].stats();
+        assert_eq!(stats.allocations, stats.deallocations);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    fn selector() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let registry = unsafe {
+            HeapRegistry::with_selector(
+                [
+                    LockedHeap::new(data, 512),
+                    LockedHeap::new(data.add(512), 512),
+                ],
+                |layout| (layout.size() >= 128) as usize,
+            )
+        };
+        let small = Layout::from_size_align(16, 8).unwrap();
+        let large = Layout::from_size_align(300, 8).unwrap();
+
+        let a = unsafe { registry.alloc(small) };
+        let b = unsafe { registry.alloc(large) };
+        // the selected heap is full, so the next one is used
+        let c = unsafe { registry.alloc(large) };
+        assert!(a < unsafe { data.add(512) });
+        assert!(b >= unsafe { data.add(512) });
+        assert!(c < unsafe { data.add(512) });
+
+        unsafe {
+            registry.dealloc(a, small);
+            registry.dealloc(b, large);
+            registry.dealloc(c, large);
+        }
+        for heap in registry.heaps() {
+            assert_eq!(heap.stats().used, 0);
+        }
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    fn init_from_regions() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let registry = HeapRegistry::new([LockedHeap::empty(), LockedHeap::empty()]);
+        let regions = unsafe {
+            [
+                // too small
+                (data.add(16), 8),
+                (data.add(33), 255),
+                // follows the previous region
+                (data.add(288), 200),
+                (data.add(600), 100),
+                // no heap left
+                (data.add(800), 100),
+            ]
+        };
+        let added = unsafe { registry.init_from_regions(regions) };
+        assert_eq!(added, 555);
+
+        let [first, second] = registry.heaps();
+        let first = first.lock();
+        assert_eq!(first.bottom(), unsafe { data.add(40) });
+        assert_eq!(first.top(), unsafe { data.add(488) });
+        drop(first);
+        assert_eq!(second.lock().bottom(), unsafe { data.add(600) });
+
+        // the extended heap serves allocations larger than its first region
+        let layout = Layout::from_size_align(300, 8).unwrap();
+        let ptr = unsafe { registry.alloc(layout) };
+        assert!(!ptr.is_null() && ptr < unsafe { data.add(488) });
+        unsafe { registry.dealloc(ptr, layout) };
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    fn remove_region() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let registry = unsafe {
+            HeapRegistry::new([
+                LockedHeap::new(data, 512),
+                LockedHeap::new(data.add(512), 512),
+            ])
+        };
+        let upper = unsafe { data.add(512) };
+        let layout = Layout::from_size_align(300, 8).unwrap();
+        let a = unsafe { registry.alloc(layout) };
+        let b = unsafe { registry.alloc(layout) };
+        assert!(b >= upper);
+
+        // the region still has a live allocation
+        assert_eq!(registry.remove_region(upper), None);
+        assert_eq!(registry.heaps()[1].lock().bottom(), upper);
+        unsafe { registry.dealloc(b, layout) };
+        assert_eq!(registry.remove_region(upper), Some(512));
+        assert!(registry.heaps()[1].lock().bottom().is_null());
+
+        // only the remaining heap serves allocations
+        assert!(unsafe { registry.alloc(layout) }.is_null());
+        unsafe { registry.dealloc(a, layout) };
+        let c = unsafe { registry.alloc(layout) };
+        assert!(!c.is_null() && c < upper);
+        unsafe { registry.dealloc(c, layout) };
+
+        // the removed heap can be initialized again
+        unsafe { registry.heaps()[1].init(upper, 512) };
+        assert_eq!(registry.heaps()[1].lock().size(), 512);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+
+    #[test]
+    fn remove_extending_region() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let registry = HeapRegistry::new([LockedHeap::empty()]);
+        let second = unsafe { data.add(512) };
+        let regions = [(data, 512), (second, 512)];
+        unsafe { registry.init_from_regions(regions) };
+        let layout = Layout::from_size_align(600, 8).unwrap();
+        let a = unsafe { registry.alloc(layout) };
+        assert!(!a.is_null());
+
+        // the allocation extends into the second region
+        assert_eq!(registry.remove_region(second), None);
+        assert_eq!(registry.heaps()[0].lock().top(), unsafe { data.add(1024) });
+        unsafe { registry.dealloc(a, layout) };
+
+        let small = Layout::from_size_align(64, 8).unwrap();
+        let b = unsafe { registry.alloc(small) };
+        assert_eq!(registry.remove_region(second), Some(512));
+        assert_eq!(registry.heaps()[0].lock().top(), second);
+        assert!(unsafe { registry.alloc(layout) }.is_null());
+        unsafe { registry.dealloc(b, small) };
+        assert_eq!(registry.heaps()[0].stats().used, 0);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+}
@@ -0,0 +1,79 @@
+//! Annotations for AddressSanitizer and Valgrind.
+//!
+//! Both tools only know about the memory region that backs the heap, so by default they
+//! can't detect accesses to freed memory or out of bounds of an allocation. With the `asan`
+//! or `valgrind` feature, the heap reports which parts of the region are free: free memory
+//! is marked as inaccessible, except for the headers of the holes, which the heap itself
+//! reads and writes. Allocations are marked as accessible again.
+//!
+//! All functions are no-ops if neither feature is enabled, and under Miri, which can't call
+//! into either tool.
+
+#![allow(unused_variables)]
+
+/// Marks the `len` bytes at `ptr` as inaccessible.
+#[inline]
+pub(crate) unsafe fn poison(ptr: *mut u8, len: usize) {
+    #[cfg(all(feature = "asan", not(miri)))]
+    asan::__asan_poison_memory_region(ptr, len);
+    #[cfg(all(feature = "valgrind", target_arch = "x86_64", not(miri)))]
+    valgrind::request(valgrind::MAKE_MEM_NOACCESS, ptr, len);
+}
+
+/// Marks the `len` bytes at `ptr` as accessible, but uninitialized.
+#[inline]
+pub(crate) unsafe fn unpoison(ptr: *mut u8, len: usize) {
+    #[cfg(all(feature = "asan", not(miri)))]
+    asan::__asan_unpoison_memory_region(ptr, len);
+    #[cfg(all(feature = "valgrind", target_arch = "x86_64", not(miri)))]
+    valgrind::request(valgrind::MAKE_MEM_UNDEFINED, ptr, len);
+}
+
+/// Marks the `len` bytes at `ptr` as accessible and initialized, e.g. because they are
+/// known to be zero.
+#[inline]
+pub(crate) unsafe fn mark_initialized(ptr: *mut u8, len: usize) {
+    #[cfg(all(feature = "asan", not(miri)))]
+    asan::__asan_unpoison_memory_region(ptr, len);
+    #[cfg(all(feature = "valgrind", target_arch = "x86_64", not(miri)))]
+    valgrind::request(valgrind::MAKE_MEM_DEFINED, ptr, len);
+}
+
+/// The manual poisoning interface of the AddressSanitizer runtime. The crate must be built
+/// with `-Zsanitizer=address` to link against it.
+#[cfg(all(feature = "asan", not(miri)))]
+mod asan {
+    extern "C" {
+        pub fn __asan_poison_memory_region(addr: *const u8, size: usize);
+        pub fn __asan_unpoison_memory_region(addr: *const u8, size: usize);
+    }
+}
+
+/// The client requests of Valgrind's Memcheck tool. They are no-ops when the program is
+/// not run under Valgrind.
+#[cfg(all(feature = "valgrind", target_arch = "x86_64", not(miri)))]
+mod valgrind {
+    use core::arch::asm;
+
+    const MEMCHECK_BASE: usize = ((b'M' as usize) << 24) | ((b'C' as usize) << 16);
+    pub const MAKE_MEM_NOACCESS: usize = MEMCHECK_BASE;
+    pub const MAKE_MEM_UNDEFINED: usize = MEMCHECK_BASE + 1;
+    pub const MAKE_MEM_DEFINED: usize = MEMCHECK_BASE + 2;
+
+    /// Issues a client request for the `len` bytes at `ptr`.
+    pub unsafe fn request(request: usize, ptr: *mut u8, len: usize) {
+        let args: [usize; 6] = [request, ptr as usize, len, 0, 0, 0];
+        // the special instruction sequence that Valgrind recognizes, see `valgrind.h`
+        asm!(
+            "rol rdi, 3",
+            "rol rdi, 13",
+            "rol rdi, 61",
+            "rol rdi, 51",
+            "xchg rbx, rbx",
+            in("rax") args.as_ptr(),
+            inout("rdx") 0usize => _,
+            inout("rdi") 0usize => _,
+            options(nostack)
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Allocations that are split across several free blocks, see [`Heap::allocate_scatter`].
+
+use core::alloc::Layout;
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+
+use crate::{AllocError, Heap};
+
+impl Heap {
+    /// Allocates at least `total` bytes in chunks that may be spread over several free
+    /// blocks, e.g. for a DMA engine that takes scatter-gather lists.
+    ///
+    /// Every chunk starts at an address that is aligned to `chunk_align`. The chunks are
+    /// written to the start of `out` as pointer and size pairs, and their number is returned.
+    /// The largest free block is used for each chunk, so a single chunk is used if one free
+    /// block is large enough. The last chunk can be slightly larger than the remaining bytes
+    /// if the rest of its free block would be too small to stay a free block. Each chunk
+    /// must be freed with a layout of its size and `chunk_align`.
+    ///
+    /// If the free memory doesn't suffice or `out` has too few entries, all chunks are freed
+    /// again and the error is returned, which is [`AllocError::Fragmented`] in the latter
+    /// case.
+    pub fn allocate_scatter(
+        &mut self,
+        total: usize,
+        chunk_align: usize,
+        out: &mut [MaybeUninit<(NonNull<u8>, usize)>],
+    ) -> Result<usize, AllocError> {
+        if !chunk_align.is_power_of_two() {
+            return Err(AllocError::InvalidLayout);
+        }
+        let mut count = 0;
+        let mut remaining = total;
+        while remaining > 0 {
+            let result = match out.get_mut(count) {
+                Some(slot) => self
+                    .allocate_chunk(remaining, chunk_align)
+                    .map(|chunk| slot.write(chunk).1)
+                    .map_err(Some),
+                None => Err(None),
+            };
+            match result {
+                Ok(size) => {
+                    remaining = remaining.saturating_sub(size);
+                    count += 1;
+                }
+                Err(err) => {
+                    for slot in &out[..count] {
+                        // SAFETY: The first `count` entries hold the chunks allocated above.
+                        unsafe { self.free_chunk(slot.assume_init(), chunk_align) };
+                    }
+                    return Err(err.unwrap_or_else(|| AllocError::Fragmented {
+                        largest_hole: self.stats().largest_hole,
+                    }));
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Allocates the next chunk of a scatter allocation with at most `remaining` bytes,
+    /// unless the largest free block only fits a slightly larger chunk.
+    fn allocate_chunk(
+        &mut self,
+        remaining: usize,
+        align: usize,
+    ) -> Result<(NonNull<u8>, usize), AllocError> {
+        let largest = self
+            .largest_allocation(align)
+            .ok_or(AllocError::OutOfMemory)?;
+        let size = remaining.min(largest);
+        if largest - size < Heap::min_allocation_size() {
+            // the rest of the block can't stay free, so the chunk covers it
+            return self.allocate_largest(align);
+        }
+        let layout = Layout::from_size_align(size, align).map_err(|_| AllocError::InvalidLayout)?;
+        match self.allocate_first_fit(layout) {
+            Ok(ptr) => Ok((ptr, size)),
+            // the rest of the block is too small to stay free, so it is taken as a whole
+            Err(_) if size < largest => self.allocate_largest(align),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Frees a chunk that was allocated by [`allocate_chunk`][Heap::allocate_chunk].
+    unsafe fn free_chunk(&mut self, (ptr, size): (NonNull<u8>, usize), align: usize) {
+        self.deallocate(ptr, Layout::from_size_align_unchecked(size, align));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::new_heap;
+    use crate::AllocError;
+    use core::alloc::Layout;
+    use core::mem::MaybeUninit;
+    use std::vec::Vec;
+
+    #[test]
+    fn scatter_over_fragmented_heap() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(150, 8).unwrap();
+        let mut blocks = Vec::new();
+        while let Ok(ptr) = heap.allocate_first_fit(layout) {
+            blocks.push(ptr);
+        }
+        // free every other block, so that no free block holds more than one of them
+        for &ptr in blocks.iter().step_by(2) {
+            unsafe { heap.deallocate(ptr, layout) };
+        }
+        let used = heap.used();
+        assert!(heap
+            .allocate_first_fit(Layout::from_size_align(300, 8).unwrap())
+            .is_err());
+
+        let mut out = [MaybeUninit::uninit(); 8];
+        let count = heap.allocate_scatter(300, 8, &mut out).unwrap();
+        assert!(count >= 2);
+        let mut chunks: Vec<_> = out[..count]
+            .iter()
+            .map(|chunk| unsafe { chunk.assume_init() })
+            .collect();
+        assert!(chunks.iter().map(|&(_, size)| size).sum::<usize>() >= 300);
+        chunks.sort();
+        for pair in chunks.windows(2) {
+            let (ptr, size) = pair[0];
+            assert!(ptr.as_ptr() as usize + size <= pair[1].0.as_ptr() as usize);
+        }
+        for &(ptr, size) in &chunks {
+            assert_eq!(ptr.as_ptr() as usize % 8, 0);
+            unsafe { heap.deallocate(ptr, Layout::from_size_align(size, 8).unwrap()) };
+        }
+        assert_eq!(heap.used(), used);
+
+        // failed scatter allocations leave the heap unchanged
+        assert!(matches!(
+            heap.allocate_scatter(300, 8, &mut out[..1]),
+            Err(AllocError::Fragmented { .. })
+        ));
+        let size = heap.size();
+        assert_eq!(
+            heap.allocate_scatter(size, 8, &mut out),
+            Err(AllocError::OutOfMemory)
+        );
+        assert_eq!(heap.used(), used);
+        assert_eq!(heap.allocate_scatter(0, 8, &mut out), Ok(0));
+    }
+}
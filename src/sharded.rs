@@ -0,0 +1,190 @@
+//! A heap that is split into independently locked shards, see [`ShardedHeap`].
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::fallback::Owns;
+use crate::{align_down_size, AllocError, Heap, LockedHeap};
+
+/// A heap whose memory is split into `N` shards with a lock each, for systems with several
+/// cores.
+///
+/// Every core allocates from the shard that is selected by its CPU ID, so cores only
+/// contend for a lock when they allocate from the same shard. If the shard of the current
+/// core can't serve an allocation, the other shards are tried in order. Frees are routed to
+/// the shard that owns the pointer, regardless of the core that frees it.
+///
+/// ```ignore
+/// use linked_list_allocator::ShardedHeap;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: ShardedHeap<8> = ShardedHeap::empty(current_cpu_id);
+/// ```
+pub struct ShardedHeap<const N: usize> {
+    shards: [LockedHeap; N],
+    cpu_id: fn() -> usize,
+    /// The start of the first shard, as passed to [`init`][ShardedHeap::init].
+    bottom: AtomicUsize,
+    /// The size of every shard, or zero if the heap isn't initialized.
+    shard_size: AtomicUsize,
+}
+
+impl<const N: usize> ShardedHeap<N> {
+    /// Creates a sharded heap without memory. `cpu_id` returns the ID of the current core,
+    /// which selects the shard to allocate from modulo `N`.
+    pub const fn empty(cpu_id: fn() -> usize) -> Self {
+        // only used to initialize the array, which creates a separate lock for each shard
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: LockedHeap = LockedHeap::empty();
+        ShardedHeap {
+            shards: [EMPTY; N],
+            cpu_id,
+            bottom: AtomicUsize::new(0),
+            shard_size: AtomicUsize::new(0),
+        }
+    }
+
+    /// Creates a sharded heap that splits the given memory into `N` shards of the same size.
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`init`][ShardedHeap::init] apply.
+    pub unsafe fn new(cpu_id: fn() -> usize, heap_bottom: *mut u8, heap_size: usize) -> Self {
+        let heap = Self::empty(cpu_id);
+        heap.init(heap_bottom, heap_size);
+        heap
+    }
+
+    /// Splits the given memory into `N` shards of the same size and initializes them.
+    ///
+    /// Each shard must be large enough for the metadata of a heap, otherwise this function
+    /// panics.
+    ///
+    /// # Safety
+    ///
+    /// This function must be called at most once and before any allocation. The requirements
+    /// of [`Heap::init`] apply to the whole memory range.
+    pub unsafe fn init(&self, heap_bottom: *mut u8, heap_size: usize) {
+        assert!(N > 0, "a sharded heap needs at least one shard");
+        let shard_size = align_down_size(heap_size / N, Heap::allocation_granularity());
+        for (i, shard) in self.shards.iter().enumerate() {
+            shard.init(heap_bottom.add(i * shard_size), shard_size);
+        }
+        self.bottom.store(heap_bottom as usize, Ordering::Relaxed);
+        self.shard_size.store(shard_size, Ordering::Release);
+    }
+
+    /// Returns the shards of the heap, e.g. to inspect their statistics or to extend them
+    /// through [`LockedHeap::with_heap`].
+    pub fn shards(&self) -> &[LockedHeap; N] {
+        &self.shards
+    }
+
+    /// Returns the index of the shard of the current core.
+    fn home(&self) -> usize {
+        (self.cpu_id)() % N
+    }
+
+    /// Returns the shards that might own `ptr`, starting with the one whose initial range
+    /// contains it.
+    fn candidates(&self, ptr: NonNull<u8>) -> impl Iterator<Item = &LockedHeap> {
+        let shard_size = self.shard_size.load(Ordering::Acquire);
+        let offset = (ptr.as_ptr() as usize).wrapping_sub(self.bottom.load(Ordering::Relaxed));
+        let guess = match offset.checked_div(shard_size) {
+            Some(index) if index < N => Some(&self.shards[index]),
+            _ => None,
+        };
+        // shards that were extended beyond their initial range are found by searching
+        guess.into_iter().chain(&self.shards)
+    }
+
+    /// Allocates from the shard of the current core, or from the other shards in order if
+    /// that fails.
+    fn allocate_with(
+        &self,
+        allocate: impl Fn(&mut Heap) -> Result<NonNull<u8>, AllocError>,
+    ) -> *mut u8 {
+        let home = self.home();
+        (0..N)
+            .find_map(|i| self.shards[(home + i) % N].allocate_with(&allocate).ok())
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+}
+
+unsafe impl<const N: usize> Owns for ShardedHeap<N> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.candidates(ptr).any(|shard| shard.lock().owns(ptr))
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for ShardedHeap<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.allocate_with(|heap| heap.allocate_first_fit(layout))
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.allocate_with(|heap| heap.allocate_zeroed(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let ptr = NonNull::new_unchecked(ptr);
+        for shard in self.candidates(ptr) {
+            let heap = shard.lock();
+            if heap.owns(ptr) {
+                shard.deallocate_locked(heap, ptr, layout);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ShardedHeap;
+    use crate::test::Chonk;
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::vec::Vec;
+
+    static CPU: AtomicUsize = AtomicUsize::new(0);
+
+    fn cpu_id() -> usize {
+        CPU.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn allocates_from_home_shard_and_falls_back() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let heap = unsafe { ShardedHeap::<2>::new(cpu_id, data, 1024) };
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        CPU.store(1, Ordering::Relaxed);
+        let mut ptrs = Vec::new();
+        loop {
+            let ptr = unsafe { heap.alloc(layout) };
+            if heap.shards()[0].lock().used() != 0 {
+                // the shard of CPU 1 is full, so the allocation fell back to shard 0
+                ptrs.push(ptr);
+                break;
+            }
+            assert!(!ptr.is_null());
+            ptrs.push(ptr);
+        }
+        assert!(ptrs.len() > 2);
+
+        // frees are routed by address, not by the CPU that frees
+        CPU.store(0, Ordering::Relaxed);
+        for ptr in ptrs {
+            unsafe { heap.dealloc(ptr, layout) };
+        }
+        for shard in heap.shards() {
+            assert_eq!(shard.lock().used(), 0);
+        }
+
+        let ptr = unsafe { heap.alloc(layout) };
+        assert_ne!(heap.shards()[0].lock().used(), 0);
+        unsafe { heap.dealloc(ptr, layout) };
+        unsafe { Chonk::unleak(chonk) };
+    }
+}
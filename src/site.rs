@@ -0,0 +1,429 @@
+//! Call sites of allocations, recorded with the `call_sites` feature, and the statistics
+//! per call site.
+
+use core::alloc::Layout;
+use core::cmp::Reverse;
+use core::fmt;
+use core::panic::Location;
+use core::ptr::NonNull;
+
+use crate::header::{self, Header};
+use crate::{Allocations, Heap};
+
+impl Heap {
+    /// Returns the call site of the allocation at `ptr`, i.e. the location of the code that
+    /// called [`allocate_first_fit`][Self::allocate_first_fit] or one of its variants.
+    ///
+    /// The methods of the heap are `#[track_caller]`, so the call site is the first caller
+    /// outside of the heap. Allocations through [`GlobalAlloc`][core::alloc::GlobalAlloc]
+    /// are made by the standard library, so they have no useful call site.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a live allocation of this heap. Zero-sized allocations have no header,
+    /// so their pointers must not be passed.
+    pub unsafe fn allocation_site(&self, ptr: NonNull<u8>) -> Option<&'static Location<'static>> {
+        Header::of_block(Header::block_of(self.strip_tag(ptr))).site
+    }
+
+    /// Returns a report of all live allocations and their call sites, which can be printed
+    /// at the end of a test or before a shutdown to find leaks.
+    ///
+    /// The report is formatted lazily and doesn't allocate. It contains one line per
+    /// allocation, e.g. `leaked 48 bytes at 0x20001040, allocated at src/uart.rs:212:17`.
+    pub fn leak_report(&self) -> LeakReport<'_> {
+        LeakReport { heap: self }
+    }
+
+    /// Installs a table that aggregates the allocations of every call site, similar to
+    /// DHAT, to find out which code uses the memory of the heap. Returns the previously
+    /// installed table, and passing `None` stops the aggregation.
+    ///
+    /// Every call site takes one entry of the table. Once all but the last entry are taken,
+    /// the last entry aggregates the allocations of all other call sites. Only allocations
+    /// that are made while the table is installed are counted, but allocations that were made
+    /// before are subtracted when they are freed, so the table should be installed right after
+    /// the heap was initialized. The lookup of the call site makes every allocation and
+    /// deallocation slower by a search of the table.
+    ///
+    /// ```ignore
+    /// static mut SITES: [SiteStats; 32] = [SiteStats::new(); 32];
+    ///
+    /// heap.set_site_stats(Some(unsafe { &mut *core::ptr::addr_of_mut!(SITES) }));
+    /// ```
+    pub fn set_site_stats(
+        &mut self,
+        table: Option<&'static mut [SiteStats]>,
+    ) -> Option<&'static mut [SiteStats]> {
+        let table = table.map(|table| {
+            table.fill(SiteStats::new());
+            NonNull::from(table)
+        });
+        // SAFETY: The table was passed in as a `&'static mut`, which is given back.
+        core::mem::replace(&mut self.site_stats, table).map(|mut table| unsafe { table.as_mut() })
+    }
+
+    /// Returns the installed call site table.
+    fn site_table(&mut self) -> Option<&mut [SiteStats]> {
+        // SAFETY: The heap holds the only reference to the table while it is installed.
+        self.site_stats.map(|mut table| unsafe { table.as_mut() })
+    }
+
+    /// Returns the used entries of the [call site table][Self::set_site_stats], with the
+    /// call sites that have the most live bytes first.
+    ///
+    /// The entry for all other call sites, whose `site` is `None`, comes last. Every entry
+    /// can be printed as one line of a report.
+    pub fn site_stats(&mut self) -> impl Iterator<Item = &SiteStats> + '_ {
+        let (others, sites) = match self.site_table().and_then(<[_]>::split_last_mut) {
+            Some((others, sites)) => {
+                let used = sites
+                    .iter()
+                    .take_while(|entry| entry.site.is_some())
+                    .count();
+                let sites = &mut sites[..used];
+                sites.sort_unstable_by_key(|entry| Reverse(entry.live_bytes));
+                (
+                    Some(&*others).filter(|others| others.allocations != 0),
+                    &*sites,
+                )
+            }
+            None => (None, &[][..]),
+        };
+        sites.iter().chain(others)
+    }
+
+    /// Records `site` in the header of the allocation at `ptr` with the given layout and
+    /// counts the allocation in the call site table.
+    pub(crate) fn record_site(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        site: &'static Location<'static>,
+    ) {
+        if !self.has_own_header(ptr, layout) {
+            return;
+        }
+        let ptr = self.strip_tag(ptr);
+        if let Some(table) = self.site_table() {
+            // SAFETY: The allocation was just made and its header belongs to the heap.
+            let size = unsafe { header::layout(ptr) }.size();
+            if let Some(entry) = SiteStats::find_or_insert(table, site) {
+                entry.record(size);
+            }
+        }
+        // SAFETY: See above.
+        unsafe { header::set_site(ptr, site) };
+    }
+
+    /// Updates the call site table for the deallocation of the block at `block`.
+    ///
+    /// # Safety
+    ///
+    /// `block` must be the start of a live block.
+    pub(crate) unsafe fn record_site_release(&mut self, block: NonNull<u8>) {
+        let header = Header::of_block(block.as_ptr());
+        if let (Some(table), Some(site)) = (self.site_table(), header.site) {
+            if let Some(entry) = SiteStats::find(table, site) {
+                entry.release(header.layout.size());
+            }
+        }
+    }
+}
+
+/// The aggregated allocations of one call site, see [`Heap::set_site_stats`].
+///
+/// The `Display` implementation prints the entry on one line, e.g. for a report of the
+/// heap usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SiteStats {
+    /// The call site, or `None` for the entry that aggregates all call sites that didn't
+    /// fit into the table.
+    pub site: Option<&'static Location<'static>>,
+    /// The number of bytes that are currently allocated, as requested by the allocations.
+    pub live_bytes: usize,
+    /// The number of live allocations.
+    pub live: usize,
+    /// The highest value of `live_bytes`.
+    pub peak_bytes: usize,
+    /// The number of bytes of all allocations since the table was installed.
+    pub total_bytes: usize,
+    /// The number of allocations since the table was installed.
+    pub allocations: usize,
+}
+
+impl SiteStats {
+    /// Returns an unused entry, to initialize a table.
+    pub const fn new() -> Self {
+        SiteStats {
+            site: None,
+            live_bytes: 0,
+            live: 0,
+            peak_bytes: 0,
+            total_bytes: 0,
+            allocations: 0,
+        }
+    }
+
+    /// Returns the entry of `site`, which is the last entry if the table is full.
+    fn find_or_insert<'a>(
+        table: &'a mut [SiteStats],
+        site: &'static Location<'static>,
+    ) -> Option<&'a mut SiteStats> {
+        let (others, sites) = table.split_last_mut()?;
+        // the used entries come first, since entries are never removed
+        let index = sites
+            .iter()
+            .position(|entry| entry.site.map_or(true, |other| same_site(other, site)));
+        Some(match index {
+            Some(index) => {
+                sites[index].site = Some(site);
+                &mut sites[index]
+            }
+            None => others,
+        })
+    }
+
+    /// Returns the entry that contains the allocations of `site`, like
+    /// [`find_or_insert`][Self::find_or_insert], but without taking a new entry.
+    fn find<'a>(
+        table: &'a mut [SiteStats],
+        site: &'static Location<'static>,
+    ) -> Option<&'a mut SiteStats> {
+        let (others, sites) = table.split_last_mut()?;
+        for entry in sites {
+            match entry.site {
+                Some(other) if same_site(other, site) => return Some(entry),
+                Some(_) => {}
+                // the site was not counted, as it was allocated before the table was
+                // installed
+                None => return None,
+            }
+        }
+        Some(others)
+    }
+
+    fn record(&mut self, size: usize) {
+        self.live_bytes += size;
+        self.live += 1;
+        self.peak_bytes = self.peak_bytes.max(self.live_bytes);
+        self.total_bytes = self.total_bytes.wrapping_add(size);
+        self.allocations = self.allocations.wrapping_add(1);
+    }
+
+    fn release(&mut self, size: usize) {
+        self.live_bytes = self.live_bytes.saturating_sub(size);
+        self.live = self.live.saturating_sub(1);
+    }
+}
+
+impl Default for SiteStats {
+    fn default() -> Self {
+        SiteStats::new()
+    }
+}
+
+impl fmt::Display for SiteStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes in {} allocations, peak {} bytes, total {} bytes in {} allocations, at ",
+            self.live_bytes, self.live, self.peak_bytes, self.total_bytes, self.allocations
+        )?;
+        match self.site {
+            Some(site) => site.fmt(f),
+            None => f.write_str("other call sites"),
+        }
+    }
+}
+
+/// Returns whether two locations are the same call site. The same location can be stored
+/// more than once in a binary, so they are compared by value if they are not identical.
+fn same_site(a: &'static Location<'static>, b: &'static Location<'static>) -> bool {
+    core::ptr::eq(a, b) || a == b
+}
+
+/// The live allocations of a [`Heap`] and their call sites, created by
+/// [`Heap::leak_report`].
+pub struct LeakReport<'a> {
+    heap: &'a Heap,
+}
+
+impl<'a> LeakReport<'a> {
+    /// Returns the number of allocations in the report.
+    pub fn len(&self) -> usize {
+        self.blocks().count()
+    }
+
+    /// Returns whether there are no live allocations.
+    pub fn is_empty(&self) -> bool {
+        self.blocks().next().is_none()
+    }
+
+    /// Returns the headers of the live allocations, without the pinned blocks.
+    fn blocks(&self) -> impl Iterator<Item = (*mut u8, &'a Header)> + 'a {
+        let mut blocks = Allocations::new(&self.heap.holes, self.heap.bottom());
+        core::iter::from_fn(move || blocks.next_block())
+            // SAFETY: The iterator only yields live blocks.
+            .map(|block| (block, unsafe { Header::of_block(block) }))
+            .filter(|(_, header)| !header.is_pinned())
+    }
+}
+
+impl<'a> fmt::Display for LeakReport<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (block, header) in self.blocks() {
+            writeln!(
+                f,
+                "leaked {} bytes at {:p}, allocated at {}",
+                header.layout.size(),
+                header.payload(block),
+                Site(header.site)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats an optional call site.
+pub(crate) struct Site(pub Option<&'static Location<'static>>);
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(site) => site.fmt(f),
+            None => f.write_str("an unknown site"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SiteStats;
+    use crate::test::new_heap;
+    #[cfg(feature = "use_spin")]
+    use crate::{test::Chonk, LockedHeap};
+    use core::alloc::Layout;
+    use std::boxed::Box;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    #[test]
+    fn records_call_sites() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        let line = line!() + 1;
+        let a = heap.allocate_first_fit(layout).unwrap();
+        let b = heap.allocate_zeroed(layout).unwrap();
+        let c = heap.allocate_first_fit(Layout::new::<()>()).unwrap();
+
+        let site = unsafe { heap.allocation_site(a) }.unwrap();
+        assert_eq!(site.file(), file!());
+        assert_eq!(site.line(), line);
+        let site = unsafe { heap.allocation_site(b) }.unwrap();
+        assert_eq!(site.line(), line + 1);
+
+        let report = heap.leak_report();
+        assert_eq!(report.len(), 2);
+        let report = report.to_string();
+        let expected = std::format!(
+            "leaked 48 bytes at {:p}, allocated at {}:{}:",
+            a,
+            file!(),
+            line
+        );
+        assert!(report.starts_with(&expected), "{}", report);
+        assert_eq!(report.lines().count(), 2);
+
+        unsafe {
+            heap.deallocate(a, layout);
+            heap.deallocate(b, layout);
+            heap.deallocate(c, Layout::new::<()>());
+        }
+        assert!(heap.leak_report().is_empty());
+        assert_eq!(heap.leak_report().to_string(), "");
+    }
+
+    #[test]
+    fn aggregates_call_sites() {
+        let mut heap = new_heap();
+        let table = Box::leak(Box::new([SiteStats::new(); 3]));
+        assert!(heap.set_site_stats(Some(table)).is_none());
+
+        let small = Layout::from_size_align(16, 8).unwrap();
+        let large = Layout::from_size_align(100, 8).unwrap();
+        let mut ptrs = Vec::new();
+        for _ in 0..3 {
+            ptrs.push(heap.allocate_first_fit(small).unwrap());
+        }
+        let line = line!() + 1;
+        let a = heap.allocate_first_fit(large).unwrap();
+        // further call sites only fit into the entry for all others
+        let b = heap.allocate_first_fit(large).unwrap();
+        unsafe { heap.deallocate(b, large) };
+        let c = heap.allocate_first_fit(small).unwrap();
+
+        let stats: Vec<_> = heap.site_stats().copied().collect();
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].site.unwrap().line(), line);
+        assert_eq!(
+            (stats[0].live_bytes, stats[0].live, stats[0].peak_bytes),
+            (100, 1, 100)
+        );
+        assert_eq!(stats[0].total_bytes, 100);
+        assert_eq!(stats[1].site.unwrap().line(), line - 3);
+        assert_eq!((stats[1].live_bytes, stats[1].live), (48, 3));
+        assert_eq!(stats[2].site, None);
+        assert_eq!((stats[2].live_bytes, stats[2].allocations), (16, 2));
+        assert_eq!(stats[2].peak_bytes, 100);
+        assert!(stats[2].to_string().ends_with("at other call sites"));
+
+        unsafe {
+            heap.deallocate(a, large);
+            heap.deallocate(c, small);
+            for ptr in ptrs {
+                heap.deallocate(ptr, small);
+            }
+        }
+        assert!(heap.site_stats().all(|entry| entry.live_bytes == 0));
+        let table = heap.set_site_stats(None).unwrap();
+        assert_eq!(table[0].peak_bytes, 100);
+        assert_eq!(heap.site_stats().count(), 0);
+        drop(unsafe { Box::from_raw(table) });
+    }
+
+    #[test]
+    #[cfg(feature = "use_spin")]
+    fn locked_heap_records_its_caller() {
+        let (chonk, data) = Chonk::<1024>::new();
+        let heap = unsafe { LockedHeap::new(data, 1024) };
+        let table = Box::leak(Box::new([SiteStats::new(); 4]));
+        heap.with_heap(move |heap| heap.set_site_stats(Some(table)));
+
+        let layout = Layout::new::<u64>();
+        let line = line!() + 1;
+        let ptr = heap.allocate_first_fit(layout).unwrap();
+        heap.with_heap(|heap| {
+            assert_eq!(unsafe { heap.allocation_site(ptr) }.unwrap().line(), line);
+            let stats: Vec<_> = heap.site_stats().copied().collect();
+            assert_eq!(stats.len(), 1);
+            assert_eq!(stats[0].site.unwrap().line(), line);
+        });
+
+        unsafe {
+            heap.deallocate(ptr, layout);
+            let table = heap.with_heap(|heap| heap.set_site_stats(None)).unwrap();
+            drop(Box::from_raw(table));
+            Chonk::unleak(chonk);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "made at src/site.rs")]
+    fn wrong_layout_names_call_site() {
+        let mut heap = new_heap();
+        let ptr = heap.allocate_first_fit(Layout::new::<u64>()).unwrap();
+        unsafe { heap.deallocate(ptr, Layout::new::<u128>()) };
+    }
+}
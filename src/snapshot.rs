@@ -0,0 +1,287 @@
+//! Serialization of the heap metadata for offline analysis.
+//!
+//! [`Heap::write_snapshot`] writes the bounds, the statistics, and the list of free blocks
+//! of a heap into a byte buffer. If the buffer lives in memory that survives a crash (or is
+//! part of a RAM dump), the heap state can be reconstructed afterwards, e.g. with the
+//! [`Snapshot`] parser that is available with the `std` feature.
+//!
+//! # Format
+//!
+//! All fields are little-endian `u64` values unless noted otherwise, independent of the
+//! pointer width of the target.
+//!
+//! | Offset | Size | Field                                              |
+//! |--------|------|----------------------------------------------------|
+//! | 0      | 8    | Magic bytes `LLAHEAP\0`                            |
+//! | 8      | 4    | Format version (`u32`), currently `1`              |
+//! | 12     | 4    | Length of this header in bytes (`u32`), `96`       |
+//! | 16     | 8    | Bottom address of the heap                         |
+//! | 24     | 8    | Top address of the heap                            |
+//! | 32     | 8    | [`size`][HeapStats::size]                          |
+//! | 40     | 8    | [`used`][HeapStats::used]                          |
+//! | 48     | 8    | [`peak_used`][HeapStats::peak_used]                |
+//! | 56     | 8    | [`allocations`][HeapStats::allocations]            |
+//! | 64     | 8    | [`deallocations`][HeapStats::deallocations]        |
+//! | 72     | 8    | [`failed_allocations`][HeapStats::failed_allocations] |
+//! | 80     | 8    | Total number of holes                              |
+//! | 88     | 8    | Number of hole records that follow                 |
+//!
+//! The header is followed by one 16 byte record per hole, in address order, consisting of
+//! the address and the size of the hole. If the buffer is too small to hold all holes, the
+//! list is truncated and the number of records is smaller than the total number of holes.
+//!
+//! Parsers must use the header length field to find the first hole record, so that future
+//! versions can append fields to the header.
+
+#[cfg(feature = "std")]
+use std::{fmt, vec::Vec};
+
+#[cfg(feature = "std")]
+use core::convert::TryInto;
+
+use crate::{Heap, HeapStats};
+
+/// The magic bytes at the start of every snapshot.
+pub const MAGIC: [u8; 8] = *b"LLAHEAP\0";
+/// The format version written by this crate.
+pub const VERSION: u32 = 1;
+/// The length of the snapshot header in bytes.
+pub const HEADER_LEN: usize = 96;
+/// The length of a single hole record in bytes.
+pub const HOLE_RECORD_LEN: usize = 16;
+
+impl Heap {
+    /// Serializes the heap metadata into `buf` in the format described in the
+    /// [`snapshot`][crate::snapshot] module.
+    ///
+    /// Returns the number of bytes written, or `None` if `buf` is smaller than
+    /// [`HEADER_LEN`]. If the buffer cannot hold all hole records, as many as possible
+    /// are written. This function does not allocate.
+    pub fn write_snapshot(&self, buf: &mut [u8]) -> Option<usize> {
+        if buf.len() < HEADER_LEN {
+            return None;
+        }
+        let HeapStats {
+            size,
+            used,
+            free: _,
+            peak_used,
+            allocations,
+            deallocations,
+            failed_allocations,
+            holes,
+            largest_hole: _,
+        } = self.stats();
+        let records = holes.min((buf.len() - HEADER_LEN) / HOLE_RECORD_LEN);
+
+        buf[0..8].copy_from_slice(&MAGIC);
+        buf[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        buf[12..16].copy_from_slice(&(HEADER_LEN as u32).to_le_bytes());
+        let fields = [
+            self.bottom() as usize,
+            self.top() as usize,
+            size,
+            used,
+            peak_used,
+            allocations,
+            deallocations,
+            failed_allocations,
+            holes,
+            records,
+        ];
+        for (i, field) in fields.iter().enumerate() {
+            let start = 16 + i * 8;
+            buf[start..start + 8].copy_from_slice(&(*field as u64).to_le_bytes());
+        }
+
+        let hole_records = buf[HEADER_LEN..].chunks_exact_mut(HOLE_RECORD_LEN);
+        for (record, (addr, size)) in hole_records.zip(self.holes.holes()).take(records) {
+            record[0..8].copy_from_slice(&(addr as usize as u64).to_le_bytes());
+            record[8..16].copy_from_slice(&(size as u64).to_le_bytes());
+        }
+        Some(HEADER_LEN + records * HOLE_RECORD_LEN)
+    }
+}
+
+/// A heap snapshot parsed from a buffer written by [`Heap::write_snapshot`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The format version of the snapshot.
+    pub version: u32,
+    /// The bottom address of the heap.
+    pub bottom: u64,
+    /// The top address of the heap.
+    pub top: u64,
+    /// The usable size of the heap in bytes.
+    pub size: u64,
+    /// The number of allocated bytes.
+    pub used: u64,
+    /// The highest number of allocated bytes.
+    pub peak_used: u64,
+    /// The number of successful allocations.
+    pub allocations: u64,
+    /// The number of deallocations.
+    pub deallocations: u64,
+    /// The number of failed allocations.
+    pub failed_allocations: u64,
+    /// The total number of holes in the heap.
+    pub hole_count: u64,
+    /// The address and size of the recorded holes, in address order.
+    ///
+    /// Contains less than `hole_count` entries if the snapshot was truncated.
+    pub holes: Vec<(u64, u64)>,
+}
+
+/// An error that occurred while parsing a [`Snapshot`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// The buffer does not start with [`MAGIC`].
+    BadMagic,
+    /// The snapshot was written in an unknown format version.
+    UnsupportedVersion(u32),
+    /// The buffer ends before the end of the snapshot.
+    Truncated,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::BadMagic => write!(f, "not a heap snapshot"),
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            ParseError::Truncated => write!(f, "heap snapshot is truncated"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+#[cfg(feature = "std")]
+impl Snapshot {
+    /// Parses a snapshot that starts at the beginning of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Snapshot, ParseError> {
+        if bytes.len() < 16 {
+            return Err(ParseError::Truncated);
+        }
+        if bytes[0..8] != MAGIC {
+            return Err(ParseError::BadMagic);
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version != VERSION {
+            return Err(ParseError::UnsupportedVersion(version));
+        }
+        let header_len = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+        if header_len < HEADER_LEN || bytes.len() < header_len {
+            return Err(ParseError::Truncated);
+        }
+
+        let field = |i: usize| read_u64(bytes, 16 + i * 8);
+        let records = field(9) as usize;
+        let holes = bytes[header_len..]
+            .chunks_exact(HOLE_RECORD_LEN)
+            .take(records)
+            .map(|record| (read_u64(record, 0), read_u64(record, 8)))
+            .collect::<Vec<_>>();
+        if holes.len() != records {
+            return Err(ParseError::Truncated);
+        }
+
+        Ok(Snapshot {
+            version,
+            bottom: field(0),
+            top: field(1),
+            size: field(2),
+            used: field(3),
+            peak_used: field(4),
+            allocations: field(5),
+            deallocations: field(6),
+            failed_allocations: field(7),
+            hole_count: field(8),
+            holes,
+        })
+    }
+
+    /// Searches `bytes` (e.g. a raw memory dump) for the first valid snapshot.
+    pub fn find(bytes: &[u8]) -> Option<Snapshot> {
+        (0..bytes.len())
+            .filter(|&start| bytes[start..].starts_with(&MAGIC))
+            .find_map(|start| Snapshot::parse(&bytes[start..]).ok())
+    }
+
+    /// Returns whether the snapshot contains fewer hole records than the heap had holes.
+    pub fn is_truncated(&self) -> bool {
+        (self.holes.len() as u64) < self.hole_count
+    }
+}
+
+#[cfg(feature = "std")]
+fn read_u64(bytes: &[u8], start: usize) -> u64 {
+    u64::from_le_bytes(bytes[start..start + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HEADER_LEN, HOLE_RECORD_LEN, MAGIC};
+    use crate::test::new_heap;
+    use core::alloc::Layout;
+    use core::convert::TryInto;
+
+    #[test]
+    fn write_snapshot() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = heap.allocate_first_fit(layout).unwrap();
+        let _b = heap.allocate_first_fit(layout).unwrap();
+        unsafe { heap.deallocate(a, layout) };
+
+        let mut buf = [0u8; 256];
+        let len = heap.write_snapshot(&mut buf).unwrap();
+        assert_eq!(len, HEADER_LEN + 2 * HOLE_RECORD_LEN);
+        assert_eq!(buf[0..8], MAGIC);
+        let hole_count = u64::from_le_bytes(buf[80..88].try_into().unwrap());
+        assert_eq!(hole_count, 2);
+        let first_hole = u64::from_le_bytes(buf[96..104].try_into().unwrap());
+        assert_eq!(first_hole, heap.bottom() as u64);
+
+        // too small for all holes
+        let mut buf = [0u8; HEADER_LEN + HOLE_RECORD_LEN];
+        assert_eq!(heap.write_snapshot(&mut buf), Some(buf.len()));
+        assert_eq!(u64::from_le_bytes(buf[88..96].try_into().unwrap()), 1);
+
+        // too small for the header
+        assert_eq!(heap.write_snapshot(&mut [0; HEADER_LEN - 1]), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn parse_snapshot() {
+        use super::{ParseError, Snapshot};
+
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = heap.allocate_first_fit(layout).unwrap();
+        let _b = heap.allocate_first_fit(layout).unwrap();
+        unsafe { heap.deallocate(a, layout) };
+
+        // embed the snapshot in a larger "memory dump"
+        let mut dump = [0xaau8; 512];
+        heap.write_snapshot(&mut dump[100..]).unwrap();
+        let snapshot = Snapshot::find(&dump).unwrap();
+        let stats = heap.stats();
+        assert_eq!(snapshot.bottom, heap.bottom() as u64);
+        assert_eq!(snapshot.used, stats.used as u64);
+        assert_eq!(snapshot.allocations, 2);
+        assert_eq!(snapshot.deallocations, 1);
+        assert_eq!(snapshot.holes.len(), 2);
+        assert_eq!(snapshot.holes[0].0, heap.bottom() as u64);
+        assert!(!snapshot.is_truncated());
+
+        assert_eq!(Snapshot::parse(&dump), Err(ParseError::BadMagic));
+        assert_eq!(Snapshot::parse(&dump[100..150]), Err(ParseError::Truncated));
+    }
+}
@@ -0,0 +1,203 @@
+//! An allocator that separates small from large allocations, see [`SplitHeap`].
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+use crate::fallback::Owns;
+use crate::{AllocError, SharedHeap};
+#[cfg(feature = "use_spin")]
+use crate::{HeapStats, LockedHeap, RawMutex};
+
+/// An allocator that sends allocations below a size threshold to a `small` allocator and
+/// all others to a `large` allocator.
+///
+/// Small allocations tend to be short-lived and frequent, while large buffers often live
+/// for a long time. Keeping them in separate heaps prevents the small ones from splitting
+/// up the free memory that the large ones need, which is one of the most effective
+/// measures against fragmentation. The small allocator can also be a slab or pool layer.
+///
+/// Deallocations are routed by the size of their layout, so they don't need to find the
+/// owner of the pointer. Unlike [`FallbackHeap`][crate::FallbackHeap], an allocation thus
+/// never moves to the other allocator when its own one is full.
+///
+/// ```ignore
+/// use linked_list_allocator::{LockedHeap, SplitHeap};
+///
+/// #[global_allocator]
+/// static ALLOCATOR: SplitHeap<LockedHeap, LockedHeap> =
+///     SplitHeap::new(256, LockedHeap::empty(), LockedHeap::empty());
+/// ```
+pub struct SplitHeap<S, L> {
+    threshold: usize,
+    small: S,
+    large: L,
+}
+
+impl<S, L> SplitHeap<S, L> {
+    /// Creates an allocator that serves allocations of less than `threshold` bytes from
+    /// `small` and all others from `large`.
+    pub const fn new(threshold: usize, small: S, large: L) -> Self {
+        SplitHeap {
+            threshold,
+            small,
+            large,
+        }
+    }
+
+    /// Returns the size from which on allocations are served by the large allocator.
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Returns a reference to the allocator for small allocations.
+    pub fn small(&self) -> &S {
+        &self.small
+    }
+
+    /// Returns a reference to the allocator for large allocations.
+    pub fn large(&self) -> &L {
+        &self.large
+    }
+
+    fn is_small(&self, layout: Layout) -> bool {
+        layout.size() < self.threshold
+    }
+}
+
+#[cfg(feature = "use_spin")]
+impl<R1: RawMutex, R2: RawMutex> SplitHeap<LockedHeap<R1>, LockedHeap<R2>> {
+    /// Returns the [combined][HeapStats::combine] statistics of both heaps.
+    pub fn stats(&self) -> HeapStats {
+        self.small.stats().combine(&self.large.stats())
+    }
+}
+
+unsafe impl<S: Owns, L: Owns> Owns for SplitHeap<S, L> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.small.owns(ptr) || self.large.owns(ptr)
+    }
+}
+
+unsafe impl<S: SharedHeap, L: SharedHeap> SharedHeap for SplitHeap<S, L> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if self.is_small(layout) {
+            self.small.allocate(layout)
+        } else {
+            self.large.allocate(layout)
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.is_small(layout) {
+            self.small.deallocate(ptr, layout)
+        } else {
+            self.large.deallocate(ptr, layout)
+        }
+    }
+}
+
+unsafe impl<S: GlobalAlloc, L: GlobalAlloc> GlobalAlloc for SplitHeap<S, L> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if self.is_small(layout) {
+            self.small.alloc(layout)
+        } else {
+            self.large.alloc(layout)
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if self.is_small(layout) {
+            self.small.alloc_zeroed(layout)
+        } else {
+            self.large.alloc_zeroed(layout)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self.is_small(layout) {
+            self.small.dealloc(ptr, layout)
+        } else {
+            self.large.dealloc(ptr, layout)
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (self.is_small(layout), self.is_small(new_layout)) {
+            (true, true) => self.small.realloc(ptr, layout, new_size),
+            (false, false) => self.large.realloc(ptr, layout, new_size),
+            // the allocation moves to the other allocator
+            _ => {
+                let new = self.alloc(new_layout);
+                if !new.is_null() {
+                    core::ptr::copy_nonoverlapping(ptr, new, layout.size().min(new_size));
+                    self.dealloc(ptr, layout);
+                }
+                new
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "use_spin"))]
+mod test {
+    use super::SplitHeap;
+    use crate::test::Chonk;
+    use crate::{LockedHeap, Owns};
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::ptr::NonNull;
+
+    #[test]
+    fn splits_by_size() {
+        let (small_chonk, small_data) = Chonk::<512>::new();
+        let (large_chonk, large_data) = Chonk::<1024>::new();
+        let heap = unsafe {
+            SplitHeap::new(
+                64,
+                LockedHeap::new(small_data, 512),
+                LockedHeap::new(large_data, 1024),
+            )
+        };
+        let small = Layout::from_size_align(16, 8).unwrap();
+        let large = Layout::from_size_align(300, 8).unwrap();
+        let owns = |heap: &LockedHeap, ptr: *mut u8| heap.owns(NonNull::new(ptr).unwrap());
+
+        let a = unsafe { heap.alloc(small) };
+        let b = unsafe { heap.alloc(large) };
+        assert!(owns(heap.small(), a) && owns(heap.large(), b));
+        let stats = heap.stats();
+        assert_eq!(stats.allocations, 2);
+        assert_eq!(
+            stats.size,
+            heap.small().stats().size + heap.large().stats().size
+        );
+        assert_eq!(
+            stats.used,
+            heap.small().stats().used + heap.large().stats().used
+        );
+
+        // growing a small allocation beyond the threshold moves it to the large heap
+        unsafe { a.write_bytes(7, 16) };
+        let a = unsafe { heap.realloc(a, small, 100) };
+        assert!(owns(heap.large(), a));
+        assert_eq!(unsafe { *a.add(15) }, 7);
+        assert_eq!(heap.small().stats().used, 0);
+
+        // the large heap is full, but small allocations still succeed
+        assert!(unsafe { heap.alloc(Layout::from_size_align(700, 8).unwrap()) }.is_null());
+        let c = unsafe { heap.alloc(small) };
+        assert!(!c.is_null());
+
+        unsafe {
+            heap.dealloc(a, Layout::from_size_align(100, 8).unwrap());
+            heap.dealloc(b, large);
+            heap.dealloc(c, small);
+        }
+        assert_eq!(heap.stats().used, 0);
+
+        unsafe {
+            Chonk::unleak(small_chonk);
+            Chonk::unleak(large_chonk);
+        }
+    }
+}
@@ -0,0 +1,427 @@
+use core::fmt;
+#[cfg(feature = "use_spin")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "use_spin")]
+use crate::Heap;
+
+/// A snapshot of the state of a [`Heap`][crate::Heap], returned by
+/// [`Heap::stats`][crate::Heap::stats].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HeapStats {
+    /// The usable size of the heap in bytes.
+    pub size: usize,
+    /// The number of bytes currently allocated, including any rounding.
+    pub used: usize,
+    /// The number of bytes currently free.
+    pub free: usize,
+    /// The highest value of `used` that was observed since the heap was initialized.
+    pub peak_used: usize,
+    /// The number of successful allocations since the heap was initialized.
+    pub allocations: usize,
+    /// The number of deallocations since the heap was initialized.
+    pub deallocations: usize,
+    /// The number of failed allocations since the heap was initialized.
+    pub failed_allocations: usize,
+    /// The number of free blocks.
+    pub holes: usize,
+    /// The size of the largest free block in bytes.
+    pub largest_hole: usize,
+}
+
+impl HeapStats {
+    /// Returns the changes since the `earlier` stats of the same heap, e.g. between two
+    /// periodic snapshots of a long-running system.
+    ///
+    /// The event counters wrap around, so their deltas are correct as long as they didn't
+    /// wrap around more than once in between.
+    pub fn diff(&self, earlier: &HeapStats) -> HeapStatsDiff {
+        let delta = |now: usize, then: usize| (now as isize).wrapping_sub(then as isize);
+        HeapStatsDiff {
+            allocations: self.allocations.wrapping_sub(earlier.allocations),
+            deallocations: self.deallocations.wrapping_sub(earlier.deallocations),
+            failed_allocations: self
+                .failed_allocations
+                .wrapping_sub(earlier.failed_allocations),
+            used: delta(self.used, earlier.used),
+            peak_used: delta(self.peak_used, earlier.peak_used),
+            holes: delta(self.holes, earlier.holes),
+            largest_hole: delta(self.largest_hole, earlier.largest_hole),
+        }
+    }
+
+    /// Returns the stats of two heaps that are used as one allocator, e.g. the parts of a
+    /// [`SplitHeap`][crate::SplitHeap].
+    ///
+    /// The sizes, byte counts and event counters are added up and the largest free block is
+    /// the larger one of both. The peaks of both heaps might have been reached at different
+    /// times, so the combined `peak_used` is an upper bound of the real peak.
+    pub fn combine(&self, other: &HeapStats) -> HeapStats {
+        HeapStats {
+            size: self.size + other.size,
+            used: self.used + other.used,
+            free: self.free + other.free,
+            peak_used: self.peak_used + other.peak_used,
+            allocations: self.allocations.wrapping_add(other.allocations),
+            deallocations: self.deallocations.wrapping_add(other.deallocations),
+            failed_allocations: self
+                .failed_allocations
+                .wrapping_add(other.failed_allocations),
+            holes: self.holes + other.holes,
+            largest_hole: self.largest_hole.max(other.largest_hole),
+        }
+    }
+
+    /// Renders the stats in the Prometheus text exposition format into `out`.
+    ///
+    /// Every field becomes a metric whose name starts with `prefix`, e.g. `heap` for
+    /// `heap_used_bytes`, so that the stats of several heaps can be told apart. The event
+    /// counters are exported as counters and all other fields as gauges.
+    pub fn render_prometheus<W: fmt::Write + ?Sized>(
+        &self,
+        out: &mut W,
+        prefix: &str,
+    ) -> fmt::Result {
+        let metrics = [
+            (
+                "size_bytes",
+                "gauge",
+                "The usable size of the heap.",
+                self.size,
+            ),
+            ("used_bytes", "gauge", "The allocated bytes.", self.used),
+            ("free_bytes", "gauge", "The free bytes.", self.free),
+            (
+                "peak_used_bytes",
+                "gauge",
+                "The peak of the allocated bytes.",
+                self.peak_used,
+            ),
+            (
+                "allocations_total",
+                "counter",
+                "The successful allocations.",
+                self.allocations,
+            ),
+            (
+                "deallocations_total",
+                "counter",
+                "The deallocations.",
+                self.deallocations,
+            ),
+            (
+                "failed_allocations_total",
+                "counter",
+                "The failed allocations.",
+                self.failed_allocations,
+            ),
+            (
+                "free_blocks",
+                "gauge",
+                "The number of free blocks.",
+                self.holes,
+            ),
+            (
+                "largest_free_block_bytes",
+                "gauge",
+                "The size of the largest free block.",
+                self.largest_hole,
+            ),
+        ];
+        for (name, kind, help, value) in metrics {
+            writeln!(out, "# HELP {}_{} {}", prefix, name, help)?;
+            writeln!(out, "# TYPE {}_{} {}", prefix, name, kind)?;
+            writeln!(out, "{}_{} {}", prefix, name, value)?;
+        }
+        Ok(())
+    }
+
+    /// Renders the stats like [`render_prometheus`][HeapStats::render_prometheus] into
+    /// `buf` and returns the number of bytes written, e.g. to serve them from a small HTTP
+    /// server without allocating.
+    ///
+    /// Fails if `buf` is too small for the whole text, in which case its contents are
+    /// unspecified.
+    pub fn render_prometheus_into(
+        &self,
+        buf: &mut [u8],
+        prefix: &str,
+    ) -> Result<usize, fmt::Error> {
+        let mut out = BufWriter { buf, len: 0 };
+        self.render_prometheus(&mut out, prefix)?;
+        Ok(out.len)
+    }
+}
+
+/// Writes text into a byte buffer and fails once the buffer is full.
+struct BufWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> fmt::Write for BufWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        let dest = self.buf.get_mut(self.len..end).ok_or(fmt::Error)?;
+        dest.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// The changes between two [`HeapStats`] of a heap, returned by [`HeapStats::diff`].
+///
+/// The `Display` implementation prints all deltas on one line, e.g. for a log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HeapStatsDiff {
+    /// The number of successful allocations in between.
+    pub allocations: usize,
+    /// The number of deallocations in between.
+    pub deallocations: usize,
+    /// The number of failed allocations in between.
+    pub failed_allocations: usize,
+    /// The change of the allocated bytes.
+    pub used: isize,
+    /// The change of the peak usage in bytes. It is only negative if the peak was reset by
+    /// [`Heap::take_stats`][crate::Heap::take_stats] or a reinitialization in between.
+    pub peak_used: isize,
+    /// The change of the number of free blocks.
+    pub holes: isize,
+    /// The change of the size of the largest free block in bytes.
+    pub largest_hole: isize,
+}
+
+impl fmt::Display for HeapStatsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations, {} deallocations, {} failed, used {:+} bytes, peak {:+} bytes, \
+             holes {:+}, largest hole {:+} bytes",
+            self.allocations,
+            self.deallocations,
+            self.failed_allocations,
+            self.used,
+            self.peak_used,
+            self.holes,
+            self.largest_hole
+        )
+    }
+}
+
+/// The live allocations whose alignment falls into one class of the histogram that is
+/// returned by [`Heap::align_stats`][crate::Heap::align_stats].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AlignStats {
+    /// The alignment of the class. The last class also contains all larger alignments.
+    pub align: usize,
+    /// The number of live allocations with this alignment.
+    pub allocations: usize,
+    /// The number of bytes that these allocations take from the heap beyond their
+    /// requested size, e.g. for alignment padding, headers and rounding.
+    pub padding: usize,
+}
+
+/// The number of alignment classes in [`AlignHistogram`], from 1 up to 2048 bytes.
+pub(crate) const ALIGN_CLASSES: usize = 12;
+
+/// The number and padding of the live allocations by alignment, see [`AlignStats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AlignHistogram {
+    allocations: [usize; ALIGN_CLASSES],
+    padding: [usize; ALIGN_CLASSES],
+}
+
+impl AlignHistogram {
+    pub const fn new() -> Self {
+        AlignHistogram {
+            allocations: [0; ALIGN_CLASSES],
+            padding: [0; ALIGN_CLASSES],
+        }
+    }
+
+    fn class(align: usize) -> usize {
+        (align.trailing_zeros() as usize).min(ALIGN_CLASSES - 1)
+    }
+
+    /// Records an allocation with the given alignment that takes `padding` bytes more
+    /// than it requested.
+    pub fn record_allocation(&mut self, align: usize, padding: usize) {
+        let class = Self::class(align);
+        self.allocations[class] = self.allocations[class].wrapping_add(1);
+        self.padding[class] = self.padding[class].wrapping_add(padding);
+    }
+
+    /// Records a deallocation with the given alignment that freed `padding` bytes more
+    /// than it requested. The freed bytes can include padding that was left in front of
+    /// a neighbouring allocation, so the counts saturate at zero.
+    pub fn record_deallocation(&mut self, align: usize, padding: usize) {
+        let class = Self::class(align);
+        self.allocations[class] = self.allocations[class].saturating_sub(1);
+        self.padding[class] = self.padding[class].saturating_sub(padding);
+    }
+
+    /// Adds the allocations of a heap that was merged into this one.
+    pub fn merge(&mut self, other: &AlignHistogram) {
+        for class in 0..ALIGN_CLASSES {
+            self.allocations[class] =
+                self.allocations[class].wrapping_add(other.allocations[class]);
+            self.padding[class] = self.padding[class].wrapping_add(other.padding[class]);
+        }
+    }
+
+    pub fn get(&self) -> [AlignStats; ALIGN_CLASSES] {
+        let mut stats = [AlignStats::default(); ALIGN_CLASSES];
+        for (class, entry) in stats.iter_mut().enumerate() {
+            *entry = AlignStats {
+                align: 1 << class,
+                allocations: self.allocations[class],
+                padding: self.padding[class],
+            };
+        }
+        stats
+    }
+}
+
+/// The counters of a [`LockedHeap`][crate::LockedHeap] that can be read without taking its
+/// lock, returned by [`LockedHeap::counters`][crate::LockedHeap::counters].
+///
+/// They are updated after the lock is released, so they may lag behind the heap for a
+/// moment while other cores allocate. Only allocations through `LockedHeap` itself are
+/// counted, not those made directly on the [`Heap`][crate::Heap] in
+/// [`with_heap`][crate::LockedHeap::with_heap].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HeapCounters {
+    /// The number of bytes currently allocated, including any rounding.
+    pub used: usize,
+    /// The highest value of `used` that was observed.
+    pub peak_used: usize,
+    /// The number of successful allocations.
+    pub allocations: usize,
+    /// The number of deallocations.
+    pub deallocations: usize,
+    /// The number of failed allocations.
+    pub failed_allocations: usize,
+}
+
+/// Event counters that are maintained by the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Counters {
+    pub peak_used: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub failed_allocations: usize,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Counters {
+            peak_used: 0,
+            allocations: 0,
+            deallocations: 0,
+            failed_allocations: 0,
+        }
+    }
+
+    /// Records a successful allocation after which `used` bytes are in use.
+    pub fn record_allocation(&mut self, used: usize) {
+        self.allocations = self.allocations.wrapping_add(1);
+        self.peak_used = self.peak_used.max(used);
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failed_allocations = self.failed_allocations.wrapping_add(1);
+    }
+
+    pub fn record_deallocation(&mut self) {
+        self.deallocations = self.deallocations.wrapping_add(1);
+    }
+
+    /// Adds up the counters of two heaps that were merged and now have `used` bytes in use.
+    pub fn merge(self, other: Counters, used: usize) -> Counters {
+        Counters {
+            peak_used: self.peak_used.max(used),
+            allocations: self.allocations.wrapping_add(other.allocations),
+            deallocations: self.deallocations.wrapping_add(other.deallocations),
+            failed_allocations: self
+                .failed_allocations
+                .wrapping_add(other.failed_allocations),
+        }
+    }
+}
+
+/// The atomic counters of a [`LockedHeap`][crate::LockedHeap], see [`HeapCounters`].
+#[cfg(feature = "use_spin")]
+pub(crate) struct SharedCounters {
+    used: AtomicUsize,
+    peak_used: AtomicUsize,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    failed_allocations: AtomicUsize,
+}
+
+#[cfg(feature = "use_spin")]
+impl SharedCounters {
+    /// Creates counters that start at the current state of `heap`.
+    pub const fn of(heap: &Heap) -> Self {
+        SharedCounters {
+            used: AtomicUsize::new(heap.used),
+            peak_used: AtomicUsize::new(heap.counters.peak_used),
+            allocations: AtomicUsize::new(heap.counters.allocations),
+            deallocations: AtomicUsize::new(heap.counters.deallocations),
+            failed_allocations: AtomicUsize::new(heap.counters.failed_allocations),
+        }
+    }
+
+    /// Records an allocation that changed the used memory by `grown` bytes, which wraps
+    /// around if the heap purged more than it allocated.
+    pub fn record_allocation(&self, success: bool, grown: usize) {
+        if success {
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failed_allocations.fetch_add(1, Ordering::Relaxed);
+        }
+        let used = self.used.fetch_add(grown, Ordering::Relaxed);
+        self.peak_used
+            .fetch_max(used.wrapping_add(grown), Ordering::Relaxed);
+    }
+
+    /// Records a deallocation that freed `freed` bytes.
+    pub fn record_deallocation(&self, freed: usize) {
+        self.record_deallocations(1, freed);
+    }
+
+    /// Records `count` deallocations that freed `freed` bytes in total.
+    pub fn record_deallocations(&self, count: usize, freed: usize) {
+        self.deallocations.fetch_add(count, Ordering::Relaxed);
+        self.used.fetch_sub(freed, Ordering::Relaxed);
+    }
+
+    /// Restarts the counters at the current state of `heap`, e.g. after it adopted the
+    /// allocations of another heap.
+    #[cfg(feature = "uefi")]
+    pub fn reset(&self, heap: &Heap) {
+        self.used.store(heap.used, Ordering::Relaxed);
+        self.peak_used
+            .store(heap.counters.peak_used.max(heap.used), Ordering::Relaxed);
+        self.allocations
+            .store(heap.counters.allocations, Ordering::Relaxed);
+        self.deallocations
+            .store(heap.counters.deallocations, Ordering::Relaxed);
+        self.failed_allocations
+            .store(heap.counters.failed_allocations, Ordering::Relaxed);
+    }
+
+    pub fn load(&self) -> HeapCounters {
+        HeapCounters {
+            used: self.used.load(Ordering::Relaxed),
+            peak_used: self.peak_used.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            failed_allocations: self.failed_allocations.load(Ordering::Relaxed),
+        }
+    }
+}
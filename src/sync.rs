@@ -0,0 +1,480 @@
+//! The locks used by [`LockedHeap`][crate::LockedHeap].
+//!
+//! `LockedHeap` wraps the heap in a [`Mutex`] of `lock_api`, whose raw lock decides how
+//! waiting cores spin. [`RawSpinlock`] of `spinning_top` is the default, and this module adds
+//! [`BackoffSpinlock`] and [`TicketLock`] for contended heaps. For heaps that are used from
+//! interrupt handlers, `MachineModeLock` masks the interrupts of a RISC-V hart and
+//! `CriticalSectionLock` takes a critical section of the `critical-section` crate.
+//!
+//! When compiled with `--cfg loom`, the mutex is replaced by an equivalent lock built on the
+//! atomics of [`loom`], so that the model checker can explore all interleavings of concurrent
+//! heap operations. The raw lock type is ignored in that case.
+
+#[cfg(feature = "critical_section")]
+use core::cell::UnsafeCell;
+use core::hint;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(any(
+    feature = "critical_section",
+    all(
+        feature = "riscv_machine_mode",
+        any(target_arch = "riscv32", target_arch = "riscv64")
+    )
+))]
+use spinning_top::lock_api::GuardNoSend;
+use spinning_top::lock_api::GuardSend;
+pub use spinning_top::lock_api::RawMutex;
+pub use spinning_top::RawSpinlock;
+
+#[cfg(not(loom))]
+pub use spinning_top::lock_api::Mutex;
+
+#[cfg(loom)]
+pub use self::model::Mutex;
+
+/// A spinlock that waits exponentially longer between attempts to take the lock.
+///
+/// Compared to [`RawSpinlock`], this reduces the traffic on the lock while it is held, which
+/// leaves more memory bandwidth to the core that holds it.
+pub struct BackoffSpinlock {
+    locked: AtomicBool,
+}
+
+impl BackoffSpinlock {
+    /// The maximum number of spin loop hints between two attempts to take the lock.
+    const MAX_SPINS: u32 = 1 << 10;
+}
+
+unsafe impl RawMutex for BackoffSpinlock {
+    const INIT: BackoffSpinlock = BackoffSpinlock {
+        locked: AtomicBool::new(false),
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        let mut spins = 1;
+        while !self.try_lock() {
+            for _ in 0..spins {
+                hint::spin_loop();
+            }
+            spins = (spins * 2).min(Self::MAX_SPINS);
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+/// A fair spinlock that hands out the lock in the order in which it was requested.
+///
+/// A plain spinlock can starve a core whose attempts to take the lock keep losing against
+/// another core, e.g. because that one is faster or closer to the memory. A ticket lock
+/// rules that out, at the cost of an atomic increment per lock.
+///
+/// Since waiting cores can't take the lock out of order, all of them wait while the next
+/// one in line is preempted. A ticket lock is thus a bad fit for threads that share a core.
+pub struct TicketLock {
+    /// The ticket for the next core that wants to take the lock.
+    next: AtomicUsize,
+    /// The ticket of the core that holds or may take the lock.
+    serving: AtomicUsize,
+}
+
+unsafe impl RawMutex for TicketLock {
+    const INIT: TicketLock = TicketLock {
+        next: AtomicUsize::new(0),
+        serving: AtomicUsize::new(0),
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            hint::spin_loop();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        // the lock is free if nobody holds or waits for the ticket that is served
+        let serving = self.serving.load(Ordering::Acquire);
+        self.next
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        // only the holder of the lock changes the served ticket
+        let serving = self.serving.load(Ordering::Relaxed);
+        self.serving
+            .store(serving.wrapping_add(1), Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.next.load(Ordering::Relaxed) != self.serving.load(Ordering::Relaxed)
+    }
+}
+
+/// Identifies the execution context that is currently running, for the deadlock detection
+/// of [`CheckedSpinlock`].
+///
+/// Two contexts that may interrupt each other must have different ids, e.g. the thread and
+/// the interrupt handlers of a core, while contexts that can't run at the same time as
+/// another one may share its id. The id `usize::MAX` is reserved.
+pub trait ExecutionContext {
+    /// Returns the id of the current execution context.
+    fn current() -> usize;
+}
+
+/// The [`ExecutionContext`] of a single core without preemptive threads, where a lock that
+/// is held when it is taken is always held by an interrupted context of the same core.
+pub struct SingleContext;
+
+impl ExecutionContext for SingleContext {
+    fn current() -> usize {
+        0
+    }
+}
+
+/// A spinlock that panics instead of spinning forever when the context that holds it tries
+/// to take it again, e.g. when an interrupt handler allocates while the interrupted thread
+/// holds the lock of the heap.
+///
+/// The holder of the lock is identified through the [`ExecutionContext`] `C`, which
+/// defaults to a [`SingleContext`]. The check is only done with debug assertions; without
+/// them, this is a plain spinlock like [`RawSpinlock`].
+///
+/// ```
+/// use linked_list_allocator::{CheckedSpinlock, Heap, LockedHeap};
+///
+/// static ALLOCATOR: LockedHeap<CheckedSpinlock> = LockedHeap::from_heap(Heap::empty());
+/// ```
+pub struct CheckedSpinlock<C: ExecutionContext = SingleContext> {
+    locked: AtomicBool,
+    /// The id of the context that holds the lock, or `usize::MAX`.
+    owner: AtomicUsize,
+    context: PhantomData<fn() -> C>,
+}
+
+impl<C: ExecutionContext> CheckedSpinlock<C> {
+    const NO_OWNER: usize = usize::MAX;
+
+    /// Panics if the current context holds the lock.
+    fn check_owner(&self) {
+        // the lock clears its owner before it is released, so the current context can only
+        // see its own id here while it holds the lock
+        let current = C::current();
+        if self.owner.load(Ordering::Relaxed) == current {
+            panic!(
+                "deadlock: the heap lock is already held by the current execution context {}, \
+                 e.g. an interrupt handler allocates while the interrupted code holds the lock",
+                current
+            );
+        }
+    }
+}
+
+unsafe impl<C: ExecutionContext> RawMutex for CheckedSpinlock<C> {
+    const INIT: CheckedSpinlock<C> = CheckedSpinlock {
+        locked: AtomicBool::new(false),
+        owner: AtomicUsize::new(Self::NO_OWNER),
+        context: PhantomData,
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        if self.try_lock() {
+            return;
+        }
+        if cfg!(debug_assertions) {
+            self.check_owner();
+        }
+        while !self.try_lock() {
+            hint::spin_loop();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let locked = self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if locked && cfg!(debug_assertions) {
+            self.owner.store(C::current(), Ordering::Relaxed);
+        }
+        locked
+    }
+
+    unsafe fn unlock(&self) {
+        if cfg!(debug_assertions) {
+            self.owner.store(Self::NO_OWNER, Ordering::Relaxed);
+        }
+        self.locked.store(false, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+/// A spinlock for RISC-V machine mode that clears `mstatus.MIE` while it is held, so that
+/// trap handlers can allocate from the heap without deadlocking against the code they
+/// interrupted.
+///
+/// The interrupts of the hart are masked before the lock is taken and restored to their
+/// previous state when it is released. While another hart holds the lock, they are enabled
+/// again between the attempts to take it. The lock needs the atomic compare-and-swap of the
+/// `A` extension; on harts without it, use `CriticalSectionLock` instead.
+///
+/// ```ignore
+/// use linked_list_allocator::{Heap, LockedHeap, MachineModeLock};
+///
+/// #[global_allocator]
+/// static ALLOCATOR: LockedHeap<MachineModeLock> = LockedHeap::from_heap(Heap::empty());
+/// ```
+#[cfg(all(
+    feature = "riscv_machine_mode",
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+pub struct MachineModeLock {
+    locked: AtomicBool,
+    /// Whether `mstatus.MIE` was set when the lock was taken.
+    mie: AtomicBool,
+}
+
+#[cfg(all(
+    feature = "riscv_machine_mode",
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+mod mie {
+    use core::arch::asm;
+
+    /// The machine interrupt enable bit of `mstatus`.
+    const MSTATUS_MIE: usize = 1 << 3;
+
+    /// Clears `mstatus.MIE` and returns whether it was set.
+    pub fn disable() -> bool {
+        let mstatus: usize;
+        // SAFETY: Masking the interrupts of the hart doesn't affect memory safety. The asm
+        // block isn't `nomem`, so memory accesses aren't moved out of the masked section.
+        unsafe {
+            asm!(
+                "csrrci {0}, mstatus, 8",
+                out(reg) mstatus,
+                options(nostack, preserves_flags)
+            )
+        };
+        mstatus & MSTATUS_MIE != 0
+    }
+
+    /// Sets `mstatus.MIE`.
+    pub fn enable() {
+        // SAFETY: See `disable`.
+        unsafe { asm!("csrsi mstatus, 8", options(nostack, preserves_flags)) };
+    }
+}
+
+#[cfg(all(
+    feature = "riscv_machine_mode",
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+unsafe impl RawMutex for MachineModeLock {
+    const INIT: MachineModeLock = MachineModeLock {
+        locked: AtomicBool::new(false),
+        mie: AtomicBool::new(false),
+    };
+
+    // the interrupts must be restored on the hart that masked them
+    type GuardMarker = GuardNoSend;
+
+    fn lock(&self) {
+        while !self.try_lock() {
+            hint::spin_loop();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let mie = mie::disable();
+        let locked = self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if locked {
+            self.mie.store(mie, Ordering::Relaxed);
+        } else if mie {
+            mie::enable();
+        }
+        locked
+    }
+
+    unsafe fn unlock(&self) {
+        let mie = self.mie.load(Ordering::Relaxed);
+        self.locked.store(false, Ordering::Release);
+        if mie {
+            mie::enable();
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+/// A lock that holds a critical section of the [`critical_section`] crate, so that the heap
+/// can be used from interrupt handlers on any target with a critical section
+/// implementation, e.g. the one of the `riscv` or `cortex-m` crate.
+///
+/// The critical section is acquired when the lock is taken and released with it. Since it
+/// already excludes all other contexts, taking the lock again while it is held can only
+/// happen within the same context, e.g. when a [`with_heap`][crate::LockedHeap::with_heap]
+/// closure allocates, so this panics instead of deadlocking.
+#[cfg(feature = "critical_section")]
+pub struct CriticalSectionLock {
+    locked: AtomicBool,
+    /// The state to restore when the critical section is released.
+    restore: UnsafeCell<critical_section::RestoreState>,
+}
+
+// SAFETY: `restore` is only accessed within the critical section.
+#[cfg(feature = "critical_section")]
+unsafe impl Sync for CriticalSectionLock {}
+
+#[cfg(feature = "critical_section")]
+unsafe impl RawMutex for CriticalSectionLock {
+    const INIT: CriticalSectionLock = CriticalSectionLock {
+        locked: AtomicBool::new(false),
+        restore: UnsafeCell::new(critical_section::RestoreState::invalid()),
+    };
+
+    // the critical section must be released in the context that acquired it
+    type GuardMarker = GuardNoSend;
+
+    fn lock(&self) {
+        if !self.try_lock() {
+            panic!(
+                "deadlock: the heap lock is already held within the critical section, \
+                 e.g. a `with_heap` closure allocates from the same heap"
+            );
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        // SAFETY: The critical section is released in `unlock` or right away.
+        let restore = unsafe { critical_section::acquire() };
+        if self.locked.load(Ordering::Relaxed) {
+            unsafe { critical_section::release(restore) };
+            return false;
+        }
+        self.locked.store(true, Ordering::Relaxed);
+        unsafe { *self.restore.get() = restore };
+        true
+    }
+
+    unsafe fn unlock(&self) {
+        let restore = *self.restore.get();
+        self.locked.store(false, Ordering::Relaxed);
+        critical_section::release(restore);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(loom)]
+mod model {
+    use core::marker::PhantomData;
+    use core::ops::{Deref, DerefMut};
+    use loom::cell::{MutPtr, UnsafeCell};
+    use loom::sync::atomic::{AtomicBool, Ordering};
+
+    /// A spinlock with the same interface as `lock_api::Mutex`, but built on the primitives
+    /// of `loom`.
+    pub struct Mutex<R, T> {
+        locked: AtomicBool,
+        data: UnsafeCell<T>,
+        raw: PhantomData<R>,
+    }
+
+    unsafe impl<R, T: Send> Send for Mutex<R, T> {}
+    unsafe impl<R, T: Send> Sync for Mutex<R, T> {}
+
+    impl<R, T> Mutex<R, T> {
+        pub fn new(data: T) -> Self {
+            Mutex {
+                locked: AtomicBool::new(false),
+                data: UnsafeCell::new(data),
+                raw: PhantomData,
+            }
+        }
+
+        pub fn lock(&self) -> SpinlockGuard<'_, R, T> {
+            loop {
+                if let Some(guard) = self.try_lock() {
+                    return guard;
+                }
+                loom::thread::yield_now();
+            }
+        }
+
+        pub fn try_lock(&self) -> Option<SpinlockGuard<'_, R, T>> {
+            self.locked
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .ok()
+                .map(|_| SpinlockGuard {
+                    lock: self,
+                    data: self.data.get_mut(),
+                })
+        }
+
+        pub fn is_locked(&self) -> bool {
+            self.locked.load(Ordering::Relaxed)
+        }
+    }
+
+    pub struct SpinlockGuard<'a, R, T> {
+        lock: &'a Mutex<R, T>,
+        data: MutPtr<T>,
+    }
+
+    impl<'a, R, T> Deref for SpinlockGuard<'a, R, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            // safety: the lock is held, so there are no other references to the data
+            unsafe { self.data.deref() }
+        }
+    }
+
+    impl<'a, R, T> DerefMut for SpinlockGuard<'a, R, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            // safety: the lock is held, so there are no other references to the data
+            unsafe { self.data.deref() }
+        }
+    }
+
+    impl<'a, R, T> Drop for SpinlockGuard<'a, R, T> {
+        fn drop(&mut self) {
+            self.lock.locked.store(false, Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Support for memory tagging, e.g. with the Memory Tagging Extension (MTE) of ARMv8.5-A.
+//!
+//! A [`MemoryTagger`] is installed with [`Heap::set_tagger`][crate::Heap::set_tagger]. The
+//! heap then rounds every allocation up to whole tag granules, lets the tagger assign a
+//! fresh tag to it and hands out the tagged pointer. When the allocation is freed, the
+//! tagger resets the tag of its memory before the block becomes free again, so that stale
+//! pointers to it no longer match.
+
+use core::ptr::NonNull;
+
+/// Assigns memory tags to allocations.
+///
+/// The heap itself accesses its metadata through untagged pointers, so freed memory must be
+/// reset to the tag that untagged pointers carry.
+///
+/// # Safety
+///
+/// `tag` must return a pointer to the same address that accesses the given memory range
+/// without faulting, and `untag` must make the range accessible through the returned
+/// untagged pointer again. Both must only touch the tags of the given range.
+pub unsafe trait MemoryTagger: Sync {
+    /// Returns the size of a tag granule in bytes, which must be a power of two.
+    ///
+    /// Allocations are aligned to and padded to a multiple of this size, so that no
+    /// granule is shared between two allocations.
+    fn granule_size(&self) -> usize;
+
+    /// Assigns a fresh tag to the `size` bytes at `ptr` and returns the tagged pointer.
+    ///
+    /// # Safety
+    ///
+    /// The range must be a newly allocated block that is aligned to the granule size.
+    unsafe fn tag(&self, ptr: NonNull<u8>, size: usize) -> NonNull<u8>;
+
+    /// Resets the tag of the `size` bytes at the tagged `ptr` and returns the untagged
+    /// pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` and `size` must be a pointer returned by [`tag`][MemoryTagger::tag] and the
+    /// size it was called with.
+    unsafe fn untag(&self, ptr: NonNull<u8>, size: usize) -> NonNull<u8>;
+
+    /// Returns `ptr` without its tag, without changing the tags of any memory.
+    ///
+    /// The default implementation returns `ptr` unchanged, which is correct for taggers
+    /// that don't encode the tag in the pointer.
+    fn strip_tag(&self, ptr: NonNull<u8>) -> NonNull<u8> {
+        ptr
+    }
+}
+
+/// A [`MemoryTagger`] for the Memory Tagging Extension of ARMv8.5-A.
+///
+/// Allocations get a random tag from the `irg` instruction and freed allocations are reset
+/// to tag 0, so that use-after-free accesses through the old pointer fault. The heap memory
+/// must be mapped as tagged memory (e.g. with `PROT_MTE`) and tag checking must be enabled
+/// for the running thread.
+#[cfg(all(feature = "mte", target_arch = "aarch64"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mte;
+
+#[cfg(all(feature = "mte", target_arch = "aarch64"))]
+mod mte {
+    use super::{MemoryTagger, Mte};
+    use core::arch::asm;
+    use core::ptr::NonNull;
+
+    const GRANULE: usize = 16;
+    /// The bits of an address that hold the logical tag.
+    const TAG_MASK: usize = 0xf << 56;
+
+    /// Sets the allocation tag of the granules in `[ptr, ptr + size)` to the tag of `ptr`.
+    unsafe fn set_tags(ptr: *mut u8, size: usize) {
+        let mut offset = 0;
+        while offset < size {
+            asm!(
+                ".arch_extension memtag",
+                "stg {0}, [{0}]",
+                in(reg) ptr.wrapping_add(offset),
+                options(nostack, preserves_flags)
+            );
+            offset += GRANULE;
+        }
+    }
+
+    unsafe impl MemoryTagger for Mte {
+        fn granule_size(&self) -> usize {
+            GRANULE
+        }
+
+        unsafe fn tag(&self, ptr: NonNull<u8>, size: usize) -> NonNull<u8> {
+            let tagged: *mut u8;
+            asm!(
+                ".arch_extension memtag",
+                "irg {tagged}, {ptr}",
+                tagged = out(reg) tagged,
+                ptr = in(reg) ptr.as_ptr(),
+                options(nomem, nostack, preserves_flags)
+            );
+            set_tags(tagged, size);
+            NonNull::new_unchecked(tagged)
+        }
+
+        unsafe fn untag(&self, ptr: NonNull<u8>, size: usize) -> NonNull<u8> {
+            let untagged = self.strip_tag(ptr);
+            set_tags(untagged.as_ptr(), size);
+            untagged
+        }
+
+        fn strip_tag(&self, ptr: NonNull<u8>) -> NonNull<u8> {
+            let ptr = ptr.as_ptr();
+            // clear the tag by offsetting the pointer, which keeps its provenance
+            unsafe { NonNull::new_unchecked(ptr.wrapping_sub(ptr as usize & TAG_MASK)) }
+        }
+    }
+}
+
+// the sizes of the tagged granules depend on the size of a free block
+#[cfg(all(test, not(feature = "redundant")))]
+mod test {
+    use super::MemoryTagger;
+    use crate::test::new_heap;
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::vec::Vec;
+
+    /// Counts the tagged bytes without changing any pointers.
+    struct CountingTagger {
+        tagged: AtomicUsize,
+    }
+
+    unsafe impl MemoryTagger for CountingTagger {
+        fn granule_size(&self) -> usize {
+            16
+        }
+
+        unsafe fn tag(&self, ptr: NonNull<u8>, size: usize) -> NonNull<u8> {
+            assert_eq!(ptr.as_ptr().align_offset(16), 0);
+            assert_eq!(size % 16, 0);
+            self.tagged.fetch_add(size, Ordering::Relaxed);
+            ptr
+        }
+
+        unsafe fn untag(&self, ptr: NonNull<u8>, size: usize) -> NonNull<u8> {
+            self.tagged.fetch_sub(size, Ordering::Relaxed);
+            ptr
+        }
+    }
+
+    #[test]
+    fn tags_whole_granules() {
+        static TAGGER: CountingTagger = CountingTagger {
+            tagged: AtomicUsize::new(0),
+        };
+        let mut heap = new_heap();
+        unsafe { heap.set_tagger(Some(&TAGGER)) };
+
+        let layouts = [
+            Layout::from_size_align(1, 1).unwrap(),
+            Layout::from_size_align(24, 8).unwrap(),
+            Layout::from_size_align(40, 64).unwrap(),
+        ];
+        let ptrs: Vec<_> = layouts
+            .iter()
+            .map(|layout| heap.allocate_first_fit(*layout).unwrap())
+            .collect();
+        assert_eq!(TAGGER.tagged.load(Ordering::Relaxed), 16 + 32 + 48);
+        for ptr in &ptrs {
+            assert_eq!(ptr.as_ptr().align_offset(16), 0);
+        }
+
+        // allocations aren't enlarged, since the rest of the block might share a granule
+        // with the next block
+        let (ptr, size) = heap.allocate_within(16..=64, 8).unwrap();
+        assert_eq!(size, 16);
+        unsafe { heap.deallocate(ptr, Layout::from_size_align(size, 8).unwrap()) };
+
+        for (ptr, layout) in ptrs.iter().zip(layouts) {
+            unsafe { heap.deallocate(*ptr, layout) };
+        }
+        assert_eq!(TAGGER.tagged.load(Ordering::Relaxed), 0);
+        assert_eq!(heap.used(), 0);
+    }
+}
@@ -9,7 +9,7 @@ use std::{
 };
 
 #[repr(align(128))]
-struct Chonk<const N: usize> {
+pub struct Chonk<const N: usize> {
     data: MaybeUninit<[u8; N]>,
 }
 
@@ -102,6 +102,7 @@ fn new_max_heap() -> OwnedHeap<2048> {
     }
 }
 
+#[cfg(not(any(feature = "headers", feature = "checksum")))]
 fn new_heap_skip(ct: usize) -> OwnedHeap<1000> {
     const HEAP_SIZE: usize = 1000;
     let (heap_space_ptr, data_ptr) = Chonk::<HEAP_SIZE>::new();
@@ -117,7 +118,50 @@ fn new_heap_skip(ct: usize) -> OwnedHeap<1000> {
 fn empty() {
     let mut heap = Heap::empty();
     let layout = Layout::from_size_align(1, 1).unwrap();
-    assert!(heap.allocate_first_fit(layout.clone()).is_err());
+    assert!(heap.allocate_first_fit(layout).is_err());
+}
+
+#[test]
+fn checked_align_helpers() {
+    assert_eq!(checked_align_down_size(17, 8), Some(16));
+    assert_eq!(checked_align_down_size(17, 6), None);
+    assert_eq!(checked_align_up_size(17, 8), Some(24));
+    assert_eq!(checked_align_up_size(17, 0), None);
+    assert_eq!(checked_align_up_size(usize::MAX - 3, 8), None);
+
+    let ptr = core::ptr::null_mut::<u8>().wrapping_add(0x1001);
+    assert_eq!(checked_align_up(ptr, 16), Some(ptr.wrapping_add(15)));
+    assert_eq!(checked_align_up(ptr, 3), None);
+    let last = core::ptr::null_mut::<u8>().wrapping_sub(1);
+    assert_eq!(checked_align_up(last, 16), None);
+}
+
+#[test]
+fn zero_sized() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(0, 64).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_zeroed(layout).unwrap();
+    let slice = heap.allocate_first_fit_slice(layout).unwrap();
+    assert_eq!(a.as_ptr() as usize, 64);
+    assert_eq!(b, a);
+    assert_eq!(slice.len(), 0);
+    assert!(!heap.contains(a));
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.holes.holes().count(), 1);
+
+    // even an empty heap can serve them
+    assert!(Heap::empty().allocate_first_fit(layout).is_ok());
+
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(b, layout);
+        heap.deallocate_batch(&mut [(a, layout)]);
+    }
+    let stats = heap.stats();
+    assert_eq!(stats.allocations, 3);
+    assert_eq!(stats.deallocations, 3);
+    assert_eq!(heap.used(), 0);
 }
 
 #[test]
@@ -138,6 +182,492 @@ fn oom() {
 }
 
 #[test]
+fn allocate_huge_layouts() {
+    let mut heap = new_heap();
+    let max_align = 1 << (usize::BITS - 2);
+    let layouts = [
+        Layout::from_size_align(1, max_align).unwrap(),
+        Layout::from_size_align(isize::MAX as usize - 15, 8).unwrap(),
+        Layout::from_size_align(isize::MAX as usize, 1).unwrap(),
+    ];
+    for layout in layouts {
+        assert!(heap.allocate_first_fit(layout).is_err());
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.stats().failed_allocations, layouts.len());
+}
+
+#[test]
+#[cfg(not(all(feature = "call_sites", feature = "timestamps")))]
+fn alloc_errors() {
+    let mut heap = new_heap();
+    let too_large = Layout::from_size_align(heap.size() + 1, 8).unwrap();
+    assert_eq!(
+        heap.allocate_first_fit(too_large),
+        Err(AllocError::OutOfMemory)
+    );
+    let invalid = Layout::from_size_align(isize::MAX as usize, 1).unwrap();
+    assert_eq!(
+        heap.allocate_first_fit(invalid),
+        Err(AllocError::InvalidLayout)
+    );
+
+    let a = Layout::from_size_align(300, 8).unwrap();
+    let b = Layout::from_size_align(100, 8).unwrap();
+    let a_ptr = heap.allocate_first_fit(a).unwrap();
+    let _b_ptr = heap.allocate_first_fit(b).unwrap();
+    unsafe { heap.deallocate(a_ptr, a) };
+    let fragmented = Layout::from_size_align(800, 8).unwrap();
+    assert_eq!(
+        heap.allocate_first_fit(fragmented),
+        Err(AllocError::Fragmented {
+            largest_hole: heap.stats().largest_hole
+        })
+    );
+}
+
+#[test]
+#[cfg(not(any(
+    all(feature = "headers", feature = "redundant"),
+    any(feature = "call_sites", feature = "timestamps")
+)))]
+fn largest_allocation() {
+    let mut heap = new_heap();
+    assert_eq!(heap.largest_allocation(3), None);
+
+    // fragment the heap
+    let layout = Layout::from_size_align(200, 8).unwrap();
+    let ptrs: Vec<_> = (0..4)
+        .map(|_| heap.allocate_first_fit(layout).unwrap())
+        .collect();
+    unsafe {
+        heap.deallocate(ptrs[0], layout);
+        heap.deallocate(ptrs[2], layout);
+    }
+
+    for align in [1, 8, 64, 256] {
+        let size = match heap.largest_allocation(align) {
+            Some(size) => size,
+            None => {
+                let smallest = Layout::from_size_align(1, align).unwrap();
+                assert!(heap.allocate_first_fit(smallest).is_err());
+                continue;
+            }
+        };
+        let largest = Layout::from_size_align(size, align).unwrap();
+        let ptr = heap.allocate_first_fit(largest).unwrap();
+        unsafe { heap.deallocate(ptr, largest) };
+
+        let too_large = Layout::from_size_align(size + size_of::<usize>(), align).unwrap();
+        assert!(heap.allocate_first_fit(too_large).is_err());
+    }
+}
+
+#[test]
+#[cfg(not(all(feature = "call_sites", feature = "timestamps")))]
+fn allocate_largest() {
+    let mut heap = new_heap();
+    assert_eq!(heap.allocate_largest(3), Err(AllocError::InvalidLayout));
+
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let a = heap.allocate_first_fit(small).unwrap();
+    let (ptr, size) = heap.allocate_largest(8).unwrap();
+    assert!(size >= heap.size() - 200);
+    assert_eq!(heap.largest_allocation(1), None);
+    assert_eq!(heap.allocate_largest(1), Err(AllocError::OutOfMemory));
+    unsafe { heap.deallocate(ptr, Layout::from_size_align(size, 8).unwrap()) };
+
+    let (ptr, size) = heap.allocate_largest(64).unwrap();
+    assert_eq!(ptr.as_ptr() as usize % 64, 0);
+    unsafe {
+        heap.deallocate(ptr, Layout::from_size_align(size, 64).unwrap());
+        heap.deallocate(a, small);
+    }
+    assert_eq!(heap.used(), 0);
+}
+
+#[test]
+#[cfg(not(feature = "checksum"))]
+fn allocate_first_fit_slice() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(13, 1).unwrap();
+    let slice = heap.allocate_first_fit_slice(layout).unwrap();
+    assert_eq!(slice.len(), 16);
+    unsafe { slice.as_ptr().cast::<u8>().write_bytes(0xab, slice.len()) };
+
+    let layout_100 = Layout::from_size_align(100, 8).unwrap();
+    let slice_100 = heap.allocate_first_fit_slice(layout_100).unwrap();
+    assert_eq!(slice_100.len(), 104);
+
+    unsafe {
+        let ptr = NonNull::new_unchecked(slice.as_ptr().cast::<u8>());
+        heap.deallocate(ptr, Layout::from_size_align(16, 1).unwrap());
+        let ptr = NonNull::new_unchecked(slice_100.as_ptr().cast::<u8>());
+        heap.deallocate(ptr, layout_100);
+    }
+    assert_eq!(heap.used(), 0);
+}
+
+/// Leaks the chonk as a `'static` slice, which must not be used after `putter` was freed.
+fn leak_slice<const N: usize>(data_ptr: *mut u8) -> &'static mut [MaybeUninit<u8>] {
+    unsafe { core::slice::from_raw_parts_mut(data_ptr.cast(), N) }
+}
+
+// the heap keeps using the pointer to the first slice, which Miri doesn't allow for the
+// memory of the second one
+#[test]
+#[cfg(not(miri))]
+fn extend_from_slice() {
+    const HEAP_SIZE: usize = 1000;
+    let (heap_space_ptr, data_ptr) = Chonk::<HEAP_SIZE>::new();
+    let _drop = Dropper::new(heap_space_ptr);
+    let (lower, upper) = leak_slice::<HEAP_SIZE>(data_ptr).split_at_mut(500);
+
+    let mut heap = Heap::from_slice(lower);
+    assert_eq!(heap.size(), 496);
+    heap.extend_from_slice(upper);
+    assert_eq!(heap.size(), HEAP_SIZE);
+
+    let layout = Layout::from_size_align(600, 8).unwrap();
+    let ptr = heap.allocate_first_fit(layout).unwrap();
+    unsafe { heap.deallocate(ptr, layout) };
+}
+
+#[test]
+#[should_panic(expected = "does not directly follow")]
+fn extend_from_slice_not_adjacent() {
+    const HEAP_SIZE: usize = 1000;
+    let (heap_space_ptr, data_ptr) = Chonk::<HEAP_SIZE>::new();
+    let _drop = Dropper::new(heap_space_ptr);
+    let (lower, upper) = leak_slice::<HEAP_SIZE>(data_ptr).split_at_mut(HEAP_SIZE / 2);
+
+    let mut heap = Heap::from_slice(&mut lower[..HEAP_SIZE / 4]);
+    heap.extend_from_slice(upper);
+}
+
+#[test]
+fn allocate_zeroed() {
+    const HEAP_SIZE: usize = 1000;
+    let (heap_space_ptr, data_ptr) = Chonk::<HEAP_SIZE>::new();
+    let _drop = Dropper::new(heap_space_ptr);
+    let mut heap = Heap::empty();
+    unsafe {
+        data_ptr.write_bytes(0, HEAP_SIZE);
+        heap.init_zeroed(data_ptr, HEAP_SIZE / 2);
+    }
+    let is_zeroed = |ptr: NonNull<u8>, size: usize| unsafe {
+        core::slice::from_raw_parts(ptr.as_ptr(), size)
+            .iter()
+            .all(|&byte| byte == 0)
+    };
+
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let a = heap.allocate_zeroed(layout).unwrap();
+    let b = heap.allocate_zeroed(layout).unwrap();
+    assert!(is_zeroed(a, 100) && is_zeroed(b, 100));
+    unsafe {
+        a.as_ptr().write_bytes(0xab, 100);
+        b.as_ptr().write_bytes(0xab, 100);
+        heap.deallocate(a, layout);
+    }
+
+    // reused memory is zeroed explicitly
+    let a = heap.allocate_zeroed(layout).unwrap();
+    assert!(is_zeroed(a, 100));
+    unsafe { a.as_ptr().write_bytes(0xab, 100) };
+
+    // freeing the block in front of the untouched memory merges it with the hole there
+    unsafe { heap.deallocate(b, layout) };
+    let layout_200 = Layout::from_size_align(200, 8).unwrap();
+    let c = heap.allocate_zeroed(layout_200).unwrap();
+    assert!(is_zeroed(c, 200));
+    unsafe { c.as_ptr().write_bytes(0xab, 200) };
+
+    unsafe { heap.extend_zeroed(HEAP_SIZE / 2) };
+    let d = heap.allocate_zeroed(layout_200).unwrap();
+    assert!(is_zeroed(d, 200));
+    unsafe { d.as_ptr().write_bytes(0xab, 200) };
+
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(c, layout_200);
+        heap.deallocate(d, layout_200);
+    }
+    let all = Layout::from_size_align(heap.free() / 2, 8).unwrap();
+    let e = heap.allocate_zeroed(all).unwrap();
+    assert!(is_zeroed(e, all.size()));
+}
+
+#[test]
+#[cfg(feature = "zeroize_on_free")]
+fn zeroize_on_free() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    unsafe {
+        a.as_ptr().write_bytes(0xab, 100);
+        b.as_ptr().write_bytes(0xab, 100);
+        heap.deallocate(b, layout);
+    }
+
+    // the start of the freed block now holds the header of a hole
+    let skip = HoleList::min_size();
+    let contents = unsafe { core::slice::from_raw_parts(b.as_ptr().add(skip), 100 - skip) };
+    assert!(contents.iter().all(|&byte| byte == 0));
+    unsafe { heap.deallocate(a, layout) };
+}
+
+#[test]
+#[cfg(all(feature = "asan", not(miri)))]
+fn asan_poisons_free_memory() {
+    extern "C" {
+        fn __asan_address_is_poisoned(addr: *const u8) -> i32;
+    }
+    let is_poisoned = |ptr: *const u8| unsafe { __asan_address_is_poisoned(ptr) != 0 };
+
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    assert!(!is_poisoned(a.as_ptr()) && !is_poisoned(a.as_ptr().wrapping_add(99)));
+    // only the header of the remaining hole is accessible
+    let (hole, _) = heap.holes.holes().next().unwrap();
+    assert!(!is_poisoned(hole));
+    assert!(is_poisoned(hole.wrapping_add(HoleList::min_size())));
+
+    unsafe { heap.deallocate(a, layout) };
+    assert!(is_poisoned(a.as_ptr().wrapping_add(HoleList::min_size())));
+    unsafe { heap.deallocate(b, layout) };
+    // the merged holes leave only the header of the first one accessible
+    assert!(is_poisoned(b.as_ptr()));
+}
+
+#[test]
+#[cfg(feature = "safe_linking")]
+fn safe_linking() {
+    let mut heap = new_heap();
+    heap.set_link_key(0x5eed_1234);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    unsafe { heap.deallocate(a, layout) };
+
+    // the link from the first hole to the second one is encoded
+    let first = heap.holes.holes().next().unwrap().0;
+    let second = heap.holes.holes().nth(1).unwrap().0;
+    let link = unsafe { first.cast::<usize>().add(1).read() };
+    assert_ne!(link, second as usize);
+
+    // re-encoding the links keeps the list intact
+    heap.set_link_key(0xfeed_5678);
+    assert_ne!(unsafe { first.cast::<usize>().add(1).read() }, link);
+    assert_eq!(heap.holes.holes().nth(1).unwrap().0, second);
+
+    unsafe { heap.deallocate(b, layout) };
+    heap.holes.check_invariants();
+}
+
+// the `redundant` feature restores the overwritten link from the mirror
+#[test]
+#[cfg(all(feature = "safe_linking", not(feature = "redundant")))]
+#[should_panic(expected = "heap corruption detected")]
+fn safe_linking_detects_forged_link() {
+    let mut heap = new_heap();
+    heap.set_link_key(0x5eed_1234);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let _b = heap.allocate_first_fit(layout).unwrap();
+    unsafe { heap.deallocate(a, layout) };
+
+    // overwrite the link with a plain pointer, as a use-after-free write would
+    let first = heap.holes.holes().next().unwrap().0;
+    let second = heap.holes.holes().nth(1).unwrap().0;
+    unsafe { first.cast::<usize>().add(1).write(second as usize) };
+    let _ = heap.allocate_first_fit(Layout::from_size_align(128, 8).unwrap());
+}
+
+#[test]
+#[cfg(all(feature = "checksum", feature = "hooks", not(feature = "redundant")))]
+fn checksum_detects_corruption() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static CORRUPTED: AtomicUsize = AtomicUsize::new(0);
+    struct Reporter;
+    impl HeapHooks for Reporter {
+        fn on_corruption(&self, addr: NonNull<u8>) {
+            CORRUPTED.store(addr.as_ptr() as usize, Ordering::Relaxed);
+        }
+    }
+
+    let mut heap = new_heap();
+    heap.set_link_key(0x5eed_1234);
+    heap.set_hooks(Some(&Reporter));
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let _b = heap.allocate_first_fit(layout).unwrap();
+    unsafe { heap.deallocate(a, layout) };
+    heap.holes.check_invariants();
+
+    // flip a bit in the size of the first hole, as a faulty RAM cell would
+    let first = heap.holes.holes().next().unwrap().0;
+    unsafe { *first.cast::<usize>() ^= 1 << 4 };
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        heap.allocate_first_fit(Layout::from_size_align(128, 8).unwrap())
+    }));
+    assert!(result.is_err());
+    assert_eq!(CORRUPTED.load(Ordering::Relaxed), first as usize);
+}
+
+#[test]
+#[cfg(all(feature = "redundant", feature = "hooks"))]
+fn redundant_repairs_bit_flips() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static REPAIRED: AtomicUsize = AtomicUsize::new(0);
+    struct Reporter;
+    impl HeapHooks for Reporter {
+        fn on_repair(&self, _addr: NonNull<u8>) {
+            REPAIRED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let mut heap = new_heap();
+    heap.set_link_key(0x5eed_1234);
+    heap.set_hooks(Some(&Reporter));
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    let c = heap.allocate_first_fit(layout).unwrap();
+    let _d = heap.allocate_first_fit(layout).unwrap();
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(c, layout);
+    }
+    let free = heap.holes.check_invariants();
+
+    // flip a bit in the size, the link and the checksum of the three holes
+    let holes: Vec<_> = heap.holes.holes().map(|(addr, _)| addr).collect();
+    for (i, hole) in holes.iter().enumerate() {
+        unsafe { *hole.cast::<usize>().add(i) ^= 1 << 4 };
+    }
+    assert_eq!(heap.scrub(), 3);
+    assert_eq!(REPAIRED.load(Ordering::Relaxed), 3);
+    assert_eq!(heap.holes.check_invariants(), free);
+
+    // a flip in the mirror is repaired by scrubbing, and one in the header on the next walk
+    let (last, size) = heap.holes.holes().last().unwrap();
+    unsafe { *last.add(size - size_of::<usize>()).cast::<usize>() ^= 1 };
+    assert_eq!(heap.scrub(), 1);
+    unsafe { *holes[0].cast::<usize>() ^= 1 << 5 };
+    assert_eq!(heap.holes.check_invariants(), free);
+    assert_eq!(heap.scrub(), 0);
+    assert_eq!(REPAIRED.load(Ordering::Relaxed), 5);
+
+    // the heap still works as before
+    let e = heap.allocate_first_fit(layout).unwrap();
+    assert!(e == a || e == c);
+    unsafe {
+        heap.deallocate(e, layout);
+        heap.deallocate(b, layout);
+    }
+
+    // without an intact copy, the corruption can't be repaired
+    let first = heap.holes.holes().next().unwrap().0;
+    let size = heap.holes.holes().next().unwrap().1;
+    unsafe {
+        *first.cast::<usize>().add(1) ^= 1 << 4;
+        *first.add(size - 2 * size_of::<usize>()).cast::<usize>() ^= 1 << 4;
+    }
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| heap.scrub()));
+    assert!(result.is_err());
+}
+
+#[test]
+#[cfg(not(all(feature = "call_sites", feature = "timestamps")))]
+fn allocate_within() {
+    let mut heap = new_heap();
+    assert_eq!(
+        heap.allocate_within(core::ops::RangeInclusive::new(200, 100), 8),
+        Err(AllocError::InvalidLayout)
+    );
+
+    let (a, a_size) = heap.allocate_within(100..=200, 8).unwrap();
+    assert_eq!(a_size, 200);
+    let (b, b_size) = heap.allocate_within(16..=usize::MAX, 8).unwrap();
+    assert!(b_size >= heap.size() - 300);
+    assert!(heap.allocate_within(1..=8, 1).is_err());
+
+    unsafe {
+        heap.deallocate(a, Layout::from_size_align(a_size, 8).unwrap());
+        heap.deallocate(b, Layout::from_size_align(b_size, 8).unwrap());
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.stats().allocations, 2);
+}
+
+#[test]
+fn split_off() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let used = heap.used();
+    let top = heap.top();
+
+    // the split point lies within the allocation
+    assert!(heap.split_off(48).is_none());
+    assert!(heap.split_off(3).is_none());
+    let size = heap.size();
+    assert!(heap.split_off(size).is_none());
+    assert_eq!(heap.used(), used);
+
+    let mut upper = heap.split_off(496).unwrap();
+    assert_eq!(heap.top(), heap.bottom().wrapping_add(496));
+    assert_eq!(upper.bottom(), heap.top());
+    assert_eq!(upper.top(), top);
+    assert_eq!(heap.used(), used);
+    assert_eq!(upper.used(), 0);
+    heap.holes.check_invariants();
+    upper.holes.check_invariants();
+
+    let b = upper.allocate_first_fit(layout).unwrap();
+    assert!(b.as_ptr() >= upper.bottom());
+    unsafe {
+        upper.deallocate(b, layout);
+        heap.deallocate(a, layout);
+    }
+    assert_eq!(heap.free(), heap.size());
+    assert_eq!(upper.free(), upper.size());
+}
+
+#[test]
+fn split_off_with_allocations_above() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptrs: Vec<_> = (0..6)
+        .map(|_| heap.allocate_first_fit(layout).unwrap())
+        .collect();
+
+    // split at the start of the hole that was left by the third allocation
+    unsafe { heap.deallocate(ptrs[2], layout) };
+    let used = heap.used();
+    let (hole, _) = heap.holes.holes().next().unwrap();
+    let at = unsafe { hole.offset_from(heap.bottom()) as usize };
+    let mut upper = heap.split_off(at).unwrap();
+    assert_eq!(heap.used() + upper.used(), used);
+    for ptr in &ptrs[3..] {
+        unsafe { upper.deallocate(*ptr, layout) };
+    }
+    for ptr in &ptrs[..2] {
+        unsafe { heap.deallocate(*ptr, layout) };
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(upper.used(), 0);
+    upper.holes.check_invariants();
+}
+
+#[test]
+#[cfg(not(any(feature = "headers", feature = "checksum")))]
 fn allocate_double_usize() {
     let mut heap = new_heap();
     let size = size_of::<usize>() * 2;
@@ -163,77 +693,80 @@ fn allocate_and_free_double_usize() {
     let mut heap = new_heap();
 
     let layout = Layout::from_size_align(size_of::<usize>() * 2, align_of::<usize>()).unwrap();
-    let x = heap.allocate_first_fit(layout.clone()).unwrap();
+    let x = heap.allocate_first_fit(layout).unwrap();
     unsafe {
         *(x.as_ptr() as *mut (usize, usize)) = (0xdeafdeadbeafbabe, 0xdeafdeadbeafbabe);
 
-        heap.deallocate(x, layout.clone());
-        let real_first = heap.holes.first.next.as_ref().unwrap().as_ref();
+        heap.deallocate(x, layout);
+        let real_first = heap.holes.first.next(heap.holes.key).unwrap().as_ref();
 
         assert_eq!(real_first.size, heap.size());
-        assert!(real_first.next.is_none());
+        assert!(real_first.next(heap.holes.key).is_none());
     }
 }
 
 #[test]
+#[cfg(not(any(feature = "headers", feature = "redundant")))]
 fn deallocate_right_before() {
     let mut heap = new_heap();
     let layout = Layout::from_size_align(size_of::<usize>() * 5, 1).unwrap();
 
-    let x = heap.allocate_first_fit(layout.clone()).unwrap();
-    let y = heap.allocate_first_fit(layout.clone()).unwrap();
-    let z = heap.allocate_first_fit(layout.clone()).unwrap();
+    let x = heap.allocate_first_fit(layout).unwrap();
+    let y = heap.allocate_first_fit(layout).unwrap();
+    let z = heap.allocate_first_fit(layout).unwrap();
 
     unsafe {
-        heap.deallocate(y, layout.clone());
+        heap.deallocate(y, layout);
         assert_eq!((*(y.as_ptr() as *const Hole)).size, layout.size());
-        heap.deallocate(x, layout.clone());
+        heap.deallocate(x, layout);
         assert_eq!((*(x.as_ptr() as *const Hole)).size, layout.size() * 2);
-        heap.deallocate(z, layout.clone());
+        heap.deallocate(z, layout);
         assert_eq!((*(x.as_ptr() as *const Hole)).size, heap.size());
     }
 }
 
 #[test]
+#[cfg(not(any(feature = "headers", feature = "redundant")))]
 fn deallocate_right_behind() {
     let mut heap = new_heap();
     let size = size_of::<usize>() * 5;
     let layout = Layout::from_size_align(size, 1).unwrap();
 
-    let x = heap.allocate_first_fit(layout.clone()).unwrap();
-    let y = heap.allocate_first_fit(layout.clone()).unwrap();
-    let z = heap.allocate_first_fit(layout.clone()).unwrap();
+    let x = heap.allocate_first_fit(layout).unwrap();
+    let y = heap.allocate_first_fit(layout).unwrap();
+    let z = heap.allocate_first_fit(layout).unwrap();
 
     unsafe {
-        heap.deallocate(x, layout.clone());
+        heap.deallocate(x, layout);
         assert_eq!((*(x.as_ptr() as *const Hole)).size, size);
-        heap.deallocate(y, layout.clone());
+        heap.deallocate(y, layout);
         assert_eq!((*(x.as_ptr() as *const Hole)).size, size * 2);
-        heap.deallocate(z, layout.clone());
+        heap.deallocate(z, layout);
         assert_eq!((*(x.as_ptr() as *const Hole)).size, heap.size());
     }
 }
 
 #[test]
+#[cfg(not(any(feature = "headers", feature = "redundant")))]
 fn deallocate_middle() {
     let mut heap = new_heap();
     let size = size_of::<usize>() * 5;
     let layout = Layout::from_size_align(size, 1).unwrap();
 
-    let x = heap.allocate_first_fit(layout.clone()).unwrap();
-    let y = heap.allocate_first_fit(layout.clone()).unwrap();
-    let z = heap.allocate_first_fit(layout.clone()).unwrap();
-    let a = heap.allocate_first_fit(layout.clone()).unwrap();
+    let x = heap.allocate_first_fit(layout).unwrap();
+    let y = heap.allocate_first_fit(layout).unwrap();
+    let z = heap.allocate_first_fit(layout).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
 
     unsafe {
-        heap.deallocate(x, layout.clone());
+        heap.deallocate(x, layout);
         assert_eq!((*(x.as_ptr() as *const Hole)).size, size);
-        heap.deallocate(z, layout.clone());
+        heap.deallocate(z, layout);
         assert_eq!((*(x.as_ptr() as *const Hole)).size, size);
         assert_eq!((*(z.as_ptr() as *const Hole)).size, size);
-        heap.deallocate(y, layout.clone());
+        heap.deallocate(y, layout);
         assert_eq!((*(x.as_ptr() as *const Hole)).size, size * 3);
-        heap.deallocate(a, layout.clone());
+        heap.deallocate(a, layout);
         assert_eq!((*(x.as_ptr() as *const Hole)).size, heap.size());
     }
 }
@@ -244,20 +777,21 @@ fn reallocate_double_usize() {
 
     let layout = Layout::from_size_align(size_of::<usize>() * 2, align_of::<usize>()).unwrap();
 
-    let x = heap.allocate_first_fit(layout.clone()).unwrap();
+    let x = heap.allocate_first_fit(layout).unwrap();
     unsafe {
-        heap.deallocate(x, layout.clone());
+        heap.deallocate(x, layout);
     }
 
-    let y = heap.allocate_first_fit(layout.clone()).unwrap();
+    let y = heap.allocate_first_fit(layout).unwrap();
     unsafe {
-        heap.deallocate(y, layout.clone());
+        heap.deallocate(y, layout);
     }
 
     assert_eq!(x, y);
 }
 
 #[test]
+#[cfg(not(feature = "headers"))]
 fn allocate_many_size_aligns() {
     use core::ops::{Range, RangeInclusive};
 
@@ -380,7 +914,7 @@ fn allocate_many_size_aligns() {
                             heap.holes.debug();
                         });
                     }
-                    _ => panic!(),
+                    _ => unreachable!(),
                 }
 
                 #[cfg(not(miri))]
@@ -399,6 +933,7 @@ fn allocate_many_size_aligns() {
 }
 
 #[test]
+#[cfg(not(any(feature = "headers", feature = "checksum")))]
 fn allocate_multiple_sizes() {
     let mut heap = new_heap();
     let base_size = size_of::<usize>();
@@ -409,18 +944,18 @@ fn allocate_multiple_sizes() {
     let layout_3 = Layout::from_size_align(base_size * 3, base_align * 4).unwrap();
     let layout_4 = Layout::from_size_align(base_size * 4, base_align).unwrap();
 
-    let x = heap.allocate_first_fit(layout_1.clone()).unwrap();
-    let y = heap.allocate_first_fit(layout_2.clone()).unwrap();
+    let x = heap.allocate_first_fit(layout_1).unwrap();
+    let y = heap.allocate_first_fit(layout_2).unwrap();
     assert_eq!(y.as_ptr() as usize, x.as_ptr() as usize + base_size * 2);
-    let z = heap.allocate_first_fit(layout_3.clone()).unwrap();
+    let z = heap.allocate_first_fit(layout_3).unwrap();
     assert_eq!(z.as_ptr() as usize % (base_size * 4), 0);
 
     unsafe {
-        heap.deallocate(x, layout_1.clone());
+        heap.deallocate(x, layout_1);
     }
 
-    let a = heap.allocate_first_fit(layout_4.clone()).unwrap();
-    let b = heap.allocate_first_fit(layout_1.clone()).unwrap();
+    let a = heap.allocate_first_fit(layout_4).unwrap();
+    let b = heap.allocate_first_fit(layout_1).unwrap();
     assert_eq!(b, x);
 
     unsafe {
@@ -434,6 +969,7 @@ fn allocate_multiple_sizes() {
 // This test makes sure that the heap works correctly when the input slice has
 // a variety of non-Hole aligned starting addresses
 #[test]
+#[cfg(not(any(feature = "headers", feature = "checksum")))]
 fn allocate_multiple_unaligned() {
     for offset in 0..=Layout::new::<Hole>().size() {
         let mut heap = new_heap_skip(offset);
@@ -445,18 +981,18 @@ fn allocate_multiple_unaligned() {
         let layout_3 = Layout::from_size_align(base_size * 3, base_align * 4).unwrap();
         let layout_4 = Layout::from_size_align(base_size * 4, base_align).unwrap();
 
-        let x = heap.allocate_first_fit(layout_1.clone()).unwrap();
-        let y = heap.allocate_first_fit(layout_2.clone()).unwrap();
+        let x = heap.allocate_first_fit(layout_1).unwrap();
+        let y = heap.allocate_first_fit(layout_2).unwrap();
         assert_eq!(y.as_ptr() as usize, x.as_ptr() as usize + base_size * 2);
-        let z = heap.allocate_first_fit(layout_3.clone()).unwrap();
+        let z = heap.allocate_first_fit(layout_3).unwrap();
         assert_eq!(z.as_ptr() as usize % (base_size * 4), 0);
 
         unsafe {
-            heap.deallocate(x, layout_1.clone());
+            heap.deallocate(x, layout_1);
         }
 
-        let a = heap.allocate_first_fit(layout_4.clone()).unwrap();
-        let b = heap.allocate_first_fit(layout_1.clone()).unwrap();
+        let a = heap.allocate_first_fit(layout_4).unwrap();
+        let b = heap.allocate_first_fit(layout_1).unwrap();
         assert_eq!(b, x);
 
         unsafe {
@@ -468,35 +1004,478 @@ fn allocate_multiple_unaligned() {
     }
 }
 
+#[test]
+#[cfg(not(any(feature = "headers", feature = "checksum")))]
+fn allocate_with_small_front_padding() {
+    let mut heap = new_heap();
+    let word = size_of::<usize>();
+    let small = Layout::from_size_align(3 * word, word).unwrap();
+    let aligned = Layout::from_size_align(8 * word, 4 * word).unwrap();
+
+    // the padding in front of `y` is a single word, which is too small for a hole
+    let x = heap.allocate_first_fit(small).unwrap();
+    let y = heap.allocate_first_fit(aligned).unwrap();
+    assert_eq!(x.as_ptr(), heap.bottom());
+    assert_eq!(y.as_ptr(), heap.bottom().wrapping_add(4 * word));
+    assert_eq!(heap.used(), 12 * word);
+    assert_eq!(heap.holes.check_invariants(), heap.free());
+
+    // the padding is merged into the free memory once both of its neighbors are freed
+    unsafe { heap.deallocate(x, small) };
+    assert_eq!(
+        heap.holes.first_hole(),
+        Some((x.as_ptr() as *const u8, 3 * word))
+    );
+    assert_eq!(heap.used(), 9 * word);
+    unsafe { heap.deallocate(y, aligned) };
+    assert_eq!(heap.used(), 0);
+    assert_eq!(
+        heap.holes.first_hole(),
+        Some((heap.bottom() as *const u8, heap.size()))
+    );
+
+    let x = heap.allocate_first_fit(small).unwrap();
+    let y = heap.allocate_first_fit(aligned).unwrap();
+    unsafe {
+        heap.deallocate(y, aligned);
+        assert_eq!(heap.used(), 4 * word);
+        heap.deallocate(x, small);
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(
+        heap.holes.first_hole(),
+        Some((heap.bottom() as *const u8, heap.size()))
+    );
+}
+
+#[test]
+fn allocate_with_offset() {
+    let mut heap = new_heap();
+    let word = size_of::<usize>();
+    let small = Layout::from_size_align(3 * word, word).unwrap();
+    let layout = Layout::from_size_align(16 * word, 8 * word).unwrap();
+
+    let x = heap.allocate_first_fit(small).unwrap();
+    let y = heap
+        .allocate_first_fit_with_offset(layout, 2 * word)
+        .unwrap();
+    assert_eq!(
+        y.as_ptr().wrapping_add(2 * word) as usize % layout.align(),
+        0
+    );
+    // offsets are taken modulo the alignment
+    let z = heap
+        .allocate_first_fit_with_offset(layout, 10 * word)
+        .unwrap();
+    assert_eq!(
+        z.as_ptr().wrapping_add(2 * word) as usize % layout.align(),
+        0
+    );
+    assert_eq!(
+        heap.allocate_first_fit_with_offset(layout, 1),
+        Err(AllocError::InvalidLayout)
+    );
+
+    unsafe {
+        heap.deallocate(y, layout);
+        heap.deallocate(x, small);
+        heap.deallocate(z, layout);
+    }
+    assert_eq!(heap.used(), 0);
+}
+
+#[test]
+#[cfg(not(any(feature = "headers", feature = "redundant")))]
+fn allocate_with_offset_avoids_padding() {
+    let mut heap = new_heap();
+    let word = size_of::<usize>();
+    let layout = Layout::from_size_align(16 * word, 8 * word).unwrap();
+
+    // the heap bottom is aligned, so the first block is placed `offset` bytes before the
+    // next aligned address
+    let x = heap
+        .allocate_first_fit_with_offset(layout, 2 * word)
+        .unwrap();
+    assert_eq!(x.as_ptr(), heap.bottom().wrapping_add(6 * word));
+    let (hole, _) = heap.holes.first_hole().unwrap();
+    assert_eq!(hole, heap.bottom() as *const u8);
+    unsafe { heap.deallocate(x, layout) };
+    assert_eq!(heap.used(), 0);
+}
+
 #[test]
 fn allocate_usize() {
     let mut heap = new_heap();
 
     let layout = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
 
-    assert!(heap.allocate_first_fit(layout.clone()).is_ok());
+    assert!(heap.allocate_first_fit(layout).is_ok());
+}
+
+#[test]
+fn exact_fit() {
+    let mut heap = new_heap();
+    let size = 2 * HoleList::min_size();
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    unsafe { heap.deallocate(a, layout) };
+    assert_eq!(heap.holes.holes().count(), 2);
+
+    // an allocation that fits exactly takes the whole free block
+    assert_eq!(heap.allocate_first_fit(layout), Ok(a));
+    assert_eq!(heap.holes.holes().count(), 1);
+    unsafe { heap.deallocate(a, layout) };
+
+    // a rest that is too small for a free block is handed out with the allocation if it
+    // ends at an address aligned to the size of a free block
+    let smaller = Layout::from_size_align(size - size_of::<usize>(), 8).unwrap();
+    let (hole_addr, hole_size) = heap.holes.first_hole().unwrap();
+    let used = heap.used();
+    let c = heap.allocate_first_fit(smaller).unwrap();
+    if (hole_addr as usize + hole_size) % HoleList::min_size() == 0 {
+        assert_eq!(c, a);
+        assert_eq!(heap.holes.holes().count(), 1);
+        // the rest is counted as used until it is merged again
+        assert_eq!(heap.used() - used, hole_size);
+    } else {
+        assert!(c > b);
+        assert_eq!(heap.holes.holes().count(), 2);
+    }
+
+    unsafe {
+        heap.deallocate(b, layout);
+        heap.deallocate(c, smaller);
+    }
+    assert_eq!(heap.used(), 0);
+}
+
+#[test]
+fn allocate_first_fit_bounded() {
+    let mut heap = new_heap();
+    let small = Layout::from_size_align(32, 8).unwrap();
+    let ptrs: Vec<_> = (0..4)
+        .map(|_| heap.allocate_first_fit(small).unwrap())
+        .collect();
+    // leaves two small free blocks in front of the large one at the top
+    for ptr in ptrs.iter().step_by(2) {
+        unsafe { heap.deallocate(*ptr, small) };
+    }
+    assert_eq!(heap.holes.holes().count(), 3);
+
+    let large = Layout::from_size_align(200, 8).unwrap();
+    assert_eq!(
+        heap.allocate_first_fit_bounded(large, 0),
+        Err(AllocError::SearchLimit)
+    );
+    assert_eq!(
+        heap.allocate_first_fit_bounded(large, 2),
+        Err(AllocError::SearchLimit)
+    );
+    let ptr = heap.allocate_first_fit_bounded(large, 3).unwrap();
+    assert!(ptr > ptrs[3]);
+    let first = heap.allocate_first_fit_bounded(small, 1).unwrap();
+    assert_eq!(first, ptrs[0]);
+
+    // the search ends early if the list is exhausted
+    let huge = Layout::from_size_align(2000, 8).unwrap();
+    assert_eq!(
+        heap.allocate_first_fit_bounded(huge, 10),
+        Err(AllocError::OutOfMemory)
+    );
+}
+
+#[test]
+fn allocate_near() {
+    let mut heap = new_heap();
+    let free = heap.holes.check_invariants();
+    // the targets are derived from the heap pointers to keep their provenance
+    let (bottom_ptr, top_ptr) = (heap.bottom(), heap.top());
+    let (bottom, top) = (bottom_ptr as usize, top_ptr as usize);
+    let layout = Layout::from_size_align(64, 64).unwrap();
+
+    // the block is taken from the end of the hole
+    let high = heap.allocate_near(top_ptr, layout).unwrap();
+    let addr = high.as_ptr() as usize;
+    assert_eq!(addr % 64, 0);
+    assert!(addr + 64 <= top && top - addr < 192);
+
+    // and from the middle
+    let target = bottom + 500;
+    let middle = heap
+        .allocate_near(bottom_ptr.wrapping_add(500), layout)
+        .unwrap();
+    let addr = middle.as_ptr() as usize;
+    assert_eq!(addr % 64, 0);
+    assert!(addr.abs_diff(target) < 128);
+    heap.holes.check_invariants();
+
+    // a regular allocation still fits in front of them
+    let low = heap.allocate_first_fit(layout).unwrap();
+    assert!(low < middle);
+
+    for ptr in [low, middle, high] {
+        unsafe { heap.deallocate(ptr, layout) };
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.holes.check_invariants(), free);
+    assert_eq!(heap.holes.holes().count(), 1);
+}
+
+#[test]
+fn allocate_near_small_rest() {
+    let mut heap = new_heap();
+    let free = heap.holes.check_invariants();
+    let layout = Layout::from_size_align(48, 16).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    let c = heap.allocate_first_fit(layout).unwrap();
+    let used = heap.used();
+    unsafe { heap.deallocate(b, layout) };
+
+    // a rest behind the smaller block that is too small for a hole is handed out with it if
+    // it ends at an address aligned to the size of a hole
+    let (hole_addr, hole_size) = heap
+        .holes
+        .holes()
+        .find(|&(addr, size)| addr <= b.as_ptr() && b.as_ptr() < addr.wrapping_add(size))
+        .unwrap();
+    let smaller = Layout::from_size_align(40, 16).unwrap();
+    let near = heap.allocate_near(b.as_ptr(), smaller).unwrap();
+    if (hole_addr as usize + hole_size) % size_of::<Hole>() == 0 {
+        assert_eq!(near, b);
+        assert_eq!(heap.used(), used);
+    } else {
+        assert_ne!(near, b);
+    }
+    assert_eq!(heap.holes.check_invariants(), heap.free());
+
+    for (ptr, layout) in [(a, layout), (near, smaller), (c, layout)] {
+        unsafe { heap.deallocate(ptr, layout) };
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.holes.check_invariants(), free);
+}
+
+#[test]
+fn cache_line_padding_and_coloring() {
+    let mut heap = new_max_heap();
+    let free = heap.holes.check_invariants();
+    unsafe { heap.set_cache_line(64, 2) };
+    let layout = Layout::from_size_align(8, 8).unwrap();
+
+    let ptrs: Vec<_> = (0..4)
+        .map(|_| heap.allocate_first_fit(layout).unwrap())
+        .collect();
+    for (i, ptr) in ptrs.iter().enumerate() {
+        let addr = ptr.as_ptr() as usize;
+        assert_eq!(addr % 64, 0);
+        // successive allocations alternate between the two colors
+        assert_eq!(addr / 64 % 2, i % 2);
+    }
+    assert!(heap.used() >= 4 * 64);
+    heap.holes.check_invariants();
+
+    // the payload must start at a cache line
+    assert_eq!(
+        heap.allocate_first_fit_with_offset(Layout::from_size_align(8, 16).unwrap(), 8),
+        Err(AllocError::InvalidLayout)
+    );
+    // and isn't enlarged into the next one
+    let (ptr, size) = heap.allocate_within(8..=512, 8).unwrap();
+    assert_eq!(size, 8);
+    unsafe { heap.deallocate(ptr, Layout::from_size_align(size, 8).unwrap()) };
+
+    for ptr in ptrs {
+        unsafe { heap.deallocate(ptr, layout) };
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.holes.check_invariants(), free);
+    assert_eq!(heap.holes.holes().count(), 1);
+}
+
+#[test]
+fn guard_gap() {
+    let mut heap = new_max_heap();
+    let free = heap.holes.check_invariants();
+    unsafe { heap.set_guard_gap(32) };
+    let layout = Layout::from_size_align(24, 8).unwrap();
+
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    assert!(b.as_ptr() as usize - (a.as_ptr() as usize + layout.size()) >= 32);
+    let (c, size) = heap.allocate_within(8..=512, 8).unwrap();
+    assert_eq!(size, 8);
+    #[cfg(feature = "headers")]
+    assert_eq!(unsafe { heap.allocation_layout(a) }, layout);
+    let largest = heap.largest_allocation(8).unwrap();
+    let d = heap
+        .allocate_first_fit(Layout::from_size_align(largest, 8).unwrap())
+        .unwrap();
+    heap.holes.check_invariants();
+
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(b, layout);
+        heap.deallocate(c, Layout::from_size_align(size, 8).unwrap());
+        heap.deallocate(d, Layout::from_size_align(largest, 8).unwrap());
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.holes.check_invariants(), free);
+    assert_eq!(heap.holes.holes().count(), 1);
+}
+
+#[test]
+fn min_block_size() {
+    let (chonk, data) = Chonk::<1024>::new();
+    let mut heap = Heap::with_min_block_size(64);
+    unsafe { heap.init(data, 1024) };
+    let free = heap.holes.check_invariants();
+    let layout = Layout::from_size_align(8, 8).unwrap();
+
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    assert!(b.as_ptr() as usize - a.as_ptr() as usize >= 64);
+    assert!(heap.used() >= 2 * 64);
+    let slice = heap.allocate_first_fit_slice(layout).unwrap();
+    assert!(slice.len() >= 64);
+    let (c, size) = heap.allocate_within(8..=16, 8).unwrap();
+    assert!(size >= 64);
+    // larger allocations aren't padded
+    let large = Layout::from_size_align(128, 8).unwrap();
+    let d = heap.allocate_first_fit(large).unwrap();
+    heap.holes.check_invariants();
+
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(b, layout);
+        heap.deallocate(NonNull::new_unchecked(slice.as_ptr().cast()), layout);
+        heap.deallocate(c, Layout::from_size_align(size, 8).unwrap());
+        heap.deallocate(d, large);
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.holes.check_invariants(), free);
+    assert_eq!(heap.holes.holes().count(), 1);
+
+    // the upper part of a split heap pads its allocations the same way
+    let mut upper = heap.split_off(512).unwrap();
+    let e = upper.allocate_first_fit(layout).unwrap();
+    assert!(upper.used() >= 64);
+    unsafe { upper.deallocate(e, layout) };
+    assert_eq!(upper.used(), 0);
+    let _ = heap.merge(upper);
+
+    unsafe { Chonk::unleak(chonk) };
+}
+
+#[test]
+#[cfg(not(all(feature = "headers", feature = "redundant")))]
+fn allocate_many() {
+    /// Leaves free blocks of different sizes between the allocations.
+    fn fragment(heap: &mut Heap) {
+        let layouts: Vec<_> = (1..6)
+            .map(|i| Layout::from_size_align(i * 16, 8).unwrap())
+            .collect();
+        let ptrs: Vec<_> = layouts
+            .iter()
+            .map(|layout| heap.allocate_first_fit(*layout).unwrap())
+            .collect();
+        for (ptr, layout) in ptrs.iter().zip(&layouts).step_by(2) {
+            unsafe { heap.deallocate(*ptr, *layout) };
+        }
+    }
+
+    let mut heap = new_heap();
+    let mut reference = new_heap();
+    fragment(&mut heap);
+    fragment(&mut reference);
+    let used = heap.used();
+    let layout = Layout::from_size_align(16, 16).unwrap();
+
+    let mut ptrs = Vec::new();
+    heap.allocate_many(layout, 6, &mut ptrs).unwrap();
+    assert_eq!(ptrs.len(), 6);
+    for ptr in &ptrs {
+        let expected = reference.allocate_first_fit(layout).unwrap();
+        assert_eq!(
+            ptr.as_ptr() as usize - heap.bottom() as usize,
+            expected.as_ptr() as usize - reference.bottom() as usize
+        );
+    }
+    assert_eq!(heap.used(), reference.used());
+
+    // the blocks that fit are kept if the batch fails
+    assert!(heap.allocate_many(layout, 100, &mut ptrs).is_err());
+    assert!(ptrs.len() > 6 && ptrs.len() < 106);
+    for ptr in ptrs {
+        unsafe { heap.deallocate(ptr, layout) };
+    }
+    assert_eq!(heap.used(), used);
+}
+
+#[test]
+fn deallocate_batch() {
+    let layouts: Vec<_> = (1..9)
+        .map(|i| Layout::from_size_align(i * 8, 8 << (i % 3)).unwrap())
+        .collect();
+    let order = [6, 1, 3, 0, 7, 4];
+    let mut heap = new_heap();
+    let mut reference = new_heap();
+    let ptrs: Vec<_> = layouts
+        .iter()
+        .map(|layout| heap.allocate_first_fit(*layout).unwrap())
+        .collect();
+    let reference_ptrs: Vec<_> = layouts
+        .iter()
+        .map(|layout| reference.allocate_first_fit(*layout).unwrap())
+        .collect();
+
+    let mut batch: Vec<_> = order.iter().map(|&i| (ptrs[i], layouts[i])).collect();
+    unsafe { heap.deallocate_batch(&mut batch) };
+    assert!(batch.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    for i in order {
+        unsafe { reference.deallocate(reference_ptrs[i], layouts[i]) };
+    }
+
+    // the same blocks are free as after separate deallocations
+    assert_eq!(heap.holes.check_invariants(), heap.free());
+    assert_eq!(heap.used(), reference.used());
+    let offsets = |heap: &Heap| -> Vec<_> {
+        heap.holes
+            .holes()
+            .map(|(addr, size)| (addr as usize - heap.bottom() as usize, size))
+            .collect()
+    };
+    assert_eq!(offsets(&heap), offsets(&reference));
+
+    unsafe {
+        heap.deallocate(ptrs[2], layouts[2]);
+        heap.deallocate(ptrs[5], layouts[5]);
+    }
+    assert_eq!(heap.used(), 0);
 }
 
 #[test]
+#[cfg(not(feature = "headers"))]
 fn allocate_usize_in_bigger_block() {
     let mut heap = new_heap();
 
     let layout_1 = Layout::from_size_align(size_of::<usize>() * 2, 1).unwrap();
     let layout_2 = Layout::from_size_align(size_of::<usize>(), 1).unwrap();
 
-    let x = heap.allocate_first_fit(layout_1.clone()).unwrap();
-    let y = heap.allocate_first_fit(layout_1.clone()).unwrap();
+    let x = heap.allocate_first_fit(layout_1).unwrap();
+    let y = heap.allocate_first_fit(layout_1).unwrap();
     unsafe {
-        heap.deallocate(x, layout_1.clone());
+        heap.deallocate(x, layout_1);
     }
 
-    let z = heap.allocate_first_fit(layout_2.clone());
+    let z = heap.allocate_first_fit(layout_2);
     assert!(z.is_ok());
     let z = z.unwrap();
     assert_eq!(x, z);
 
     unsafe {
-        heap.deallocate(y, layout_1.clone());
+        heap.deallocate(y, layout_1);
         heap.deallocate(z, layout_2);
     }
 }
@@ -510,12 +1489,13 @@ fn align_from_small_to_big() {
     let layout_2 = Layout::from_size_align(8, 8).unwrap();
 
     // allocate 28 bytes so that the heap end is only 4 byte aligned
-    assert!(heap.allocate_first_fit(layout_1.clone()).is_ok());
+    assert!(heap.allocate_first_fit(layout_1).is_ok());
     // try to allocate a 8 byte aligned block
-    assert!(heap.allocate_first_fit(layout_2.clone()).is_ok());
+    assert!(heap.allocate_first_fit(layout_2).is_ok());
 }
 
 #[test]
+#[cfg(not(feature = "headers"))]
 fn extend_empty_heap() {
     let mut heap = new_max_heap();
 
@@ -525,39 +1505,41 @@ fn extend_empty_heap() {
 
     // Try to allocate full heap after extend
     let layout = Layout::from_size_align(2048, 1).unwrap();
-    assert!(heap.allocate_first_fit(layout.clone()).is_ok());
+    assert!(heap.allocate_first_fit(layout).is_ok());
 }
 
 #[test]
+#[cfg(not(feature = "headers"))]
 fn extend_full_heap() {
     let mut heap = new_max_heap();
 
     let layout = Layout::from_size_align(1024, 1).unwrap();
 
     // Allocate full heap, extend and allocate again to the max
-    assert!(heap.allocate_first_fit(layout.clone()).is_ok());
+    assert!(heap.allocate_first_fit(layout).is_ok());
     unsafe {
         heap.extend(1024);
     }
-    assert!(heap.allocate_first_fit(layout.clone()).is_ok());
+    assert!(heap.allocate_first_fit(layout).is_ok());
 }
 
 #[test]
+#[cfg(not(feature = "headers"))]
 fn extend_fragmented_heap() {
     let mut heap = new_max_heap();
 
     let layout_1 = Layout::from_size_align(512, 1).unwrap();
     let layout_2 = Layout::from_size_align(1024, 1).unwrap();
 
-    let alloc1 = heap.allocate_first_fit(layout_1.clone());
-    let alloc2 = heap.allocate_first_fit(layout_1.clone());
+    let alloc1 = heap.allocate_first_fit(layout_1);
+    let alloc2 = heap.allocate_first_fit(layout_1);
 
     assert!(alloc1.is_ok());
     assert!(alloc2.is_ok());
 
     unsafe {
         // Create a hole at the beginning of the heap
-        heap.deallocate(alloc1.unwrap(), layout_1.clone());
+        heap.deallocate(alloc1.unwrap(), layout_1);
     }
 
     unsafe {
@@ -566,7 +1548,7 @@ fn extend_fragmented_heap() {
 
     // We got additional 1024 bytes hole at the end of the heap
     // Try to allocate there
-    assert!(heap.allocate_first_fit(layout_2.clone()).is_ok());
+    assert!(heap.allocate_first_fit(layout_2).is_ok());
 }
 
 /// Ensures that `Heap::extend` fails for very small sizes.
@@ -574,11 +1556,12 @@ fn extend_fragmented_heap() {
 /// The size needs to be big enough to hold a hole, otherwise
 /// the hole write would result in an out of bounds write.
 #[test]
+#[cfg(not(feature = "redundant"))]
 fn small_heap_extension() {
     // define an array of `u64` instead of `u8` for alignment
     static mut HEAP: [u64; 5] = [0; 5];
     unsafe {
-        let mut heap = Heap::new(HEAP.as_mut_ptr().cast(), 32);
+        let mut heap = Heap::new(core::ptr::addr_of_mut!(HEAP).cast(), 32);
         heap.extend(1);
         assert_eq!(1, heap.holes.pending_extend);
     }
@@ -586,11 +1569,12 @@ fn small_heap_extension() {
 
 /// Ensures that `Heap::extend` fails for sizes that are not a multiple of the hole size.
 #[test]
+#[cfg(not(feature = "checksum"))]
 fn oddly_sized_heap_extension() {
     // define an array of `u64` instead of `u8` for alignment
     static mut HEAP: [u64; 5] = [0; 5];
     unsafe {
-        let mut heap = Heap::new(HEAP.as_mut_ptr().cast(), 16);
+        let mut heap = Heap::new(core::ptr::addr_of_mut!(HEAP).cast(), 16);
         heap.extend(17);
         assert_eq!(1, heap.holes.pending_extend);
         assert_eq!(16 + 16, heap.size());
@@ -602,11 +1586,12 @@ fn oddly_sized_heap_extension() {
 /// To extend the heap, we need to place a hole at the old top of the heap. This
 /// only works if the top pointer is sufficiently aligned.
 #[test]
+#[cfg(not(feature = "checksum"))]
 fn extend_odd_size() {
     // define an array of `u64` instead of `u8` for alignment
     static mut HEAP: [u64; 6] = [0; 6];
     unsafe {
-        let mut heap = Heap::new(HEAP.as_mut_ptr().cast(), 17);
+        let mut heap = Heap::new(core::ptr::addr_of_mut!(HEAP).cast(), 17);
         assert_eq!(1, heap.holes.pending_extend);
         heap.extend(16);
         assert_eq!(1, heap.holes.pending_extend);
@@ -615,3 +1600,1519 @@ fn extend_odd_size() {
         assert_eq!(17 + 16 + 15, heap.size());
     }
 }
+
+#[test]
+#[cfg(feature = "headers")]
+fn allocations_iter() {
+    let mut heap = new_heap();
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let aligned = Layout::from_size_align(40, 64).unwrap();
+
+    let a = heap.allocate_first_fit(small).unwrap();
+    let b = heap.allocate_first_fit(aligned).unwrap();
+    let c = heap.allocate_first_fit(small).unwrap();
+    assert_eq!(b.as_ptr() as usize % 64, 0);
+
+    // the allocations are yielded in address order
+    let live: Vec<_> = heap.allocations().collect();
+    assert_eq!(live, [(a, small), (b, aligned), (c, small)]);
+
+    unsafe { heap.deallocate(b, aligned) };
+    let live: Vec<_> = heap.allocations().collect();
+    assert_eq!(live, [(a, small), (c, small)]);
+
+    unsafe {
+        heap.deallocate(a, small);
+        heap.deallocate(c, small);
+    }
+    assert_eq!(heap.allocations().count(), 0);
+}
+
+#[test]
+#[cfg(feature = "headers")]
+fn find_allocation_start() {
+    let mut heap = new_heap();
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let aligned = Layout::from_size_align(40, 64).unwrap();
+    let empty = Layout::from_size_align(0, 8).unwrap();
+
+    let a = heap.allocate_first_fit(small).unwrap();
+    let b = heap.allocate_first_fit(aligned).unwrap();
+    let c = heap.allocate_first_fit(empty).unwrap();
+
+    assert_eq!(heap.find_allocation_start(a.as_ptr()), Some((a, small)));
+    let inner = a.as_ptr().wrapping_add(23);
+    assert_eq!(heap.find_allocation_start(inner), Some((a, small)));
+    let inner = b.as_ptr().wrapping_add(17);
+    assert_eq!(heap.find_allocation_start(inner), Some((b, aligned)));
+    // zero-sized allocations don't take any memory
+    assert_eq!(heap.find_allocation_start(c.as_ptr()), None);
+
+    // the metadata in front of an allocation doesn't belong to it
+    assert_eq!(
+        heap.find_allocation_start(a.as_ptr().wrapping_add(24)),
+        None
+    );
+    assert_eq!(heap.find_allocation_start(b.as_ptr().wrapping_sub(1)), None);
+    assert_eq!(heap.find_allocation_start(heap.top()), None);
+    assert_eq!(
+        heap.find_allocation_start(heap.bottom().wrapping_sub(1)),
+        None
+    );
+
+    unsafe { heap.deallocate(b, aligned) };
+    assert_eq!(heap.find_allocation_start(b.as_ptr()), None);
+    unsafe { heap.deallocate(c, empty) };
+}
+
+#[test]
+#[cfg(feature = "headers")]
+fn deallocate_unsized() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(40, 64).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let (b, size) = heap.allocate_within(16..=512, 8).unwrap();
+    assert_eq!(unsafe { heap.allocation_layout(a) }, layout);
+    let actual = unsafe { heap.allocation_layout(b) };
+    assert_eq!(actual, Layout::from_size_align(size, 8).unwrap());
+
+    unsafe {
+        heap.deallocate_unsized(a);
+        heap.deallocate_unsized(b);
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.allocations().count(), 0);
+}
+
+#[test]
+#[cfg(feature = "headers")]
+fn free_all_tagged() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let aligned = Layout::from_size_align(32, 64).unwrap();
+    let a = heap.allocate_tagged(layout, 1).unwrap();
+    let b = heap.allocate_tagged(layout, 2).unwrap();
+    let c = heap.allocate_tagged(aligned, 1).unwrap();
+    let d = heap.allocate_first_fit(layout).unwrap();
+    let e = heap.allocate_tagged(layout, 1).unwrap();
+    let f = heap.allocate_tagged(layout, 2).unwrap();
+    let used = heap.used();
+
+    assert_eq!(unsafe { heap.free_all_tagged(1) }, 3);
+    let live: Vec<_> = heap.allocations().map(|(ptr, _)| ptr).collect();
+    assert_eq!(live, [b, d, f]);
+    for ptr in [a, c, e] {
+        assert!(heap.find_allocation_start(ptr.as_ptr()).is_none());
+    }
+    assert!(heap.used() < used);
+    assert_eq!(unsafe { heap.free_all_tagged(1) }, 0);
+
+    // the freed blocks were merged with their free neighbors
+    let g = heap.allocate_tagged(layout, 3).unwrap();
+    assert_eq!(g, a);
+    unsafe {
+        heap.deallocate(g, layout);
+        heap.deallocate(d, layout);
+    }
+    assert_eq!(unsafe { heap.free_all_tagged(2) }, 2);
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.holes.holes().count(), 1);
+}
+
+#[test]
+#[cfg(all(feature = "headers", not(feature = "redundant")))]
+fn purgeable_allocations() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static PURGED: AtomicUsize = AtomicUsize::new(0);
+    fn purge(_ptr: NonNull<u8>, layout: Layout) {
+        PURGED.fetch_add(layout.size(), Ordering::Relaxed);
+    }
+
+    let mut heap = new_heap();
+    let large = Layout::from_size_align(300, 8).unwrap();
+    let small = Layout::from_size_align(100, 8).unwrap();
+    let a = heap.allocate_purgeable(large).unwrap();
+    let b = heap.allocate_first_fit(small).unwrap();
+    let c = heap.allocate_purgeable(large).unwrap();
+
+    // without a purger, purgeable allocations are never freed by the heap
+    let layout = Layout::from_size_align(200, 8).unwrap();
+    assert!(heap.allocate_first_fit(layout).is_err());
+
+    heap.set_purger(Some(&(purge as fn(NonNull<u8>, Layout))));
+    let d = heap.allocate_first_fit(layout).unwrap();
+    assert_eq!(PURGED.load(Ordering::Relaxed), 300);
+    assert_eq!(d, a);
+    let live: Vec<_> = heap.allocations().map(|(ptr, _)| ptr).collect();
+    assert_eq!(live, [d, b, c]);
+
+    // regular allocations are never purged
+    assert!(heap
+        .allocate_first_fit(Layout::from_size_align(800, 8).unwrap())
+        .is_err());
+    assert_eq!(PURGED.load(Ordering::Relaxed), 600);
+    unsafe {
+        heap.deallocate(b, small);
+        heap.deallocate(d, layout);
+    }
+    assert_eq!(heap.used(), 0);
+}
+
+#[test]
+#[cfg(all(
+    feature = "headers",
+    not(any(feature = "redundant", feature = "call_sites", feature = "timestamps"))
+))]
+fn defragment() {
+    let mut heap = new_heap();
+    let layouts = [
+        Layout::from_size_align(32, 8).unwrap(),
+        Layout::from_size_align(100, 1).unwrap(),
+        Layout::from_size_align(32, 64).unwrap(),
+        Layout::from_size_align(16, 8).unwrap(),
+    ];
+    let mut ptrs: Vec<_> = layouts
+        .iter()
+        .enumerate()
+        .map(|(i, layout)| {
+            let ptr = heap.allocate_first_fit(*layout).unwrap();
+            unsafe { ptr.as_ptr().write_bytes(i as u8, layout.size()) };
+            ptr
+        })
+        .collect();
+
+    unsafe { heap.deallocate(ptrs[1], layouts[1]) };
+    let free_before = heap.free();
+
+    let moved = unsafe {
+        heap.defragment(&mut |old, new, layout| {
+            assert!(new < old);
+            let i = ptrs.iter().position(|ptr| *ptr == old).unwrap();
+            assert_eq!(layout, layouts[i]);
+            ptrs[i] = new;
+        })
+    };
+    assert!(moved >= 1);
+    assert_eq!(heap.free(), free_before);
+
+    for i in [0, 2, 3] {
+        let contents = unsafe { core::slice::from_raw_parts(ptrs[i].as_ptr(), layouts[i].size()) };
+        assert!(contents.iter().all(|&byte| byte == i as u8));
+        assert_eq!(ptrs[i].as_ptr() as usize % layouts[i].align(), 0);
+    }
+
+    // the free memory is now a single block at the end of the heap
+    let live: Vec<_> = heap.allocations().collect();
+    assert_eq!(live.len(), 3);
+    let end = live
+        .iter()
+        .map(|(ptr, layout)| ptr.as_ptr() as usize + layout.size())
+        .max()
+        .unwrap();
+    let (hole_addr, hole_size) = heap.holes.first_hole().unwrap();
+    assert!(hole_addr as usize >= end);
+    assert_eq!(hole_addr as usize + hole_size, heap.top() as usize);
+}
+
+#[test]
+#[cfg(feature = "headers")]
+fn defragment_many_holes() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let mut ptrs = Vec::new();
+    while let Ok(ptr) = heap.allocate_first_fit(layout) {
+        unsafe { ptr.as_ptr().write_bytes(ptrs.len() as u8, layout.size()) };
+        ptrs.push(ptr);
+    }
+    // leave a hole between each pair of blocks that are kept
+    let mut kept = Vec::new();
+    for (i, ptr) in ptrs.into_iter().enumerate() {
+        if i % 3 == 1 {
+            unsafe { heap.deallocate(ptr, layout) };
+        } else {
+            kept.push((i as u8, ptr));
+        }
+    }
+    let free_before = heap.free();
+
+    let moved = unsafe {
+        heap.defragment(&mut |old, new, _| {
+            let entry = kept.iter_mut().find(|(_, ptr)| *ptr == old).unwrap();
+            entry.1 = new;
+        })
+    };
+    assert!(moved >= kept.len() / 2);
+    assert_eq!(heap.free(), free_before);
+    for &(i, ptr) in &kept {
+        let contents = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+        assert!(contents.iter().all(|&byte| byte == i));
+    }
+    assert_eq!(heap.holes.holes().count(), 1);
+}
+
+#[test]
+fn stats() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    let peak = heap.used();
+    unsafe { heap.deallocate(a, layout) };
+    let too_big = Layout::from_size_align(heap.size(), 8).unwrap();
+    assert!(heap.allocate_first_fit(too_big).is_err());
+
+    let stats = heap.stats();
+    assert_eq!(stats.size, heap.size());
+    assert_eq!(stats.used, heap.used());
+    assert_eq!(stats.free, heap.free());
+    assert_eq!(stats.peak_used, peak);
+    assert_eq!(stats.allocations, 2);
+    assert_eq!(stats.deallocations, 1);
+    assert_eq!(stats.failed_allocations, 1);
+    assert_eq!(stats.holes, 2);
+    assert_eq!(stats.largest_hole, heap.free() - (peak - heap.used()));
+
+    unsafe { heap.deallocate(b, layout) };
+    let stats = heap.stats();
+    assert_eq!(stats.holes, 1);
+    assert_eq!(stats.largest_hole, heap.size());
+}
+
+#[test]
+fn align_stats() {
+    let mut heap = new_max_heap();
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let aligned = Layout::from_size_align(8, 64).unwrap();
+    let a = heap.allocate_first_fit(small).unwrap();
+    let b = heap.allocate_first_fit(aligned).unwrap();
+    let c = heap.allocate_first_fit(small).unwrap();
+
+    let stats = heap.align_stats();
+    assert_eq!(stats[3].align, 8);
+    assert_eq!(stats[3].allocations, 2);
+    assert_eq!(stats[6].align, 64);
+    assert_eq!(stats[6].allocations, 1);
+    let live = stats.iter().map(|class| class.allocations).sum::<usize>();
+    let padding = stats.iter().map(|class| class.padding).sum::<usize>();
+    assert_eq!(padding, heap.used() - live * 8);
+
+    unsafe {
+        heap.deallocate(a, small);
+        heap.deallocate(b, aligned);
+        heap.deallocate(c, small);
+    }
+    assert!(heap
+        .align_stats()
+        .iter()
+        .all(|class| class.allocations == 0 && class.padding == 0));
+}
+
+#[test]
+#[cfg(feature = "interval_stats")]
+fn take_stats() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    let peak = heap.used();
+    unsafe { heap.deallocate(a, layout) };
+
+    let interval = heap.take_stats();
+    assert_eq!(interval.allocations, 2);
+    assert_eq!(interval.deallocations, 1);
+    assert_eq!(interval.peak_used, peak);
+    assert_eq!(interval.used, heap.used());
+
+    // the next interval starts at the current usage
+    let interval = heap.take_stats();
+    assert_eq!(interval.allocations, 0);
+    assert_eq!(interval.deallocations, 0);
+    assert_eq!(interval.peak_used, heap.used());
+
+    unsafe { heap.deallocate(b, layout) };
+    let too_big = Layout::from_size_align(heap.size() + 1, 8).unwrap();
+    assert!(heap.allocate_first_fit(too_big).is_err());
+    let interval = heap.take_stats();
+    assert_eq!(interval.deallocations, 1);
+    assert_eq!(interval.failed_allocations, 1);
+    assert_eq!(interval.peak_used, peak / 2);
+
+    // the totals are not reset
+    let stats = heap.stats();
+    assert_eq!(stats.allocations, 2);
+    assert_eq!(stats.deallocations, 2);
+    assert_eq!(stats.peak_used, peak);
+}
+
+#[test]
+fn alloc_value() {
+    use core::cell::Cell;
+
+    struct Dropped<'a>(&'a Cell<usize>, u64);
+
+    impl<'a> Drop for Dropped<'a> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    let mut heap = new_heap();
+    let dropped = Cell::new(0);
+    let ptr = heap.alloc_value(Dropped(&dropped, 42)).unwrap();
+    assert_eq!(ptr.as_ptr() as usize % align_of::<Dropped>(), 0);
+    assert_eq!(unsafe { ptr.as_ref() }.1, 42);
+    assert!(heap.used() >= size_of::<Dropped>());
+
+    unsafe { heap.dealloc_value(ptr) };
+    assert_eq!(dropped.get(), 1);
+    assert_eq!(heap.used(), 0);
+
+    // the value is dropped if the allocation fails
+    assert!(heap
+        .alloc_value(([0u8; 2000], Dropped(&dropped, 0)))
+        .is_err());
+    assert_eq!(dropped.get(), 2);
+}
+
+#[test]
+fn allocate_slice() {
+    let mut heap = new_heap();
+    let slice = heap.allocate_slice::<u32>(10).unwrap();
+    assert_eq!(slice.as_ptr().cast::<u32>() as usize % align_of::<u32>(), 0);
+    unsafe { slice.as_ptr().cast::<u32>().write_bytes(0, 10) };
+    let elements = unsafe { &*slice.as_ptr() };
+    assert_eq!(elements, &[0; 10]);
+    assert!(heap.used() >= 40);
+    unsafe { heap.deallocate_slice(slice) };
+    assert_eq!(heap.used(), 0);
+
+    let empty = heap.allocate_slice::<u64>(0).unwrap();
+    assert_eq!(heap.used(), 0);
+    unsafe { heap.deallocate_slice(empty) };
+
+    assert_eq!(
+        heap.allocate_slice::<u64>(usize::MAX / 4),
+        Err(AllocError::InvalidLayout)
+    );
+}
+
+#[test]
+#[cfg(not(any(feature = "call_sites", feature = "timestamps")))]
+fn allocate_pages() {
+    let mut heap = new_heap();
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let a = heap.allocate_first_fit(small).unwrap();
+    let pages = heap.allocate_pages(2, 128).unwrap();
+    assert_eq!(pages.as_ptr() as usize % 128, 0);
+    unsafe { pages.as_ptr().write_bytes(0, 256) };
+
+    // the memory in front of the pages stays free
+    let b = heap.allocate_first_fit(small).unwrap();
+    assert!(b < pages);
+
+    unsafe {
+        heap.deallocate_pages(pages, 2, 128);
+        heap.deallocate(a, small);
+        heap.deallocate(b, small);
+    }
+    assert_eq!(heap.used(), 0);
+
+    assert_eq!(heap.allocate_pages(1, 100), Err(AllocError::InvalidLayout));
+    assert_eq!(
+        heap.allocate_pages(usize::MAX, 4096),
+        Err(AllocError::InvalidLayout)
+    );
+}
+
+#[test]
+fn allocation_overhead() {
+    // usable in constant expressions, e.g. to size a static heap
+    const BLOCK: usize = Heap::block_size(1);
+    assert!(BLOCK >= Heap::min_allocation_size());
+    assert_eq!(BLOCK % Heap::allocation_granularity(), 0);
+    assert_eq!(Heap::block_size(0), 0);
+
+    let mut heap = new_heap();
+    for size in [1, 7, 8, 24, 33, 100] {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let used = heap.used();
+        let ptr = heap.allocate_first_fit(layout).unwrap();
+        assert_eq!(heap.used() - used, Heap::block_size(size), "size {}", size);
+        assert!(Heap::block_size(size) >= size + Heap::per_allocation_overhead());
+        unsafe { heap.deallocate(ptr, layout) };
+    }
+}
+
+#[test]
+fn scan_step() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let blocks: Vec<_> = (0..8)
+        .map(|_| heap.allocate_first_fit(layout).unwrap())
+        .collect();
+    for &ptr in blocks.iter().step_by(2) {
+        unsafe { heap.deallocate(ptr, layout) };
+    }
+    let holes = heap.stats().holes;
+    assert_eq!(holes, 5);
+
+    for _ in 0..holes {
+        assert_eq!(heap.scan_step(1), ScanState::InProgress);
+    }
+    assert_eq!(heap.scan_step(1), ScanState::Complete);
+    assert_eq!(heap.scan_step(100), ScanState::Complete);
+
+    // removing the block that the scan stopped at doesn't restart the pass
+    assert_eq!(heap.scan_step(2), ScanState::InProgress);
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    assert_eq!(heap.scan_step(1), ScanState::InProgress);
+    unsafe { heap.deallocate(blocks[3], layout) };
+    assert_eq!(heap.scan_step(holes), ScanState::Complete);
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(b, layout);
+    }
+
+    // corruption is reported instead of causing a panic
+    #[cfg(not(feature = "redundant"))]
+    {
+        let (addr, size) = heap.holes.holes().nth(1).unwrap();
+        unsafe { addr.cast::<usize>().write(size + 1) };
+        assert_eq!(heap.scan_step(1), ScanState::InProgress);
+        assert_eq!(
+            heap.scan_step(1),
+            ScanState::Corrupted {
+                addr: addr as usize
+            }
+        );
+        unsafe { addr.cast::<usize>().write(size) };
+        assert_eq!(heap.scan_step(holes), ScanState::Complete);
+    }
+}
+
+#[test]
+fn freeze() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    assert!(!heap.is_frozen());
+
+    heap.freeze(FreezeMode::Fail);
+    assert!(heap.is_frozen());
+    assert_eq!(heap.allocate_first_fit(layout), Err(AllocError::Frozen));
+    assert_eq!(
+        heap.allocate_first_fit(Layout::new::<()>()),
+        Err(AllocError::Frozen)
+    );
+    assert_eq!(
+        heap.allocate_first_fit_slice(layout).map(|_| ()),
+        Err(AllocError::Frozen)
+    );
+    assert_eq!(
+        heap.allocate_near(b.as_ptr(), layout),
+        Err(AllocError::Frozen)
+    );
+    assert_eq!(heap.allocate_within(8..=64, 8), Err(AllocError::Frozen));
+    assert_eq!(heap.stats().failed_allocations, 5);
+
+    // deallocations still work
+    unsafe { heap.deallocate(a, layout) };
+    assert!(heap.used() > 0);
+    unsafe { heap.deallocate(b, layout) };
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.allocate_first_fit(layout), Err(AllocError::Frozen));
+
+    heap.freeze(FreezeMode::Panic);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _ = heap.allocate_zeroed(layout);
+    }));
+    assert!(result.is_err());
+    assert_eq!(heap.used(), 0);
+}
+
+#[test]
+fn stats_diff() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let earlier = heap.stats();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    let c = heap.allocate_first_fit(layout).unwrap();
+    unsafe { heap.deallocate(b, layout) };
+    let too_big = Layout::from_size_align(heap.size() + 1, 8).unwrap();
+    assert!(heap.allocate_first_fit(too_big).is_err());
+
+    let later = heap.stats();
+    let diff = later.diff(&earlier);
+    assert_eq!(diff.allocations, 2);
+    assert_eq!(diff.deallocations, 1);
+    assert_eq!(diff.failed_allocations, 1);
+    assert_eq!(diff.used, (later.used - earlier.used) as isize);
+    assert_eq!(
+        diff.peak_used,
+        (later.peak_used - earlier.peak_used) as isize
+    );
+    assert_eq!(diff.holes, 1);
+    assert_eq!(
+        diff.largest_hole,
+        -((earlier.largest_hole - later.largest_hole) as isize)
+    );
+    assert_eq!(earlier.diff(&earlier), HeapStatsDiff::default());
+
+    let reverse = earlier.diff(&later);
+    assert_eq!(reverse.used, -diff.used);
+    assert_eq!(
+        diff.to_string(),
+        format!(
+            "2 allocations, 1 deallocations, 1 failed, used +{} bytes, peak +{} bytes, \
+             holes +1, largest hole {} bytes",
+            diff.used, diff.peak_used, diff.largest_hole
+        )
+    );
+
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(c, layout);
+    }
+}
+
+#[test]
+fn render_prometheus() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = heap.allocate_first_fit(layout).unwrap();
+    let stats = heap.stats();
+
+    let mut buf = [0; 2048];
+    let len = stats.render_prometheus_into(&mut buf, "app_heap").unwrap();
+    let text = core::str::from_utf8(&buf[..len]).unwrap();
+    let mut rendered = String::new();
+    stats.render_prometheus(&mut rendered, "app_heap").unwrap();
+    assert_eq!(text, rendered);
+
+    assert!(
+        text.contains("# TYPE app_heap_allocations_total counter\napp_heap_allocations_total 1\n")
+    );
+    assert!(text.contains(&format!("\napp_heap_used_bytes {}\n", stats.used)));
+    assert!(text.contains(&format!(
+        "\napp_heap_largest_free_block_bytes {}\n",
+        stats.largest_hole
+    )));
+    // every sample is preceded by its help and type
+    let samples: Vec<_> = text.lines().filter(|line| !line.starts_with('#')).collect();
+    assert_eq!(samples.len(), 9);
+    assert_eq!(text.lines().count(), 27);
+
+    // a buffer that is too small is reported
+    assert!(stats
+        .render_prometheus_into(&mut buf[..100], "app_heap")
+        .is_err());
+
+    unsafe { heap.deallocate(ptr, layout) };
+}
+
+#[test]
+#[cfg(feature = "hooks")]
+fn hooks() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingHooks {
+        allocs: AtomicUsize,
+        deallocs: AtomicUsize,
+        fails: AtomicUsize,
+        extended: AtomicUsize,
+        used: AtomicUsize,
+    }
+
+    impl HeapHooks for CountingHooks {
+        fn on_alloc(&self, _ptr: NonNull<u8>, _layout: Layout, context: &HookContext) {
+            self.allocs.fetch_add(1, Ordering::Relaxed);
+            self.used.store(context.used, Ordering::Relaxed);
+        }
+
+        fn on_dealloc(&self, _ptr: NonNull<u8>, _layout: Layout, context: &HookContext) {
+            self.deallocs.fetch_add(1, Ordering::Relaxed);
+            self.used.store(context.used, Ordering::Relaxed);
+        }
+
+        fn on_fail(&self, layout: Layout, context: &HookContext) {
+            assert!(layout.size() > context.size);
+            self.fails.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_extend(&self, by: usize, _context: &HookContext) {
+            self.extended.fetch_add(by, Ordering::Relaxed);
+        }
+    }
+
+    let hooks: &'static CountingHooks = Box::leak(Box::default());
+    let mut heap = new_max_heap();
+    heap.set_hooks(Some(hooks));
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = heap.allocate_first_fit(layout).unwrap();
+    assert_eq!(hooks.used.load(Ordering::Relaxed), heap.used());
+    unsafe { heap.deallocate(ptr, layout) };
+    let too_big = Layout::from_size_align(4096, 8).unwrap();
+    assert!(heap.allocate_first_fit(too_big).is_err());
+    unsafe { heap.extend(512) };
+
+    assert_eq!(hooks.allocs.load(Ordering::Relaxed), 1);
+    assert_eq!(hooks.deallocs.load(Ordering::Relaxed), 1);
+    assert_eq!(hooks.used.load(Ordering::Relaxed), 0);
+    assert_eq!(hooks.fails.load(Ordering::Relaxed), 1);
+    assert_eq!(hooks.extended.load(Ordering::Relaxed), 512);
+
+    heap.set_hooks(None);
+    let ptr = heap.allocate_first_fit(layout).unwrap();
+    assert_eq!(hooks.allocs.load(Ordering::Relaxed), 1);
+    unsafe { heap.deallocate(ptr, layout) };
+
+    unsafe {
+        drop(Box::from_raw(
+            hooks as *const CountingHooks as *mut CountingHooks,
+        ))
+    };
+}
+
+#[test]
+#[cfg(feature = "hooks")]
+fn bounds_hooks() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct GuardHooks {
+        calls: AtomicUsize,
+        bottom: AtomicUsize,
+        top: AtomicUsize,
+    }
+
+    impl GuardHooks {
+        fn bounds(&self) -> (usize, usize) {
+            (
+                self.bottom.load(Ordering::Relaxed),
+                self.top.load(Ordering::Relaxed),
+            )
+        }
+    }
+
+    impl HeapHooks for GuardHooks {
+        fn on_bounds(&self, bottom: *mut u8, top: *mut u8, _context: &HookContext) {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.bottom.store(bottom as usize, Ordering::Relaxed);
+            self.top.store(top as usize, Ordering::Relaxed);
+        }
+    }
+
+    let hooks: &'static GuardHooks = Box::leak(Box::default());
+    let (chonk, data) = Chonk::<2048>::new();
+    let mut heap = Heap::empty();
+    heap.set_hooks(Some(hooks));
+    assert_eq!(hooks.calls.load(Ordering::Relaxed), 0);
+
+    unsafe { heap.init(data, 1024) };
+    let bounds = |heap: &Heap| (heap.bottom() as usize, heap.top() as usize);
+    assert_eq!(hooks.bounds(), bounds(&heap));
+    unsafe { heap.extend(1024) };
+    assert_eq!(hooks.bounds(), bounds(&heap));
+
+    let upper = heap.split_off(1024).unwrap();
+    assert_eq!(hooks.bounds(), bounds(&heap));
+    assert_eq!(hooks.top.load(Ordering::Relaxed), upper.bottom() as usize);
+    let heap = heap.merge(upper);
+    assert_eq!(hooks.bounds(), bounds(&heap));
+    assert_eq!(hooks.calls.load(Ordering::Relaxed), 4);
+
+    // hooks that are installed later learn the current bounds right away
+    let mut heap = heap;
+    heap.set_hooks(None);
+    heap.set_hooks(Some(hooks));
+    assert_eq!(hooks.calls.load(Ordering::Relaxed), 5);
+
+    unsafe {
+        Chonk::unleak(chonk);
+        drop(Box::from_raw(hooks as *const GuardHooks as *mut GuardHooks));
+    }
+}
+
+#[test]
+#[cfg(all(feature = "hooks", feature = "pressure", not(feature = "redundant")))]
+fn pressure_levels() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct PressureHooks {
+        changes: AtomicUsize,
+        level: AtomicUsize,
+    }
+
+    impl HeapHooks for PressureHooks {
+        fn on_pressure(&self, level: Pressure, _context: &HookContext) {
+            self.changes.fetch_add(1, Ordering::Relaxed);
+            self.level.store(level as usize, Ordering::Relaxed);
+        }
+    }
+
+    let hooks: &'static PressureHooks = Box::leak(Box::default());
+    let mut heap = new_heap();
+    heap.set_hooks(Some(hooks));
+    let size = heap.size();
+    heap.set_pressure_thresholds(
+        PressureThreshold {
+            free: size / 2,
+            largest_hole: 0,
+        },
+        PressureThreshold {
+            free: 16,
+            largest_hole: 64,
+        },
+    );
+    assert_eq!(heap.pressure(), Pressure::Normal);
+    assert_eq!(hooks.changes.load(Ordering::Relaxed), 0);
+
+    let half = Layout::from_size_align(size / 2, 8).unwrap();
+    let a = heap.allocate_first_fit(half).unwrap();
+    assert_eq!(heap.pressure(), Pressure::Elevated);
+    assert_eq!(
+        hooks.level.load(Ordering::Relaxed),
+        Pressure::Elevated as usize
+    );
+
+    // the free memory is above the critical limit, but the largest free block isn't
+    let rest = heap.largest_allocation(8).unwrap();
+    let b = heap
+        .allocate_first_fit(Layout::from_size_align(rest - 48, 8).unwrap())
+        .unwrap();
+    assert!(heap.free() >= 16);
+    assert_eq!(heap.pressure(), Pressure::Critical);
+    assert_eq!(hooks.changes.load(Ordering::Relaxed), 2);
+
+    unsafe { heap.deallocate(a, half) };
+    assert_eq!(heap.pressure(), Pressure::Normal);
+    assert_eq!(hooks.changes.load(Ordering::Relaxed), 3);
+    unsafe { heap.deallocate(b, Layout::from_size_align(rest - 48, 8).unwrap()) };
+    assert_eq!(hooks.changes.load(Ordering::Relaxed), 3);
+
+    unsafe {
+        drop(Box::from_raw(
+            hooks as *const PressureHooks as *mut PressureHooks,
+        ))
+    };
+}
+
+#[test]
+#[cfg(all(feature = "use_spin", not(loom)))]
+fn locked_heap_threads() {
+    use crate::{BackoffSpinlock, CheckedSpinlock, RawSpinlock, TicketLock};
+
+    const ROUNDS: usize = if cfg!(miri) { 10 } else { 500 };
+
+    locked_heap_threads_with::<RawSpinlock>(ROUNDS);
+    locked_heap_threads_with::<BackoffSpinlock>(ROUNDS);
+    // a waiting thread can't take a ticket lock out of order, so it spins for its whole time
+    // slice when the thread before it was preempted, which is slow on machines with few cores
+    locked_heap_threads_with::<TicketLock>(ROUNDS / 10);
+    locked_heap_threads_with::<CheckedSpinlock<ThreadContext>>(ROUNDS);
+    #[cfg(feature = "critical_section")]
+    locked_heap_threads_with::<crate::CriticalSectionLock>(ROUNDS);
+}
+
+/// An [`ExecutionContext`][crate::ExecutionContext] for every thread.
+#[cfg(all(feature = "use_spin", not(loom)))]
+struct ThreadContext;
+
+#[cfg(all(feature = "use_spin", not(loom)))]
+impl crate::ExecutionContext for ThreadContext {
+    fn current() -> usize {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        std::thread_local!(static ID: usize = NEXT.fetch_add(1, Ordering::Relaxed));
+        ID.with(|id| *id)
+    }
+}
+
+#[test]
+#[cfg(all(feature = "use_spin", not(loom), debug_assertions))]
+#[should_panic(expected = "deadlock: the heap lock is already held by the current execution")]
+fn checked_spinlock_detects_reentrancy() {
+    let heap = LockedHeap::<crate::CheckedSpinlock>::from_heap(Heap::empty());
+    // like an interrupt handler that allocates while the interrupted code holds the lock
+    heap.with_heap(|_| heap.allocate_first_fit(Layout::new::<u64>()))
+        .unwrap_err();
+}
+
+#[test]
+#[cfg(all(feature = "critical_section", not(loom)))]
+#[should_panic(expected = "deadlock: the heap lock is already held within the critical section")]
+fn critical_section_lock_detects_reentrancy() {
+    let heap = LockedHeap::<crate::CriticalSectionLock>::from_heap(Heap::empty());
+    heap.with_heap(|_| heap.allocate_first_fit(Layout::new::<u64>()))
+        .unwrap_err();
+}
+
+/// Allocates and frees from several threads through a heap with the lock `R`.
+#[cfg(all(feature = "use_spin", not(loom)))]
+fn locked_heap_threads_with<R: crate::RawMutex + Send + Sync>(rounds: usize) {
+    const HEAP_SIZE: usize = 1 << 14;
+    const THREADS: usize = 4;
+
+    let (heap_space_ptr, data_ptr) = Chonk::<HEAP_SIZE>::new();
+    let heap = LockedHeap::<R>::from_heap(unsafe { Heap::new(data_ptr, HEAP_SIZE) });
+
+    std::thread::scope(|scope| {
+        for tag in 1..=THREADS as u8 {
+            let heap = &heap;
+            scope.spawn(move || {
+                let mut ptrs = Vec::new();
+                for round in 0..rounds {
+                    let layout =
+                        Layout::from_size_align(8 + round % 100, 1 << (round % 5)).unwrap();
+                    let ptr = unsafe { heap.alloc(layout) };
+                    if !ptr.is_null() {
+                        unsafe { ptr.write_bytes(tag, layout.size()) };
+                        ptrs.push((ptr, layout));
+                    }
+                    if round % 3 == 0 && !ptrs.is_empty() {
+                        let (ptr, layout) = ptrs.swap_remove(round % ptrs.len());
+                        let contents = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+                        assert!(contents.iter().all(|&byte| byte == tag));
+                        unsafe { heap.dealloc(ptr, layout) };
+                    }
+                }
+                for (ptr, layout) in ptrs {
+                    let contents = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+                    assert!(contents.iter().all(|&byte| byte == tag));
+                    unsafe { heap.dealloc(ptr, layout) };
+                }
+            });
+        }
+    });
+
+    let stats = heap.stats();
+    assert_eq!(stats.used, 0);
+    assert_eq!(stats.holes, 1);
+    // all allocations went through the allocator trait, so the lock-free counters agree
+    let counters = heap.counters();
+    assert_eq!(counters.used, 0);
+    assert_eq!(counters.allocations, stats.allocations);
+    assert_eq!(counters.deallocations, stats.deallocations);
+    assert_eq!(counters.failed_allocations, stats.failed_allocations);
+    // the counters are updated after the lock is released, so the lock-free peak can
+    // include one deallocation per thread that was not recorded yet
+    assert!(counters.peak_used > 0 && counters.peak_used <= stats.peak_used + THREADS * 256);
+    unsafe { Chonk::unleak(heap_space_ptr) };
+}
+
+#[test]
+#[cfg(all(feature = "use_spin", not(loom)))]
+fn locked_heap_methods() {
+    let (heap_space_ptr, data_ptr) = Chonk::<1000>::new();
+    let heap = LockedHeap::empty();
+    unsafe { heap.init(data_ptr, 1000) };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    let ptr = heap.allocate_first_fit(layout).unwrap();
+    assert!(heap.with_heap(|heap| heap.contains(ptr)));
+    assert_eq!(heap.stats().allocations, 1);
+    assert_eq!(heap.counters().used, heap.stats().used);
+    unsafe { heap.deallocate(ptr, layout) };
+    let stats = heap.stats();
+    assert_eq!(stats.used, 0);
+    assert_eq!(stats.deallocations, 1);
+    assert_eq!(heap.counters().used, 0);
+
+    let init_again = std::panic::AssertUnwindSafe(|| unsafe { heap.init(data_ptr, 1000) });
+    assert!(std::panic::catch_unwind(init_again).is_err());
+    unsafe { Chonk::unleak(heap_space_ptr) };
+}
+
+/// Property-based tests that run random sequences of heap operations and check the
+/// invariants of the heap after every step.
+mod proptests {
+    use super::{Chonk, Dropper};
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use hole::HoleList;
+    use proptest::prelude::*;
+    use std::{mem::size_of, vec::Vec};
+    use Heap;
+
+    const MAX_HEAP_SIZE: usize = 4096;
+
+    #[derive(Debug, Clone)]
+    enum Action {
+        Alloc {
+            size: usize,
+            align_shift: u32,
+        },
+        AllocWithin {
+            min: usize,
+            extra: usize,
+            align_shift: u32,
+        },
+        AllocWithOffset {
+            size: usize,
+            align_shift: u32,
+            words: usize,
+        },
+        AllocMany {
+            size: usize,
+            align_shift: u32,
+            n: usize,
+        },
+        AllocNear {
+            size: usize,
+            align_shift: u32,
+            permille: usize,
+        },
+        Free {
+            index: usize,
+        },
+        FreeBatch {
+            index: usize,
+            count: usize,
+        },
+        Extend {
+            by: usize,
+        },
+        Scan {
+            budget: usize,
+        },
+        Drain,
+    }
+
+    fn action() -> impl Strategy<Value = Action> {
+        prop_oneof![
+            4 => (1..512usize, 0..8u32)
+                .prop_map(|(size, align_shift)| Action::Alloc { size, align_shift }),
+            1 => (1..256usize, 0..1024usize, 0..8u32).prop_map(|(min, extra, align_shift)| {
+                Action::AllocWithin { min, extra, align_shift }
+            }),
+            1 => (1..256usize, 0..8u32, 0..16usize).prop_map(|(size, align_shift, words)| {
+                Action::AllocWithOffset { size, align_shift, words }
+            }),
+            1 => (1..128usize, 0..8u32, 1..8usize).prop_map(|(size, align_shift, n)| {
+                Action::AllocMany { size, align_shift, n }
+            }),
+            1 => (1..256usize, 0..8u32, 0..1000usize).prop_map(|(size, align_shift, permille)| {
+                Action::AllocNear { size, align_shift, permille }
+            }),
+            3 => any::<usize>().prop_map(|index| Action::Free { index }),
+            1 => (any::<usize>(), 1..8usize)
+                .prop_map(|(index, count)| Action::FreeBatch { index, count }),
+            1 => (0..256usize).prop_map(|by| Action::Extend { by }),
+            2 => (0..4usize).prop_map(|budget| Action::Scan { budget }),
+            1 => Just(Action::Drain),
+        ]
+    }
+
+    /// Checks that the allocations are disjoint and that the hole list is sorted and
+    /// fully coalesced.
+    fn check(heap: &Heap, live: &[(NonNull<u8>, Layout)]) {
+        let free = heap.holes.check_invariants();
+        assert_eq!(free, heap.free());
+        assert_eq!(heap.used() + heap.free(), heap.size());
+
+        let mut ranges: Vec<_> = live
+            .iter()
+            .map(|(ptr, layout)| {
+                let start = ptr.as_ptr() as usize;
+                (start, start + layout.size())
+            })
+            .collect();
+        ranges.sort_unstable();
+        for pair in ranges.windows(2) {
+            assert!(pair[0].1 <= pair[1].0, "allocations overlap");
+        }
+    }
+
+    /// Frees all live allocations and checks that the whole heap is usable as a single
+    /// block again.
+    fn drain(heap: &mut Heap, live: &mut Vec<(NonNull<u8>, Layout)>) {
+        for (ptr, layout) in live.drain(..) {
+            unsafe { heap.deallocate(ptr, layout) };
+        }
+        check(heap, live);
+        assert_eq!(heap.used(), 0);
+
+        let mut holes = heap.holes.holes();
+        assert_eq!(holes.next(), Some((heap.bottom(), heap.size())));
+        assert_eq!(holes.next(), None);
+    }
+
+    /// Runs the actions on the heap, whose memory ends at `end`. If `zeroed` is set, the
+    /// heap memory is zero-initialized and all allocations are made zeroed.
+    fn run(
+        heap: &mut Heap,
+        end: *mut u8,
+        actions: Vec<Action>,
+        zeroed: bool,
+    ) -> Result<(), TestCaseError> {
+        let mut live = Vec::new();
+
+        for action in actions {
+            match action {
+                Action::Alloc { size, align_shift } => {
+                    let layout = Layout::from_size_align(size, 1 << align_shift).unwrap();
+                    let largest = heap.largest_allocation(layout.align()).unwrap_or(0);
+                    let result = if zeroed {
+                        heap.allocate_zeroed(layout)
+                    } else {
+                        heap.allocate_first_fit(layout)
+                    };
+                    if let Ok(ptr) = result {
+                        prop_assert!(size <= largest);
+                        prop_assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+                        prop_assert!(ptr.as_ptr() >= heap.bottom());
+                        prop_assert!(ptr.as_ptr().wrapping_add(size) <= heap.top());
+                        if zeroed {
+                            let bytes = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), size) };
+                            prop_assert!(bytes.iter().all(|&byte| byte == 0));
+                        }
+                        unsafe { ptr.as_ptr().write_bytes(0xab, size) };
+                        live.push((ptr, layout));
+                    } else {
+                        // smaller allocations fail if they would leave a gap behind that
+                        // is too small for a hole
+                        prop_assert!(
+                            size.max(HoleList::min_size()) + HoleList::min_size() > largest
+                        );
+                    }
+                }
+                Action::AllocWithin {
+                    min,
+                    extra,
+                    align_shift,
+                } => {
+                    let align = 1 << align_shift;
+                    if let Ok((ptr, size)) = heap.allocate_within(min..=min + extra, align) {
+                        prop_assert!(size >= min);
+                        prop_assert_eq!(ptr.as_ptr() as usize % align, 0);
+                        prop_assert!(ptr.as_ptr().wrapping_add(size) <= heap.top());
+                        unsafe { ptr.as_ptr().write_bytes(0xab, size) };
+                        live.push((ptr, Layout::from_size_align(size, align).unwrap()));
+                    }
+                }
+                Action::AllocWithOffset {
+                    size,
+                    align_shift,
+                    words,
+                } => {
+                    let layout = Layout::from_size_align(size, 1 << align_shift).unwrap();
+                    let offset = words * size_of::<usize>();
+                    if let Ok(ptr) = heap.allocate_first_fit_with_offset(layout, offset) {
+                        let aligned = ptr.as_ptr().wrapping_add(offset);
+                        prop_assert_eq!(aligned as usize % layout.align(), 0);
+                        prop_assert!(ptr.as_ptr().wrapping_add(size) <= heap.top());
+                        unsafe { ptr.as_ptr().write_bytes(0xab, size) };
+                        live.push((ptr, layout));
+                    }
+                }
+                Action::AllocNear {
+                    size,
+                    align_shift,
+                    permille,
+                } => {
+                    let layout = Layout::from_size_align(size, 1 << align_shift).unwrap();
+                    let target = heap.bottom().wrapping_add(heap.size() * permille / 1000);
+                    if let Ok(ptr) = heap.allocate_near(target, layout) {
+                        prop_assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+                        prop_assert!(ptr.as_ptr().wrapping_add(size) <= heap.top());
+                        unsafe { ptr.as_ptr().write_bytes(0xab, size) };
+                        live.push((ptr, layout));
+                    }
+                }
+                Action::AllocMany {
+                    size,
+                    align_shift,
+                    n,
+                } => {
+                    let layout = Layout::from_size_align(size, 1 << align_shift).unwrap();
+                    let mut ptrs = Vec::new();
+                    let result = heap.allocate_many(layout, n, &mut ptrs);
+                    prop_assert_eq!(result.is_ok(), ptrs.len() == n);
+                    for ptr in ptrs {
+                        prop_assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+                        prop_assert!(ptr.as_ptr().wrapping_add(size) <= heap.top());
+                        unsafe { ptr.as_ptr().write_bytes(0xab, size) };
+                        live.push((ptr, layout));
+                    }
+                }
+                Action::Free { index } => {
+                    if !live.is_empty() {
+                        let (ptr, layout) = live.swap_remove(index % live.len());
+                        unsafe { heap.deallocate(ptr, layout) };
+                    }
+                }
+                Action::FreeBatch { index, count } => {
+                    if !live.is_empty() {
+                        let start = index % live.len();
+                        let end = live.len().min(start + count);
+                        let mut batch: Vec<_> = live.drain(start..end).collect();
+                        unsafe { heap.deallocate_batch(&mut batch) };
+                    }
+                }
+                Action::Extend { by } => {
+                    if heap.top().wrapping_add(by) <= end {
+                        if zeroed {
+                            unsafe { heap.extend_zeroed(by) };
+                        } else {
+                            unsafe { heap.extend(by) };
+                        }
+                    }
+                }
+                Action::Scan { budget } => {
+                    let state = heap.scan_step(budget);
+                    let corrupted = matches!(state, crate::ScanState::Corrupted { .. });
+                    prop_assert!(!corrupted, "{:?}", state);
+                }
+                Action::Drain => drain(heap, &mut live),
+            }
+            check(heap, &live);
+        }
+        drain(heap, &mut live);
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(if cfg!(miri) { 4 } else { 256 }))]
+
+        #[test]
+        fn alloc_free_sequences(
+            offset in 0..16usize,
+            size in (2 * HoleList::min_size())..MAX_HEAP_SIZE,
+            actions in prop::collection::vec(action(), 0..64),
+        ) {
+            let (heap_space_ptr, data_ptr) = Chonk::<{ MAX_HEAP_SIZE + 16 }>::new();
+            let _drop = Dropper::new(heap_space_ptr);
+            let mut heap = unsafe { Heap::new(data_ptr.add(offset), size) };
+            let end = data_ptr.wrapping_add(MAX_HEAP_SIZE + 16);
+            run(&mut heap, end, actions, false)?;
+        }
+
+        #[test]
+        fn zeroed_sequences(
+            offset in 0..16usize,
+            size in (2 * HoleList::min_size())..MAX_HEAP_SIZE,
+            actions in prop::collection::vec(action(), 0..64),
+        ) {
+            let (heap_space_ptr, data_ptr) = Chonk::<{ MAX_HEAP_SIZE + 16 }>::new();
+            let _drop = Dropper::new(heap_space_ptr);
+            let mut heap = Heap::empty();
+            unsafe {
+                data_ptr.write_bytes(0, MAX_HEAP_SIZE + 16);
+                heap.init_zeroed(data_ptr.add(offset), size);
+            }
+            let end = data_ptr.wrapping_add(MAX_HEAP_SIZE + 16);
+            run(&mut heap, end, actions, true)?;
+        }
+    }
+}
+
+/// Model-checked tests for [`LockedHeap`], run with
+/// `RUSTFLAGS="--cfg loom" cargo test --release loom`.
+#[cfg(all(feature = "use_spin", loom))]
+mod loom_model {
+    use super::*;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    const HEAP_SIZE: usize = 256;
+
+    fn locked_heap() -> (*mut Chonk<HEAP_SIZE>, Arc<LockedHeap>) {
+        let (heap_space_ptr, data_ptr) = Chonk::<HEAP_SIZE>::new();
+        let heap = unsafe { LockedHeap::new(data_ptr, HEAP_SIZE) };
+        (heap_space_ptr, Arc::new(heap))
+    }
+
+    /// Allocates a block, fills it with `tag` and checks that nobody else wrote to it
+    /// before it is freed again.
+    fn alloc_and_free(heap: &LockedHeap, tag: u8) {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = unsafe { heap.alloc(layout) };
+        if ptr.is_null() {
+            return;
+        }
+        unsafe { ptr.write_bytes(tag, layout.size()) };
+        thread::yield_now();
+        let contents = unsafe { core::slice::from_raw_parts(ptr, layout.size()) };
+        assert!(contents.iter().all(|&byte| byte == tag));
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+
+    #[test]
+    fn loom_alloc_dealloc() {
+        loom::model(|| {
+            let (heap_space_ptr, heap) = locked_heap();
+
+            let worker = {
+                let heap = heap.clone();
+                thread::spawn(move || alloc_and_free(&heap, 1))
+            };
+            alloc_and_free(&heap, 2);
+            worker.join().unwrap();
+
+            let stats = heap.stats();
+            assert_eq!(stats.used, 0);
+            assert_eq!(stats.holes, 1);
+            assert_eq!(stats.allocations, stats.deallocations);
+            unsafe { Chonk::unleak(heap_space_ptr) };
+        });
+    }
+
+    #[test]
+    fn loom_stats_consistent() {
+        loom::model(|| {
+            let (heap_space_ptr, heap) = locked_heap();
+
+            let worker = {
+                let heap = heap.clone();
+                thread::spawn(move || {
+                    alloc_and_free(&heap, 1);
+                    alloc_and_free(&heap, 2);
+                })
+            };
+            for _ in 0..2 {
+                let stats = heap.stats();
+                assert_eq!(stats.used + stats.free, stats.size);
+                assert!(stats.allocations >= stats.deallocations);
+                assert!(stats.allocations - stats.deallocations <= 1);
+                assert!(stats.peak_used >= stats.used);
+            }
+            worker.join().unwrap();
+
+            let stats = heap.stats();
+            assert_eq!(stats.allocations, 2);
+            assert_eq!(stats.used, 0);
+            unsafe { Chonk::unleak(heap_space_ptr) };
+        });
+    }
+
+    #[test]
+    fn loom_contended_exhaustion() {
+        loom::model(|| {
+            // only a single 32 byte block fits into this heap
+            let (heap_space_ptr, data_ptr) = Chonk::<HEAP_SIZE>::new();
+            let heap = Arc::new(unsafe { LockedHeap::new(data_ptr, 48) });
+
+            let threads: Vec<_> = (1..=2)
+                .map(|tag| {
+                    let heap = heap.clone();
+                    thread::spawn(move || alloc_and_free(&heap, tag))
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+
+            let stats = heap.stats();
+            assert_eq!(stats.allocations + stats.failed_allocations, 2);
+            assert_eq!(stats.used, 0);
+            assert_eq!(stats.holes, 1);
+            unsafe { Chonk::unleak(heap_space_ptr) };
+        });
+    }
+}
+
+#[test]
+fn contains() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    assert!(heap.contains(a));
+    assert!(heap.contains(NonNull::new(heap.bottom()).unwrap()));
+    assert!(heap.contains(NonNull::new(heap.top().wrapping_sub(1)).unwrap()));
+    assert!(!heap.contains(NonNull::new(heap.top()).unwrap()));
+    assert!(!heap.contains(NonNull::new(heap.bottom().wrapping_sub(1)).unwrap()));
+    unsafe { heap.deallocate(a, layout) };
+
+    let empty = Heap::empty();
+    assert!(!empty.contains(NonNull::dangling()));
+}
+
+#[test]
+#[cfg(not(any(feature = "call_sites", feature = "timestamps")))]
+fn priority_reserves() {
+    let mut heap = new_heap();
+    let size = heap.size();
+    heap.set_reserve(Priority::Low, size / 2);
+    heap.set_reserve(Priority::Normal, size / 4);
+    let layout = Layout::from_size_align(size / 8, 8).unwrap();
+
+    let mut ptrs = Vec::new();
+    while let Ok(ptr) = heap.allocate_with_priority(layout, Priority::Low) {
+        ptrs.push(ptr);
+    }
+    assert!(heap.free() >= size / 2);
+    assert_eq!(
+        heap.allocate_with_priority(layout, Priority::Low),
+        Err(AllocError::Reserved)
+    );
+    while let Ok(ptr) = heap.allocate_first_fit(layout) {
+        ptrs.push(ptr);
+    }
+    assert!(heap.free() >= size / 4);
+    assert!(heap.free() < size / 2);
+    assert_eq!(heap.allocate_first_fit(layout), Err(AllocError::Reserved));
+    // enlarged allocations stop at the reserve, too
+    let (ptr, len) = heap.allocate_within(1..=size, 8).unwrap();
+    assert!(heap.free() >= size / 4);
+    unsafe { heap.deallocate(ptr, Layout::from_size_align(len, 8).unwrap()) };
+    let high = heap.allocate_with_priority(layout, Priority::High).unwrap();
+
+    unsafe {
+        heap.deallocate(high, layout);
+        for ptr in ptrs {
+            heap.deallocate(ptr, layout);
+        }
+    }
+    assert_eq!(heap.used(), 0);
+}
+
+#[test]
+fn adopt_persistent_heap() {
+    const HEAP_SIZE: usize = 2048;
+    let (heap_space_ptr, data_ptr) = Chonk::<HEAP_SIZE>::new();
+    let _drop = Dropper::new(heap_space_ptr);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    let mut heap = Heap::empty();
+    unsafe {
+        heap.init_persistent(data_ptr, HEAP_SIZE - 100);
+        heap.extend(100);
+    }
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    let c = heap.allocate_first_fit(layout).unwrap();
+    unsafe {
+        b.as_ptr().write_bytes(0xab, 64);
+        heap.deallocate(a, layout);
+    }
+    let (bottom, top, used) = (heap.bottom(), heap.top(), heap.used());
+
+    // the list is found in the memory after the heap itself was lost
+    let mut heap = Heap::empty();
+    unsafe { heap.adopt(data_ptr, HEAP_SIZE) }.unwrap();
+    assert_eq!(
+        (heap.bottom(), heap.top(), heap.used()),
+        (bottom, top, used)
+    );
+    assert_eq!(heap.stats().holes, 2);
+    assert_eq!(unsafe { *b.as_ptr().add(63) }, 0xab);
+    unsafe {
+        heap.deallocate(b, layout);
+        heap.deallocate(c, layout);
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.holes.check_invariants(), heap.size());
+    heap.allocate_first_fit(layout).unwrap();
+    let hole = heap.holes.first_hole().unwrap().0 as *mut u8;
+
+    assert_eq!(
+        unsafe { Heap::empty().adopt(data_ptr, HEAP_SIZE / 2) },
+        Err(AdoptError::Mismatch)
+    );
+    // a size that is too small for a hole
+    unsafe { *hole.cast::<usize>() = 1 };
+    assert_eq!(
+        unsafe { Heap::empty().adopt(data_ptr, HEAP_SIZE) },
+        Err(AdoptError::Corrupted {
+            addr: hole as usize
+        })
+    );
+    unsafe { data_ptr.write_bytes(0, 16) };
+    assert_eq!(
+        unsafe { Heap::empty().adopt(data_ptr, HEAP_SIZE) },
+        Err(AdoptError::NotFound)
+    );
+}
+
+#[test]
+fn relocate() {
+    const HEAP_SIZE: usize = 2048;
+    let (heap_space_ptr, data_ptr) = Chonk::<HEAP_SIZE>::new();
+    let _drop = Dropper::new(heap_space_ptr);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+
+    let mut heap = unsafe { Heap::new(data_ptr, 1024) };
+    let ptrs: Vec<_> = (0..4)
+        .map(|i| {
+            let ptr = heap.allocate_first_fit(layout).unwrap();
+            unsafe { ptr.as_ptr().write_bytes(i, 64) };
+            ptr
+        })
+        .collect();
+    unsafe { heap.deallocate(ptrs[1], layout) };
+    let (size, used, holes) = (heap.size(), heap.used(), heap.stats().holes);
+
+    // the new location overlaps the old one
+    let distance = 512;
+    unsafe { heap.relocate(heap.bottom().add(distance)) };
+    assert_eq!(heap.bottom(), unsafe { data_ptr.add(distance) });
+    assert_eq!((heap.size(), heap.used()), (size, used));
+    assert_eq!(heap.stats().holes, holes);
+    assert_eq!(heap.holes.check_invariants(), size - used);
+    for (i, ptr) in ptrs.iter().enumerate().filter(|&(i, _)| i != 1) {
+        let moved = unsafe { NonNull::new_unchecked(ptr.as_ptr().add(distance)) };
+        assert_eq!(unsafe { *moved.as_ptr().add(63) }, i as u8);
+        unsafe { heap.deallocate(moved, layout) };
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.holes.check_invariants(), size);
+
+    // a persistent heap can still be adopted at its new location
+    let mut heap = Heap::empty();
+    unsafe { heap.init_persistent(data_ptr.add(distance), 1024) };
+    let ptr = heap.allocate_first_fit(layout).unwrap();
+    let offset = ptr.as_ptr() as usize - heap.bottom() as usize;
+    unsafe { heap.relocate(heap.bottom().sub(distance)) };
+    let mut adopted = Heap::empty();
+    unsafe { adopted.adopt(data_ptr, 1024) }.unwrap();
+    assert_eq!(adopted.used(), heap.used());
+    let moved = unsafe { NonNull::new_unchecked(adopted.bottom().add(offset)) };
+    unsafe { adopted.deallocate(moved, layout) };
+    assert_eq!(adopted.used(), 0);
+}
+
+#[test]
+fn merge() {
+    const HEAP_SIZE: usize = 1024;
+    let (heap_space_ptr, data_ptr) = Chonk::<HEAP_SIZE>::new();
+    let _drop = Dropper::new(heap_space_ptr);
+    let mut heap = unsafe { Heap::new(data_ptr, HEAP_SIZE) };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let mut upper = heap.split_off(HEAP_SIZE / 2).unwrap();
+    let b = upper.allocate_first_fit(layout).unwrap();
+    let used = heap.used() + upper.used();
+
+    // the order of the heaps doesn't matter
+    let mut heap = upper.merge(heap);
+    assert_eq!(heap.size(), HEAP_SIZE);
+    assert_eq!(heap.used(), used);
+    assert_eq!(heap.stats().allocations, 2);
+    // the free blocks at the split point were merged
+    assert_eq!(heap.stats().holes, 2);
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(b, layout);
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.holes.check_invariants(), HEAP_SIZE);
+
+    // the memory between two heaps that were initialized separately becomes free
+    let mut lower = unsafe { Heap::new(data_ptr, 300) };
+    let upper = unsafe { Heap::new(data_ptr.add(300), HEAP_SIZE - 300) };
+    let a = lower.allocate_first_fit(layout).unwrap();
+    let mut heap = lower.merge(upper);
+    assert_eq!(heap.bottom(), data_ptr);
+    assert_eq!(heap.top(), unsafe { data_ptr.add(HEAP_SIZE) });
+    assert_eq!(heap.stats().holes, 1);
+    unsafe { heap.deallocate(a, layout) };
+    assert_eq!(heap.holes.check_invariants(), heap.size());
+}
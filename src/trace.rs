@@ -0,0 +1,443 @@
+//! Recording and replaying of allocation traces.
+//!
+//! [`TraceRecorder`] is a set of [`HeapHooks`] that encodes every allocation, deallocation
+//! and failed allocation of a heap into a fixed-size record and passes it to a
+//! [`TraceSink`], e.g. a ring buffer or a serial port. The [`Replayer`] that is available
+//! with the `std` feature re-runs such a trace against a fresh [`Heap`], so that the
+//! fragmentation of the original heap can be reproduced deterministically.
+//!
+//! # Format
+//!
+//! Every record is [`RECORD_LEN`] bytes long. All fields are little-endian `u64` values
+//! unless noted otherwise, independent of the pointer width of the target.
+//!
+//! | Offset | Size | Field                                                    |
+//! |--------|------|----------------------------------------------------------|
+//! | 0      | 8    | Sequence number, starting at 0 for the first record      |
+//! | 8      | 1    | Event (`u8`): 0 = allocation, 1 = deallocation, 2 = failure |
+//! | 9      | 1    | Base 2 logarithm of the alignment (`u8`)                 |
+//! | 10     | 6    | Reserved, zero                                           |
+//! | 16     | 8    | Size of the layout in bytes                              |
+//! | 24     | 8    | Address of the allocation, zero for failed allocations   |
+
+use core::alloc::Layout;
+use core::convert::TryFrom;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "std")]
+use std::{collections::HashMap, fmt};
+
+#[cfg(feature = "std")]
+use crate::{AllocError, Heap};
+use crate::{HeapHooks, HookContext};
+
+/// The length of a single trace record in bytes.
+pub const RECORD_LEN: usize = 32;
+
+/// Receives the records of a [`TraceRecorder`].
+///
+/// The sink is called from the hooks of the heap, so the restrictions of [`HeapHooks`]
+/// apply: it must not allocate from the traced heap.
+pub trait TraceSink: Sync {
+    /// Called with the encoded record of every traced event, in order.
+    fn write(&self, record: &[u8; RECORD_LEN]);
+}
+
+/// The event that a [`TraceRecord`] describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TraceEvent {
+    /// An allocation at `addr` succeeded.
+    Alloc {
+        /// The address of the allocation.
+        addr: u64,
+    },
+    /// The allocation at `addr` was freed.
+    Dealloc {
+        /// The address of the allocation.
+        addr: u64,
+    },
+    /// An allocation failed.
+    Failed,
+}
+
+/// A single record of an allocation trace, in the format described in the
+/// [`trace`][crate::trace] module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// The position of the record in the trace.
+    pub sequence: u64,
+    /// The layout of the allocation.
+    pub layout: Layout,
+    /// What happened to the allocation.
+    pub event: TraceEvent,
+}
+
+impl TraceRecord {
+    /// Encodes the record.
+    pub fn to_bytes(&self) -> [u8; RECORD_LEN] {
+        let (event, addr) = match self.event {
+            TraceEvent::Alloc { addr } => (0, addr),
+            TraceEvent::Dealloc { addr } => (1, addr),
+            TraceEvent::Failed => (2, 0),
+        };
+        let mut bytes = [0; RECORD_LEN];
+        bytes[0..8].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[8] = event;
+        bytes[9] = self.layout.align().trailing_zeros() as u8;
+        bytes[16..24].copy_from_slice(&(self.layout.size() as u64).to_le_bytes());
+        bytes[24..32].copy_from_slice(&addr.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a record from the start of `bytes`.
+    ///
+    /// Returns `None` if `bytes` is shorter than [`RECORD_LEN`], if the event is unknown or
+    /// if the layout is not valid on this target.
+    pub fn parse(bytes: &[u8]) -> Option<TraceRecord> {
+        let bytes = bytes.get(..RECORD_LEN)?;
+        let field = |start: usize| {
+            let mut field = [0; 8];
+            field.copy_from_slice(&bytes[start..start + 8]);
+            u64::from_le_bytes(field)
+        };
+        let addr = field(24);
+        let event = match bytes[8] {
+            0 => TraceEvent::Alloc { addr },
+            1 => TraceEvent::Dealloc { addr },
+            2 => TraceEvent::Failed,
+            _ => return None,
+        };
+        let align = 1usize.checked_shl(u32::from(bytes[9]))?;
+        let size = usize::try_from(field(16)).ok()?;
+        Some(TraceRecord {
+            sequence: field(0),
+            layout: Layout::from_size_align(size, align).ok()?,
+            event,
+        })
+    }
+}
+
+/// [`HeapHooks`] that record every allocation, deallocation and failed allocation of a
+/// heap to a [`TraceSink`].
+///
+/// Install the recorder with [`Heap::set_hooks`][crate::Heap::set_hooks]:
+///
+/// ```ignore
+/// use linked_list_allocator::trace::{TraceRecorder, TraceSink, RECORD_LEN};
+///
+/// struct Uart;
+///
+/// impl TraceSink for Uart {
+///     fn write(&self, record: &[u8; RECORD_LEN]) {
+///         uart_write(record);
+///     }
+/// }
+///
+/// static RECORDER: TraceRecorder<Uart> = TraceRecorder::new(Uart);
+///
+/// ALLOCATOR.with_heap(|heap| heap.set_hooks(Some(&RECORDER)));
+/// ```
+pub struct TraceRecorder<S> {
+    sink: S,
+    sequence: AtomicUsize,
+}
+
+impl<S> TraceRecorder<S> {
+    /// Creates a recorder that writes to `sink`.
+    pub const fn new(sink: S) -> Self {
+        TraceRecorder {
+            sink,
+            sequence: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the sink of the recorder.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+}
+
+impl<S: TraceSink> TraceRecorder<S> {
+    fn record(&self, layout: Layout, event: TraceEvent) {
+        let record = TraceRecord {
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed) as u64,
+            layout,
+            event,
+        };
+        self.sink.write(&record.to_bytes());
+    }
+}
+
+impl<S: TraceSink> HeapHooks for TraceRecorder<S> {
+    fn on_alloc(&self, ptr: NonNull<u8>, layout: Layout, _context: &HookContext) {
+        let addr = ptr.as_ptr() as usize as u64;
+        self.record(layout, TraceEvent::Alloc { addr });
+    }
+
+    fn on_dealloc(&self, ptr: NonNull<u8>, layout: Layout, _context: &HookContext) {
+        let addr = ptr.as_ptr() as usize as u64;
+        self.record(layout, TraceEvent::Dealloc { addr });
+    }
+
+    fn on_fail(&self, layout: Layout, _context: &HookContext) {
+        self.record(layout, TraceEvent::Failed);
+    }
+}
+
+/// Re-runs an allocation trace against a heap.
+///
+/// Every allocation is replayed with [`Heap::allocate_first_fit`], and the replayer maps
+/// the addresses of the trace to the allocations that it made, so that deallocations free
+/// the right block. Traces of allocations that were made with another method, e.g.
+/// [`Heap::allocate_near`], may therefore diverge from the original heap.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct Replayer {
+    /// The live allocations by their address in the trace.
+    live: HashMap<u64, (NonNull<u8>, Layout)>,
+}
+
+/// The reason why [`Replayer::replay`] couldn't replay a record.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The record at the given index of the trace is malformed.
+    Malformed {
+        /// The index of the record.
+        index: usize,
+    },
+    /// An allocation failed during the replay, but succeeded in the trace, or the other way
+    /// around.
+    Diverged {
+        /// The sequence number of the record.
+        sequence: u64,
+        /// The result of the allocation during the replay.
+        result: Result<(), AllocError>,
+    },
+    /// A deallocation freed an address that is not live in the replay.
+    UnknownAddress {
+        /// The sequence number of the record.
+        sequence: u64,
+    },
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Malformed { index } => write!(f, "trace record {} is malformed", index),
+            ReplayError::Diverged { sequence, result } => match result {
+                Ok(()) => write!(
+                    f,
+                    "allocation {} succeeded, but failed in the trace",
+                    sequence
+                ),
+                Err(err) => write!(f, "allocation {} failed: {}", sequence, err),
+            },
+            ReplayError::UnknownAddress { sequence } => {
+                write!(f, "deallocation {} frees an unknown address", sequence)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReplayError {}
+
+#[cfg(feature = "std")]
+impl Replayer {
+    /// Creates a replayer without live allocations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of allocations of the trace that are live in the replay.
+    pub fn live(&self) -> usize {
+        self.live.len()
+    }
+
+    /// Replays a single record against `heap`.
+    ///
+    /// On a [divergence][ReplayError::Diverged], an allocation that succeeded during the
+    /// replay is kept and freed again by the deallocation in the trace, so the replay can
+    /// continue.
+    pub fn replay(&mut self, heap: &mut Heap, record: &TraceRecord) -> Result<(), ReplayError> {
+        let sequence = record.sequence;
+        match record.event {
+            TraceEvent::Alloc { addr } => {
+                let ptr = heap.allocate_first_fit(record.layout).map_err(|err| {
+                    ReplayError::Diverged {
+                        sequence,
+                        result: Err(err),
+                    }
+                })?;
+                self.live.insert(addr, (ptr, record.layout));
+                Ok(())
+            }
+            TraceEvent::Failed => match heap.allocate_first_fit(record.layout) {
+                Ok(ptr) => {
+                    // SAFETY: The allocation was just made with this layout.
+                    unsafe { heap.deallocate(ptr, record.layout) };
+                    Err(ReplayError::Diverged {
+                        sequence,
+                        result: Ok(()),
+                    })
+                }
+                Err(_) => Ok(()),
+            },
+            TraceEvent::Dealloc { addr } => {
+                let (ptr, layout) = match self.live.remove(&addr) {
+                    Some(allocation) => allocation,
+                    None if record.layout.size() == 0 => return Ok(()),
+                    None => return Err(ReplayError::UnknownAddress { sequence }),
+                };
+                // SAFETY: The allocation was made by this replayer on `heap`.
+                unsafe { heap.deallocate(ptr, layout) };
+                Ok(())
+            }
+        }
+    }
+
+    /// Replays all records in `trace` against `heap`, stopping at the first error.
+    ///
+    /// `trace` must consist of whole records, as written to a [`TraceSink`].
+    pub fn replay_all(&mut self, heap: &mut Heap, trace: &[u8]) -> Result<(), ReplayError> {
+        if trace.len() % RECORD_LEN != 0 {
+            return Err(ReplayError::Malformed {
+                index: trace.len() / RECORD_LEN,
+            });
+        }
+        for (index, bytes) in trace.chunks_exact(RECORD_LEN).enumerate() {
+            let record = TraceRecord::parse(bytes).ok_or(ReplayError::Malformed { index })?;
+            self.replay(heap, &record)?;
+        }
+        Ok(())
+    }
+
+    /// Frees all allocations of the trace that are still live in the replay.
+    pub fn free_all(&mut self, heap: &mut Heap) {
+        for (_, (ptr, layout)) in self.live.drain() {
+            // SAFETY: The allocation was made by this replayer on `heap`.
+            unsafe { heap.deallocate(ptr, layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TraceEvent, TraceRecord, TraceRecorder, TraceSink, RECORD_LEN};
+    use crate::test::new_heap;
+    use crate::Heap;
+    use core::alloc::Layout;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    /// Collects the records in memory.
+    struct VecSink(Mutex<Vec<u8>>);
+
+    impl TraceSink for VecSink {
+        fn write(&self, record: &[u8; RECORD_LEN]) {
+            self.0.lock().unwrap().extend_from_slice(record);
+        }
+    }
+
+    /// Records a few allocations, a failure and a deallocation on `heap`, and returns the
+    /// trace and the address of the freed allocation. Two allocations of 24 bytes stay live.
+    fn record(heap: &mut Heap, recorder: &'static TraceRecorder<VecSink>) -> (Vec<u8>, usize) {
+        heap.set_hooks(Some(recorder));
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let large = Layout::from_size_align(200, 64).unwrap();
+        heap.allocate_first_fit(small).unwrap();
+        let b = heap.allocate_first_fit(large).unwrap();
+        heap.allocate_first_fit(small).unwrap();
+        let too_large = Layout::from_size_align(heap.size(), 8).unwrap();
+        assert!(heap.allocate_first_fit(too_large).is_err());
+        unsafe { heap.deallocate(b, large) };
+        heap.set_hooks(None);
+        let trace = recorder.sink().0.lock().unwrap().clone();
+        (trace, b.as_ptr() as usize)
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let record = TraceRecord {
+            sequence: 7,
+            layout: Layout::from_size_align(24, 64).unwrap(),
+            event: TraceEvent::Dealloc { addr: 0x1234 },
+        };
+        let bytes = record.to_bytes();
+        assert_eq!(TraceRecord::parse(&bytes), Some(record));
+        assert_eq!(TraceRecord::parse(&bytes[..RECORD_LEN - 1]), None);
+        let mut unknown = bytes;
+        unknown[8] = 3;
+        assert_eq!(TraceRecord::parse(&unknown), None);
+    }
+
+    #[test]
+    fn records_events() {
+        static RECORDER: TraceRecorder<VecSink> =
+            TraceRecorder::new(VecSink(Mutex::new(Vec::new())));
+
+        let mut heap = new_heap();
+        let (trace, freed) = record(&mut heap, &RECORDER);
+        let records: Vec<_> = trace
+            .chunks(RECORD_LEN)
+            .map(|bytes| TraceRecord::parse(bytes).unwrap())
+            .collect();
+        assert_eq!(records.len(), 5);
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(record.sequence, i as u64);
+        }
+        assert_eq!(records[3].event, TraceEvent::Failed);
+        assert_eq!(records[3].layout.size(), heap.size());
+        assert_eq!(records[4].event, TraceEvent::Dealloc { addr: freed as u64 });
+        assert_eq!(records[4].layout.align(), 64);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn replay() {
+        use super::{ReplayError, Replayer};
+
+        static RECORDER: TraceRecorder<VecSink> =
+            TraceRecorder::new(VecSink(Mutex::new(Vec::new())));
+
+        let mut heap = new_heap();
+        let (trace, _) = record(&mut heap, &RECORDER);
+        let holes: Vec<_> = heap.holes.holes().map(|(_, size)| size).collect();
+
+        // the replay reproduces the free blocks of the original heap
+        let mut replay = new_heap();
+        let mut replayer = Replayer::new();
+        replayer.replay_all(&mut replay, &trace).unwrap();
+        assert_eq!(replayer.live(), 2);
+        let replayed: Vec<_> = replay.holes.holes().map(|(_, size)| size).collect();
+        assert_eq!(replayed, holes);
+        replayer.free_all(&mut replay);
+        assert_eq!(replay.used(), 0);
+
+        // an allocation that fails during the replay
+        let filler = Layout::from_size_align(replay.free() - 160, 8).unwrap();
+        let ptr = replay.allocate_first_fit(filler).unwrap();
+        let mut replayer = Replayer::new();
+        assert!(matches!(
+            replayer.replay_all(&mut replay, &trace),
+            Err(ReplayError::Diverged { result: Err(_), .. })
+        ));
+        replayer.free_all(&mut replay);
+        unsafe { replay.deallocate(ptr, filler) };
+
+        // freeing an allocation that isn't live
+        let mut replayer = Replayer::new();
+        assert_eq!(
+            replayer.replay_all(&mut replay, &trace[4 * RECORD_LEN..]),
+            Err(ReplayError::UnknownAddress { sequence: 4 })
+        );
+        assert_eq!(
+            replayer.replay_all(&mut replay, &trace[..RECORD_LEN - 1]),
+            Err(ReplayError::Malformed { index: 0 })
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Setting up a [`LockedHeap`] with memory of the UEFI boot services, enabled by the `uefi`
+//! feature.
+
+use r_efi::efi::{BootServices, PhysicalAddress, Status, ALLOCATE_ANY_PAGES, LOADER_DATA};
+
+use crate::{align_up_size, AdoptError, LockedHeap, RawMutex};
+
+/// The size of the pages that the UEFI boot services hand out.
+pub const UEFI_PAGE_SIZE: usize = 4096;
+
+/// The memory of a heap that was allocated from the UEFI boot services by
+/// [`LockedHeap::init_uefi`].
+///
+/// The pages have the memory type `LoaderData`, so the firmware doesn't reclaim them when
+/// `ExitBootServices` is called and they show up as such in the final memory map. A kernel
+/// that takes over from a loader must not hand them to its frame allocator. The region has
+/// a C layout, so that a loader can pass it on, e.g. in its boot information, to a kernel
+/// that [adopts][LockedHeap::adopt_uefi] the heap.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UefiRegion {
+    /// The physical address of the first page.
+    pub start: PhysicalAddress,
+    /// The number of pages of [`UEFI_PAGE_SIZE`] bytes.
+    pub pages: usize,
+}
+
+impl UefiRegion {
+    /// Allocates `size` bytes, rounded up to whole pages, as `LoaderData` from the boot
+    /// services. Returns the status of the boot services if the allocation fails.
+    ///
+    /// # Safety
+    ///
+    /// `boot_services` must point to the boot services table of the firmware, and
+    /// `ExitBootServices` must not have been called yet.
+    pub unsafe fn allocate(boot_services: *mut BootServices, size: usize) -> Result<Self, Status> {
+        let pages = align_up_size(size, UEFI_PAGE_SIZE) / UEFI_PAGE_SIZE;
+        let mut start: PhysicalAddress = 0;
+        let status =
+            ((*boot_services).allocate_pages)(ALLOCATE_ANY_PAGES, LOADER_DATA, pages, &mut start);
+        if status.is_error() {
+            return Err(status);
+        }
+        Ok(UefiRegion { start, pages })
+    }
+
+    /// Returns the first byte of the region, which UEFI maps at its physical address.
+    pub fn bottom(&self) -> *mut u8 {
+        self.start as usize as *mut u8
+    }
+
+    /// Returns the size of the region in bytes.
+    pub fn size(&self) -> usize {
+        self.pages * UEFI_PAGE_SIZE
+    }
+}
+
+impl<R: RawMutex> LockedHeap<R> {
+    /// Allocates `size` bytes from the UEFI boot services and initializes this heap with
+    /// them, see [`UefiRegion::allocate`].
+    ///
+    /// The heap is initialized as a [persistent][crate::Heap::init_persistent] heap, so
+    /// that it can be [adopted][Self::adopt_uefi] again from the returned region, e.g. by a
+    /// kernel that was loaded by this loader, after `ExitBootServices`. Within the same
+    /// program, the heap stays usable after `ExitBootServices` without doing anything.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the heap is already initialized.
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`UefiRegion::allocate`] apply.
+    pub unsafe fn init_uefi(
+        &self,
+        boot_services: *mut BootServices,
+        size: usize,
+    ) -> Result<UefiRegion, Status> {
+        let mut heap = self.lock();
+        assert!(
+            heap.bottom().is_null(),
+            "The heap has already been initialized."
+        );
+        let region = UefiRegion::allocate(boot_services, size)?;
+        heap.init_persistent(region.bottom(), region.size());
+        Ok(region)
+    }
+
+    /// Resumes using the heap in a region of [`init_uefi`][Self::init_uefi] with this empty
+    /// heap, see [`Heap::adopt`][crate::Heap::adopt].
+    ///
+    /// The allocations that were made before stay valid, and the
+    /// [counters][Self::counters] start at the memory they use.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the heap is already initialized.
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`Heap::adopt`][crate::Heap::adopt] apply. The region must still
+    /// be mapped at its physical address.
+    pub unsafe fn adopt_uefi(&self, region: UefiRegion) -> Result<(), AdoptError> {
+        let mut heap = self.lock();
+        assert!(
+            heap.bottom().is_null(),
+            "The heap has already been initialized."
+        );
+        heap.adopt(region.bottom(), region.size())?;
+        self.1.reset(&heap);
+        Ok(())
+    }
+}
+
+// the region is passed on as a physical address, which Miri can't turn back into a pointer
+// with strict provenance
+#[cfg(all(test, not(miri)))]
+mod test {
+    use super::{UefiRegion, UEFI_PAGE_SIZE};
+    use crate::test::Chonk;
+    use crate::LockedHeap;
+    use core::alloc::Layout;
+
+    #[test]
+    fn adopts_uefi_region() {
+        let (chonk, data) = Chonk::<UEFI_PAGE_SIZE>::new();
+        let region = UefiRegion {
+            start: data as u64,
+            pages: 1,
+        };
+        // like `init_uefi` with memory of the boot services
+        let loader: LockedHeap = LockedHeap::empty();
+        loader.with_heap(|heap| unsafe { heap.init_persistent(region.bottom(), region.size()) });
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = loader.allocate_first_fit(layout).unwrap();
+        let used = loader.stats().used;
+
+        let kernel: LockedHeap = LockedHeap::empty();
+        unsafe { kernel.adopt_uefi(region) }.unwrap();
+        assert_eq!(kernel.stats().used, used);
+        assert_eq!(kernel.counters().used, used);
+        unsafe { kernel.deallocate(a, layout) };
+        assert_eq!(kernel.counters().used, 0);
+        assert_eq!(kernel.stats().used, 0);
+        unsafe { Chonk::unleak(chonk) };
+    }
+}
@@ -0,0 +1,251 @@
+//! Allocations that wait for memory to be freed, see [`AsyncHeap`].
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::future::Future;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::{Context, Poll, Waker};
+
+use crate::sync::{Mutex, RawSpinlock};
+use crate::{AllocError, LockedHeap};
+
+/// A task that waits for a free block of at least `size` bytes.
+struct Waiter {
+    waker: Waker,
+    size: usize,
+}
+
+/// A [`LockedHeap`] whose allocations can wait for memory instead of failing.
+///
+/// [`allocate`][AsyncHeap::allocate] returns a future that resolves once the allocation
+/// succeeds. If the heap is out of memory, the waker of the task is registered in one of `N`
+/// slots and woken when an allocation is freed into a block that might be large enough.
+/// Only frees through the `AsyncHeap` itself, i.e. [`deallocate`][AsyncHeap::deallocate]
+/// and its [`GlobalAlloc`] implementation, wake waiting tasks. After memory was added in
+/// another way, e.g. by extending the heap, [`wake_all`][AsyncHeap::wake_all] must be
+/// called.
+///
+/// ```ignore
+/// use linked_list_allocator::{AsyncHeap, LockedHeap};
+///
+/// static HEAP: AsyncHeap<4> = AsyncHeap::new(LockedHeap::empty());
+///
+/// async fn receive() {
+///     let layout = Layout::from_size_align(1500, 4).unwrap();
+///     let buffer = HEAP.allocate(layout).await.unwrap();
+///     // ...
+/// }
+/// ```
+pub struct AsyncHeap<const N: usize> {
+    heap: LockedHeap,
+    waiters: Mutex<RawSpinlock, [Option<Waiter>; N]>,
+}
+
+impl<const N: usize> AsyncHeap<N> {
+    /// Wraps the given heap. At most `N` allocations can wait for memory at the same time.
+    pub const fn new(heap: LockedHeap) -> Self {
+        const NONE: Option<Waiter> = None;
+        AsyncHeap {
+            heap,
+            waiters: Mutex::new([NONE; N]),
+        }
+    }
+
+    /// Returns the wrapped heap.
+    pub fn heap(&self) -> &LockedHeap {
+        &self.heap
+    }
+
+    /// Returns a future that allocates a block for `layout` and waits for memory to be freed
+    /// while the heap has no block that is large enough.
+    ///
+    /// The future fails with the error of the allocation if it can't be fixed by freeing
+    /// memory, e.g. [`AllocError::InvalidLayout`], and with [`AllocError::Exhausted`] if all
+    /// `N` slots for waiting tasks are taken.
+    pub fn allocate(&self, layout: Layout) -> AllocateFuture<'_, N> {
+        AllocateFuture {
+            heap: self,
+            layout,
+            slot: None,
+        }
+    }
+
+    /// Frees the given allocation and wakes the tasks that wait for a block that might fit
+    /// into the free block that the allocation became part of.
+    ///
+    /// # Safety
+    ///
+    /// The requirements of [`Heap::deallocate`][crate::Heap::deallocate] apply.
+    pub unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let free_block = self.heap.deallocate_locked(self.heap.lock(), ptr, layout);
+        self.wake(free_block);
+    }
+
+    /// Wakes all tasks that wait for memory, e.g. after the heap was extended.
+    pub fn wake_all(&self) {
+        self.wake(usize::MAX);
+    }
+
+    /// Wakes the tasks that wait for at most `size` bytes. They stay registered until their
+    /// allocation succeeds, since the block might be taken by someone else in the meantime.
+    fn wake(&self, size: usize) {
+        for waiter in self.waiters.lock().iter().flatten() {
+            if waiter.size <= size {
+                waiter.waker.wake_by_ref();
+            }
+        }
+    }
+
+    /// Registers the waker of a task that waits for `size` bytes in the given slot or in a
+    /// free one. Returns the slot, or `None` if all slots are taken.
+    fn register(&self, slot: Option<usize>, waker: &Waker, size: usize) -> Option<usize> {
+        let mut waiters = self.waiters.lock();
+        let slot = slot.or_else(|| waiters.iter().position(Option::is_none))?;
+        match &mut waiters[slot] {
+            Some(waiter) if waiter.waker.will_wake(waker) => {}
+            entry => {
+                *entry = Some(Waiter {
+                    waker: waker.clone(),
+                    size,
+                })
+            }
+        }
+        Some(slot)
+    }
+
+    fn unregister(&self, slot: usize) {
+        self.waiters.lock()[slot] = None;
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for AsyncHeap<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.heap.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.heap.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new_unchecked(ptr), layout)
+    }
+}
+
+/// The future returned by [`AsyncHeap::allocate`].
+pub struct AllocateFuture<'a, const N: usize> {
+    heap: &'a AsyncHeap<N>,
+    layout: Layout,
+    /// The slot of the waker while the allocation waits for memory.
+    slot: Option<usize>,
+}
+
+impl<'a, const N: usize> AllocateFuture<'a, N> {
+    fn try_allocate(&mut self) -> Poll<Result<NonNull<u8>, AllocError>> {
+        let layout = self.layout;
+        match self
+            .heap
+            .heap
+            .allocate_with(|heap| heap.allocate_first_fit(layout))
+        {
+            Err(AllocError::OutOfMemory)
+            | Err(AllocError::Fragmented { .. })
+            | Err(AllocError::Reserved) => Poll::Pending,
+            result => {
+                if let Some(slot) = self.slot.take() {
+                    self.heap.unregister(slot);
+                }
+                Poll::Ready(result)
+            }
+        }
+    }
+}
+
+impl<'a, const N: usize> Future for AllocateFuture<'a, N> {
+    type Output = Result<NonNull<u8>, AllocError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(result) = self.try_allocate() {
+            return Poll::Ready(result);
+        }
+        let size = self.layout.size();
+        self.slot = match self.heap.register(self.slot, cx.waker(), size) {
+            Some(slot) => Some(slot),
+            None => return Poll::Ready(Err(AllocError::Exhausted)),
+        };
+        // memory that was freed before the waker was registered didn't wake the task
+        self.try_allocate()
+    }
+}
+
+impl<'a, const N: usize> Drop for AllocateFuture<'a, N> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            self.heap.unregister(slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AsyncHeap;
+    use crate::test::Chonk;
+    use crate::{AllocError, LockedHeap};
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    /// Counts how often it was woken.
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn waits_for_free() {
+        let (chonk, data) = Chonk::<512>::new();
+        let heap = AsyncHeap::<1>::new(unsafe { LockedHeap::new(data, 512) });
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = counter.clone().into();
+        let mut cx = Context::from_waker(&waker);
+
+        let large = Layout::from_size_align(300, 8).unwrap();
+        let first = match Pin::new(&mut heap.allocate(large)).poll(&mut cx) {
+            Poll::Ready(Ok(ptr)) => ptr,
+            other => panic!("unexpected {:?}", other),
+        };
+
+        let mut second = heap.allocate(large);
+        assert!(Pin::new(&mut second).poll(&mut cx).is_pending());
+        // the only slot is taken
+        let mut third = heap.allocate(large);
+        assert_eq!(
+            Pin::new(&mut third).poll(&mut cx),
+            Poll::Ready(Err(AllocError::Exhausted))
+        );
+
+        // freeing a block that is too small doesn't wake the task
+        let small = Layout::from_size_align(32, 8).unwrap();
+        let ptr = unsafe { heap.alloc(small) };
+        unsafe { heap.dealloc(ptr, small) };
+        assert_eq!(counter.0.load(Ordering::Relaxed), 0);
+
+        unsafe { heap.deallocate(first, large) };
+        assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+        let second = match Pin::new(&mut second).poll(&mut cx) {
+            Poll::Ready(Ok(ptr)) => ptr,
+            other => panic!("unexpected {:?}", other),
+        };
+        unsafe { heap.deallocate(second, large) };
+        assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+
+        unsafe { Chonk::unleak(chonk) };
+    }
+}
@@ -0,0 +1,190 @@
+//! Watchpoints on address ranges, which report every allocation and deallocation that
+//! overlaps them.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::Heap;
+
+/// The number of watchpoints that a heap can hold at the same time.
+pub const MAX_WATCHPOINTS: usize = 4;
+
+/// Called for allocations and deallocations that overlap a watched address range, see
+/// [`Heap::watch`].
+///
+/// This trait is implemented for all functions and closures that take a [`WatchEvent`].
+/// Like [`HeapHooks`][crate::HeapHooks], watchers are called while the heap is borrowed
+/// mutably, so they must not allocate from the same heap.
+pub trait Watcher: Sync {
+    /// Called after the allocation or deallocation of `event`.
+    fn on_event(&self, event: WatchEvent);
+}
+
+impl<F: Fn(WatchEvent) + Sync> Watcher for F {
+    fn on_event(&self, event: WatchEvent) {
+        self(event)
+    }
+}
+
+/// An allocation or deallocation that overlaps a watched address range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WatchEvent {
+    /// The watchpoint whose range is overlapped.
+    pub id: WatchId,
+    /// Whether the memory was allocated or freed.
+    pub kind: WatchKind,
+    /// The pointer to the allocation.
+    pub ptr: NonNull<u8>,
+    /// The layout of the allocation.
+    pub layout: Layout,
+}
+
+/// The kind of a [`WatchEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WatchKind {
+    /// The memory was allocated.
+    Allocate,
+    /// The memory was freed.
+    Deallocate,
+}
+
+/// Identifies a watchpoint of a heap, returned by [`Heap::watch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WatchId(usize);
+
+#[derive(Clone, Copy)]
+struct Watchpoint {
+    start: usize,
+    end: usize,
+    watcher: &'static dyn Watcher,
+}
+
+/// The watchpoints of a heap.
+#[derive(Clone, Copy)]
+pub(crate) struct Watchpoints {
+    slots: [Option<Watchpoint>; MAX_WATCHPOINTS],
+}
+
+impl Watchpoints {
+    pub const fn new() -> Self {
+        Watchpoints {
+            slots: [None; MAX_WATCHPOINTS],
+        }
+    }
+
+    /// Calls the watchers whose range overlaps the given allocation, whose untagged
+    /// address is `start`.
+    pub fn notify(&self, kind: WatchKind, ptr: NonNull<u8>, start: usize, layout: Layout) {
+        let end = start.saturating_add(layout.size());
+        for (index, slot) in self.slots.iter().enumerate() {
+            if let Some(watchpoint) = slot {
+                if start < watchpoint.end && watchpoint.start < end {
+                    watchpoint.watcher.on_event(WatchEvent {
+                        id: WatchId(index),
+                        kind,
+                        ptr,
+                        layout,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Heap {
+    /// Calls `watcher` for every allocation and deallocation whose bytes overlap the `len`
+    /// bytes at `addr`, e.g. to find out which code owned the memory at an address that
+    /// gets corrupted.
+    ///
+    /// The watcher is called after the same events as
+    /// [`HeapHooks::on_alloc`][crate::HeapHooks::on_alloc] and
+    /// [`HeapHooks::on_dealloc`][crate::HeapHooks::on_dealloc], with the pointer and
+    /// layout that the caller of the heap sees. Zero-sized allocations overlap no range.
+    /// Returns `None` if all [`MAX_WATCHPOINTS`] watchpoints are in use.
+    ///
+    /// ```
+    /// # use linked_list_allocator::{Heap, WatchEvent};
+    /// # use std::alloc::Layout;
+    /// # let mut heap_space = [0u8; 1024];
+    /// # let mut heap = unsafe { Heap::new(heap_space.as_mut_ptr(), heap_space.len()) };
+    /// fn report(event: WatchEvent) {
+    ///     println!("{:?} of {:?} at {:p}", event.kind, event.layout, event.ptr);
+    /// }
+    ///
+    /// let id = heap.watch(heap.bottom(), 1, &(report as fn(WatchEvent))).unwrap();
+    /// let ptr = heap.allocate_first_fit(Layout::new::<u64>()).unwrap();
+    /// heap.unwatch(id);
+    /// ```
+    pub fn watch(
+        &mut self,
+        addr: *const u8,
+        len: usize,
+        watcher: &'static dyn Watcher,
+    ) -> Option<WatchId> {
+        let index = self.watchpoints.slots.iter().position(Option::is_none)?;
+        let start = addr as usize;
+        self.watchpoints.slots[index] = Some(Watchpoint {
+            start,
+            end: start.saturating_add(len),
+            watcher,
+        });
+        Some(WatchId(index))
+    }
+
+    /// Removes a watchpoint that was added with [`watch`][Self::watch].
+    pub fn unwatch(&mut self, id: WatchId) {
+        self.watchpoints.slots[id.0] = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WatchEvent, WatchKind, MAX_WATCHPOINTS};
+    use crate::test::new_heap;
+    use core::alloc::Layout;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+
+    fn count(event: WatchEvent) {
+        match event.kind {
+            WatchKind::Allocate => ALLOCATED.fetch_add(1, Ordering::Relaxed),
+            WatchKind::Deallocate => FREED.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    #[test]
+    fn watches_address_range() {
+        let mut heap = new_heap();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let a = heap.allocate_first_fit(layout).unwrap();
+        let b = heap.allocate_first_fit(layout).unwrap();
+        unsafe { heap.deallocate(b, layout) };
+
+        // the last byte of `b`
+        let addr = unsafe { b.as_ptr().add(63) };
+        let id = heap.watch(addr, 1, &(count as fn(WatchEvent))).unwrap();
+        let c = heap.allocate_first_fit(layout).unwrap();
+        assert_eq!(c, b);
+        assert_eq!(ALLOCATED.load(Ordering::Relaxed), 1);
+        unsafe { heap.deallocate(a, layout) };
+        assert_eq!(FREED.load(Ordering::Relaxed), 0);
+        unsafe { heap.deallocate(c, layout) };
+        assert_eq!(FREED.load(Ordering::Relaxed), 1);
+
+        heap.unwatch(id);
+        let d = heap.allocate_first_fit(layout).unwrap();
+        unsafe { heap.deallocate(d, layout) };
+        assert_eq!(ALLOCATED.load(Ordering::Relaxed), 1);
+        assert_eq!(FREED.load(Ordering::Relaxed), 1);
+
+        for _ in 0..MAX_WATCHPOINTS {
+            heap.watch(addr, 1, &(count as fn(WatchEvent))).unwrap();
+        }
+        assert!(heap.watch(addr, 1, &(count as fn(WatchEvent))).is_none());
+    }
+}